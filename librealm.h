@@ -11,12 +11,57 @@
 
 #define UDP_TIMEOUT 30
 
+#define COALESCE_DELAY 5
+
+#define UNHEALTHY_COOLDOWN 10
+
+#define SCALING_DEBOUNCE 500
+
+#define TRACE_MAX_SIZE ((16 * 1024) * 1024)
+
 #define PROXY_PROTOCOL_VERSION 2
 
 #define PROXY_PROTOCOL_TIMEOUT 5
 
+/**
+ * 实例的生命周期事件
+ */
+typedef enum LifecycleEvent {
+  /**
+   * 开始创建实例
+   */
+  Starting = 0,
+  /**
+   * 已绑定监听地址并开始接受连接
+   */
+  Ready = 1,
+  /**
+   * 开始关闭实例
+   */
+  Stopping = 2,
+  /**
+   * 已关闭，创建失败时紧随Starting
+   */
+  Stopped = 3,
+} LifecycleEvent;
+
 typedef struct Features Features;
 
+/**
+ * 扩缩容回调，up为true表示活跃连接数达到高水位，false表示回落到低水位
+ */
+typedef void (*ScalingCallback)(const char *config_key, bool up, uint64_t active);
+
+/**
+ * 连接关闭前的回调，client为客户端地址，reason为日志中的关闭原因，如"idle"
+ */
+typedef void (*CloseCallback)(const char *config_key, const char *client, const char *reason);
+
+/**
+ * 生命周期回调，timestamp为事件发生时的unix时间，毫秒
+ */
+typedef void (*LifecycleCallback)(const char *config_key, LifecycleEvent event, uint64_t timestamp);
+
 
 
 /**
@@ -34,6 +79,8 @@ typedef struct Features Features;
  * 注意:
  * - 确保已经正确编译并链接了Realm库
  * - start_realm函数不再阻塞，而是在后台运行
+ * - 配置无效、绑定失败或运行时创建失败时返回NULL，可调用realm_last_error获取错误信息
+ * - 相同参数的实例已存在时共享该实例并增加引用计数，严格模式见realm_set_strict_start
  */
 const char *start_realm(const char *remote,
                         const char *host,
@@ -41,4 +88,288 @@ const char *start_realm(const char *remote,
                         bool tls,
                         bool insecure);
 
+/**
+ * 返回当前线程最近一次失败的错误信息，用于start_realm返回NULL之后
+ *
+ * 注意:
+ * - 没有错误时返回NULL
+ * - 返回的字符串需要调用realm_free_string释放
+ */
+const char *realm_last_error(void);
+
+/**
+ * 检查start_realm的参数，不启动实例。配置有效时返回NULL，否则返回JSON数组，
+ * 逐项指出出错的传输选项及原因:
+ *
+ *    [{"field":"remote_transport","option":"path","reason":"ws does not start with /"}]
+ *
+ * 注意:
+ * - 传输选项之外的错误只有一项，其field和option为null
+ * - 返回的字符串需要调用realm_free_string释放
+ */
+const char *realm_validate_config(const char *remote,
+                                  const char *host,
+                                  const char *path,
+                                  bool tls,
+                                  bool insecure);
+
 void stop_realm(const char *remote, const char *host, const char *path, bool tls, bool insecure);
+
+/**
+ * 设置就绪信号，所有正在启动的实例绑定完监听端口后，向fd写入一个字节，
+ * 可用于eventfd或pipe，配合systemd Type=notify式的进程管理
+ *
+ * 注意:
+ * - 仅发送一次，再次设置会替换之前未发送的fd
+ * - 调用时没有正在启动的实例则立即发送，启动失败的实例不会阻塞信号
+ * - realm_start_batch的所有配置处理完后才发送
+ * - eventfd写入的是8字节计数1
+ * - fd由调用方负责关闭，发送前不应关闭
+ * - 仅支持Unix
+ */
+void realm_notify_ready_fd(int fd);
+
+/**
+ * 按start_realm返回的监听地址关闭实例，与stop_realm相同，减少其引用计数
+ *
+ * 注意:
+ * - 未找到监听该地址的实例时返回false
+ */
+bool realm_stop_by_listen(const char *listen_addr);
+
+/**
+ * 关闭所有实例，不论引用计数，先停止接受新连接并等待已有连接结束，
+ * 超过deadline_ms毫秒仍未结束的连接被强制断开，返回强制断开的连接数
+ *
+ * 注意:
+ * - 阻塞直到所有实例关闭，最长约deadline_ms毫秒
+ * - deadline_ms为0时立即强制断开
+ * - 强制断开时记录警告日志
+ * - 调用期间启动的实例不受影响
+ */
+uint64_t realm_stop_all(uint64_t deadline_ms);
+
+/**
+ * 批量启动Realm实例，configs为JSON数组:
+ *
+ *    [{"remote":"example.com:443","host":"example.com","path":"/ws","tls":true,"insecure":false}]
+ *
+ * 返回与configs一一对应的JSON数组，成功项包含监听地址，失败项包含错误信息:
+ *
+ *    [{"config_key":"example.com:443-example.com-/ws-true-false","listen_addr":"127.0.0.1:40000"},
+ *     {"config_key":null,"error":"missing field `path`"}]
+ *
+ * 注意:
+ * - tls和insecure可省略，默认为false
+ * - 某项失败不影响其他项，已启动的实例不会回滚
+ * - atomic为true时，任一项失败则回滚本次启动的所有实例，成功项的错误信息为"rolled back"
+ * - configs不是JSON数组时返回NULL
+ * - 返回的字符串需要调用realm_free_string释放
+ */
+const char *realm_start_batch(const char *configs, bool atomic);
+
+/**
+ * 清除隧道远端域名的DNS缓存，之后的连接将重新解析
+ *
+ * config_key由start_realm的参数组成: "remote-host-path-tls-insecure"，
+ * 例如 "example.com:443-host-/path-true-false"
+ *
+ * 注意:
+ * - 解析器不支持按域名清除，这会清空整个DNS缓存
+ * - 未找到对应实例时返回false
+ */
+bool realm_flush_remote_dns(const char *config_key);
+
+/**
+ * 禁用隧道，关闭其运行时和监听套接字，释放端口，但保留实例及其引用计数
+ *
+ * 注意:
+ * - 与stop_realm不同，实例仍在列表中，可通过realm_enable重新启用
+ * - 活跃连接会被断开，扩缩容回调需重新设置
+ * - 已禁用时不做任何操作
+ * - 未找到对应实例时返回false
+ */
+bool realm_disable(const char *config_key);
+
+/**
+ * 重新启用realm_disable禁用的隧道，在原监听地址上重新绑定并启动
+ *
+ * 注意:
+ * - 未禁用时不做任何操作
+ * - 监听网卡所有地址的端点按网卡当前的地址重新绑定
+ * - 沿用启动时的CPU亲和性
+ * - 未找到对应实例或原监听地址无法绑定时返回false
+ */
+bool realm_enable(const char *config_key);
+
+/**
+ * 设置扩缩容回调，活跃连接数达到high时以up=true调用，回落到low时以up=false调用
+ *
+ * 注意:
+ * - 需满足low < high，水位需持续越过SCALING_DEBOUNCE毫秒才会触发，避免抖动
+ * - 回调在Realm的后台线程中执行，不应阻塞
+ * - 再次设置会替换之前的回调，callback为NULL时取消
+ * - 未找到对应实例或参数无效时返回false
+ */
+bool realm_set_scaling_callback(const char *config_key,
+                                uint64_t high,
+                                uint64_t low,
+                                ScalingCallback callback);
+
+/**
+ * 设置连接关闭前的回调，连接因超时、连接数限制等原因被主动关闭时，在客户端的连接关闭之前调用，
+ * 宿主程序可据此做出反应，例如预先建立替代的连接
+ *
+ * 注意:
+ * - reason与日志中的reason相同，见readme的log一节
+ * - 回调在Realm的工作线程中执行，返回后才关闭连接，不应阻塞
+ * - 仅对之后接受的TCP连接生效，每个连接会多占用一个fd
+ * - 再次设置会替换之前的回调，callback为NULL时取消
+ * - 未找到对应实例时返回false
+ */
+bool realm_set_close_callback(const char *config_key, CloseCallback callback);
+
+/**
+ * 启用备用运行时，实例的主运行时超过watchdog_timeout毫秒没有心跳时，
+ * 关闭主运行时，并在备用运行时上重新启动其端点
+ *
+ * 注意:
+ * - watchdog_timeout为0时关闭
+ * - 端点复用原有的监听套接字，监听地址不变
+ * - 主运行时上的连接会被断开，扩缩容回调继续运行
+ */
+void realm_enable_standby(uint64_t watchdog_timeout);
+
+/**
+ * 列出隧道的活跃TCP连接，返回JSON字符串:
+ *
+ *    [{"id":1,"protocol":"tcp","src":"127.0.0.1:50000","dst":"127.0.0.1:40000"}]
+ *
+ * 注意:
+ * - src为客户端地址，dst为本地监听地址
+ * - 未找到对应实例时返回NULL
+ * - 返回的字符串需要调用realm_free_string释放
+ */
+const char *realm_list_connections(const char *config_key);
+
+/**
+ * 断开隧道的指定连接，connection_id来自realm_list_connections
+ *
+ * 注意:
+ * - 未找到对应实例或连接时返回false
+ */
+bool realm_kill_connection(const char *config_key, uint64_t connection_id);
+
+/**
+ * 设置隧道的TCP限速，单位为字节/秒，上下行分别计算，0表示不限速
+ *
+ * 注意:
+ * - 立即对活跃连接和之后的连接生效，无需重启实例
+ * - 隧道内所有TCP连接共享该限速，不限制UDP
+ * - 未找到对应实例时返回false
+ */
+bool realm_set_rate_limit(const char *config_key, uint64_t bytes_per_sec);
+
+/**
+ * 设置实例工作线程的CPU亲和性，之后以config_key启动的实例，其运行时每个核心一个工作线程，
+ * 并绑定到这些核心，用于NUMA或大小核系统
+ *
+ * config_key由start_realm的参数组成: "remote-host-path-tls-insecure"
+ *
+ * 注意:
+ * - 仅支持Linux，其他平台上启动该实例会失败
+ * - cores为NULL或len为0时清除设置
+ * - 已运行的实例不受影响，需停止后重新启动
+ * - 核心不在本进程可用的范围内时，启动失败
+ * - 故障转移后端点运行在备用运行时上，不再绑定
+ */
+void realm_set_cpu_affinity(const char *config_key, const uint32_t *cores, size_t len);
+
+/**
+ * 设置严格模式。参数相同的实例已存在时，start_realm默认共享该实例；
+ * 严格模式下，若按当前设置（如CPU亲和性、TCP keepalive）解析出的配置与该实例不同，
+ * 则返回NULL而不是共享
+ *
+ * 注意:
+ * - 默认关闭，对之后的start_realm和realm_start_batch生效
+ * - 配置相同时仍共享实例
+ */
+void realm_set_strict_start(bool strict);
+
+/**
+ * 设置生命周期回调，实例启动和关闭时依次以Starting、Ready、Stopping、Stopped调用
+ *
+ * 注意:
+ * - 对所有实例生效，再次设置会替换之前的回调，callback为NULL时取消
+ * - 共享已有实例的start_realm，以及未关闭实例的stop_realm不产生事件
+ * - 回调在调用start_realm、stop_realm等函数的线程中执行，且持有内部锁，不应阻塞，也不应调用Realm的其他函数
+ */
+void realm_set_lifecycle_callback(LifecycleCallback callback);
+
+/**
+ * 设置TCP keepalive参数，单位为秒，仅对之后启动的实例生效
+ *
+ * 注意:
+ * - time为0时关闭keepalive，interval为0时与time相同
+ * - OpenBSD不支持interval和retries，Windows不支持retries，此时使用系统默认值
+ */
+void realm_set_tcp_keepalive(uint32_t time, uint32_t interval, uint32_t retries);
+
+/**
+ * 列出所有隧道及其统计，返回JSON字符串:
+ *
+ *    [{"config_key":"...","listen_addr":"127.0.0.1:40000","remote":"example.com:443","family":"v4","enabled":true,
+ *      "active_connections":1,"total_connections":5,"bytes_up":1024,"bytes_down":4096}]
+ *
+ * 注意:
+ * - family取自监听套接字: v4、v6，或同时接受v4与v6的dual
+ * - 返回的字符串需要调用realm_free_string释放
+ */
+const char *realm_list_endpoints(void);
+
+/**
+ * 获取所有Realm实例的汇总统计，返回JSON字符串:
+ *
+ *    {"tunnels":2,"active_connections":3,"total_connections":10,"bytes_up":1024,"bytes_down":4096}
+ *
+ * 注意:
+ * - UDP关联也计为连接
+ * - 返回的字符串需要调用realm_free_string释放
+ */
+const char *realm_global_stats(void);
+
+/**
+ * 定期将各隧道的统计以UDP推送到StatsD，interval_ms为推送间隔，毫秒:
+ *
+ *    realm.<tunnel>.active_connections:1|g
+ *    realm.<tunnel>.connections:5|c
+ *    realm.<tunnel>.bytes_up:1024|c
+ *    realm.<tunnel>.bytes_down:4096|c
+ *
+ * 注意:
+ * - realm为prefix，为空时省略；<tunnel>为配置键，字母、数字、-和_以外的字符替换为_
+ * - 计数器为两次推送之间的增量，每个隧道一个UDP包
+ * - 再次调用时替换原有配置，interval_ms为0时停止推送
+ * - addr无法解析时返回false
+ * - 需要启用statsd特性
+ */
+bool realm_start_statsd(const char *addr, const char *prefix, uint64_t interval_ms);
+
+/**
+ * 在addr上启动HTTP健康检查:
+ *
+ *    GET /healthz  进程存活，总是返回200
+ *    GET /readyz   所有实例已就绪时返回200，否则返回503
+ *
+ * 注意:
+ * - 就绪指至少有一个实例，没有正在启动或已禁用的实例，且每个实例至少有一个健康的远端
+ * - 运行在后台运行时上，再次调用时替换原有的监听，addr为NULL时停止
+ * - addr无法绑定时返回false
+ * - 需要启用admin特性
+ */
+bool realm_start_health(const char *addr);
+
+/**
+ * 释放由本库返回的字符串
+ */
+void realm_free_string(char *s);
@@ -0,0 +1,223 @@
+//! Access log in the common or combined log format.
+//!
+//! The relay is not an http server, so a line is made of what can be
+//! seen passing by: the request line and headers at the head of the
+//! first chunk read from the client, and the status line at the head of
+//! the first chunk written to it. For a ws listen transport, the
+//! upgrade request is peeked before the handshake, and the status is
+//! 101 once it is accepted. Anything not seen is logged as `-`.
+
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64};
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Log target of access lines, which are written without any prefix.
+pub const TARGET: &str = "realm::access";
+
+/// Format of access lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `host - - [time] "request" status bytes`
+    Common,
+    /// The common format, then `"referer" "user-agent"`.
+    Combined,
+}
+
+impl From<&str> for Format {
+    fn from(s: &str) -> Self {
+        match s {
+            "common" => Format::Common,
+            "combined" => Format::Combined,
+            _ => panic!("unknown access log format: {}", s),
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Common => write!(f, "common"),
+            Format::Combined => write!(f, "combined"),
+        }
+    }
+}
+
+/// An http request, as seen at the head of a chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub line: String,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// What is seen of the http exchange of a connection.
+#[derive(Debug, Default)]
+pub struct Exchange {
+    request: OnceLock<Request>,
+    status: AtomicU16,
+    sent: AtomicU64,
+    // only the first chunk of each direction is sniffed
+    sniffed: [AtomicBool; 2],
+}
+
+impl Exchange {
+    /// Sniff the first chunk read from the client.
+    pub fn on_request(&self, data: &[u8]) {
+        if !self.sniffed[0].swap(true, Relaxed) {
+            if let Some(request) = parse_request(data) {
+                let _ = self.request.set(request);
+            }
+        }
+    }
+
+    /// Sniff the first chunk written to the client, and count the bytes.
+    pub fn on_response(&self, data: &[u8]) {
+        self.sent.fetch_add(data.len() as u64, Relaxed);
+        if !self.sniffed[1].swap(true, Relaxed) {
+            if let Some(status) = parse_status(data) {
+                self.status.store(status, Relaxed);
+            }
+        }
+    }
+
+    /// The peeked upgrade request is accepted.
+    pub fn on_upgrade(&self) {
+        if self.request.get().is_some() && !self.sniffed[1].swap(true, Relaxed) {
+            self.status.store(101, Relaxed);
+        }
+    }
+
+    pub fn request(&self) -> Option<&Request> {
+        self.request.get()
+    }
+
+    /// 0 if not seen.
+    pub fn status(&self) -> u16 {
+        self.status.load(Relaxed)
+    }
+
+    /// Bytes written to the client, after the handshake if any.
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Relaxed)
+    }
+
+    /// An access line of a client, whose connection started at `time`.
+    pub fn line(&self, format: Format, client: IpAddr, time: SystemTime) -> String {
+        let request = self.request();
+        let or_dash = |x: Option<&str>| x.map_or_else(|| String::from("-"), escape);
+        let number = |x: u64| if x == 0 { String::from("-") } else { x.to_string() };
+
+        let mut line = format!(
+            "{} - - [{}] \"{}\" {} {}",
+            client,
+            clf_time(time),
+            or_dash(request.map(|x| x.line.as_str())),
+            number(self.status() as u64),
+            number(self.sent())
+        );
+        if format == Format::Combined {
+            let referer = or_dash(request.and_then(|x| x.referer.as_deref()));
+            let user_agent = or_dash(request.and_then(|x| x.user_agent.as_deref()));
+            line.push_str(&format!(" \"{}\" \"{}\"", referer, user_agent));
+        }
+        line
+    }
+}
+
+/// Parse a request line and its headers, the head may be cut off.
+pub fn parse_request(data: &[u8]) -> Option<Request> {
+    let mut lines = data.split(|x| *x == b'\n').map(|x| x.strip_suffix(b"\r").unwrap_or(x));
+
+    // METHOD target HTTP/x.y
+    let line = std::str::from_utf8(lines.next()?).ok()?;
+    let mut parts = line.split(' ');
+    let (method, target, version) = (parts.next()?, parts.next()?, parts.next()?);
+    let valid = !method.is_empty()
+        && method.bytes().all(|x| x.is_ascii_uppercase())
+        && !target.is_empty()
+        && version.starts_with("HTTP/")
+        && parts.next().is_none();
+    if !valid {
+        return None;
+    }
+
+    let mut request = Request {
+        line: String::from(line),
+        referer: None,
+        user_agent: None,
+    };
+    for header in lines.take_while(|x| !x.is_empty()) {
+        let Some(pos) = header.iter().position(|x| *x == b':') else {
+            continue;
+        };
+        let (name, value) = header.split_at(pos);
+        let value = String::from_utf8_lossy(value[1..].trim_ascii()).into_owned();
+        if name.eq_ignore_ascii_case(b"referer") {
+            request.referer.get_or_insert(value);
+        } else if name.eq_ignore_ascii_case(b"user-agent") {
+            request.user_agent.get_or_insert(value);
+        }
+    }
+    Some(request)
+}
+
+/// Parse the status code of a response.
+pub fn parse_status(data: &[u8]) -> Option<u16> {
+    // HTTP/x.y 200 OK
+    let rest = data.strip_prefix(b"HTTP/")?;
+    let pos = rest.iter().position(|x| *x == b' ')?;
+    let code = rest[pos + 1..].get(..3)?;
+    match std::str::from_utf8(code).ok()?.parse() {
+        Ok(x @ 100..=599) => Some(x),
+        _ => None,
+    }
+}
+
+// quotes and backslashes, like apache does
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_control() => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// 10/Oct/2000:13:55:36 +0000
+fn clf_time(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |x| x.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
@@ -1,4 +1,6 @@
 //! Global dns resolver.
+//!
+//! An endpoint may have a resolver of its own, see [`Resolver`].
 
 use std::io::{Result, Error, ErrorKind};
 use std::net::SocketAddr;
@@ -78,25 +80,55 @@ pub fn build_lazy(conf: Option<ResolverConfig>, opts: Option<ResolverOpts>) {
     }
 }
 
-/// Lookup ip with global dns resolver.
-pub async fn resolve_ip(ip: &str) -> Result<LookupIp> {
+/// Drop cached records, so that later lookups are sent to the nameservers.
+///
+/// The resolver can not evict a single name, the whole cache is cleared.
+pub fn clear_cache() {
+    use std::ptr;
     unsafe {
-        DNS.lookup_ip(ip)
-            .await
-            .map_or_else(|e| Err(Error::new(ErrorKind::Other, e)), Ok)
+        if let Some(dns) = Lazy::get(&*ptr::addr_of!(DNS)) {
+            dns.clear_cache();
+        }
     }
 }
 
+/// A dns resolver apart from the global one, with its own cache.
+pub type Resolver = TokioAsyncResolver;
+
+/// Create a resolver, missing config falls back to [`DnsConf::default`].
+pub fn new_resolver(conf: Option<ResolverConfig>, opts: Option<ResolverOpts>) -> Resolver {
+    let default = DnsConf::default();
+    TokioAsyncResolver::tokio(conf.unwrap_or(default.conf), opts.unwrap_or(default.opts))
+}
+
+/// Lookup ip with global dns resolver.
+pub async fn resolve_ip(ip: &str) -> Result<LookupIp> {
+    unsafe { lookup_ip(&*std::ptr::addr_of!(DNS), ip).await }
+}
+
 /// Lookup socketaddr with global dns resolver.
 pub async fn resolve_addr(addr: &RemoteAddr) -> Result<LookupRemoteAddr<'_>> {
+    resolve_addr_with(addr, None).await
+}
+
+/// Lookup socketaddr with the given resolver, or the global one if None.
+pub async fn resolve_addr_with<'a>(addr: &'a RemoteAddr, resolver: Option<&Resolver>) -> Result<LookupRemoteAddr<'a>> {
     use RemoteAddr::*;
     use LookupRemoteAddr::*;
-    match addr {
-        SocketAddr(addr) => Ok(NoLookup(addr)),
-        DomainName(ip, port) => resolve_ip(ip).await.map(|ip| Dolookup(ip, *port)),
+    match (addr, resolver) {
+        (SocketAddr(addr), _) => Ok(NoLookup(addr)),
+        (DomainName(ip, port), Some(resolver)) => lookup_ip(resolver, ip).await.map(|ip| Dolookup(ip, *port)),
+        (DomainName(ip, port), None) => resolve_ip(ip).await.map(|ip| Dolookup(ip, *port)),
     }
 }
 
+async fn lookup_ip(resolver: &Resolver, ip: &str) -> Result<LookupIp> {
+    resolver
+        .lookup_ip(ip)
+        .await
+        .map_or_else(|e| Err(Error::new(ErrorKind::Other, e)), Ok)
+}
+
 /// Resolved result.
 pub enum LookupRemoteAddr<'a> {
     NoLookup(&'a SocketAddr),
@@ -2,13 +2,33 @@
 
 use std::fmt::{Display, Formatter};
 use std::net::SocketAddr;
+use std::sync::Arc;
 
 #[cfg(feature = "transport")]
 use kaminari::mix::{MixAccept, MixConnect};
 
+#[cfg(feature = "transport")]
+use kaminari::{nop::NopAccept, nop::NopConnect, tls::TlsAccept, tls::TlsConnect, ws::WsConf};
+
 #[cfg(feature = "balance")]
 use realm_lb::Balancer;
 
+#[cfg(feature = "balance")]
+use crate::health::{Affinity, Health, Load, UnhealthyPolicy};
+
+use crate::stat::Stat;
+use crate::limit::{RateLimit, ConnLimit, HandshakeLimit};
+use crate::dns::Resolver;
+use crate::registry::Registry;
+use crate::tcp::PreClose;
+use crate::access;
+
+#[cfg(feature = "trace")]
+use crate::trace::Tracer;
+
+#[cfg(feature = "geo")]
+use crate::geo::GeoRoutes;
+
 /// Remote address.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum RemoteAddr {
@@ -34,13 +54,163 @@ impl ProxyOpts {
     }
 }
 
+/// Options of a single remote peer, which override the endpoint's.
+#[derive(Debug, Default, Clone)]
+pub struct PeerOpts {
+    pub bind_address: Option<SocketAddr>,
+    pub bind_interface: Option<String>,
+
+    #[cfg(feature = "proxy")]
+    pub send_proxy: Option<bool>,
+
+    #[cfg(feature = "proxy")]
+    pub send_proxy_version: Option<usize>,
+
+    /// Replaces the connector of the remote transport,
+    /// e.g. to verify tls of this peer differently.
+    #[cfg(feature = "transport")]
+    pub transport: Option<MixConnect>,
+}
+
+#[cfg(feature = "proxy")]
+impl PeerOpts {
+    #[inline]
+    pub(crate) fn proxy_opts(&self, opts: ProxyOpts) -> ProxyOpts {
+        ProxyOpts {
+            send_proxy: self.send_proxy.unwrap_or(opts.send_proxy),
+            send_proxy_version: self.send_proxy_version.unwrap_or(opts.send_proxy_version),
+            ..opts
+        }
+    }
+}
+
+/// What the iphash balancer hashes.
+#[cfg(feature = "balance")]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum HashKey {
+    /// The client's ip.
+    #[default]
+    Source,
+    /// A header of the http request, in lower case.
+    Header(String),
+    /// A cookie of the http request.
+    Cookie(String),
+}
+
+#[cfg(feature = "balance")]
+impl From<&str> for HashKey {
+    fn from(s: &str) -> Self {
+        use HashKey::*;
+        match s.split_once(':').map(|(k, v)| (k.trim(), v.trim())) {
+            None if s.trim() == "source" => Source,
+            Some(("header", name)) if !name.is_empty() => Header(name.to_ascii_lowercase()),
+            Some(("cookie", name)) if !name.is_empty() => Cookie(String::from(name)),
+            _ => panic!("unknown hash key: {}", s),
+        }
+    }
+}
+
+#[cfg(feature = "balance")]
+impl Display for HashKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HashKey::Source => write!(f, "source"),
+            HashKey::Header(name) => write!(f, "header:{}", name),
+            HashKey::Cookie(name) => write!(f, "cookie:{}", name),
+        }
+    }
+}
+
+/// Tags the ws upgrade request to the remote peer with a correlation id.
+#[cfg(feature = "transport")]
+#[derive(Debug, Clone)]
+pub struct Correlation {
+    /// Name of the header carrying the id.
+    pub header: String,
+    /// The same as the remote transport.
+    pub ws: WsConf,
+    /// Some for wss.
+    pub tls: Option<TlsConnect<NopConnect>>,
+}
+
+/// Closes ws connections on an incoming frame larger than allowed.
+#[cfg(feature = "transport")]
+#[derive(Debug, Clone)]
+pub struct FrameLimit {
+    /// Max payload size of a frame.
+    pub max: usize,
+    /// The same as a ws listen transport, the tls part is Some for wss.
+    pub listen: Option<(WsConf, Option<TlsAccept<NopAccept>>)>,
+    /// The same as a ws remote transport.
+    pub remote: Option<(WsConf, Option<TlsConnect<NopConnect>>)>,
+}
+
+/// Sends a close frame to the remote peer once a ws connection ends normally.
+#[cfg(feature = "transport")]
+#[derive(Debug, Clone)]
+pub struct WsClose {
+    /// Status code of the close frame.
+    pub code: u16,
+    /// The same as the remote transport.
+    pub ws: WsConf,
+    /// Some for wss.
+    pub tls: Option<TlsConnect<NopConnect>>,
+}
+
+/// Which side of the relay faces another realm which compresses.
+#[cfg(feature = "compress")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compress {
+    /// Bytes from and to the client, with the level.
+    Listen(u8),
+    /// Bytes from and to the remote peer, with the level.
+    Remote(u8),
+}
+
+#[cfg(feature = "compress")]
+impl Display for Compress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compress::Listen(level) => write!(f, "listen:{}", level),
+            Compress::Remote(level) => write!(f, "remote:{}", level),
+        }
+    }
+}
+
 /// Connect or associate options.
 #[derive(Debug, Default, Clone)]
 pub struct ConnectOpts {
     pub connect_timeout: usize,
     pub associate_timeout: usize,
     pub tcp_keepalive: usize,
+    /// 0 means the same as tcp_keepalive.
+    pub tcp_keepalive_interval: usize,
     pub tcp_keepalive_probe: usize,
+    pub coalesce_size: usize,
+    pub coalesce_delay: usize,
+    pub slow_conn_threshold: usize,
+    pub accept_delay: usize,
+    /// Accept at most this many connections per second, 0 means unlimited.
+    pub accept_rate: usize,
+    /// Close clients sending nothing for this long, 0 means never.
+    pub first_byte_timeout: usize,
+    /// Close remotes sending nothing for this long once relaying, 0 means never.
+    pub remote_first_byte_timeout: usize,
+    /// Nudge or close when neither side sends anything for this long once relaying, 0 means never.
+    pub deadlock_timeout: usize,
+    /// Sent to the remote peer once the deadlock timeout is over, empty means close at once.
+    pub deadlock_nudge: Vec<u8>,
+    /// Close connections moving no data for this long, in seconds, 0 means never.
+    pub idle_timeout: usize,
+    /// How often idle connections are swept, in milliseconds, 0 means every second.
+    pub prune_interval: usize,
+    /// Write an access line once a connection is closed, None means off.
+    pub access_log: Option<access::Format>,
+    /// SO_LINGER of relay sockets in seconds, 0 resets on close, None keeps the system default.
+    pub linger: Option<usize>,
+    /// TCP_CONGESTION of sockets to the remote peer, None keeps the system default.
+    #[cfg(target_os = "linux")]
+    pub congestion: Option<String>,
     pub bind_address: Option<SocketAddr>,
     pub bind_interface: Option<String>,
 
@@ -50,13 +220,116 @@ pub struct ConnectOpts {
     #[cfg(feature = "transport")]
     pub transport: Option<(MixAccept, MixConnect)>,
 
+    /// Remote peers selected by the client's offered alpn,
+    /// the first one offered wins, in this order.
+    #[cfg(feature = "transport")]
+    pub alpn_routes: Vec<(String, RemoteAddr)>,
+
+    /// Server names accepted by the listen side tls, in lower case,
+    /// `*.` matches any subdomain. Empty means all of them.
+    #[cfg(feature = "transport")]
+    pub sni_allowlist: Vec<String>,
+
+    /// Accept a ClientHello without sni, if there is an allowlist.
+    #[cfg(feature = "transport")]
+    pub allow_missing_sni: bool,
+
+    /// Max size of the listen side ws upgrade request,
+    /// 0 means the relay buffer size.
+    #[cfg(feature = "transport")]
+    pub ws_max_header_size: usize,
+
+    /// Send a correlation id per connection to the remote peer.
+    #[cfg(feature = "transport")]
+    pub correlation: Option<Correlation>,
+
+    /// Max size of ws frames from either side.
+    #[cfg(feature = "transport")]
+    pub frame_limit: Option<FrameLimit>,
+
+    /// Close frame to the ws remote peer.
+    #[cfg(feature = "transport")]
+    pub ws_close: Option<WsClose>,
+
+    /// Remote transports tried in order on a new connection,
+    /// once the handshake of the previous one fails.
+    #[cfg(feature = "transport")]
+    pub transport_fallback: Vec<MixConnect>,
+
+    /// Remote peers selected by the original destination port.
+    pub port_routes: Vec<(u16, RemoteAddr)>,
+
+    /// Remote peers selected by the client's country or asn.
+    #[cfg(feature = "geo")]
+    pub geo_routes: Option<Arc<GeoRoutes>>,
+
+    /// Indexed like balance tokens, 0 is the default remote peer.
+    pub peer_opts: Vec<PeerOpts>,
+
     #[cfg(feature = "balance")]
     pub balancer: Balancer,
+
+    #[cfg(feature = "balance")]
+    pub health: Arc<Health>,
+
+    #[cfg(feature = "balance")]
+    pub unhealthy_policy: UnhealthyPolicy,
+
+    /// Peers that iphash clients failed over to.
+    #[cfg(feature = "balance")]
+    pub affinity: Arc<Affinity>,
+
+    /// Active connections of each remote peer, which leastconn picks by.
+    #[cfg(feature = "balance")]
+    pub load: Arc<Load>,
+
+    /// Hashed by iphash instead of the client's ip, if found.
+    #[cfg(feature = "balance")]
+    pub hash_key: HashKey,
+
+    /// Connect timeout of each remote peer, the next healthy one is
+    /// tried once it expires or fails, 0 means no failover.
+    #[cfg(feature = "balance")]
+    pub per_attempt_timeout: usize,
+
+    /// Shared by all clones of the options.
+    pub stat: Arc<Stat>,
+
+    /// Live tcp connections, shared like stat.
+    pub conns: Arc<Registry>,
+
+    /// Notified of tcp connections about to be dropped, shared like stat.
+    pub pre_close: Arc<PreClose>,
+
+    /// Tcp bandwidth limit, shared like stat, and may be changed at runtime.
+    pub rate_limit: Arc<RateLimit>,
+
+    /// Max tcp connections, shared like stat.
+    pub conn_limit: Arc<ConnLimit>,
+
+    /// Max concurrent transport handshakes, shared like stat.
+    pub handshake_limit: Arc<HandshakeLimit>,
+
+    /// Close connections whose transport handshake, including the wait
+    /// for a handshake slot, is not done within this long, 0 means never.
+    pub handshake_timeout: usize,
+
+    /// Resolve remote peers with this instead of the global resolver.
+    pub resolver: Option<Arc<Resolver>>,
+
+    #[cfg(feature = "trace")]
+    pub tracer: Option<Arc<Tracer>>,
+
+    #[cfg(feature = "compress")]
+    pub compress: Option<Compress>,
 }
 
 #[derive(Debug, Default, Clone)]
 pub struct BindOpts {
     pub ipv6_only: bool,
+    /// TCP_CONGESTION of the listener, inherited by accepted sockets.
+    #[cfg(target_os = "linux")]
+    pub congestion: Option<String>,
 }
 
 /// Relay endpoint.
@@ -93,8 +366,43 @@ impl Display for Endpoint {
 
 impl Display for BindOpts {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let BindOpts { ipv6_only } = self;
-        write!(f, "ipv6_only={}", ipv6_only)
+        let BindOpts {
+            ipv6_only,
+            #[cfg(target_os = "linux")]
+            congestion,
+        } = self;
+        write!(f, "ipv6_only={}", ipv6_only)?;
+        #[cfg(target_os = "linux")]
+        if let Some(x) = congestion {
+            write!(f, ", congestion={}", x)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for PeerOpts {
+    #[allow(unused)]
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(iface) = &self.bind_interface {
+            write!(f, "bind-iface={} ", iface)?;
+        }
+        if let Some(send_through) = self.bind_address {
+            write!(f, "send-through={} ", send_through)?;
+        }
+        #[cfg(feature = "proxy")]
+        {
+            if let Some(send_proxy) = self.send_proxy {
+                write!(f, "send-proxy={} ", send_proxy)?;
+            }
+            if let Some(version) = self.send_proxy_version {
+                write!(f, "send-proxy-version={} ", version)?;
+            }
+        }
+        #[cfg(feature = "transport")]
+        if let Some(cc) = &self.transport {
+            write!(f, "remote-transport={} ", cc)?;
+        }
+        Ok(())
     }
 }
 
@@ -104,7 +412,24 @@ impl Display for ConnectOpts {
             connect_timeout,
             associate_timeout,
             tcp_keepalive,
+            tcp_keepalive_interval,
             tcp_keepalive_probe,
+            coalesce_size,
+            coalesce_delay,
+            slow_conn_threshold,
+            accept_delay,
+            accept_rate,
+            first_byte_timeout,
+            remote_first_byte_timeout,
+            deadlock_timeout,
+            deadlock_nudge,
+            idle_timeout,
+            prune_interval,
+            access_log,
+            linger,
+
+            #[cfg(target_os = "linux")]
+            congestion,
             bind_address,
             bind_interface,
 
@@ -114,8 +439,72 @@ impl Display for ConnectOpts {
             #[cfg(feature = "transport")]
             transport,
 
+            #[cfg(feature = "transport")]
+            alpn_routes,
+
+            #[cfg(feature = "transport")]
+            sni_allowlist,
+
+            #[cfg(feature = "transport")]
+            allow_missing_sni,
+
+            #[cfg(feature = "transport")]
+            ws_max_header_size,
+
+            #[cfg(feature = "transport")]
+            correlation,
+
+            #[cfg(feature = "transport")]
+            frame_limit,
+
+            #[cfg(feature = "transport")]
+            ws_close,
+
+            #[cfg(feature = "transport")]
+            transport_fallback,
+
+            port_routes,
+
+            #[cfg(feature = "geo")]
+            geo_routes,
+
+            peer_opts,
+
             #[cfg(feature = "balance")]
             balancer,
+
+            #[cfg(feature = "balance")]
+            health,
+
+            #[cfg(feature = "balance")]
+            unhealthy_policy,
+
+            #[cfg(feature = "balance")]
+            affinity,
+
+            #[cfg(feature = "balance")]
+            load,
+
+            #[cfg(feature = "balance")]
+            hash_key,
+
+            #[cfg(feature = "balance")]
+            per_attempt_timeout,
+
+            stat: _,
+            conns: _,
+            pre_close: _,
+            rate_limit,
+            conn_limit,
+            handshake_limit,
+            handshake_timeout,
+            resolver,
+
+            #[cfg(feature = "trace")]
+            tracer,
+
+            #[cfg(feature = "compress")]
+            compress,
         } = self;
 
         if let Some(iface) = bind_interface {
@@ -147,13 +536,202 @@ impl Display for ConnectOpts {
             tcp_keepalive, tcp_keepalive_probe, connect_timeout, associate_timeout
         )?;
 
+        if *tcp_keepalive_interval != 0 {
+            write!(f, "tcp-keepalive-interval={}s; ", tcp_keepalive_interval)?;
+        }
+
+        if let Some(linger) = linger {
+            write!(f, "linger={}s; ", linger)?;
+        }
+
+        #[cfg(target_os = "linux")]
+        if let Some(x) = congestion {
+            write!(f, "congestion={}; ", x)?;
+        }
+
+        if *coalesce_size != 0 {
+            write!(f, "coalesce={}b[{}ms]; ", coalesce_size, coalesce_delay)?;
+        }
+
+        if *slow_conn_threshold != 0 {
+            write!(f, "slow-conn-threshold={}ms; ", slow_conn_threshold)?;
+        }
+
+        if *accept_delay != 0 {
+            write!(f, "accept-delay={}ms; ", accept_delay)?;
+        }
+
+        if *accept_rate != 0 {
+            write!(f, "accept-rate={}/s; ", accept_rate)?;
+        }
+
+        if *first_byte_timeout != 0 {
+            write!(f, "first-byte-timeout={}s; ", first_byte_timeout)?;
+        }
+
+        if *remote_first_byte_timeout != 0 {
+            write!(f, "remote-first-byte-timeout={}s; ", remote_first_byte_timeout)?;
+        }
+
+        if *deadlock_timeout != 0 {
+            write!(
+                f,
+                "deadlock-timeout={}s[nudge={}b]; ",
+                deadlock_timeout,
+                deadlock_nudge.len()
+            )?;
+        }
+
+        if *idle_timeout != 0 {
+            write!(f, "idle-timeout={}s[prune={}ms]; ", idle_timeout, prune_interval)?;
+        }
+
+        if let Some(format) = access_log {
+            write!(f, "access-log={}; ", format)?;
+        }
+
+        if resolver.is_some() {
+            write!(f, "resolver=endpoint; ")?;
+        }
+
+        if rate_limit.rate() != 0 {
+            write!(f, "rate-limit={}B/s; ", rate_limit.rate())?;
+        }
+
+        if conn_limit.max() != 0 {
+            write!(
+                f,
+                "max-conns={}[queue={}, {}s]; ",
+                conn_limit.max(),
+                conn_limit.depth(),
+                conn_limit.timeout().as_secs()
+            )?;
+        }
+
+        if handshake_limit.max() != 0 {
+            write!(f, "max-handshakes={}; ", handshake_limit.max())?;
+        }
+
+        if *handshake_timeout != 0 {
+            write!(f, "handshake-timeout={}s; ", handshake_timeout)?;
+        }
+
         #[cfg(feature = "transport")]
         if let Some((ac, cc)) = transport {
             write!(f, "transport={}||{}; ", ac, cc)?;
         }
 
+        #[cfg(feature = "transport")]
+        if *ws_max_header_size != 0 {
+            write!(f, "ws-max-header-size={}; ", ws_max_header_size)?;
+        }
+
+        #[cfg(feature = "transport")]
+        if let Some(x) = correlation {
+            write!(f, "correlation-header={}; ", x.header)?;
+        }
+
+        #[cfg(feature = "transport")]
+        if let Some(x) = frame_limit {
+            write!(f, "ws-max-frame-size={}; ", x.max)?;
+        }
+
+        #[cfg(feature = "transport")]
+        if let Some(x) = ws_close {
+            write!(f, "ws-close-code={}; ", x.code)?;
+        }
+
+        #[cfg(feature = "transport")]
+        if !transport_fallback.is_empty() {
+            write!(f, "transport-fallback=[")?;
+            for (i, cc) in transport_fallback.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", cc)?;
+            }
+            write!(f, "]; ")?;
+        }
+
+        #[cfg(feature = "transport")]
+        if !alpn_routes.is_empty() {
+            write!(f, "alpn-routes=[")?;
+            for (i, (alpn, raddr)) in alpn_routes.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}=>{}", alpn, raddr)?;
+            }
+            write!(f, "]; ")?;
+        }
+
+        #[cfg(feature = "transport")]
+        if !sni_allowlist.is_empty() {
+            write!(
+                f,
+                "sni-allowlist=[{}], allow-missing-sni={}; ",
+                sni_allowlist.join(", "),
+                allow_missing_sni
+            )?;
+        }
+
+        if !port_routes.is_empty() {
+            write!(f, "port-routes=[")?;
+            for (i, (port, raddr)) in port_routes.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}=>{}", port, raddr)?;
+            }
+            write!(f, "]; ")?;
+        }
+
+        #[cfg(feature = "geo")]
+        if let Some(geo_routes) = geo_routes {
+            write!(f, "geo-routes={}; ", geo_routes)?;
+        }
+
+        #[cfg(feature = "trace")]
+        if let Some(tracer) = tracer {
+            write!(f, "trace={:?}; ", tracer)?;
+        }
+
+        #[cfg(feature = "compress")]
+        if let Some(compress) = compress {
+            write!(f, "compress={}; ", compress)?;
+        }
+
+        for (i, peer) in peer_opts.iter().enumerate() {
+            write!(f, "peer[{}]: {}; ", i, peer)?;
+        }
+
+        // counted at runtime, not an option
         #[cfg(feature = "balance")]
-        write!(f, "balance={}", balancer.strategy())?;
+        let _ = load;
+
+        #[cfg(feature = "balance")]
+        write!(
+            f,
+            "balance={}, unhealthy-policy={}, unhealthy-cooldown={}s",
+            balancer.strategy(),
+            unhealthy_policy,
+            health.cooldown().as_secs()
+        )?;
+
+        #[cfg(feature = "balance")]
+        if !affinity.timeout().is_zero() {
+            write!(f, ", affinity-timeout={}s", affinity.timeout().as_secs())?;
+        }
+
+        #[cfg(feature = "balance")]
+        if *hash_key != HashKey::Source {
+            write!(f, ", hash-key={}", hash_key)?;
+        }
+
+        #[cfg(feature = "balance")]
+        if *per_attempt_timeout != 0 {
+            write!(f, ", per-attempt-timeout={}s", per_attempt_timeout)?;
+        }
         Ok(())
     }
 }
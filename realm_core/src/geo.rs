@@ -0,0 +1,177 @@
+//! Geo routing by client ip.
+//!
+//! The database is a csv file of non-overlapping networks:
+//!
+//! `<network>,<country>,<asn>`
+//!
+//! e.g. `1.0.0.0/24,AU,13335`. Country or asn may be left empty.
+//! Empty lines, `#` comments and a leading `network,...` header are skipped.
+
+use std::fmt::{Display, Formatter};
+use std::io::{Error, ErrorKind, Result};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::endpoint::RemoteAddr;
+
+/// Geo info of a network.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GeoInfo {
+    /// ISO country code, uppercase.
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+}
+
+/// Networks sorted by their first address.
+/// Ipv4 is stored as ipv4-mapped ipv6.
+#[derive(Debug, Default)]
+pub struct GeoDb {
+    entries: Vec<(u128, u128, GeoInfo)>,
+}
+
+impl GeoDb {
+    /// Load a database from a csv file.
+    pub fn load(path: &str) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parse a database from csv text.
+    pub fn parse(s: &str) -> Result<Self> {
+        let invalid =
+            |line: usize, msg: &str| Error::new(ErrorKind::InvalidData, format!("line {}: {}", line + 1, msg));
+
+        let mut entries = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || (i == 0 && line.starts_with("network")) {
+                continue;
+            }
+
+            let mut fields = line.split(',').map(str::trim);
+            let (start, end) = fields
+                .next()
+                .and_then(parse_network)
+                .ok_or_else(|| invalid(i, "invalid network"))?;
+            let country = match fields.next() {
+                Some("") | None => None,
+                Some(x) => Some(x.to_ascii_uppercase()),
+            };
+            let asn = match fields.next() {
+                Some("") | None => None,
+                Some(x) => Some(x.parse().map_err(|_| invalid(i, "invalid asn"))?),
+            };
+            entries.push((start, end, GeoInfo { country, asn }));
+        }
+
+        entries.sort_by_key(|(start, ..)| *start);
+        if entries.windows(2).any(|x| x[0].1 >= x[1].0) {
+            return Err(Error::new(ErrorKind::InvalidData, "overlapped networks"));
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Geo info of the network which contains this ip.
+    pub fn lookup(&self, ip: &IpAddr) -> Option<&GeoInfo> {
+        let key = to_key(ip);
+        let idx = self.entries.partition_point(|(start, ..)| *start <= key);
+        let (_, end, info) = self.entries.get(idx.checked_sub(1)?)?;
+        (key <= *end).then_some(info)
+    }
+}
+
+/// Route key, a country code like `CN` or an asn like `AS13335`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GeoKey {
+    Country(String),
+    Asn(u32),
+}
+
+impl FromStr for GeoKey {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, ()> {
+        if let Some(asn) = s.strip_prefix("AS").or_else(|| s.strip_prefix("as")) {
+            return asn.parse().map(GeoKey::Asn).map_err(|_| ());
+        }
+        if s.len() == 2 && s.bytes().all(|x| x.is_ascii_alphabetic()) {
+            return Ok(GeoKey::Country(s.to_ascii_uppercase()));
+        }
+        Err(())
+    }
+}
+
+impl Display for GeoKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GeoKey::Country(x) => write!(f, "{}", x),
+            GeoKey::Asn(x) => write!(f, "AS{}", x),
+        }
+    }
+}
+
+/// Remote peers selected by the client's geo info.
+#[derive(Debug)]
+pub struct GeoRoutes {
+    db: GeoDb,
+    routes: Vec<(GeoKey, RemoteAddr)>,
+}
+
+impl GeoRoutes {
+    pub fn new(db: GeoDb, routes: Vec<(GeoKey, RemoteAddr)>) -> Self {
+        Self { db, routes }
+    }
+
+    /// A matched asn takes precedence over the country.
+    /// Return None if the client is not in the database or not routed.
+    pub fn select(&self, ip: &IpAddr) -> Option<&RemoteAddr> {
+        let info = self.db.lookup(ip)?;
+        let find = |key: GeoKey| self.routes.iter().find(|(x, _)| *x == key).map(|(_, r)| r);
+
+        info.asn
+            .and_then(|x| find(GeoKey::Asn(x)))
+            .or_else(|| info.country.clone().and_then(|x| find(GeoKey::Country(x))))
+    }
+}
+
+impl Display for GeoRoutes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} networks, [", self.db.len())?;
+        for (i, (key, raddr)) in self.routes.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}=>{}", key, raddr)?;
+        }
+        write!(f, "]")
+    }
+}
+
+fn to_key(ip: &IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(x) => x.to_ipv6_mapped().into(),
+        IpAddr::V6(x) => (*x).into(),
+    }
+}
+
+// first and last address of a network
+fn parse_network(s: &str) -> Option<(u128, u128)> {
+    let (ip, prefix) = s.split_once('/')?;
+    let ip: IpAddr = ip.parse().ok()?;
+    let prefix: u32 = prefix.parse().ok()?;
+    let prefix = match ip {
+        IpAddr::V4(_) if prefix <= 32 => prefix + 96,
+        IpAddr::V6(_) if prefix <= 128 => prefix,
+        _ => return None,
+    };
+    let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+    let start = to_key(&ip) & mask;
+    Some((start, start | !mask))
+}
@@ -0,0 +1,204 @@
+//! Passive health of remote peers.
+//!
+//! A peer is marked down once a connect to it fails, and is back up
+//! after a cooldown, or once a connect to it succeeds.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// What to do when no peer is healthy.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum UnhealthyPolicy {
+    /// Close the client connection at once.
+    DropImmediately,
+    /// Ignore health, try each peer in turn.
+    #[default]
+    TryAllAnyway,
+    /// Send a fixed response to the client, then close.
+    Maintenance(Vec<u8>),
+}
+
+impl From<&str> for UnhealthyPolicy {
+    fn from(s: &str) -> Self {
+        use UnhealthyPolicy::*;
+        match s {
+            "drop" => DropImmediately,
+            "try_all" => TryAllAnyway,
+            "maintenance" => Maintenance(Vec::new()),
+            _ => panic!("unknown unhealthy policy: {}", s),
+        }
+    }
+}
+
+impl Display for UnhealthyPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnhealthyPolicy::DropImmediately => write!(f, "drop"),
+            UnhealthyPolicy::TryAllAnyway => write!(f, "try_all"),
+            UnhealthyPolicy::Maintenance(_) => write!(f, "maintenance"),
+        }
+    }
+}
+
+/// Health of peers, indexed by balance token.
+///
+/// Peers out of range are always healthy.
+#[derive(Debug, Default)]
+pub struct Health {
+    cooldown: Duration,
+    // unix time in millis, 0 means up
+    down_until: Vec<AtomicU64>,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_millis() as u64)
+}
+
+impl Health {
+    /// Constructor.
+    pub fn new(peers: usize, cooldown: Duration) -> Self {
+        Self {
+            cooldown,
+            down_until: (0..peers).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    /// How long a failed peer is skipped.
+    pub fn cooldown(&self) -> Duration {
+        self.cooldown
+    }
+
+    /// Status of a peer.
+    pub fn is_up(&self, peer: usize) -> bool {
+        self.down_until
+            .get(peer)
+            .map_or(true, |x| x.load(Relaxed) <= now_millis())
+    }
+
+    /// Mark a peer as down until the cooldown expires.
+    pub fn mark_down(&self, peer: usize) {
+        if let Some(x) = self.down_until.get(peer) {
+            x.store(now_millis() + self.cooldown.as_millis() as u64, Relaxed);
+        }
+    }
+
+    /// Mark a peer as up.
+    pub fn mark_up(&self, peer: usize) {
+        if let Some(x) = self.down_until.get(peer) {
+            x.store(0, Relaxed);
+        }
+    }
+
+    /// Pick the given peer, or the next healthy one.
+    /// Return None if all of the `total` peers are down.
+    pub fn pick(&self, peer: usize, total: usize) -> Option<usize> {
+        (0..total).map(|i| (peer + i) % total).find(|x| self.is_up(*x))
+    }
+}
+
+/// Active connections of peers, indexed by balance token.
+///
+/// Peers out of range are not counted.
+#[derive(Debug, Default)]
+pub struct Load {
+    active: Vec<AtomicU64>,
+}
+
+/// Marks a connection to a peer as active until dropped.
+pub struct LoadGuard(Arc<Load>, usize);
+
+impl Load {
+    pub fn new(peers: usize) -> Self {
+        Self {
+            active: (0..peers).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    /// Active connections of a peer.
+    pub fn active(&self, peer: usize) -> u64 {
+        self.active.get(peer).map_or(0, |x| x.load(Relaxed))
+    }
+
+    /// Count a new connection to a peer.
+    pub fn open(self: &Arc<Self>, peer: usize) -> LoadGuard {
+        if let Some(x) = self.active.get(peer) {
+            x.fetch_add(1, Relaxed);
+        }
+        LoadGuard(self.clone(), peer)
+    }
+
+    /// Active connections of all peers, None if a peer is down.
+    pub fn loads(&self, health: &Health) -> Vec<Option<u64>> {
+        (0..self.active.len())
+            .map(|idx| health.is_up(idx).then(|| self.active(idx)))
+            .collect()
+    }
+}
+
+impl Drop for LoadGuard {
+    fn drop(&mut self) {
+        if let Some(x) = self.0.active.get(self.1) {
+            x.fetch_sub(1, Relaxed);
+        }
+    }
+}
+
+/// Peers that clients failed over to, by client ip.
+///
+/// A client sticks to the new peer until the timeout expires, even if
+/// its original peer is back, so that it does not flap between peers.
+#[derive(Debug, Default)]
+pub struct Affinity {
+    timeout: Duration,
+    // peer and unix time in millis
+    peers: Mutex<HashMap<IpAddr, (usize, u64)>>,
+}
+
+impl Affinity {
+    /// Timeout = 0 disables affinity.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            peers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How long a client sticks to its new peer.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// The peer a client failed over to, if not expired.
+    pub fn get(&self, ip: &IpAddr) -> Option<usize> {
+        if self.timeout.is_zero() {
+            return None;
+        }
+        let mut peers = self.peers.lock().unwrap();
+        match peers.get(ip) {
+            Some((peer, until)) if *until > now_millis() => Some(*peer),
+            Some(_) => {
+                peers.remove(ip);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Record a failover, the window starts now.
+    pub fn set(&self, ip: IpAddr, peer: usize) {
+        if self.timeout.is_zero() {
+            return;
+        }
+        let now = now_millis();
+        let mut peers = self.peers.lock().unwrap();
+        peers.retain(|_, (_, until)| *until > now);
+        peers.insert(ip, (peer, now + self.timeout.as_millis() as u64));
+    }
+}
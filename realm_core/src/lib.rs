@@ -5,8 +5,21 @@ pub mod tcp;
 pub mod udp;
 pub mod time;
 pub mod trick;
+pub mod stat;
+pub mod limit;
+pub mod registry;
+pub mod access;
 pub mod endpoint;
 
+#[cfg(feature = "balance")]
+pub mod health;
+
+#[cfg(feature = "trace")]
+pub mod trace;
+
+#[cfg(feature = "geo")]
+pub mod geo;
+
 pub use realm_io;
 pub use realm_syscall;
 
@@ -0,0 +1,240 @@
+//! Bandwidth and connection limits.
+//!
+//! A token bucket per direction, shared by all tcp connections of an endpoint.
+//! The rate may be changed at any time, and applies to active connections too.
+//!
+//! Connections beyond the limit may wait in a bounded queue for a free slot.
+//! Transport handshakes may have a limit of their own, to bound cpu usage.
+
+use std::io::{Error, ErrorKind, Result};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize};
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::{Duration, Instant};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::timeout;
+
+/// Longest wait before the rate is read again.
+const TICK: Duration = Duration::from_millis(100);
+
+/// Direction of traffic, seen from the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dir {
+    /// Client => remote.
+    Up = 0,
+    /// Remote => client.
+    Down = 1,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    // negative once overdrawn
+    tokens: f64,
+    last: Instant,
+}
+
+/// Rate limit of an endpoint, in bytes per second for each direction.
+#[derive(Debug)]
+pub struct RateLimit {
+    // 0 means unlimited
+    rate: AtomicU64,
+    buckets: [Mutex<Bucket>; 2],
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl RateLimit {
+    pub fn new(rate: u64) -> Self {
+        let bucket = || {
+            Mutex::new(Bucket {
+                tokens: 0.0,
+                last: Instant::now(),
+            })
+        };
+        Self {
+            rate: AtomicU64::new(rate),
+            buckets: [bucket(), bucket()],
+        }
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate.load(Relaxed)
+    }
+
+    /// Change the rate, 0 means unlimited.
+    pub fn set(&self, rate: u64) {
+        self.rate.store(rate, Relaxed);
+        // a lower rate must not allow the burst of the old one
+        for bucket in &self.buckets {
+            let mut bucket = bucket.lock().unwrap();
+            bucket.tokens = bucket.tokens.min(rate as f64);
+        }
+    }
+
+    /// How long to wait before more bytes may pass, None if they may pass now.
+    pub fn delay(&self, dir: Dir) -> Option<Duration> {
+        let rate = self.rate();
+        if rate == 0 {
+            return None;
+        }
+        let mut bucket = self.buckets[dir as usize].lock().unwrap();
+        bucket.refill(rate);
+        if bucket.tokens >= 0.0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64(-bucket.tokens / rate as f64).min(TICK))
+    }
+
+    /// Account bytes which have passed, the bucket may be overdrawn.
+    pub fn consume(&self, dir: Dir, n: usize) {
+        let rate = self.rate();
+        if rate == 0 || n == 0 {
+            return;
+        }
+        let mut bucket = self.buckets[dir as usize].lock().unwrap();
+        bucket.refill(rate);
+        bucket.tokens -= n as f64;
+    }
+}
+
+impl Bucket {
+    // at most one second of burst
+    fn refill(&mut self, rate: u64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate as f64).min(rate as f64);
+        self.last = now;
+    }
+}
+
+/// Max tcp connections of an endpoint, shared like [`RateLimit`].
+#[derive(Debug)]
+pub struct ConnLimit {
+    // 0 means unlimited
+    max: usize,
+    slots: Arc<Semaphore>,
+    depth: usize,
+    timeout: Duration,
+    queued: AtomicUsize,
+}
+
+impl Default for ConnLimit {
+    fn default() -> Self {
+        Self::new(0, 0, Duration::ZERO)
+    }
+}
+
+impl ConnLimit {
+    /// Allow at most `max` connections, 0 means unlimited.
+    /// Up to `depth` more wait for at most `timeout` in a queue, a zero timeout never expires.
+    pub fn new(max: usize, depth: usize, timeout: Duration) -> Self {
+        Self {
+            max,
+            slots: Arc::new(Semaphore::new(max)),
+            depth,
+            timeout,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Wait for a free slot, which is taken until the permit is dropped.
+    /// Fail if the queue is full, or the wait has timed out.
+    pub async fn acquire(&self) -> Result<Option<OwnedSemaphorePermit>> {
+        if self.max == 0 {
+            return Ok(None);
+        }
+
+        // waiters are served first, an ongoing wait is never overtaken
+        if let Ok(permit) = self.slots.clone().try_acquire_owned() {
+            return Ok(Some(permit));
+        }
+
+        if self.queued.fetch_add(1, Relaxed) >= self.depth {
+            self.queued.fetch_sub(1, Relaxed);
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("max connections({}) reached, queue is full", self.max),
+            ));
+        }
+
+        let slot = self.slots.clone().acquire_owned();
+        let res = if self.timeout.is_zero() {
+            Ok(slot.await)
+        } else {
+            timeout(self.timeout, slot).await
+        };
+        self.queued.fetch_sub(1, Relaxed);
+
+        match res {
+            // never closed
+            Ok(permit) => Ok(permit.ok()),
+            Err(_) => Err(Error::new(
+                ErrorKind::TimedOut,
+                format!(
+                    "max connections({}) reached, queued for {}s",
+                    self.max,
+                    self.timeout.as_secs()
+                ),
+            )),
+        }
+    }
+}
+
+/// Max concurrent transport handshakes of an endpoint, shared like [`RateLimit`].
+#[derive(Debug)]
+pub struct HandshakeLimit {
+    // 0 means unlimited
+    max: usize,
+    slots: Arc<Semaphore>,
+}
+
+impl Default for HandshakeLimit {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl HandshakeLimit {
+    /// Allow at most `max` handshakes at the same time, 0 means unlimited.
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            slots: Arc::new(Semaphore::new(max)),
+        }
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Handshakes in progress, always 0 if unlimited.
+    pub fn in_progress(&self) -> usize {
+        self.max - self.slots.available_permits()
+    }
+
+    /// Wait for a free slot, which is taken until the permit is dropped.
+    pub async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        if self.max == 0 {
+            return None;
+        }
+        // never closed
+        self.slots.clone().acquire_owned().await.ok()
+    }
+}
@@ -0,0 +1,168 @@
+//! Registry of live connections.
+//!
+//! Each tcp connection is registered once accepted, along with the
+//! handle of its task, so that it can be listed or aborted later.
+//! Its last activity is recorded as well, so that idle ones can be pruned,
+//! and so is its http exchange if the access log is on.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::{Duration, Instant};
+
+use tokio::task::JoinHandle;
+
+use crate::access::Exchange;
+
+/// A live connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnInfo {
+    pub id: u64,
+    /// Client address.
+    pub src: SocketAddr,
+    /// Listen address.
+    pub dst: SocketAddr,
+}
+
+struct Entry {
+    info: ConnInfo,
+    task: Option<JoinHandle<()>>,
+    activity: Arc<Activity>,
+}
+
+/// When a connection last moved any data.
+#[derive(Debug)]
+pub struct Activity {
+    start: Instant,
+    // millis since start
+    last: AtomicU64,
+    http: OnceLock<Exchange>,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            last: AtomicU64::new(0),
+            http: OnceLock::new(),
+        }
+    }
+
+    /// Record some data, from either side.
+    #[inline]
+    pub fn touch(&self) {
+        self.last.store(self.start.elapsed().as_millis() as u64, Relaxed);
+    }
+
+    /// Time since the last data, or since registered.
+    pub fn idle(&self) -> Duration {
+        let last = Duration::from_millis(self.last.load(Relaxed));
+        self.start.elapsed().saturating_sub(last)
+    }
+
+    /// Start recording the http exchange.
+    pub fn watch_http(&self) -> &Exchange {
+        self.http.get_or_init(Exchange::default)
+    }
+
+    /// The http exchange, if watched.
+    #[inline]
+    pub fn http(&self) -> Option<&Exchange> {
+        self.http.get()
+    }
+}
+
+/// Connections of an endpoint, ids are never reused.
+#[derive(Default)]
+pub struct Registry {
+    next_id: AtomicU64,
+    conns: Mutex<BTreeMap<u64, Entry>>,
+}
+
+/// Unregisters a connection once dropped, see [`crate::stat::ConnGuard`].
+pub struct Tracked(Arc<Registry>, u64, Arc<Activity>);
+
+impl Registry {
+    /// Register a connection, its task is attached later.
+    pub fn register(&self, src: SocketAddr, dst: SocketAddr) -> u64 {
+        let id = self.next_id.fetch_add(1, Relaxed) + 1;
+        let info = ConnInfo { id, src, dst };
+        let entry = Entry {
+            info,
+            task: None,
+            activity: Arc::new(Activity::new()),
+        };
+        self.conns.lock().unwrap().insert(id, entry);
+        id
+    }
+
+    /// Attach the task of a connection.
+    /// The task is aborted if the connection is already killed.
+    pub fn set_task(&self, id: u64, task: JoinHandle<()>) {
+        match self.conns.lock().unwrap().get_mut(&id) {
+            Some(entry) => entry.task = Some(task),
+            // killed, or finished which makes abort a no-op
+            None => task.abort(),
+        }
+    }
+
+    /// Keep a connection registered until the guard is dropped.
+    pub fn track(self: &Arc<Self>, id: u64) -> Tracked {
+        let activity = match self.conns.lock().unwrap().get(&id) {
+            Some(entry) => entry.activity.clone(),
+            // killed, the task is about to be aborted
+            None => Arc::new(Activity::new()),
+        };
+        Tracked(self.clone(), id, activity)
+    }
+
+    /// All live connections, ordered by id.
+    pub fn list(&self) -> Vec<ConnInfo> {
+        self.conns.lock().unwrap().values().map(|x| x.info.clone()).collect()
+    }
+
+    /// Abort a connection. Return false if not found.
+    pub fn kill(&self, id: u64) -> bool {
+        let entry = self.conns.lock().unwrap().remove(&id);
+        match entry {
+            Some(Entry { task, .. }) => {
+                if let Some(task) = task {
+                    task.abort();
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Connections idle for at least this long, see [`Activity`].
+    pub fn idle(&self, idle: Duration) -> Vec<ConnInfo> {
+        let conns = self.conns.lock().unwrap();
+        conns
+            .values()
+            .filter(|x| x.activity.idle() >= idle)
+            .map(|x| x.info.clone())
+            .collect()
+    }
+}
+
+impl std::fmt::Debug for Registry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.list()).finish()
+    }
+}
+
+impl Tracked {
+    /// Activity of the connection, see [`Registry::idle`].
+    pub fn activity(&self) -> &Activity {
+        &self.2
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.0.conns.lock().unwrap().remove(&self.1);
+    }
+}
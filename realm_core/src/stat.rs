@@ -0,0 +1,75 @@
+//! Traffic statistics.
+
+use std::ops::Add;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+
+/// Counters of an endpoint, shared by its tcp and udp relays.
+///
+/// A udp association is counted as a connection.
+#[derive(Debug, Default)]
+pub struct Stat {
+    active: AtomicU64,
+    total: AtomicU64,
+    bytes_up: AtomicU64,
+    bytes_down: AtomicU64,
+}
+
+/// A point-in-time copy of [`Stat`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StatSnapshot {
+    pub active_conns: u64,
+    pub total_conns: u64,
+    pub bytes_up: u64,
+    pub bytes_down: u64,
+}
+
+/// Marks a connection as active until dropped.
+///
+/// It owns the counters, since a task may be dropped
+/// after the relay which spawned it.
+pub struct ConnGuard(Arc<Stat>);
+
+impl Stat {
+    /// Count a new connection.
+    pub fn open(self: &Arc<Self>) -> ConnGuard {
+        self.total.fetch_add(1, Relaxed);
+        self.active.fetch_add(1, Relaxed);
+        ConnGuard(self.clone())
+    }
+
+    /// Account client => remote and remote => client bytes.
+    pub fn add_traffic(&self, up: u64, down: u64) {
+        self.bytes_up.fetch_add(up, Relaxed);
+        self.bytes_down.fetch_add(down, Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StatSnapshot {
+        StatSnapshot {
+            active_conns: self.active.load(Relaxed),
+            total_conns: self.total.load(Relaxed),
+            bytes_up: self.bytes_up.load(Relaxed),
+            bytes_down: self.bytes_down.load(Relaxed),
+        }
+    }
+}
+
+impl Drop for ConnGuard {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Relaxed);
+    }
+}
+
+impl Add for StatSnapshot {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        StatSnapshot {
+            active_conns: self.active_conns + other.active_conns,
+            total_conns: self.total_conns + other.total_conns,
+            bytes_up: self.bytes_up + other.bytes_up,
+            bytes_down: self.bytes_down + other.bytes_down,
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Userspace write coalescing.
+//!
+//! Small writes are held back and merged until either `size` bytes
+//! are pending or `delay` has elapsed since the first pending byte,
+//! whichever comes first. This trades up to `delay` of extra latency
+//! for fewer, larger segments on the wire.
+//!
+//! It differs from TCP Nagle (which `nodelay` disables): Nagle
+//! only holds data while an ACK is outstanding, this buffer holds
+//! data for a fixed window regardless of the peer.
+
+use std::io::Result;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use std::future::Future;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{sleep, Sleep};
+
+/// A wrapper that merges small writes.
+pub struct CoalesceStream<S> {
+    io: S,
+    buf: Vec<u8>,
+    pos: usize,
+    size: usize,
+    delay: Duration,
+    timer: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> CoalesceStream<S> {
+    /// Size = 0 means pass through.
+    pub fn new(io: S, size: usize, delay: Duration) -> Self {
+        Self {
+            io,
+            buf: Vec::with_capacity(size),
+            pos: 0,
+            size,
+            delay,
+            timer: None,
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> CoalesceStream<S> {
+    // write out all pending bytes
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        while self.pos < self.buf.len() {
+            let n = ready!(Pin::new(&mut self.io).poll_write(cx, &self.buf[self.pos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            self.pos += n;
+        }
+        self.buf.clear();
+        self.pos = 0;
+        self.timer = None;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CoalesceStream<S> {
+    #[inline]
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CoalesceStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, data: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+
+        if this.size == 0 {
+            return Pin::new(&mut this.io).poll_write(cx, data);
+        }
+
+        // make room first
+        if this.buf.len() >= this.size {
+            ready!(this.poll_drain(cx))?;
+        }
+
+        // large writes gain nothing from waiting
+        if this.buf.is_empty() && data.len() >= this.size {
+            return Pin::new(&mut this.io).poll_write(cx, data);
+        }
+
+        let n = std::cmp::min(data.len(), this.size - this.buf.len());
+        this.buf.extend_from_slice(&data[..n]);
+
+        if this.timer.is_none() {
+            this.timer = Some(Box::pin(sleep(this.delay)));
+        }
+
+        // opportunistic, the data has been accepted anyway
+        if this.buf.len() >= this.size {
+            let _ = this.poll_drain(cx)?;
+        }
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+
+        // hold the data until the window closes,
+        // the relay polls flush whenever the reader is idle
+        if this.buf.len() < this.size {
+            if let Some(timer) = this.timer.as_mut() {
+                ready!(timer.as_mut().poll(cx));
+            }
+        }
+
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.io).poll_shutdown(cx)
+    }
+}
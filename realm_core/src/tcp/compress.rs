@@ -0,0 +1,160 @@
+//! Transparent deflate compression.
+//!
+//! Bytes written to the wrapped stream are compressed, bytes read from
+//! it are decompressed, so it must face another realm which does the
+//! same. Each write is sync flushed, so that nothing is held back
+//! waiting for more data, at the cost of a few bytes per write.
+
+use std::io::{Error, ErrorKind, Result};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use miniz_oxide::{MZError, MZFlush};
+use miniz_oxide::deflate::core::{create_comp_flags_from_zip_params, CompressorOxide};
+use miniz_oxide::deflate::stream::deflate;
+use miniz_oxide::inflate::stream::{inflate, InflateState};
+use miniz_oxide::DataFormat;
+
+const CHUNK: usize = 0x4000;
+
+/// A wrapper that compresses writes and decompresses reads.
+pub struct CompressStream<S> {
+    io: S,
+    compressor: Box<CompressorOxide>,
+    decompressor: Box<InflateState>,
+    // compressed, not written yet
+    wbuf: Vec<u8>,
+    wpos: usize,
+    // compressed, not decompressed yet
+    rbuf: Box<[u8]>,
+    rpos: usize,
+    rlen: usize,
+}
+
+impl<S> CompressStream<S> {
+    /// Level is 0 to 10, higher is smaller but slower.
+    pub fn new(io: S, level: u8) -> Self {
+        // raw deflate, without zlib header and checksum
+        let flags = create_comp_flags_from_zip_params(level as i32, -15, 0);
+        Self {
+            io,
+            compressor: Box::new(CompressorOxide::new(flags)),
+            decompressor: InflateState::new_boxed(DataFormat::Raw),
+            wbuf: Vec::with_capacity(CHUNK),
+            wpos: 0,
+            rbuf: vec![0; CHUNK].into_boxed_slice(),
+            rpos: 0,
+            rlen: 0,
+        }
+    }
+
+    // compress and sync flush a chunk
+    fn compress(&mut self, mut data: &[u8]) -> Result<()> {
+        let mut out = [0u8; CHUNK];
+        loop {
+            let res = deflate(&mut self.compressor, data, &mut out, MZFlush::Sync);
+            res.status
+                .map_err(|e| Error::new(ErrorKind::Other, format!("deflate: {:?}", e)))?;
+            self.wbuf.extend_from_slice(&out[..res.bytes_written]);
+            data = &data[res.bytes_consumed..];
+            // flushed once there is room left
+            if data.is_empty() && res.bytes_written < out.len() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> CompressStream<S> {
+    // write out all compressed bytes
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        while self.wpos < self.wbuf.len() {
+            let n = ready!(Pin::new(&mut self.io).poll_write(cx, &self.wbuf[self.wpos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+            }
+            self.wpos += n;
+        }
+        self.wbuf.clear();
+        self.wpos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CompressStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+
+        // nothing could be inflated, which is not an error
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        loop {
+            // what is left from the last call comes out first
+            let res = inflate(
+                &mut this.decompressor,
+                &this.rbuf[this.rpos..this.rlen],
+                buf.initialize_unfilled(),
+                MZFlush::None,
+            );
+            this.rpos += res.bytes_consumed;
+            match res.status {
+                // with room to write, some of the input is always taken
+                Err(MZError::Buf) if res.bytes_consumed == 0 && this.rpos < this.rlen => {
+                    return Poll::Ready(Err(Error::new(ErrorKind::InvalidData, "inflate: stuck")));
+                }
+                Err(MZError::Buf) => {}
+                Err(e) => return Poll::Ready(Err(Error::new(ErrorKind::InvalidData, format!("inflate: {:?}", e)))),
+                Ok(_) if res.bytes_written != 0 => {
+                    buf.advance(res.bytes_written);
+                    return Poll::Ready(Ok(()));
+                }
+                Ok(_) => {}
+            }
+
+            // needs more input
+            if this.rpos < this.rlen {
+                continue;
+            }
+            let mut rbuf = ReadBuf::new(&mut this.rbuf);
+            ready!(Pin::new(&mut this.io).poll_read(cx, &mut rbuf))?;
+            let n = rbuf.filled().len();
+            if n == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            (this.rpos, this.rlen) = (0, n);
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CompressStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, data: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+
+        // make room first
+        ready!(this.poll_drain(cx))?;
+
+        let n = std::cmp::min(data.len(), CHUNK);
+        this.compress(&data[..n])?;
+
+        // opportunistic, the data has been accepted anyway,
+        // the relay polls flush whenever the reader is idle
+        let _ = this.poll_drain(cx)?;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.io).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,58 @@
+//! Correlation id.
+//!
+//! Each connection gets a random id, which is sent to the remote peer
+//! as a header of the ws upgrade request, and logged with the connection.
+//! Logs on both sides of the relay can be joined by it.
+
+use std::io::Result;
+
+use kaminari::IOStream;
+use kaminari::ws::WsConf;
+
+use lightws::endpoint::Endpoint;
+use lightws::error::HandshakeError;
+use lightws::handshake::{HttpHeader, Request, Response};
+use lightws::handshake::{new_sec_key, derive_accept_key};
+use lightws::role::Client;
+use lightws::stream::{Guarded, Stream};
+
+/// A random uuid, version 4.
+pub fn new_id() -> String {
+    let x: u128 = rand::random();
+    let x = x & !(0xf << 76) | (0x4 << 76);
+    let x = x & !(0x3 << 62) | (0x2 << 62);
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        x >> 96,
+        (x >> 80) & 0xffff,
+        (x >> 64) & 0xffff,
+        (x >> 48) & 0xffff,
+        x & 0xffff_ffff_ffff
+    )
+}
+
+/// The same as the upgrade of lightws, with an extra header.
+pub async fn upgrade<T: IOStream>(
+    mut io: T,
+    buf: &mut [u8],
+    ws: &WsConf,
+    name: &str,
+    id: &str,
+) -> Result<Stream<T, Client, Guarded>> {
+    let sec_key = new_sec_key();
+    let sec_accept = derive_accept_key(&sec_key);
+
+    let mut headers = [HttpHeader::new(name.as_bytes(), id.as_bytes())];
+    let request = Request::new_with_headers(ws.path.as_bytes(), ws.host.as_bytes(), &sec_key, &mut headers);
+    Endpoint::<_, Client>::send_request_async(&mut io, buf, &request).await?;
+
+    let mut other_headers = HttpHeader::new_storage();
+    let mut response = Response::new_storage(&mut other_headers);
+    // the buffer is not touched until the response is checked
+    unsafe { Endpoint::<_, Client>::recv_response_async(&mut io, buf, &mut response) }.await?;
+    if response.sec_accept != sec_accept {
+        return Err(HandshakeError::SecWebSocketAccept.into());
+    }
+
+    Ok(Stream::new(io, Client).guard())
+}
@@ -0,0 +1,130 @@
+//! Traffic accounting.
+//!
+//! Wraps the client side stream, so that bytes read from it are
+//! counted as upload, and bytes written to it as download.
+//! Either of them marks the connection as active, and the first of
+//! them is sniffed for the access log.
+//! Raw io is forwarded as well, which keeps zero copy available.
+
+use std::io::Result;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::stat::Stat;
+use crate::registry::Activity;
+
+/// A wrapper that counts bytes as they pass.
+pub struct CountStream<'a, S> {
+    io: S,
+    stat: &'a Stat,
+    activity: &'a Activity,
+}
+
+impl<'a, S> CountStream<'a, S> {
+    pub fn new(io: S, stat: &'a Stat, activity: &'a Activity) -> Self {
+        Self { io, stat, activity }
+    }
+
+    #[inline]
+    fn count(&self, up: usize, down: usize) {
+        if up + down != 0 {
+            self.stat.add_traffic(up as u64, down as u64);
+            self.activity.touch();
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountStream<'_, S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut this.io).poll_read(cx, buf);
+        if let Some(http) = this.activity.http() {
+            if buf.filled().len() != filled {
+                http.on_request(&buf.filled()[filled..]);
+            }
+        }
+        this.count(buf.filled().len() - filled, 0);
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountStream<'_, S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, data: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.io).poll_write(cx, data);
+        if let Poll::Ready(Ok(n)) = res {
+            if let Some(http) = this.activity.http() {
+                http.on_response(&data[..n]);
+            }
+            this.count(0, n);
+        }
+        res
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod raw {
+    use super::*;
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use tokio::io::Interest;
+    use realm_io::AsyncRawIO;
+
+    impl<S: AsRawFd> AsRawFd for CountStream<'_, S> {
+        #[inline]
+        fn as_raw_fd(&self) -> RawFd {
+            self.io.as_raw_fd()
+        }
+    }
+
+    impl<S: AsyncRawIO> AsyncRawIO for CountStream<'_, S> {
+        #[inline]
+        fn x_poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+            self.io.x_poll_read_ready(cx)
+        }
+
+        #[inline]
+        fn x_poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+            self.io.x_poll_write_ready(cx)
+        }
+
+        #[inline]
+        fn x_try_io<R>(&self, interest: Interest, f: impl FnOnce() -> Result<R>) -> Result<R> {
+            self.io.x_try_io(interest, f)
+        }
+
+        fn poll_read_raw<F>(&self, cx: &mut Context<'_>, syscall: F) -> Poll<Result<usize>>
+        where
+            F: FnMut() -> isize,
+        {
+            let res = self.io.poll_read_raw(cx, syscall);
+            if let Poll::Ready(Ok(n)) = res {
+                self.count(n, 0);
+            }
+            res
+        }
+
+        fn poll_write_raw<F>(&self, cx: &mut Context<'_>, syscall: F) -> Poll<Result<usize>>
+        where
+            F: FnMut() -> isize,
+        {
+            let res = self.io.poll_write_raw(cx, syscall);
+            if let Poll::Ready(Ok(n)) = res {
+                self.count(0, n);
+            }
+            res
+        }
+    }
+}
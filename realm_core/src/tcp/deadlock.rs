@@ -0,0 +1,71 @@
+//! Deadlock detection.
+//!
+//! Before relaying, wait for either side to speak. The first chunk is
+//! forwarded as is, then the relay takes over. If both sides wait for
+//! each other, the nudge is sent to the remote peer once, or the
+//! connection is dropped.
+
+use std::io::{Error, ErrorKind, Result};
+use std::pin::pin;
+
+use futures::future::{select, Either};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::dropped::{DropReason, dropped};
+use crate::time::timeoutfut;
+use crate::endpoint::ConnectOpts;
+
+pub async fn wait_either<A, B>(local: &mut A, remote: &mut B, conn_opts: &ConnectOpts) -> Result<()>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    let ConnectOpts {
+        deadlock_timeout: timeout,
+        deadlock_nudge: nudge,
+        ..
+    } = conn_opts;
+    if *timeout == 0 {
+        return Ok(());
+    }
+
+    let mut buf1 = vec![0; 0x1000];
+    let mut buf2 = vec![0; 0x1000];
+    let mut nudged = nudge.is_empty();
+    loop {
+        // the other read is dropped with nothing consumed
+        let res = timeoutfut(
+            select(pin!(local.read(&mut buf1)), pin!(remote.read(&mut buf2))),
+            *timeout,
+        )
+        .await
+        .map(|x| match x {
+            Either::Left((res, _)) => res.map(Either::Left),
+            Either::Right((res, _)) => res.map(Either::Right),
+        });
+
+        match res {
+            Ok(Ok(Either::Left(n))) => {
+                remote.write_all(&buf1[..n]).await?;
+                return remote.flush().await;
+            }
+            Ok(Ok(Either::Right(n))) => {
+                local.write_all(&buf2[..n]).await?;
+                return local.flush().await;
+            }
+            Ok(Err(e)) => return Err(e),
+            Err(_) if !nudged => {
+                log::debug!("[tcp]no data from either side in {}s, nudge the remote peer", timeout);
+                remote.write_all(nudge).await?;
+                remote.flush().await?;
+                nudged = true;
+            }
+            Err(_) => {
+                return Err(dropped(
+                    DropReason::Deadlock,
+                    Error::new(ErrorKind::TimedOut, format!("no data from either side in {}s", timeout)),
+                ))
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! Dropped connections.
+//!
+//! A connection closed on purpose carries a reason code in its error,
+//! all of them are logged the same way:
+//!
+//! `[tcp]<client> dropped, reason=<code>: <detail>`
+//!
+//! A [`PreClose`] callback is notified first, while the client is still
+//! connected.
+
+use std::fmt::{Display, Formatter};
+use std::io::Error;
+use std::net::SocketAddr;
+use std::sync::RwLock;
+
+/// Why a connection is dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// Rejected by the pre-connect hook.
+    #[cfg(feature = "hook")]
+    HookDenied,
+    /// The tls server name is not allowed.
+    #[cfg(feature = "transport")]
+    SniDenied,
+    /// The PROXY header is malformed or truncated.
+    #[cfg(feature = "proxy")]
+    ProxyMalformed,
+    /// The PROXY header is not completed in time.
+    #[cfg(feature = "proxy")]
+    ProxyTimeout,
+    /// The transport handshake is not done in time.
+    #[cfg(feature = "transport")]
+    HandshakeTimeout,
+    /// A ws frame is larger than allowed.
+    #[cfg(feature = "transport")]
+    FrameTooLarge,
+    /// Max connections reached, with a full queue.
+    MaxConns,
+    /// Queued for too long.
+    QueueTimeout,
+    /// The client sent nothing in time.
+    ClientSilent,
+    /// The remote peer sent nothing in time.
+    RemoteSilent,
+    /// Neither side sent anything in time.
+    Deadlock,
+    /// All remote peers are down.
+    NoHealthyRemote,
+    /// No data for the idle timeout.
+    Idle,
+}
+
+impl DropReason {
+    /// Machine-parseable reason code.
+    pub const fn code(&self) -> &'static str {
+        use DropReason::*;
+        match self {
+            #[cfg(feature = "hook")]
+            HookDenied => "hook_denied",
+            #[cfg(feature = "transport")]
+            SniDenied => "sni_denied",
+            #[cfg(feature = "proxy")]
+            ProxyMalformed => "proxy_malformed",
+            #[cfg(feature = "proxy")]
+            ProxyTimeout => "proxy_timeout",
+            #[cfg(feature = "transport")]
+            HandshakeTimeout => "handshake_timeout",
+            #[cfg(feature = "transport")]
+            FrameTooLarge => "frame_too_large",
+            MaxConns => "max_conns",
+            QueueTimeout => "queue_timeout",
+            ClientSilent => "client_silent",
+            RemoteSilent => "remote_silent",
+            Deadlock => "deadlock",
+            NoHealthyRemote => "no_healthy_remote",
+            Idle => "idle",
+        }
+    }
+}
+
+impl Display for DropReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+#[derive(Debug)]
+struct Dropped {
+    reason: DropReason,
+    source: Error,
+}
+
+impl Display for Dropped {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.source.fmt(f)
+    }
+}
+
+impl std::error::Error for Dropped {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Attach a reason to an error, the kind is kept.
+pub fn dropped(reason: DropReason, source: Error) -> Error {
+    Error::new(source.kind(), Dropped { reason, source })
+}
+
+/// The reason attached by [`dropped`].
+pub fn reason_of(e: &Error) -> Option<DropReason> {
+    e.get_ref()?.downcast_ref::<Dropped>().map(|x| x.reason)
+}
+
+type Callback = Box<dyn Fn(SocketAddr, DropReason) + Send + Sync>;
+
+/// Notified of a connection about to be dropped, before the client sees it closed.
+///
+/// May be changed at runtime, for the connections accepted since, as the
+/// client socket is only kept open if a callback is set.
+#[derive(Default)]
+pub struct PreClose(RwLock<Option<Callback>>);
+
+impl PreClose {
+    /// Replace the callback, None removes it.
+    pub fn set(&self, callback: Option<Callback>) {
+        *self.0.write().unwrap() = callback;
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.0.read().unwrap().is_some()
+    }
+
+    /// Call the callback, if any.
+    pub fn notify(&self, client: SocketAddr, reason: DropReason) {
+        if let Some(callback) = &*self.0.read().unwrap() {
+            callback(client, reason);
+        }
+    }
+}
+
+impl std::fmt::Debug for PreClose {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PreClose").field(&self.is_set()).finish()
+    }
+}
+
+/// The only place a dropped connection is logged.
+pub fn drop_connection(reason: DropReason, client: SocketAddr, detail: &dyn Display) {
+    log::warn!("[tcp]{} dropped, reason={}: {}", client, reason, detail);
+}
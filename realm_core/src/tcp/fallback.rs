@@ -0,0 +1,98 @@
+//! Remote transport fallback.
+//!
+//! The remote transports are tried in order, the next one is tried on
+//! a new connection once the handshake of the previous one fails, e.g.
+//! a middlebox breaks tls. The stream of a failed handshake is dropped,
+//! as whatever was written to it is lost.
+//!
+//! A handshake that hangs is given up after its share of the handshake
+//! timeout, or the per attempt timeout if set, then the next one is tried.
+
+use std::future::Future;
+use std::io::{Error, ErrorKind, Result};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use kaminari::AsyncConnect;
+use kaminari::mix::{MixConnect, MixClientStream};
+
+use super::socket;
+use super::timing::Timing;
+use crate::trick::Ref;
+use crate::endpoint::{RemoteAddr, ConnectOpts, PeerOpts};
+
+/// Connects with the first transport whose handshake succeeds.
+pub struct Fallback {
+    pub transports: Vec<Ref<MixConnect>>,
+    pub raddr: Ref<RemoteAddr>,
+    pub peer_opts: Option<Ref<PeerOpts>>,
+    pub conn_opts: Ref<ConnectOpts>,
+    pub client: SocketAddr,
+}
+
+impl Fallback {
+    /// How long a single handshake may take, None if only
+    /// the handshake timeout bounds them.
+    fn attempt_timeout(&self) -> Option<Duration> {
+        #[cfg(feature = "balance")]
+        if self.conn_opts.per_attempt_timeout != 0 {
+            return Some(Duration::from_secs(self.conn_opts.per_attempt_timeout as u64));
+        }
+        match self.conn_opts.handshake_timeout {
+            0 => None,
+            n => Some(Duration::from_millis(n as u64 * 1000 / self.transports.len() as u64)),
+        }
+    }
+}
+
+impl AsyncConnect<TcpStream> for Fallback {
+    type Stream = MixClientStream<TcpStream>;
+
+    type ConnectFut<'a>
+        = Pin<Box<dyn Future<Output = Result<Self::Stream>> + Send + 'a>>
+    where
+        Self: 'a;
+
+    fn connect<'a>(&'a self, stream: TcpStream, buf: &'a mut [u8]) -> Self::ConnectFut<'a> {
+        Box::pin(async move {
+            let mut stream = stream;
+            let mut transports = self.transports.iter().peekable();
+            let attempt = self.attempt_timeout();
+            loop {
+                let cc = transports.next().expect("no remote transport");
+                let res = match attempt {
+                    Some(x) => timeout(x, cc.connect(stream, buf)).await.unwrap_or_else(|_| {
+                        Err(Error::new(
+                            ErrorKind::TimedOut,
+                            format!("not done in {}ms", x.as_millis()),
+                        ))
+                    }),
+                    None => cc.connect(stream, buf).await,
+                };
+                let e = match res {
+                    Ok(x) => return Ok(x),
+                    Err(e) => e,
+                };
+                let Some(next) = transports.peek() else {
+                    return Err(e);
+                };
+                log::warn!(
+                    "[tcp]{} => {}, handshake of {} failed: {}, fall back to {}",
+                    self.client,
+                    self.raddr.as_ref(),
+                    cc.as_ref(),
+                    e,
+                    next.as_ref()
+                );
+                // the setup is timed by the first dial only
+                let mut timing = Timing::new(0);
+                let peer_opts = self.peer_opts.as_deref();
+                stream = socket::connect(&self.raddr, peer_opts, &self.conn_opts, &mut timing).await?;
+            }
+        })
+    }
+}
@@ -0,0 +1,169 @@
+//! TLS ClientHello sniffing.
+//!
+//! The listen side tls handshake is done by kaminari after the
+//! remote peer is connected, so options that decide the remote peer
+//! or reject the client are read from the ClientHello in advance,
+//! without consuming it.
+
+use std::io::Result;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+
+const RECORD_HEADER: usize = 5;
+const MAX_RECORD: usize = RECORD_HEADER + 0x4000;
+
+const CONTENT_HANDSHAKE: u8 = 0x16;
+const HANDSHAKE_CLIENT_HELLO: u8 = 0x01;
+
+const EXT_SERVER_NAME: u16 = 0x0000;
+const EXT_ALPN: u16 = 0x0010;
+
+/// Fields picked from a ClientHello.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ClientHello<'a> {
+    pub sni: Option<&'a str>,
+    pub alpn: Vec<&'a [u8]>,
+}
+
+/// Peek the first tls record, return None if it is not a handshake.
+pub async fn peek_record(local: &TcpStream) -> Result<Option<Vec<u8>>> {
+    let mut buf = vec![0u8; MAX_RECORD];
+    let mut last = 0;
+
+    loop {
+        let n = local.peek(&mut buf).await?;
+
+        if n == 0 {
+            return Ok(None);
+        }
+
+        if n >= RECORD_HEADER {
+            if buf[0] != CONTENT_HANDSHAKE {
+                return Ok(None);
+            }
+            let len = RECORD_HEADER + u16::from_be_bytes([buf[3], buf[4]]) as usize;
+            if n >= len || len > MAX_RECORD {
+                buf.truncate(std::cmp::min(n, len));
+                return Ok(Some(buf));
+            }
+        }
+
+        // peek returns at once while there are unread bytes,
+        // wait a little for the rest of the record
+        if n == last {
+            sleep(Duration::from_millis(5)).await;
+        }
+        last = n;
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (x, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(x)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|x| x[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|x| u16::from_be_bytes([x[0], x[1]]))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        self.take(3)
+            .map(|x| ((x[0] as usize) << 16) | ((x[1] as usize) << 8) | x[2] as usize)
+    }
+
+    fn vec8(&mut self) -> Option<&'a [u8]> {
+        let n = self.u8()? as usize;
+        self.take(n)
+    }
+
+    fn vec16(&mut self) -> Option<&'a [u8]> {
+        let n = self.u16()? as usize;
+        self.take(n)
+    }
+}
+
+/// Match a server name against an allowlist in lower case,
+/// `*.example.com` matches any subdomain of `example.com`.
+pub fn sni_allowed(sni: &str, allowlist: &[String]) -> bool {
+    let sni = sni.trim_end_matches('.').to_ascii_lowercase();
+    allowlist.iter().any(|x| match x.strip_prefix("*.") {
+        Some(domain) => sni
+            .strip_suffix(domain)
+            .is_some_and(|x| x.len() > 1 && x.ends_with('.')),
+        None => *x == sni,
+    })
+}
+
+/// Parse a ClientHello from a complete tls record.
+pub fn parse(record: &[u8]) -> Option<ClientHello<'_>> {
+    let mut rd = Reader(record);
+
+    // record header
+    if rd.u8()? != CONTENT_HANDSHAKE {
+        return None;
+    }
+    let _version = rd.u16()?;
+    let mut rd = Reader(rd.vec16()?);
+
+    // handshake header
+    if rd.u8()? != HANDSHAKE_CLIENT_HELLO {
+        return None;
+    }
+    let len = rd.u24()?;
+    let mut rd = Reader(rd.take(len)?);
+
+    // version, random, session id, cipher suites, compression methods
+    rd.take(2 + 32)?;
+    rd.vec8()?;
+    rd.vec16()?;
+    rd.vec8()?;
+
+    let mut hello = ClientHello::default();
+
+    // extensions are optional
+    let mut exts = match rd.vec16() {
+        Some(x) => Reader(x),
+        None => return Some(hello),
+    };
+
+    while !exts.0.is_empty() {
+        let ty = exts.u16()?;
+        let mut data = Reader(exts.vec16()?);
+
+        match ty {
+            EXT_SERVER_NAME => {
+                let mut list = Reader(data.vec16()?);
+                while !list.0.is_empty() {
+                    let name_type = list.u8()?;
+                    let name = list.vec16()?;
+                    // host_name
+                    if name_type == 0 {
+                        hello.sni = std::str::from_utf8(name).ok();
+                    }
+                }
+            }
+            EXT_ALPN => {
+                let mut list = Reader(data.vec16()?);
+                while !list.0.is_empty() {
+                    hello.alpn.push(list.vec8()?);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Some(hello)
+}
@@ -3,6 +3,7 @@ use std::io::{Result, Error, ErrorKind};
 use tokio::net::TcpStream;
 use realm_hook::pre_conn::{self, first_pkt_len, decide_remote_idx};
 
+use super::dropped::{DropReason, dropped};
 use crate::endpoint::RemoteAddr;
 
 pub async fn pre_connect_hook<'a>(
@@ -28,6 +29,9 @@ pub async fn pre_connect_hook<'a>(
     match idx {
         0 => Ok(raddr),
         i if i >= 1 && i <= idx => Ok(&extra_raddrs[i as usize - 1]),
-        _ => Err(Error::new(ErrorKind::Other, "rejected by pre-connect hook")),
+        _ => Err(dropped(
+            DropReason::HookDenied,
+            Error::new(ErrorKind::Other, "rejected by pre-connect hook"),
+        )),
     }
 }
@@ -0,0 +1,64 @@
+//! Idle connections.
+//!
+//! Connections are swept every prune interval, those without any data
+//! for the idle timeout are aborted. So a connection is closed between
+//! `idle_timeout` and `idle_timeout + prune_interval` after its last data.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+
+use super::dropped::{DropReason, PreClose, drop_connection};
+use crate::registry::Registry;
+use crate::endpoint::ConnectOpts;
+
+/// Stops the sweeping once dropped, along with the listener
+/// and the last of its connections.
+pub struct Pruner(JoinHandle<()>);
+
+impl Pruner {
+    /// Sweep the connections of an endpoint, None if there is no idle timeout.
+    pub fn spawn(conn_opts: &ConnectOpts) -> Option<Self> {
+        if conn_opts.idle_timeout == 0 {
+            return None;
+        }
+        let interval = match conn_opts.prune_interval {
+            0 => 1000,
+            n => n,
+        };
+        let task = tokio::spawn(prune(
+            conn_opts.conns.clone(),
+            conn_opts.pre_close.clone(),
+            conn_opts.idle_timeout,
+            interval,
+        ));
+        Some(Self(task))
+    }
+}
+
+impl Drop for Pruner {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+async fn prune(conns: Arc<Registry>, pre_close: Arc<PreClose>, idle_timeout: usize, prune_interval: usize) {
+    let idle = Duration::from_secs(idle_timeout as u64);
+    let mut interval = tokio::time::interval(Duration::from_millis(prune_interval as u64));
+    loop {
+        interval.tick().await;
+        for conn in conns.idle(idle) {
+            pre_close.notify(conn.src, DropReason::Idle);
+            // finished meanwhile
+            if !conns.kill(conn.id) {
+                continue;
+            }
+            drop_connection(
+                DropReason::Idle,
+                conn.src,
+                &format_args!("no data in {}s", idle_timeout),
+            );
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Bandwidth limiting.
+//!
+//! Wraps the client side stream, reads from it are limited as upload,
+//! and writes to it as download. Raw io is forwarded as well,
+//! which keeps zero copy available.
+
+use std::future::Future;
+use std::io::Result;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{sleep, Sleep};
+
+use crate::limit::{Dir, RateLimit};
+
+/// A wrapper that waits while the rate limit is exceeded.
+pub struct LimitStream<'a, S> {
+    io: S,
+    limit: &'a RateLimit,
+    // pending wait of each direction
+    waits: [Mutex<Option<Pin<Box<Sleep>>>>; 2],
+}
+
+impl<'a, S> LimitStream<'a, S> {
+    pub fn new(io: S, limit: &'a RateLimit) -> Self {
+        Self {
+            io,
+            limit,
+            waits: [Mutex::new(None), Mutex::new(None)],
+        }
+    }
+
+    fn poll_wait(&self, cx: &mut Context<'_>, dir: Dir) -> Poll<()> {
+        let mut wait = self.waits[dir as usize].lock().unwrap();
+        loop {
+            if let Some(x) = wait.as_mut() {
+                ready!(x.as_mut().poll(cx));
+                *wait = None;
+            }
+            // the rate is read again after each wait
+            match self.limit.delay(dir) {
+                Some(delay) => *wait = Some(Box::pin(sleep(delay))),
+                None => return Poll::Ready(()),
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for LimitStream<'_, S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_wait(cx, Dir::Up));
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut this.io).poll_read(cx, buf);
+        this.limit.consume(Dir::Up, buf.filled().len() - filled);
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for LimitStream<'_, S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, data: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_wait(cx, Dir::Down));
+        let res = Pin::new(&mut this.io).poll_write(cx, data);
+        if let Poll::Ready(Ok(n)) = res {
+            this.limit.consume(Dir::Down, n);
+        }
+        res
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod raw {
+    use super::*;
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use tokio::io::Interest;
+    use realm_io::AsyncRawIO;
+
+    impl<S: AsRawFd> AsRawFd for LimitStream<'_, S> {
+        #[inline]
+        fn as_raw_fd(&self) -> RawFd {
+            self.io.as_raw_fd()
+        }
+    }
+
+    impl<S: AsyncRawIO> AsyncRawIO for LimitStream<'_, S> {
+        #[inline]
+        fn x_poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+            self.io.x_poll_read_ready(cx)
+        }
+
+        #[inline]
+        fn x_poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+            self.io.x_poll_write_ready(cx)
+        }
+
+        #[inline]
+        fn x_try_io<R>(&self, interest: Interest, f: impl FnOnce() -> Result<R>) -> Result<R> {
+            self.io.x_try_io(interest, f)
+        }
+
+        fn poll_read_raw<F>(&self, cx: &mut Context<'_>, syscall: F) -> Poll<Result<usize>>
+        where
+            F: FnMut() -> isize,
+        {
+            ready!(self.poll_wait(cx, Dir::Up));
+            let res = self.io.poll_read_raw(cx, syscall);
+            if let Poll::Ready(Ok(n)) = res {
+                self.limit.consume(Dir::Up, n);
+            }
+            res
+        }
+
+        fn poll_write_raw<F>(&self, cx: &mut Context<'_>, syscall: F) -> Poll<Result<usize>>
+        where
+            F: FnMut() -> isize,
+        {
+            ready!(self.poll_wait(cx, Dir::Down));
+            let res = self.io.poll_write_raw(cx, syscall);
+            if let Poll::Ready(Ok(n)) = res {
+                self.limit.consume(Dir::Down, n);
+            }
+            res
+        }
+    }
+}
@@ -3,6 +3,9 @@ use tokio::net::TcpStream;
 
 use super::socket;
 use super::plain;
+use super::silent::RemoteSilent;
+use super::dropped::{DropReason, dropped, reason_of};
+use super::timing::Timing;
 
 #[cfg(feature = "hook")]
 use super::hook;
@@ -11,16 +14,30 @@ use super::hook;
 use super::proxy;
 
 #[cfg(feature = "transport")]
-use super::transport;
+use super::{transport, hello, correlation};
+
+#[cfg(feature = "transport")]
+use super::fallback::Fallback;
+
+#[cfg(any(feature = "balance", feature = "transport"))]
+use super::request;
 
 use crate::trick::Ref;
-use crate::endpoint::{RemoteAddr, ConnectOpts};
+use crate::endpoint::{RemoteAddr, ConnectOpts, PeerOpts};
+use crate::registry::Activity;
+
+#[cfg(feature = "balance")]
+use crate::endpoint::HashKey;
+#[cfg(feature = "balance")]
+use crate::health::LoadGuard;
+
 #[allow(unused)]
 pub async fn connect_and_relay(
     mut local: TcpStream,
     raddr: Ref<RemoteAddr>,
     conn_opts: Ref<ConnectOpts>,
     extra_raddrs: Ref<Vec<RemoteAddr>>,
+    activity: &Activity,
 ) -> Result<()> {
     let ConnectOpts {
         #[cfg(feature = "proxy")]
@@ -29,59 +46,204 @@ pub async fn connect_and_relay(
         #[cfg(feature = "transport")]
         transport,
 
+        #[cfg(feature = "transport")]
+        alpn_routes,
+
+        #[cfg(feature = "transport")]
+        sni_allowlist,
+
+        #[cfg(feature = "transport")]
+        allow_missing_sni,
+
         #[cfg(feature = "balance")]
         balancer,
 
+        #[cfg(feature = "balance")]
+        hash_key,
+
+        #[cfg(feature = "geo")]
+        geo_routes,
+
+        port_routes,
         tcp_keepalive,
+        slow_conn_threshold,
+        first_byte_timeout,
         ..
     } = conn_opts.as_ref();
+    let remotes = (raddr, extra_raddrs);
+
+    // drop clients sending nothing, before any other work
+    if *first_byte_timeout != 0 {
+        wait_first_byte(&local, *first_byte_timeout).await?;
+    }
+
+    // the ClientHello, if any option needs it
+    #[cfg(feature = "transport")]
+    let record = match transport {
+        Some((ac, _)) if accept_tls(ac) && !(alpn_routes.is_empty() && sni_allowlist.is_empty()) => {
+            peek_hello(&local, conn_opts.as_ref()).await?
+        }
+        _ => None,
+    };
+    #[cfg(feature = "transport")]
+    let hello = record.as_deref().and_then(hello::parse);
+
+    // the upgrade request is consumed by the handshake, peek it first
+    #[cfg(feature = "transport")]
+    if let (Some(http), Some((ac, _))) = (activity.http(), transport) {
+        if ac.as_ws().is_some() {
+            if let Some(head) = peek_head(&local, conn_opts.as_ref()).await? {
+                http.on_request(&head);
+            }
+        }
+    }
+
+    // reject unknown server names before the handshake
+    #[cfg(feature = "transport")]
+    if !sni_allowlist.is_empty() {
+        check_sni(hello.as_ref().and_then(|x| x.sni), sni_allowlist, *allow_missing_sni)?;
+    }
 
     // before connect:
     // - pre-connect hook
     // - load balance
     // ..
-    let raddr = {
+    #[cfg(feature = "balance")]
+    let peer = {
+        // accept or deny connection.
         #[cfg(feature = "hook")]
-        {
-            // accept or deny connection.
-            #[cfg(feature = "balance")]
-            {
-                hook::pre_connect_hook(&mut local, raddr.as_ref(), extra_raddrs.as_ref()).await?;
-            }
+        hook::pre_connect_hook(&mut local, raddr.as_ref(), extra_raddrs.as_ref()).await?;
+
+        use realm_lb::{Token, BalanceCtx, Strategy};
+        let src_ip = socket::peer_addr(&local)?.ip();
+        let key_ip = match hash_key {
+            HashKey::Source => src_ip,
+            key => peek_head(&local, conn_opts.as_ref())
+                .await?
+                .as_deref()
+                .and_then(|head| request::hash_value(head, key))
+                .map_or(src_ip, request::hash_ip),
+        };
+        let loads = match balancer.strategy() {
+            Strategy::LeastConn => conn_opts.load.loads(&conn_opts.health),
+            _ => Vec::new(),
+        };
+        let token = balancer.next(BalanceCtx {
+            src_ip: &key_ip,
+            loads: &loads,
+        });
+        log::debug!("[tcp]select remote peer, token: {:?}", token);
 
-            // accept or deny connection, or select a remote peer.
-            #[cfg(not(feature = "balance"))]
-            {
-                hook::pre_connect_hook(&mut local, raddr.as_ref(), extra_raddrs.as_ref()).await?
+        // stick to the peer failed over to
+        match (conn_opts.affinity.get(&src_ip), token) {
+            (Some(idx), _) => {
+                log::debug!("[tcp]select remote peer by affinity: {}", idx);
+                idx
             }
+            (None, None) => 0,
+            (None, Some(Token(idx))) => idx as usize,
         }
+    };
 
-        #[cfg(feature = "balance")]
+    #[cfg(not(feature = "balance"))]
+    let raddr = {
+        // accept or deny connection, or select a remote peer.
+        #[cfg(feature = "hook")]
         {
-            use realm_lb::{Token, BalanceCtx};
-            let token = balancer.next(BalanceCtx {
-                src_ip: &local.peer_addr()?.ip(),
-            });
-            log::debug!("[tcp]select remote peer, token: {:?}", token);
-            match token {
-                None | Some(Token(0)) => raddr.as_ref(),
-                Some(Token(idx)) => &extra_raddrs.as_ref()[idx as usize - 1],
-            }
+            hook::pre_connect_hook(&mut local, raddr.as_ref(), extra_raddrs.as_ref()).await?
         }
 
-        #[cfg(not(any(feature = "hook", feature = "balance")))]
+        #[cfg(not(feature = "hook"))]
         raddr.as_ref()
     };
 
+    // a matched alpn overrides the peer chosen above
+    #[cfg(feature = "transport")]
+    let routed = match &hello {
+        Some(hello) if !alpn_routes.is_empty() => select_by_alpn(hello, alpn_routes),
+        _ => None,
+    };
+    #[cfg(not(feature = "transport"))]
+    let routed: Option<&RemoteAddr> = None;
+
+    // then the original destination port
+    let routed = match routed {
+        None if !port_routes.is_empty() => select_by_port(&local, port_routes)?,
+        x => x,
+    };
+
+    // then the client's geo info
+    #[cfg(feature = "geo")]
+    let routed = match (routed, geo_routes) {
+        (None, Some(geo)) => select_by_geo(&local, geo)?,
+        (x, _) => x,
+    };
+
     // connect!
-    let mut remote = socket::connect(raddr, conn_opts.as_ref()).await?;
-    log::info!("[tcp]{} => {} as {}", local.peer_addr()?, raddr, remote.peer_addr()?);
+    let mut timing = Timing::new(*slow_conn_threshold);
+
+    #[cfg(feature = "balance")]
+    let (raddr, mut remote, _load) = match routed {
+        Some(raddr) => (
+            raddr,
+            socket::connect(
+                raddr,
+                peer_opts(raddr, remotes, conn_opts.as_ref()),
+                conn_opts.as_ref(),
+                &mut timing,
+            )
+            .await?,
+            None,
+        ),
+        None => match connect_healthy(
+            &mut local,
+            peer,
+            raddr.as_ref(),
+            extra_raddrs.as_ref(),
+            conn_opts.as_ref(),
+            &mut timing,
+        )
+        .await?
+        {
+            Some((raddr, remote, load)) => (raddr, remote, Some(load)),
+            // maintenance response sent
+            None => return Ok(()),
+        },
+    };
+
+    #[cfg(not(feature = "balance"))]
+    let raddr = routed.unwrap_or(raddr);
+    #[cfg(not(feature = "balance"))]
+    let mut remote = socket::connect(
+        raddr,
+        peer_opts(raddr, remotes, conn_opts.as_ref()),
+        conn_opts.as_ref(),
+        &mut timing,
+    )
+    .await?;
+
+    let local_addr = socket::peer_addr(&local)?;
+    #[cfg(feature = "transport")]
+    let id = conn_opts.correlation.as_ref().map(|_| correlation::new_id());
+    #[cfg(feature = "transport")]
+    let tag = id.as_ref().map_or_else(String::new, |x| format!(", id={}", x));
+    #[cfg(not(feature = "transport"))]
+    let tag = "";
+    log::info!("[tcp]{} => {} as {}{}", local_addr, raddr, remote.peer_addr()?, tag);
+    timing.set_peer(local_addr, raddr);
 
     // after connected
     // ..
+    #[cfg(feature = "proxy")]
+    let proxy_opts = match peer_opts(raddr, remotes, conn_opts.as_ref()) {
+        Some(peer) => peer.proxy_opts(*proxy_opts),
+        None => *proxy_opts,
+    };
+
     #[cfg(feature = "proxy")]
     if proxy_opts.enabled() {
-        proxy::handle_proxy(&mut local, &mut remote, *proxy_opts).await?;
+        proxy::handle_proxy(&mut local, &mut remote, proxy_opts).await?;
+        timing.handshake_done();
     }
 
     // relay
@@ -89,21 +251,330 @@ pub async fn connect_and_relay(
         #[cfg(feature = "transport")]
         {
             if let Some((ac, cc)) = transport {
-                transport::run_relay(local, remote, ac, cc).await
+                let client = socket::peer_addr(&local)?;
+                // the peer may verify tls differently
+                let peer = peer_opts(raddr, remotes, conn_opts.as_ref());
+                let cc = peer.and_then(|x| x.transport.as_ref()).unwrap_or(cc);
+                if conn_opts.transport_fallback.is_empty() {
+                    transport::run_relay(
+                        local,
+                        remote,
+                        (ac, cc),
+                        conn_opts.as_ref(),
+                        activity,
+                        timing,
+                        client,
+                        id.as_deref(),
+                    )
+                    .await
+                } else {
+                    let fallback = Fallback {
+                        transports: std::iter::once(cc)
+                            .chain(&conn_opts.transport_fallback)
+                            .map(Ref::new)
+                            .collect(),
+                        raddr: Ref::new(raddr),
+                        peer_opts: peer.map(Ref::new),
+                        conn_opts,
+                        client,
+                    };
+                    transport::run_relay_fallback(local, remote, ac, &fallback, conn_opts.as_ref(), activity, timing)
+                        .await
+                }
             } else {
-                plain::run_relay(local, remote).await
+                timing.report();
+                plain::run_relay(local, remote, conn_opts.as_ref(), activity).await
             }
         }
         #[cfg(not(feature = "transport"))]
         {
-            plain::run_relay(local, remote).await
+            timing.report();
+            plain::run_relay(local, remote, conn_opts.as_ref(), activity).await
+        }
+    };
+
+    // dropped ones are logged by the caller
+    let with_remote = |reason, e: std::io::Error| {
+        let detail = format!("{}, remote={}", e, raddr);
+        dropped(reason, std::io::Error::new(e.kind(), detail))
+    };
+    match res {
+        // a hanging upstream, let later connections go elsewhere
+        Err(e) if RemoteSilent::is(&e) => {
+            #[cfg(feature = "balance")]
+            if let Some(idx) = peer_index(raddr, remotes) {
+                log::warn!("[tcp]mark {} as down", raddr);
+                conn_opts.health.mark_down(idx);
+            }
+            Err(with_remote(DropReason::RemoteSilent, e))
+        }
+        // both sides wait for each other
+        Err(e) if reason_of(&e) == Some(DropReason::Deadlock) => Err(with_remote(DropReason::Deadlock, e)),
+        // a ws peer sends more than allowed
+        #[cfg(feature = "transport")]
+        Err(e) if reason_of(&e) == Some(DropReason::FrameTooLarge) => Err(with_remote(DropReason::FrameTooLarge, e)),
+        // ignore relay error
+        Err(e) => {
+            log::debug!("[tcp]forward error: {}, ignored", e);
+            Ok(())
+        }
+        Ok(()) => Ok(()),
+    }
+}
+
+// index of the connected peer, if it is one of the endpoint's remotes
+fn peer_index(raddr: &RemoteAddr, (first, extra): (Ref<RemoteAddr>, Ref<Vec<RemoteAddr>>)) -> Option<usize> {
+    std::iter::once(first.as_ref())
+        .chain(extra.as_ref().iter())
+        .position(|x| std::ptr::eq(x, raddr))
+}
+
+// options of the connected peer, if it is one of the endpoint's remotes,
+// a routed peer takes those of a remote with the same address
+fn peer_opts<'a>(
+    raddr: &RemoteAddr,
+    remotes: (Ref<RemoteAddr>, Ref<Vec<RemoteAddr>>),
+    conn_opts: &'a ConnectOpts,
+) -> Option<&'a PeerOpts> {
+    let (first, extra) = remotes;
+    let idx = peer_index(raddr, remotes).or_else(|| {
+        std::iter::once(first.as_ref())
+            .chain(extra.as_ref().iter())
+            .position(|x| x == raddr)
+    })?;
+    conn_opts.peer_opts.get(idx)
+}
+
+#[cfg(feature = "balance")]
+async fn connect_healthy<'a>(
+    local: &mut TcpStream,
+    peer: usize,
+    raddr: &'a RemoteAddr,
+    extra_raddrs: &'a [RemoteAddr],
+    conn_opts: &ConnectOpts,
+    timing: &mut Timing,
+) -> Result<Option<(&'a RemoteAddr, TcpStream, LoadGuard)>> {
+    use std::io::{Error, ErrorKind};
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::time::{Instant, timeout};
+    use crate::health::UnhealthyPolicy;
+
+    let ConnectOpts {
+        health,
+        load,
+        unhealthy_policy,
+        affinity,
+        per_attempt_timeout,
+        connect_timeout,
+        ..
+    } = conn_opts;
+
+    let peer_addr = |idx: usize| if idx == 0 { raddr } else { &extra_raddrs[idx - 1] };
+    let total = extra_raddrs.len() + 1;
+
+    let peers: Vec<usize> = match health.pick(peer, total) {
+        // then the other healthy ones
+        Some(idx) if *per_attempt_timeout != 0 => (0..total)
+            .map(|i| (idx + i) % total)
+            .filter(|x| *x == idx || health.is_up(*x))
+            .collect(),
+        Some(idx) => vec![idx],
+        None => {
+            log::warn!("[tcp]no healthy remote peer, policy: {}", unhealthy_policy);
+            match unhealthy_policy {
+                UnhealthyPolicy::DropImmediately => {
+                    return Err(dropped(
+                        DropReason::NoHealthyRemote,
+                        Error::new(ErrorKind::ConnectionRefused, "no healthy remote peer"),
+                    ))
+                }
+                UnhealthyPolicy::TryAllAnyway => (0..total).map(|i| (peer + i) % total).collect(),
+                UnhealthyPolicy::Maintenance(resp) => {
+                    local.write_all(resp).await?;
+                    local.shutdown().await?;
+                    // close with unread data resets the response,
+                    // drain the request for a while
+                    let mut buf = [0u8; 1024];
+                    let drain = async { while local.read(&mut buf).await.is_ok_and(|n| n != 0) {} };
+                    let _ = crate::time::timeoutfut(drain, 1).await;
+                    return Ok(None);
+                }
+            }
         }
     };
 
-    // ignore relay error
-    if let Err(e) = res {
-        log::debug!("[tcp]forward error: {}, ignored", e);
+    // the tries share one connect timeout, a single one is bounded by socket::connect
+    let deadline = match *connect_timeout {
+        n if n != 0 && peers.len() > 1 => Some(Instant::now() + Duration::from_secs(n as u64)),
+        _ => None,
+    };
+    let attempt = match *per_attempt_timeout {
+        0 => None,
+        n => Some(Duration::from_secs(n as u64)),
+    };
+
+    let mut last_err = None;
+    for idx in peers {
+        let left = deadline.map(|x| x.saturating_duration_since(Instant::now()));
+        let limit = match (attempt, left) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        if limit.is_some_and(|x| x.is_zero()) {
+            log::warn!("[tcp]not connected in {}s, give up the rest", connect_timeout);
+            last_err = Some(Error::new(
+                ErrorKind::TimedOut,
+                format!("not connected in {}s", connect_timeout),
+            ));
+            break;
+        }
+        // counted before connected, so that the next pick sees it
+        let guard = load.open(idx);
+        let connect = socket::connect(peer_addr(idx), conn_opts.peer_opts.get(idx), conn_opts, timing);
+        let res = match limit {
+            None => connect.await,
+            Some(x) => timeout(x, connect).await.unwrap_or_else(|_| {
+                Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!("not connected in {}ms", x.as_millis()),
+                ))
+            }),
+        };
+        match res {
+            Ok(remote) => {
+                health.mark_up(idx);
+                if idx != peer {
+                    affinity.set(socket::peer_addr(local)?.ip(), idx);
+                }
+                return Ok(Some((peer_addr(idx), remote, guard)));
+            }
+            Err(e) => {
+                log::warn!("[tcp]connect to {} failed: {}, mark as down", peer_addr(idx), e);
+                health.mark_down(idx);
+                last_err = Some(e);
+            }
+        }
     }
 
-    Ok(())
+    Err(last_err.unwrap())
+}
+
+async fn wait_first_byte(local: &TcpStream, timeout: usize) -> Result<()> {
+    use std::io::{Error, ErrorKind};
+    use crate::time::timeoutfut;
+
+    let mut buf = [0u8; 1];
+    match timeoutfut(local.peek(&mut buf), timeout).await {
+        Ok(res) => res.map(|_| ()),
+        Err(_) => Err(dropped(
+            DropReason::ClientSilent,
+            Error::new(ErrorKind::TimedOut, format!("no data from client in {}s", timeout)),
+        )),
+    }
+}
+
+// without a handshake timeout, a peek before the handshake is still bounded
+#[cfg(any(feature = "balance", feature = "transport"))]
+const PEEK_TIMEOUT: usize = 10;
+
+/// How long a peek before the handshake may take,
+/// a stalled client must not hold the connection forever.
+#[cfg(any(feature = "balance", feature = "transport"))]
+fn peek_timeout(conn_opts: &ConnectOpts) -> usize {
+    match conn_opts.handshake_timeout {
+        0 => PEEK_TIMEOUT,
+        n => n,
+    }
+}
+
+#[cfg(feature = "transport")]
+async fn peek_hello(local: &TcpStream, conn_opts: &ConnectOpts) -> Result<Option<Vec<u8>>> {
+    use std::io::{Error, ErrorKind};
+    use crate::time::timeoutfut;
+
+    let timeout = peek_timeout(conn_opts);
+    match timeoutfut(hello::peek_record(local), timeout).await {
+        Ok(res) => res,
+        // counted as no server name, only if that is allowed
+        Err(_) if !conn_opts.sni_allowlist.is_empty() && conn_opts.allow_missing_sni => {
+            log::debug!("[tcp]no client hello in {}s, as if no server name", timeout);
+            Ok(None)
+        }
+        Err(_) => Err(dropped(
+            DropReason::HandshakeTimeout,
+            Error::new(ErrorKind::TimedOut, format!("no client hello in {}s", timeout)),
+        )),
+    }
+}
+
+/// The request head, None if it is not there in time.
+#[cfg(any(feature = "balance", feature = "transport"))]
+async fn peek_head(local: &TcpStream, conn_opts: &ConnectOpts) -> Result<Option<Vec<u8>>> {
+    use crate::time::timeoutfut;
+
+    let timeout = peek_timeout(conn_opts);
+    match timeoutfut(request::peek_head(local), timeout).await {
+        Ok(res) => res,
+        Err(_) => {
+            log::debug!("[tcp]no request head in {}s", timeout);
+            Ok(None)
+        }
+    }
+}
+
+fn select_by_port<'a>(local: &TcpStream, routes: &'a [(u16, RemoteAddr)]) -> Result<Option<&'a RemoteAddr>> {
+    let port = socket::original_dst(local)?.port();
+    let raddr = routes.iter().find(|(x, _)| *x == port).map(|(_, r)| r);
+
+    log::debug!("[tcp]select remote peer by port {}: {:?}", port, raddr);
+    Ok(raddr)
+}
+
+#[cfg(feature = "geo")]
+fn select_by_geo<'a>(local: &TcpStream, geo: &'a crate::geo::GeoRoutes) -> Result<Option<&'a RemoteAddr>> {
+    let ip = socket::peer_addr(local)?.ip();
+    let raddr = geo.select(&ip);
+
+    log::debug!("[tcp]select remote peer by geo of {}: {:?}", ip, raddr);
+    Ok(raddr)
+}
+
+#[cfg(feature = "transport")]
+fn accept_tls(ac: &kaminari::mix::MixAccept) -> bool {
+    ac.as_tls().is_some() || ac.as_wss().is_some()
+}
+
+#[cfg(feature = "transport")]
+fn select_by_alpn<'a>(hello: &hello::ClientHello, routes: &'a [(String, RemoteAddr)]) -> Option<&'a RemoteAddr> {
+    // follow the order of the routes, as a tls server does with its own preference
+    let raddr = routes
+        .iter()
+        .find(|(x, _)| hello.alpn.contains(&x.as_bytes()))
+        .map(|(_, r)| r);
+
+    log::debug!("[tcp]select remote peer by alpn: {:?}", raddr);
+    raddr
+}
+
+#[cfg(feature = "transport")]
+fn check_sni(sni: Option<&str>, allowlist: &[String], allow_missing: bool) -> Result<()> {
+    use std::io::{Error, ErrorKind};
+
+    let allowed = match sni {
+        Some(sni) => hello::sni_allowed(sni, allowlist),
+        None => allow_missing,
+    };
+    if allowed {
+        return Ok(());
+    }
+
+    let msg = match sni {
+        Some(sni) => format!("server name {} is not allowed", sni),
+        None => String::from("no server name"),
+    };
+    Err(dropped(
+        DropReason::SniDenied,
+        Error::new(ErrorKind::PermissionDenied, msg),
+    ))
 }
@@ -3,6 +3,14 @@
 mod socket;
 mod middle;
 mod plain;
+mod coalesce;
+mod counter;
+mod limit;
+mod silent;
+mod deadlock;
+mod dropped;
+mod timing;
+mod idle;
 
 #[cfg(feature = "hook")]
 mod hook;
@@ -13,31 +21,93 @@ mod proxy;
 #[cfg(feature = "transport")]
 mod transport;
 
+#[cfg(feature = "transport")]
+mod hello;
+
+#[cfg(feature = "transport")]
+mod correlation;
+
+#[cfg(feature = "transport")]
+mod fallback;
+
+#[cfg(feature = "transport")]
+mod ws;
+
+#[cfg(any(feature = "balance", feature = "transport"))]
+mod request;
+
+#[cfg(feature = "trace")]
+mod trace;
+
+#[cfg(feature = "compress")]
+mod compress;
+
 use std::io::{ErrorKind, Result};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::net::TcpListener;
+use tokio::time::{sleep, sleep_until, Instant};
 
 use crate::trick::Ref;
 use crate::endpoint::Endpoint;
 
 use middle::connect_and_relay;
+use idle::Pruner;
+use dropped::{drop_connection, reason_of};
+
+pub use dropped::{DropReason, PreClose};
 
 /// Launch a tcp relay.
 pub async fn run_tcp(endpoint: Endpoint) -> Result<()> {
+    let lis = bind(&endpoint).unwrap_or_else(|e| panic!("[tcp]failed to bind {}: {}", &endpoint.laddr, e));
+    run_tcp_with(lis, endpoint).await
+}
+
+/// Bind the listener of a tcp relay.
+///
+/// The listener may be cloned, so that another runtime
+/// can take over the relay, see [`run_tcp_with`].
+pub fn bind(endpoint: &Endpoint) -> Result<std::net::TcpListener> {
+    socket::bind(&endpoint.laddr, endpoint.bind_opts.clone())
+}
+
+/// Launch a tcp relay on a bound listener.
+///
+/// Connections keep the endpoint alive, so they go on
+/// after this future is dropped, e.g. to stop accepting.
+pub async fn run_tcp_with(lis: std::net::TcpListener, endpoint: Endpoint) -> Result<()> {
+    let endpoint = Arc::new(endpoint);
     let Endpoint {
         laddr,
         raddr,
-        bind_opts,
         conn_opts,
         extra_raddrs,
-    } = endpoint;
+        ..
+    } = endpoint.as_ref();
 
-    let raddr = Ref::new(&raddr);
-    let conn_opts = Ref::new(&conn_opts);
-    let extra_raddrs = Ref::new(&extra_raddrs);
+    let laddr = *laddr;
+    let raddr = Ref::new(raddr);
+    let conn_opts = Ref::new(conn_opts);
+    let extra_raddrs = Ref::new(extra_raddrs);
 
-    let lis = socket::bind(&laddr, bind_opts).unwrap_or_else(|e| panic!("[tcp]failed to bind {}: {}", &laddr, e));
+    let lis = TcpListener::from_std(lis)?;
     let keepalive = socket::keepalive::build(&conn_opts);
+    // held by the connections as well, which are still swept after the listener stops
+    let pruner = Arc::new(Pruner::spawn(&conn_opts));
+
+    // evenly spaced, the rest wait in the backlog
+    let pace = match conn_opts.accept_rate {
+        0 => None,
+        x => Some(Duration::from_nanos(1_000_000_000 / x as u64)),
+    };
+    let mut next = Instant::now();
 
     loop {
+        if pace.is_some() {
+            sleep_until(next).await;
+        }
+
         let (local, addr) = match lis.accept().await {
             Ok(x) => x,
             Err(e) if e.kind() == ErrorKind::ConnectionAborted => {
@@ -49,6 +119,11 @@ pub async fn run_tcp(endpoint: Endpoint) -> Result<()> {
                 break;
             }
         };
+        if let Some(pace) = pace {
+            next = Instant::now() + pace;
+        }
+        // ipv4 clients of a dual-stack listener
+        let addr = socket::canonical(addr);
 
         // ignore error
         let _ = local.set_nodelay(true);
@@ -57,13 +132,63 @@ pub async fn run_tcp(endpoint: Endpoint) -> Result<()> {
             use socket::keepalive::SockRef;
             SockRef::from(&local).set_tcp_keepalive(kpa)?;
         }
+        // set linger
+        if let Some(linger) = conn_opts.linger {
+            use socket::keepalive::SockRef;
+            SockRef::from(&local).set_linger(Some(Duration::from_secs(linger as u64)))?;
+        }
 
-        tokio::spawn(async move {
-            match connect_and_relay(local, raddr, conn_opts, extra_raddrs).await {
+        let id = conn_opts
+            .conns
+            .register(addr, local.local_addr().map_or(laddr, socket::canonical));
+        let endpoint = endpoint.clone();
+        let pruner = pruner.clone();
+        let task = tokio::spawn(async move {
+            // the refs point into it
+            let _endpoint = endpoint;
+            let _pruner = pruner;
+            let tracked = conn_opts.conns.track(id);
+            // queued before counted as active
+            let _slot = match conn_opts.conn_limit.acquire().await {
+                Ok(x) => x,
+                Err(e) => {
+                    let reason = match e.kind() {
+                        ErrorKind::TimedOut => DropReason::QueueTimeout,
+                        _ => DropReason::MaxConns,
+                    };
+                    conn_opts.pre_close.notify(addr, reason);
+                    drop_connection(reason, addr, &e);
+                    return;
+                }
+            };
+            // the relay closes its own, this one is closed after notified
+            let _keep = match conn_opts.pre_close.is_set() {
+                true => socket::keepalive::SockRef::from(&local).try_clone().ok(),
+                false => None,
+            };
+            let _conn = conn_opts.stat.open();
+            let time = SystemTime::now();
+            if conn_opts.access_log.is_some() {
+                tracked.activity().watch_http();
+            }
+            if conn_opts.accept_delay != 0 {
+                sleep(Duration::from_millis(conn_opts.accept_delay as u64)).await;
+            }
+            match connect_and_relay(local, raddr, conn_opts, extra_raddrs, tracked.activity()).await {
                 Ok(..) => log::debug!("[tcp]{} => {}, finish", addr, raddr.as_ref()),
-                Err(e) => log::error!("[tcp]{} => {}, error: {}", addr, raddr.as_ref(), e),
+                Err(e) => match reason_of(&e) {
+                    Some(reason) => {
+                        conn_opts.pre_close.notify(addr, reason);
+                        drop_connection(reason, addr, &e)
+                    }
+                    None => log::error!("[tcp]{} => {}, error: {}", addr, raddr.as_ref(), e),
+                },
+            }
+            if let (Some(format), Some(http)) = (conn_opts.access_log, tracked.activity().http()) {
+                log::info!(target: crate::access::TARGET, "{}", http.line(format, addr.ip(), time));
             }
         });
+        conn_opts.conns.set_task(id, task);
     }
 
     Ok(())
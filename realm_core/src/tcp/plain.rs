@@ -1,8 +1,67 @@
 use std::io::Result;
+use std::time::Duration;
 use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use super::coalesce::CoalesceStream;
+use super::counter::CountStream;
+use super::limit::LimitStream;
+use super::silent::SilentStream;
+use super::deadlock;
+#[cfg(feature = "trace")]
+use super::socket;
+#[cfg(feature = "trace")]
+use super::trace::TraceStream;
+#[cfg(feature = "compress")]
+use super::compress::CompressStream;
+use crate::endpoint::ConnectOpts;
+use crate::registry::Activity;
 
 #[inline]
-pub async fn run_relay(mut local: TcpStream, mut remote: TcpStream) -> Result<()> {
+pub async fn run_relay(
+    local: TcpStream,
+    remote: TcpStream,
+    conn_opts: &ConnectOpts,
+    activity: &Activity,
+) -> Result<()> {
+    let mut remote = SilentStream::new(remote, conn_opts.remote_first_byte_timeout);
+
+    // bytes are inspected in userspace, which rules out zero copy
+    #[cfg(feature = "trace")]
+    if let Some(tracer) = &conn_opts.tracer {
+        let client = socket::peer_addr(&local)?;
+        let local = CountStream::new(local, &conn_opts.stat, activity);
+        let local = LimitStream::new(local, &conn_opts.rate_limit);
+        let local = TraceStream::new(local, tracer, client);
+        return copy(local, remote, conn_opts).await;
+    }
+
+    let local = CountStream::new(local, &conn_opts.stat, activity);
+    let mut local = LimitStream::new(local, &conn_opts.rate_limit);
+
+    // bytes are (de)compressed in userspace, which rules out zero copy
+    #[cfg(feature = "compress")]
+    {
+        use crate::endpoint::Compress;
+        match conn_opts.compress {
+            Some(Compress::Listen(level)) => {
+                return copy(CompressStream::new(local, level), remote, conn_opts).await;
+            }
+            Some(Compress::Remote(level)) => {
+                return copy(local, CompressStream::new(remote, level), conn_opts).await;
+            }
+            None => {}
+        }
+    }
+
+    // writes are merged, or the first chunks are sniffed for the
+    // access log in userspace, which rules out zero copy
+    if conn_opts.coalesce_size != 0 || activity.http().is_some() {
+        return copy(local, remote, conn_opts).await;
+    }
+
+    deadlock::wait_either(&mut local, &mut remote, conn_opts).await?;
+
     #[cfg(target_os = "linux")]
     {
         use std::io::ErrorKind;
@@ -20,3 +79,17 @@ pub async fn run_relay(mut local: TcpStream, mut remote: TcpStream) -> Result<()
         realm_io::bidi_copy(&mut local, &mut remote).await.map(|_| ())
     }
 }
+
+// userspace copy, size = 0 passes through
+async fn copy<S, R>(mut local: S, mut remote: R, conn_opts: &ConnectOpts) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    R: AsyncRead + AsyncWrite + Unpin,
+{
+    deadlock::wait_either(&mut local, &mut remote, conn_opts).await?;
+
+    let delay = Duration::from_millis(conn_opts.coalesce_delay as u64);
+    let mut local = CoalesceStream::new(local, conn_opts.coalesce_size, delay);
+    let mut remote = CoalesceStream::new(remote, conn_opts.coalesce_size, delay);
+    realm_io::bidi_copy(&mut local, &mut remote).await.map(|_| ())
+}
@@ -1,6 +1,7 @@
 use std::io::{Error, ErrorKind, Result};
 use std::mem::MaybeUninit;
 use std::net::{SocketAddr, IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
 
 use log::{info, debug};
 use bytes::{BytesMut, Buf};
@@ -9,9 +10,12 @@ use proxy_protocol::ProxyHeader;
 use proxy_protocol::{version1 as v1, version2 as v2};
 use proxy_protocol::{encode, parse};
 
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, Interest};
 use tokio::net::TcpStream;
+use tokio::time::sleep;
 
+use super::socket;
+use super::dropped::{DropReason, dropped};
 use crate::endpoint::ProxyOpts;
 use crate::time::timeoutfut;
 
@@ -38,23 +42,28 @@ pub async fn handle_proxy(src: &mut TcpStream, dst: &mut TcpStream, opts: ProxyO
     // parse PROXY header from client and write log
     // may not get src and dst addr
     if accept_proxy {
-        let buf = buf.write(BytesMut::with_capacity(256));
-        buf.resize(256, 0);
-
-        // FIXME: may not read the entire header
-
         // The receiver may apply a short timeout and decide to
         // abort the connection if the protocol header is not seen
         // within a few seconds (at least 3 seconds to cover a TCP retransmit).
-        let peek_n = timeoutfut(src.peek(buf), accept_proxy_timeout).await??;
-
-        buf.truncate(peek_n);
+        let buf = buf.write(match timeoutfut(peek_header(src), accept_proxy_timeout).await {
+            Ok(res) => res?,
+            Err(_) => {
+                return Err(dropped(
+                    DropReason::ProxyTimeout,
+                    Error::new(
+                        ErrorKind::TimedOut,
+                        format!("proxy-protocol header not completed in {}s", accept_proxy_timeout),
+                    ),
+                ))
+            }
+        });
+        let peek_n = buf.len();
         debug!("[tcp]peek initial {} bytes: {:#x}", peek_n, buf);
 
         let mut slice = buf.as_ref();
 
         // slice is advanced
-        let header = parse(&mut slice).map_err(|e| Error::new(ErrorKind::Other, e))?;
+        let header = parse(&mut slice).map_err(|e| malformed(e.to_string()))?;
         let parsed_n = peek_n - slice.remaining();
         debug!("[tcp]proxy-protocol parsed, {} bytes", parsed_n);
 
@@ -77,7 +86,7 @@ pub async fn handle_proxy(src: &mut TcpStream, dst: &mut TcpStream, opts: ProxyO
 
     // use real addr
     if !fwd_hdr {
-        client_addr.write(src.peer_addr()?);
+        client_addr.write(socket::peer_addr(src)?);
         // FIXME: what is the dst addr here? seems not defined in the doc
         // the doc only mentions that this field is similar to X-Origin-To
         // which is seldom used
@@ -101,6 +110,80 @@ pub async fn handle_proxy(src: &mut TcpStream, dst: &mut TcpStream, opts: ProxyO
     Ok(())
 }
 
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_HEADER: usize = 16;
+
+fn malformed(msg: String) -> Error {
+    dropped(
+        DropReason::ProxyMalformed,
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("malformed proxy-protocol header: {}", msg),
+        ),
+    )
+}
+
+// length of a complete header, None if more bytes are needed
+fn header_len(buf: &[u8]) -> Result<Option<usize>> {
+    let n = std::cmp::min(buf.len(), V2_SIGNATURE.len());
+    if buf[..n] == V2_SIGNATURE[..n] {
+        if buf.len() < V2_HEADER {
+            return Ok(None);
+        }
+        let len = V2_HEADER + u16::from_be_bytes([buf[14], buf[15]]) as usize;
+        return Ok((buf.len() >= len).then_some(len));
+    }
+
+    let n = std::cmp::min(buf.len(), V1_PREFIX.len());
+    if buf[..n] == V1_PREFIX[..n] {
+        let end = buf.windows(2).position(|x| x == b"\r\n");
+        return match end {
+            Some(x) if x + 2 <= V1_MAX => Ok(Some(x + 2)),
+            None if buf.len() < V1_MAX => Ok(None),
+            _ => Err(malformed(format!("v1 header longer than {} bytes", V1_MAX))),
+        };
+    }
+
+    Err(malformed(String::from("unknown signature")))
+}
+
+// peek until the header is complete, without consuming it
+async fn peek_header(src: &TcpStream) -> Result<BytesMut> {
+    let mut buf = BytesMut::new();
+    buf.resize(256, 0);
+    let mut last = 0;
+
+    loop {
+        let n = src.peek(&mut buf).await?;
+        if n == 0 {
+            return Err(malformed(format!("truncated after {} bytes", last)));
+        }
+
+        match header_len(&buf[..n])? {
+            Some(len) => {
+                buf.truncate(len);
+                return Ok(buf);
+            }
+            // a v2 header may carry more than the buffer
+            None if n == buf.len() => buf.resize(V2_HEADER + u16::MAX as usize, 0),
+            None => {}
+        }
+
+        // peek returns at once while there are unread bytes,
+        // wait a little for the rest of the header
+        if n == last {
+            // closed after a partial header
+            if src.ready(Interest::READABLE).await?.is_read_closed() {
+                return Err(malformed(format!("truncated after {} bytes", n)));
+            }
+            sleep(Duration::from_millis(5)).await;
+        }
+        last = n;
+    }
+}
+
 macro_rules! unpack {
     ($addr: expr, sin4) => {
         match $addr {
@@ -0,0 +1,87 @@
+//! HTTP request sniffing.
+//!
+//! Like the ClientHello, the request head of a plain http or ws
+//! client is read in advance without consuming it, so that a header
+//! or cookie can be hashed by the balancer instead of the client's ip,
+//! and a ws upgrade request can be written to the access log.
+
+use std::io::Result;
+#[cfg(feature = "balance")]
+use std::net::{IpAddr, Ipv6Addr};
+use std::time::Duration;
+
+use tokio::io::Interest;
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+
+#[cfg(feature = "balance")]
+use crate::endpoint::HashKey;
+
+const MAX_HEAD: usize = 0x2000;
+
+/// Peek the request head up to the blank line,
+/// return None if it is closed or too large before that.
+pub async fn peek_head(local: &TcpStream) -> Result<Option<Vec<u8>>> {
+    let mut buf = vec![0u8; MAX_HEAD];
+    let mut last = 0;
+
+    loop {
+        let n = local.peek(&mut buf).await?;
+
+        if let Some(pos) = buf[..n].windows(4).position(|x| x == b"\r\n\r\n") {
+            buf.truncate(pos + 4);
+            return Ok(Some(buf));
+        }
+        if n == 0 || n == buf.len() {
+            return Ok(None);
+        }
+
+        // peek returns at once while there are unread bytes,
+        // wait a little for the rest of the head
+        if n == last {
+            if local.ready(Interest::READABLE).await?.is_read_closed() {
+                return Ok(None);
+            }
+            sleep(Duration::from_millis(5)).await;
+        }
+        last = n;
+    }
+}
+
+/// The value of a hash key in a request head, the first one wins.
+#[cfg(feature = "balance")]
+pub fn hash_value<'a>(head: &'a [u8], key: &HashKey) -> Option<&'a [u8]> {
+    let mut headers = head.split(|x| *x == b'\n').skip(1).filter_map(|line| {
+        let pos = line.iter().position(|x| *x == b':')?;
+        let (name, value) = line.split_at(pos);
+        Some((name, value[1..].trim_ascii()))
+    });
+
+    match key {
+        HashKey::Source => None,
+        HashKey::Header(name) => headers
+            .find(|(x, _)| x.eq_ignore_ascii_case(name.as_bytes()))
+            .map(|(_, value)| value),
+        HashKey::Cookie(name) => headers
+            .filter(|(x, _)| x.eq_ignore_ascii_case(b"cookie"))
+            .flat_map(|(_, value)| value.split(|x| *x == b';'))
+            .find_map(|pair| {
+                let pos = pair.iter().position(|x| *x == b'=')?;
+                let (k, v) = pair.split_at(pos);
+                (k.trim_ascii() == name.as_bytes()).then(|| v[1..].trim_ascii())
+            }),
+    }
+}
+
+/// An address that stands for a value, so that it can be
+/// hashed onto the same ring as client ips.
+#[cfg(feature = "balance")]
+pub fn hash_ip(value: &[u8]) -> IpAddr {
+    // fnv-1a
+    let mut h: u64 = 0xcbf29ce484222325;
+    for x in value {
+        h ^= *x as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    IpAddr::V6(Ipv6Addr::from((h as u128) << 64 | h.rotate_left(32) as u128))
+}
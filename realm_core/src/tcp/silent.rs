@@ -0,0 +1,159 @@
+//! Silent remote detection.
+//!
+//! Wraps the remote side stream, reads from it fail if nothing
+//! arrives within a window once the relay starts.
+//! Raw io is forwarded as well, which keeps zero copy available.
+
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::io::{Error, ErrorKind, Result};
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{sleep, Sleep};
+
+/// Marks the error of a remote peer which has sent nothing.
+#[derive(Debug)]
+pub struct RemoteSilent(usize);
+
+impl Display for RemoteSilent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no data from remote in {}s", self.0)
+    }
+}
+
+impl std::error::Error for RemoteSilent {}
+
+impl RemoteSilent {
+    pub fn is(e: &Error) -> bool {
+        e.get_ref().is_some_and(|x| x.is::<RemoteSilent>())
+    }
+}
+
+/// A wrapper that fails reads until the first byte, once the window is over.
+pub struct SilentStream<S> {
+    io: S,
+    timeout: usize,
+    // None after the first byte
+    deadline: Mutex<Option<Pin<Box<Sleep>>>>,
+}
+
+impl<S> SilentStream<S> {
+    /// A zero timeout never expires.
+    pub fn new(io: S, timeout: usize) -> Self {
+        let deadline = (timeout != 0).then(|| Box::pin(sleep(Duration::from_secs(timeout as u64))));
+        Self {
+            io,
+            timeout,
+            deadline: Mutex::new(deadline),
+        }
+    }
+
+    // called while the read is pending
+    fn poll_deadline(&self, cx: &mut Context<'_>) -> Result<()> {
+        let mut deadline = self.deadline.lock().unwrap();
+        match deadline.as_mut().map(|x| x.as_mut().poll(cx)) {
+            Some(Poll::Ready(())) => Err(Error::new(ErrorKind::TimedOut, RemoteSilent(self.timeout))),
+            _ => Ok(()),
+        }
+    }
+
+    fn on_read(&self, n: usize) {
+        if n != 0 {
+            *self.deadline.lock().unwrap() = None;
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for SilentStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        match Pin::new(&mut this.io).poll_read(cx, buf) {
+            Poll::Pending => this
+                .poll_deadline(cx)
+                .map_or_else(|e| Poll::Ready(Err(e)), |_| Poll::Pending),
+            res => {
+                this.on_read(buf.filled().len() - filled);
+                res
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for SilentStream<S> {
+    #[inline]
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, data: &[u8]) -> Poll<Result<usize>> {
+        Pin::new(&mut self.get_mut().io).poll_write(cx, data)
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod raw {
+    use super::*;
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use tokio::io::Interest;
+    use realm_io::AsyncRawIO;
+
+    impl<S: AsRawFd> AsRawFd for SilentStream<S> {
+        #[inline]
+        fn as_raw_fd(&self) -> RawFd {
+            self.io.as_raw_fd()
+        }
+    }
+
+    impl<S: AsyncRawIO> AsyncRawIO for SilentStream<S> {
+        #[inline]
+        fn x_poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+            self.io.x_poll_read_ready(cx)
+        }
+
+        #[inline]
+        fn x_poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+            self.io.x_poll_write_ready(cx)
+        }
+
+        #[inline]
+        fn x_try_io<R>(&self, interest: Interest, f: impl FnOnce() -> Result<R>) -> Result<R> {
+            self.io.x_try_io(interest, f)
+        }
+
+        fn poll_read_raw<F>(&self, cx: &mut Context<'_>, syscall: F) -> Poll<Result<usize>>
+        where
+            F: FnMut() -> isize,
+        {
+            match self.io.poll_read_raw(cx, syscall) {
+                Poll::Pending => self
+                    .poll_deadline(cx)
+                    .map_or_else(|e| Poll::Ready(Err(e)), |_| Poll::Pending),
+                res => {
+                    if let Poll::Ready(Ok(n)) = res {
+                        self.on_read(n);
+                    }
+                    res
+                }
+            }
+        }
+
+        #[inline]
+        fn poll_write_raw<F>(&self, cx: &mut Context<'_>, syscall: F) -> Poll<Result<usize>>
+        where
+            F: FnMut() -> isize,
+        {
+            self.io.poll_write_raw(cx, syscall)
+        }
+    }
+}
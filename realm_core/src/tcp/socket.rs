@@ -3,14 +3,20 @@ use std::net::SocketAddr;
 use std::time::Duration;
 
 use realm_syscall::new_tcp_socket;
-use tokio::net::{TcpSocket, TcpStream, TcpListener};
+use tokio::net::{TcpSocket, TcpStream};
 
-use crate::dns::resolve_addr;
+use crate::dns::resolve_addr_with;
 use crate::time::timeoutfut;
-use crate::endpoint::{RemoteAddr, BindOpts, ConnectOpts};
+use crate::endpoint::{RemoteAddr, BindOpts, ConnectOpts, PeerOpts};
 
-pub fn bind(laddr: &SocketAddr, bind_opts: BindOpts) -> Result<TcpListener> {
-    let BindOpts { ipv6_only } = bind_opts;
+use super::timing::Timing;
+
+pub fn bind(laddr: &SocketAddr, bind_opts: BindOpts) -> Result<std::net::TcpListener> {
+    let BindOpts {
+        ipv6_only,
+        #[cfg(target_os = "linux")]
+        congestion,
+    } = bind_opts;
     let socket = new_tcp_socket(laddr)?;
 
     // ipv6_only
@@ -18,29 +24,106 @@ pub fn bind(laddr: &SocketAddr, bind_opts: BindOpts) -> Result<TcpListener> {
         socket.set_only_v6(ipv6_only)?;
     }
 
+    #[cfg(target_os = "linux")]
+    if let Some(algo) = congestion {
+        set_congestion(&socket, &algo)?;
+    }
+
     // ignore error
     let _ = socket.set_reuse_address(true);
 
     socket.bind(&(*laddr).into())?;
     socket.listen(1024)?;
 
-    TcpListener::from_std(socket.into())
+    Ok(socket.into())
+}
+
+/// Set TCP_CONGESTION of a tcp socket.
+#[cfg(target_os = "linux")]
+fn set_congestion<T: std::os::unix::io::AsRawFd>(socket: &T, algo: &str) -> Result<()> {
+    let algo = algo.as_bytes();
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_CONGESTION,
+            algo.as_ptr() as *const libc::c_void,
+            algo.len() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(())
+    }
 }
 
-pub async fn connect(raddr: &RemoteAddr, conn_opts: &ConnectOpts) -> Result<TcpStream> {
+/// Unwrap an ipv4-mapped ipv6 address, which a dual-stack listener
+/// reports for ipv4 clients, so that they look the same everywhere.
+pub fn canonical(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(x) => match x.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::new(ip.into(), x.port()),
+            None => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+/// Canonical address of the client, see [`canonical`].
+pub fn peer_addr(local: &TcpStream) -> Result<SocketAddr> {
+    local.peer_addr().map(canonical)
+}
+
+/// Original destination of a redirected connection.
+/// Fall back to the local address if not redirected.
+pub fn original_dst(local: &TcpStream) -> Result<SocketAddr> {
+    #[cfg(target_os = "linux")]
+    {
+        use realm_syscall::socket2::SockRef;
+        let sock = SockRef::from(local);
+        let dst = match local.local_addr()? {
+            SocketAddr::V4(_) => sock.original_dst(),
+            SocketAddr::V6(_) => sock.original_dst_ipv6(),
+        };
+        if let Some(addr) = dst.ok().and_then(|x| x.as_socket()) {
+            return Ok(addr);
+        }
+    }
+    local.local_addr()
+}
+
+/// Options of the remote peer, if any, override the endpoint's.
+pub async fn connect(
+    raddr: &RemoteAddr,
+    peer_opts: Option<&PeerOpts>,
+    conn_opts: &ConnectOpts,
+    timing: &mut Timing,
+) -> Result<TcpStream> {
     let ConnectOpts {
         connect_timeout,
         bind_address,
 
         #[cfg(target_os = "linux")]
         bind_interface,
+
+        resolver,
         ..
     } = conn_opts;
 
+    let bind_address = peer_opts.and_then(|x| x.bind_address).or(*bind_address);
+    #[cfg(target_os = "linux")]
+    let bind_interface = peer_opts
+        .and_then(|x| x.bind_interface.as_ref())
+        .or(bind_interface.as_ref());
+
     let mut last_err = None;
     let keepalive = keepalive::build(conn_opts);
 
-    for addr in resolve_addr(raddr).await?.iter() {
+    let addrs = resolve_addr_with(raddr, resolver.as_deref()).await?;
+    timing.dns_done();
+
+    for addr in addrs.iter() {
         log::debug!("[tcp]{} resolved as {}", raddr, &addr);
 
         let socket = new_tcp_socket(&addr)?;
@@ -49,7 +132,7 @@ pub async fn connect(raddr: &RemoteAddr, conn_opts: &ConnectOpts) -> Result<TcpS
         let _ = socket.set_nodelay(true);
         let _ = socket.set_reuse_address(true);
 
-        if let Some(addr) = *bind_address {
+        if let Some(addr) = bind_address {
             socket.bind(&addr.into())?;
         }
 
@@ -62,11 +145,21 @@ pub async fn connect(raddr: &RemoteAddr, conn_opts: &ConnectOpts) -> Result<TcpS
             socket.set_tcp_keepalive(kpa)?;
         }
 
+        if let Some(linger) = conn_opts.linger {
+            socket.set_linger(Some(Duration::from_secs(linger as u64)))?;
+        }
+
+        #[cfg(target_os = "linux")]
+        if let Some(algo) = &conn_opts.congestion {
+            set_congestion(&socket, algo)?;
+        }
+
         let socket = TcpSocket::from_std_stream(socket.into());
 
         match timeoutfut(socket.connect(addr), *connect_timeout).await {
             Ok(Ok(stream)) => {
                 log::debug!("[tcp]connect to {} as {}", raddr, &addr,);
+                timing.connect_done();
                 return Ok(stream);
             }
             Ok(Err(e)) => {
@@ -77,6 +170,7 @@ pub async fn connect(raddr: &RemoteAddr, conn_opts: &ConnectOpts) -> Result<TcpS
         }
     }
 
+    timing.connect_done();
     Err(last_err.unwrap_or_else(|| Error::new(ErrorKind::InvalidInput, "could not connect to any address")))
 }
 
@@ -86,6 +180,7 @@ pub(super) mod keepalive {
     pub fn build(conn_opts: &ConnectOpts) -> Option<TcpKeepalive> {
         let ConnectOpts {
             tcp_keepalive,
+            tcp_keepalive_interval,
             tcp_keepalive_probe,
             ..
         } = conn_opts;
@@ -96,7 +191,11 @@ pub(super) mod keepalive {
         let mut kpa = TcpKeepalive::new().with_time(secs);
         #[cfg(not(target_os = "openbsd"))]
         {
-            kpa = TcpKeepalive::with_interval(kpa, secs);
+            let intv = match *tcp_keepalive_interval {
+                0 => secs,
+                x => Duration::from_secs(x as u64),
+            };
+            kpa = TcpKeepalive::with_interval(kpa, intv);
         }
         #[cfg(not(any(target_os = "openbsd", target_os = "windows")))]
         {
@@ -0,0 +1,82 @@
+//! Connection setup timing.
+//!
+//! Setup starts when the remote peer is about to be dialed, and ends
+//! once all handshakes are done. Setups slower than the threshold are
+//! logged with a breakdown.
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::endpoint::RemoteAddr;
+
+pub struct Timing {
+    threshold: Duration,
+    start: Instant,
+    last: Instant,
+    dns: Duration,
+    connect: Duration,
+    handshake: Duration,
+    peer: String,
+}
+
+impl Timing {
+    /// Threshold = 0 means never log.
+    pub fn new(threshold: usize) -> Self {
+        let now = Instant::now();
+        Self {
+            threshold: Duration::from_millis(threshold as u64),
+            start: now,
+            last: now,
+            dns: Duration::ZERO,
+            connect: Duration::ZERO,
+            handshake: Duration::ZERO,
+            peer: String::new(),
+        }
+    }
+
+    fn lap(&mut self) -> Duration {
+        let now = Instant::now();
+        let lap = now - self.last;
+        self.last = now;
+        lap
+    }
+
+    pub fn dns_done(&mut self) {
+        let lap = self.lap();
+        self.dns += lap;
+    }
+
+    /// Failed attempts are counted as well.
+    pub fn connect_done(&mut self) {
+        let lap = self.lap();
+        self.connect += lap;
+    }
+
+    #[cfg(any(feature = "proxy", feature = "transport"))]
+    pub fn handshake_done(&mut self) {
+        let lap = self.lap();
+        self.handshake += lap;
+    }
+
+    pub fn set_peer(&mut self, laddr: SocketAddr, raddr: &RemoteAddr) {
+        if !self.threshold.is_zero() {
+            self.peer = format!("{} => {}", laddr, raddr);
+        }
+    }
+
+    /// Log if slower than the threshold.
+    pub fn report(&self) {
+        let total = self.start.elapsed();
+        if self.threshold.is_zero() || total < self.threshold {
+            return;
+        }
+        log::warn!(
+            "[tcp]{}, slow connection: total={}ms, dns={}ms, connect={}ms, handshake={}ms",
+            self.peer,
+            total.as_millis(),
+            self.dns.as_millis(),
+            self.connect.as_millis(),
+            self.handshake.as_millis()
+        );
+    }
+}
@@ -0,0 +1,57 @@
+//! Byte tracing.
+//!
+//! Wraps the client side stream, bytes read from it are traced
+//! as upload, and bytes written to it as download.
+
+use std::io::Result;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::trace::{Dir, Tracer};
+
+/// A wrapper that traces bytes as they pass.
+pub struct TraceStream<'a, S> {
+    io: S,
+    tracer: &'a Tracer,
+    client: SocketAddr,
+}
+
+impl<'a, S> TraceStream<'a, S> {
+    pub fn new(io: S, tracer: &'a Tracer, client: SocketAddr) -> Self {
+        Self { io, tracer, client }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for TraceStream<'_, S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut this.io).poll_read(cx, buf);
+        this.tracer.record(&this.client, Dir::Up, &buf.filled()[filled..]);
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for TraceStream<'_, S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, data: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.io).poll_write(cx, data);
+        if let Poll::Ready(Ok(n)) = res {
+            this.tracer.record(&this.client, Dir::Down, &data[..n]);
+        }
+        res
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+    }
+}
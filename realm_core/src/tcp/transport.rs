@@ -1,18 +1,90 @@
-use std::io::Result;
+use std::io::{Error, ErrorKind, Result};
+use std::fmt::Debug;
+use std::net::SocketAddr;
+use std::time::Duration;
 use futures::try_join;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
 
 use kaminari::{AsyncAccept, AsyncConnect, IOStream};
-use kaminari::mix::{MixAccept, MixConnect};
+use kaminari::mix::{MixAccept, MixConnect, MixClientStream, MixServerStream};
+use kaminari::tls::{TlsClientStream, TlsServerStream};
+use kaminari::ws::{WsClientStream, WsServerStream};
 
 use realm_io::{CopyBuffer, bidi_copy_buf, buf_size};
 
-pub async fn run_relay<S: IOStream>(src: S, dst: S, ac: &MixAccept, cc: &MixConnect) -> Result<()> {
+use super::coalesce::CoalesceStream;
+use super::counter::CountStream;
+use super::limit::LimitStream;
+use super::silent::SilentStream;
+use super::deadlock;
+#[cfg(feature = "trace")]
+use super::trace::TraceStream;
+use super::timing::Timing;
+use super::dropped::{DropReason, dropped};
+use super::ws;
+use super::fallback::Fallback;
+use crate::endpoint::ConnectOpts;
+use crate::registry::Activity;
+use crate::time::timeoutfut;
+
+#[allow(clippy::too_many_arguments)]
+pub async fn run_relay<S: IOStream + Send + TlsInfo>(
+    src: S,
+    dst: S,
+    (ac, cc): (&MixAccept, &MixConnect),
+    conn_opts: &ConnectOpts,
+    activity: &Activity,
+    timing: Timing,
+    client: SocketAddr,
+    id: Option<&str>,
+) -> Result<()> {
     macro_rules! hs_relay {
         ($ac: expr, $cc: expr) => {
-            handshake_and_relay(src, dst, $ac, $cc).await
+            handshake_and_relay(src, dst, $ac, $cc, conn_opts, activity, timing, client).await
         };
     }
 
+    // replaces the ws transports
+    let max_frame_size = conn_opts.frame_limit.as_ref().map_or(0, |x| x.max);
+    let ws_ac = conn_opts
+        .frame_limit
+        .as_ref()
+        .and_then(|x| x.listen.clone())
+        .map(|(ws, tls)| ws::Accept {
+            ws,
+            tls,
+            max_frame_size,
+        });
+    let close_code = conn_opts.ws_close.as_ref().map_or(0, |x| x.code);
+    let ws_cc = match (&conn_opts.correlation, id) {
+        (Some(tag), Some(id)) => Some(ws::Connect {
+            ws: tag.ws.clone(),
+            tls: tag.tls.clone(),
+            max_frame_size,
+            header: Some((tag.header.clone(), String::from(id))),
+            close_code,
+        }),
+        _ => conn_opts
+            .frame_limit
+            .as_ref()
+            .and_then(|x| x.remote.clone())
+            .or_else(|| conn_opts.ws_close.as_ref().map(|x| (x.ws.clone(), x.tls.clone())))
+            .map(|(ws, tls)| ws::Connect {
+                ws,
+                tls,
+                max_frame_size,
+                header: None,
+                close_code,
+            }),
+    };
+    match (&ws_ac, &ws_cc) {
+        (Some(ac), Some(cc)) => return hs_relay!(ac, cc),
+        (Some(ac), None) => return hs_relay!(ac, cc),
+        (None, Some(cc)) => return hs_relay!(ac, cc),
+        (None, None) => {}
+    }
+
     #[cfg(feature = "transport-boost")]
     {
         use MixConnect::*;
@@ -42,19 +114,167 @@ pub async fn run_relay<S: IOStream>(src: S, dst: S, ac: &MixAccept, cc: &MixConn
     hs_relay!(ac, cc)
 }
 
-async fn handshake_and_relay<S, AC, CC>(src: S, dst: S, ac: &AC, cc: &CC) -> Result<()>
+/// The same as [`run_relay`], trying the remote transports in order.
+pub async fn run_relay_fallback(
+    src: TcpStream,
+    dst: TcpStream,
+    ac: &MixAccept,
+    cc: &Fallback,
+    conn_opts: &ConnectOpts,
+    activity: &Activity,
+    timing: Timing,
+) -> Result<()> {
+    let client = cc.client;
+    handshake_and_relay(src, dst, ac, cc, conn_opts, activity, timing, client).await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handshake_and_relay<S, AC, CC>(
+    src: S,
+    dst: S,
+    ac: &AC,
+    cc: &CC,
+    conn_opts: &ConnectOpts,
+    activity: &Activity,
+    mut timing: Timing,
+    client: SocketAddr,
+) -> Result<()>
 where
     S: IOStream,
     AC: AsyncAccept<S>,
     CC: AsyncConnect<S>,
+    AC::Stream: TlsInfo,
+    CC::Stream: TlsInfo,
 {
-    let mut buf1 = vec![0; buf_size()];
+    // the upgrade request must fit in the handshake buffer
+    let hs_size = match conn_opts.ws_max_header_size {
+        0 => buf_size(),
+        n => n,
+    };
+    let mut buf1 = vec![0; std::cmp::max(hs_size, buf_size())];
     let mut buf2 = vec![0; buf_size()];
 
-    let (mut src, mut dst) = try_join!(ac.accept(src, &mut buf1), cc.connect(dst, &mut buf2))?;
+    // the slot is held by both sides of a handshake
+    let handshake = async {
+        let _slot = conn_opts.handshake_limit.acquire().await;
+        try_join!(ac.accept(src, &mut buf1[..hs_size]), cc.connect(dst, &mut buf2))
+    };
+    let (src, dst) = match conn_opts.handshake_timeout {
+        0 => handshake.await?,
+        n => timeoutfut(handshake, n).await.map_err(|_| {
+            dropped(
+                DropReason::HandshakeTimeout,
+                Error::new(ErrorKind::TimedOut, format!("handshake not done in {}s", n)),
+            )
+        })??,
+    };
+    timing.handshake_done();
+    timing.report();
+    if let Some(http) = activity.http() {
+        http.on_upgrade();
+    }
+
+    if let Some(info) = src.tls_info() {
+        log::debug!("[tcp]{} tls accepted: {}", client, info);
+    }
+    if let Some(info) = dst.tls_info() {
+        log::debug!("[tcp]{} tls connected: {}", client, info);
+    }
+
+    let src = CountStream::new(src, &conn_opts.stat, activity);
+    let src = LimitStream::new(src, &conn_opts.rate_limit);
+    // the window starts after the handshake
+    let dst = SilentStream::new(dst, conn_opts.remote_first_byte_timeout);
+
+    #[cfg(feature = "trace")]
+    if let Some(tracer) = &conn_opts.tracer {
+        let src = TraceStream::new(src, tracer, client);
+        return relay(src, dst, buf1, buf2, conn_opts).await;
+    }
+
+    relay(src, dst, buf1, buf2, conn_opts).await
+}
+
+async fn relay<A, B>(mut src: A, mut dst: B, buf1: Vec<u8>, buf2: Vec<u8>, conn_opts: &ConnectOpts) -> Result<()>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    deadlock::wait_either(&mut src, &mut dst, conn_opts).await?;
+
+    // size = 0 passes through
+    let delay = Duration::from_millis(conn_opts.coalesce_delay as u64);
+    let mut src = CoalesceStream::new(src, conn_opts.coalesce_size, delay);
+    let mut dst = CoalesceStream::new(dst, conn_opts.coalesce_size, delay);
 
     let buf1 = CopyBuffer::new(buf1);
     let buf2 = CopyBuffer::new(buf2);
 
     bidi_copy_buf(&mut src, &mut dst, buf1, buf2).await.map(|_| ())
 }
+
+/// Parameters negotiated by the tls handshake, None without tls.
+pub trait TlsInfo {
+    fn tls_info(&self) -> Option<String>;
+}
+
+fn describe(version: Option<impl Debug>, cipher: Option<impl Debug>, alpn: Option<&[u8]>) -> Option<String> {
+    let alpn = alpn.map_or_else(|| String::from("none"), |x| String::from_utf8_lossy(x).into_owned());
+    Some(format!("version={:?}, cipher={:?}, alpn={}", version?, cipher?, alpn))
+}
+
+impl TlsInfo for TcpStream {
+    fn tls_info(&self) -> Option<String> {
+        None
+    }
+}
+
+impl<T> TlsInfo for TlsClientStream<T> {
+    fn tls_info(&self) -> Option<String> {
+        let (_, conn) = self.get_ref();
+        let cipher = conn.negotiated_cipher_suite().map(|x| x.suite());
+        describe(conn.protocol_version(), cipher, conn.alpn_protocol())
+    }
+}
+
+impl<T> TlsInfo for TlsServerStream<T> {
+    fn tls_info(&self) -> Option<String> {
+        let (_, conn) = self.get_ref();
+        let cipher = conn.negotiated_cipher_suite().map(|x| x.suite());
+        describe(conn.protocol_version(), cipher, conn.alpn_protocol())
+    }
+}
+
+impl<T: TlsInfo> TlsInfo for WsClientStream<T> {
+    fn tls_info(&self) -> Option<String> {
+        self.as_ref().tls_info()
+    }
+}
+
+impl<T: TlsInfo> TlsInfo for WsServerStream<T> {
+    fn tls_info(&self) -> Option<String> {
+        self.as_ref().tls_info()
+    }
+}
+
+impl<T: TlsInfo> TlsInfo for MixClientStream<T> {
+    fn tls_info(&self) -> Option<String> {
+        match self {
+            MixClientStream::Plain(x) => x.tls_info(),
+            MixClientStream::Ws(x) => x.tls_info(),
+            MixClientStream::Tls(x) => x.tls_info(),
+            MixClientStream::Wss(x) => x.tls_info(),
+        }
+    }
+}
+
+impl<T: TlsInfo> TlsInfo for MixServerStream<T> {
+    fn tls_info(&self) -> Option<String> {
+        match self {
+            MixServerStream::Plain(x) => x.tls_info(),
+            MixServerStream::Ws(x) => x.tls_info(),
+            MixServerStream::Tls(x) => x.tls_info(),
+            MixServerStream::Wss(x) => x.tls_info(),
+        }
+    }
+}
@@ -0,0 +1,298 @@
+//! Ws transports.
+//!
+//! The same as the ws and wss transports of kaminari, with what it has
+//! no option for: a max size of incoming frames, an extra header
+//! sent with the upgrade request, and a close frame sent on shutdown.
+//!
+//! Frames are checked below lightws, once the upgrade is done.
+//! Payload is never buffered by lightws, so the limit is about a
+//! peer announcing a frame larger than it should, which closes the
+//! connection at once instead of being relayed.
+
+use std::cmp::min;
+use std::future::Future;
+use std::io::{Error, ErrorKind, Result};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use kaminari::{AsyncAccept, AsyncConnect, IOStream};
+use kaminari::nop::{NopAccept, NopConnect};
+use kaminari::tls::{TlsAccept, TlsConnect, TlsClientStream, TlsServerStream};
+use kaminari::ws::{WsConf, WsClientStream, WsServerStream};
+
+use lightws::endpoint::Endpoint;
+use lightws::role::{Client, Server};
+
+use super::correlation;
+use super::dropped::{DropReason, dropped};
+use super::transport::TlsInfo;
+
+pub type ServerStream<S> = WsServerStream<FrameStream<MaybeTls<S, TlsServerStream<S>>>>;
+pub type ClientStream<S> = WsClientStream<FrameStream<MaybeTls<S, TlsClientStream<S>>>>;
+
+/// Accepts like the ws or wss listen transport.
+pub struct Accept {
+    pub ws: WsConf,
+    pub tls: Option<TlsAccept<NopAccept>>,
+    /// 0 means no limit.
+    pub max_frame_size: usize,
+}
+
+/// Connects like the ws or wss remote transport.
+pub struct Connect {
+    pub ws: WsConf,
+    pub tls: Option<TlsConnect<NopConnect>>,
+    /// 0 means no limit.
+    pub max_frame_size: usize,
+    /// Name and value.
+    pub header: Option<(String, String)>,
+    /// Status code of the close frame sent on shutdown, 0 sends none.
+    pub close_code: u16,
+}
+
+impl<S: IOStream + Send> AsyncAccept<S> for Accept {
+    type Stream = ServerStream<S>;
+
+    type AcceptFut<'a>
+        = Pin<Box<dyn Future<Output = Result<Self::Stream>> + Send + 'a>>
+    where
+        Self: 'a;
+
+    fn accept<'a>(&'a self, stream: S, buf: &'a mut [u8]) -> Self::AcceptFut<'a> {
+        Box::pin(async move {
+            let stream = match &self.tls {
+                Some(tls) => MaybeTls::Tls(tls.accept(stream, buf).await?),
+                None => MaybeTls::Plain(stream),
+            };
+            let WsConf { host, path } = &self.ws;
+            let mut stream = Endpoint::<_, Server>::accept_async(FrameStream::new(stream), buf, host, path)
+                .await?
+                .guard();
+            stream.as_mut().arm(self.max_frame_size);
+            Ok(stream)
+        })
+    }
+}
+
+impl<S: IOStream + Send> AsyncConnect<S> for Connect {
+    type Stream = ClientStream<S>;
+
+    type ConnectFut<'a>
+        = Pin<Box<dyn Future<Output = Result<Self::Stream>> + Send + 'a>>
+    where
+        Self: 'a;
+
+    fn connect<'a>(&'a self, stream: S, buf: &'a mut [u8]) -> Self::ConnectFut<'a> {
+        Box::pin(async move {
+            let stream = match &self.tls {
+                Some(tls) => MaybeTls::Tls(tls.connect(stream, buf).await?),
+                None => MaybeTls::Plain(stream),
+            };
+            let stream = FrameStream::new(stream);
+            let WsConf { host, path } = &self.ws;
+            let mut stream = match &self.header {
+                Some((name, value)) => correlation::upgrade(stream, buf, &self.ws, name, value).await?,
+                None => Endpoint::<_, Client>::connect_async(stream, buf, host, path)
+                    .await?
+                    .guard(),
+            };
+            stream.as_mut().arm(self.max_frame_size);
+            if self.close_code != 0 {
+                stream.as_mut().close_with(self.close_code);
+            }
+            Ok(stream)
+        })
+    }
+}
+
+/// Checks the size of incoming frames, once armed.
+///
+/// Also sends a close frame before the shutdown of the stream.
+/// Lightws has written whole frames by then, as the relay only
+/// shuts down once all the data has been written.
+pub struct FrameStream<T> {
+    io: T,
+    max: u64,
+    head: [u8; 14],
+    head_len: usize,
+    // payload of the current frame
+    left: u64,
+    // the part of the close frame not yet written
+    close: Vec<u8>,
+}
+
+impl<T> FrameStream<T> {
+    const fn new(io: T) -> Self {
+        Self {
+            io,
+            max: 0,
+            head: [0; 14],
+            head_len: 0,
+            left: 0,
+            close: Vec::new(),
+        }
+    }
+
+    // a masked frame, from the client side
+    fn close_with(&mut self, code: u16) {
+        let mask: [u8; 4] = rand::random();
+        let code = code.to_be_bytes();
+        self.close = vec![0x88, 0x82];
+        self.close.extend_from_slice(&mask);
+        self.close.extend([code[0] ^ mask[0], code[1] ^ mask[1]]);
+    }
+
+    // the upgrade is done, 0 leaves it unchecked
+    fn arm(&mut self, max: usize) {
+        self.max = max as u64;
+    }
+
+    fn check(&mut self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            if self.left != 0 {
+                let n = min(self.left, data.len() as u64);
+                self.left -= n;
+                data = &data[n as usize..];
+                continue;
+            }
+
+            self.head[self.head_len] = data[0];
+            self.head_len += 1;
+            data = &data[1..];
+            if let Some(len) = payload_len(&self.head[..self.head_len]) {
+                if len > self.max {
+                    return Err(dropped(
+                        DropReason::FrameTooLarge,
+                        Error::new(
+                            ErrorKind::InvalidData,
+                            format!("ws frame of {}b exceeds {}b", len, self.max),
+                        ),
+                    ));
+                }
+                self.head_len = 0;
+                self.left = len;
+            }
+        }
+        Ok(())
+    }
+}
+
+// some once the head is complete
+fn payload_len(head: &[u8]) -> Option<u64> {
+    let b = *head.get(1)?;
+    let mask = if b & 0x80 != 0 { 4 } else { 0 };
+    let ext = match b & 0x7f {
+        126 => 2,
+        127 => 8,
+        _ => 0,
+    };
+    if head.len() < 2 + ext + mask {
+        return None;
+    }
+
+    let len = match ext {
+        2 => u16::from_be_bytes([head[2], head[3]]) as u64,
+        8 => u64::from_be_bytes(head[2..10].try_into().unwrap()),
+        _ => (b & 0x7f) as u64,
+    };
+    Some(len)
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for FrameStream<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut this.io).poll_read(cx, buf))?;
+        if this.max != 0 {
+            this.check(&buf.filled()[filled..])?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for FrameStream<T> {
+    #[inline]
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Pin::new(&mut self.get_mut().io).poll_write(cx, buf)
+    }
+
+    #[inline]
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        while !this.close.is_empty() {
+            let n = ready!(Pin::new(&mut this.io).poll_write(cx, &this.close))?;
+            if n == 0 {
+                return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+            }
+            this.close.drain(..n);
+        }
+        Pin::new(&mut this.io).poll_shutdown(cx)
+    }
+}
+
+impl<T: TlsInfo> TlsInfo for FrameStream<T> {
+    fn tls_info(&self) -> Option<String> {
+        self.io.tls_info()
+    }
+}
+
+/// Plain for ws, tls for wss.
+pub enum MaybeTls<S, T> {
+    Plain(S),
+    Tls(T),
+}
+
+impl<S, T> AsyncRead for MaybeTls<S, T>
+where
+    S: AsyncRead + Unpin,
+    T: AsyncRead + Unpin,
+{
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        match self.get_mut() {
+            MaybeTls::Plain(x) => Pin::new(x).poll_read(cx, buf),
+            MaybeTls::Tls(x) => Pin::new(x).poll_read(cx, buf),
+        }
+    }
+}
+
+impl<S, T> AsyncWrite for MaybeTls<S, T>
+where
+    S: AsyncWrite + Unpin,
+    T: AsyncWrite + Unpin,
+{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        match self.get_mut() {
+            MaybeTls::Plain(x) => Pin::new(x).poll_write(cx, buf),
+            MaybeTls::Tls(x) => Pin::new(x).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        match self.get_mut() {
+            MaybeTls::Plain(x) => Pin::new(x).poll_flush(cx),
+            MaybeTls::Tls(x) => Pin::new(x).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        match self.get_mut() {
+            MaybeTls::Plain(x) => Pin::new(x).poll_shutdown(cx),
+            MaybeTls::Tls(x) => Pin::new(x).poll_shutdown(cx),
+        }
+    }
+}
+
+impl<S: TlsInfo, T: TlsInfo> TlsInfo for MaybeTls<S, T> {
+    fn tls_info(&self) -> Option<String> {
+        match self {
+            MaybeTls::Plain(x) => x.tls_info(),
+            MaybeTls::Tls(x) => x.tls_info(),
+        }
+    }
+}
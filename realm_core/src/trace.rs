@@ -0,0 +1,107 @@
+//! Byte tracer for debugging.
+//!
+//! Each chunk relayed from or to a client is recorded as a line:
+//!
+//! `<unix millis> <client> <up|down> <length> <fnv=hash|hex=payload>`
+//!
+//! Payloads are only hashed unless explicitly enabled.
+//! Recording stops once the file reaches its size cap.
+
+use std::fs::File;
+use std::io::{Result, Write};
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Direction of relayed bytes, seen from the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dir {
+    /// Client => remote.
+    Up,
+    /// Remote => client.
+    Down,
+}
+
+struct Inner {
+    file: File,
+    written: u64,
+    full: bool,
+}
+
+/// Trace file of an endpoint.
+pub struct Tracer {
+    inner: Mutex<Inner>,
+    max_size: u64,
+    payload: bool,
+}
+
+impl Tracer {
+    /// Create or truncate the trace file.
+    pub fn create(path: &str, max_size: u64, payload: bool) -> Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self {
+            inner: Mutex::new(Inner {
+                file,
+                written: 0,
+                full: false,
+            }),
+            max_size,
+            payload,
+        })
+    }
+
+    /// Record a chunk, errors are ignored.
+    pub fn record(&self, client: &SocketAddr, dir: Dir, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_millis());
+        let dir = match dir {
+            Dir::Up => "up",
+            Dir::Down => "down",
+        };
+        let content = if self.payload {
+            format!("hex={}", hex(data))
+        } else {
+            format!("fnv={:016x}", fnv1a(data))
+        };
+        let line = format!("{} {} {} {} {}\n", now, client, dir, data.len(), content);
+
+        let mut inner = self.inner.lock().unwrap();
+        if inner.full {
+            return;
+        }
+        if inner.written + line.len() as u64 > self.max_size {
+            inner.full = true;
+            let _ = inner.file.write_all(b"# size cap reached\n");
+            return;
+        }
+        inner.written += line.len() as u64;
+        let _ = inner.file.write_all(line.as_bytes());
+    }
+}
+
+impl std::fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tracer")
+            .field("max_size", &self.max_size)
+            .field("payload", &self.payload)
+            .finish()
+    }
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter()
+        .fold(0xcbf29ce484222325, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+fn hex(data: &[u8]) -> String {
+    use std::fmt::Write;
+    data.iter().fold(String::with_capacity(data.len() * 2), |mut s, b| {
+        let _ = write!(s, "{:02x}", b);
+        s
+    })
+}
@@ -70,6 +70,10 @@ impl Packet {
         }
     }
 
+    pub const fn len(&self) -> usize {
+        self.cursor as usize
+    }
+
     pub fn ref_with_addr<'a>(&self, addr: &'a SockAddrStore) -> PacketRef<'_, 'a> {
         PacketRef {
             buf: &self.buf[..self.cursor as usize],
@@ -0,0 +1,97 @@
+//! ICMP errors of associations.
+//!
+//! An association socket is not connected, so the kernel only reports
+//! icmp errors with `IP_RECVERR`, by queueing them on the socket's
+//! error queue, which is then read with `MSG_ERRQUEUE`.
+
+use std::io::{Error, ErrorKind, Result};
+use std::mem::{size_of, zeroed};
+use std::net::SocketAddr;
+use std::os::fd::AsRawFd;
+
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+
+use realm_syscall::socket2::{SockAddr, Socket};
+
+const ICMP_DEST_UNREACH: u8 = 3;
+const ICMP_PORT_UNREACH: u8 = 3;
+const ICMP6_DST_UNREACH: u8 = 1;
+const ICMP6_DST_UNREACH_NOPORT: u8 = 4;
+
+/// Queue icmp errors on the socket.
+pub fn set_recv_err(socket: &Socket, raddr: &SocketAddr) -> Result<()> {
+    let (level, name) = match raddr {
+        SocketAddr::V4(_) => (libc::SOL_IP, libc::IP_RECVERR),
+        SocketAddr::V6(_) => (libc::SOL_IPV6, libc::IPV6_RECVERR),
+    };
+    let on: libc::c_int = 1;
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &on as *const _ as *const libc::c_void,
+            size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    match ret {
+        0 => Ok(()),
+        _ => Err(Error::last_os_error()),
+    }
+}
+
+/// Wait for a port unreachable, return the peer which is unreachable.
+///
+/// Other icmp errors are dropped from the queue.
+pub async fn unreachable(sock: &UdpSocket) -> Result<SocketAddr> {
+    loop {
+        sock.ready(Interest::ERROR).await?;
+        match sock.try_io(Interest::ERROR, || recv_err(sock)) {
+            Ok(Some(addr)) => return Ok(addr),
+            Ok(None) => continue,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// read one error from the queue
+fn recv_err(sock: &UdpSocket) -> Result<Option<SocketAddr>> {
+    let mut name: libc::sockaddr_storage = unsafe { zeroed() };
+    let mut control = [0u64; 64];
+    let mut msg: libc::msghdr = unsafe { zeroed() };
+    msg.msg_name = &mut name as *mut _ as *mut libc::c_void;
+    msg.msg_namelen = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = size_of::<[u64; 64]>() as _;
+
+    let ret = unsafe { libc::recvmsg(sock.as_raw_fd(), &mut msg, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT) };
+    if ret < 0 {
+        return Err(Error::last_os_error());
+    }
+
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let hdr = unsafe { &*cmsg };
+        let is_err = matches!(
+            (hdr.cmsg_level, hdr.cmsg_type),
+            (libc::SOL_IP, libc::IP_RECVERR) | (libc::SOL_IPV6, libc::IPV6_RECVERR)
+        );
+        if is_err {
+            let ee = unsafe { &*(libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err) };
+            let port_unreach = match ee.ee_origin {
+                libc::SO_EE_ORIGIN_ICMP => (ee.ee_type, ee.ee_code) == (ICMP_DEST_UNREACH, ICMP_PORT_UNREACH),
+                libc::SO_EE_ORIGIN_ICMP6 => (ee.ee_type, ee.ee_code) == (ICMP6_DST_UNREACH, ICMP6_DST_UNREACH_NOPORT),
+                _ => false,
+            };
+            if port_unreach {
+                // the original destination
+                let addr = unsafe { SockAddr::new(name, msg.msg_namelen) };
+                return Ok(addr.as_socket());
+            }
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+    Ok(None)
+}
@@ -1,15 +1,18 @@
-use std::io::Result;
+use std::io::{ErrorKind, Result};
 use std::net::SocketAddr;
+use std::pin::pin;
 use std::sync::Arc;
 use tokio::net::UdpSocket;
+use futures::future::{select, Either};
 
-use super::SockMap;
+use super::Relay;
 use super::{socket, batched};
 
-use crate::trick::Ref;
+#[cfg(target_os = "linux")]
+use super::errqueue;
+
 use crate::time::timeoutfut;
-use crate::dns::resolve_addr;
-use crate::endpoint::{RemoteAddr, ConnectOpts};
+use crate::dns::resolve_addr_with;
 
 use batched::{Packet, SockAddrStore};
 use registry::Registry;
@@ -106,52 +109,99 @@ mod registry {
     }
 }
 
-pub async fn associate_and_relay(
-    lis: Ref<UdpSocket>,
-    rname: Ref<RemoteAddr>,
-    conn_opts: Ref<ConnectOpts>,
-    sockmap: Ref<SockMap>,
-) -> Result<()> {
+pub async fn associate_and_relay(relay: &Arc<Relay>) -> Result<()> {
+    let Relay {
+        lis,
+        raddr: rname,
+        conn_opts,
+        sockmap,
+        ..
+    } = relay.as_ref();
     let mut registry = Registry::new(batched::MAX_PACKETS);
 
     loop {
-        registry.batched_recv_on(&lis).await?;
+        registry.batched_recv_on(lis).await?;
         log::debug!("[udp]entry batched recvfrom[{}]", registry.count());
-        let raddr = resolve_addr(&rname).await?.iter().next().unwrap();
+        let raddr = resolve_addr_with(rname, conn_opts.resolver.as_deref())
+            .await?
+            .iter()
+            .next()
+            .unwrap();
         log::debug!("[udp]{} resolved as {}", *rname, raddr);
 
         registry.group_by_addr();
         for pkts in registry.group_iter() {
             let laddr = pkts[0].addr.clone().into();
             let rsock = sockmap.find_or_insert(&laddr, || {
-                let s = Arc::new(socket::associate(&raddr, &conn_opts)?);
-                tokio::spawn(send_back(lis, laddr, s.clone(), conn_opts, sockmap));
+                let s = Arc::new(socket::associate(&raddr, conn_opts)?);
+                tokio::spawn(send_back(relay.clone(), laddr, s.clone()));
                 log::info!("[udp]new association {} => {} as {}", laddr, *rname, raddr);
                 Result::Ok(s)
             })?;
             let raddr: SockAddrStore = raddr.into();
-            batched::send_all(&rsock, pkts.iter().map(|x| x.ref_with_addr(&raddr))).await?;
+            match batched::send_all(&rsock, pkts.iter().map(|x| x.ref_with_addr(&raddr))).await {
+                // a queued icmp error, the association is removed by send_back
+                Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+                    log::debug!("[udp]sendto {} refused: {}", *rname, e);
+                    continue;
+                }
+                x => x?,
+            }
+            conn_opts.stat.add_traffic(pkts.iter().map(|x| x.len() as u64).sum(), 0);
         }
     }
 }
 
-async fn send_back(
-    lsock: Ref<UdpSocket>,
-    laddr: SocketAddr,
-    rsock: Arc<UdpSocket>,
-    conn_opts: Ref<ConnectOpts>,
-    sockmap: Ref<SockMap>,
-) {
+async fn send_back(relay: Arc<Relay>, laddr: SocketAddr, rsock: Arc<UdpSocket>) {
+    let Relay {
+        lis: lsock,
+        conn_opts,
+        sockmap,
+        closed,
+        ..
+    } = relay.as_ref();
     let mut registry = Registry::new(batched::MAX_PACKETS);
     let timeout = conn_opts.associate_timeout;
     let laddr_s: SockAddrStore = laddr.into();
+    let _conn = conn_opts.stat.open();
+    let mut closed = closed.clone();
 
     loop {
-        match timeoutfut(registry.batched_recv_on(&rsock), timeout).await {
+        let recv = timeoutfut(registry.batched_recv_on(&rsock), timeout);
+
+        #[cfg(target_os = "linux")]
+        let recv = async {
+            let unreachable = errqueue::unreachable(&rsock);
+            match select(pin!(recv), pin!(unreachable)).await {
+                Either::Left((x, _)) => x,
+                Either::Right((x, _)) => {
+                    match x {
+                        Ok(raddr) => log::warn!("[udp]{} => {} is unreachable, remove the association", laddr, raddr),
+                        Err(e) => log::error!("[udp]failed to read the error queue: {}", e),
+                    }
+                    Ok(Err(ErrorKind::ConnectionRefused.into()))
+                }
+            }
+        };
+
+        // no more clients once the listening loop is gone
+        let recv = async {
+            match select(pin!(recv), pin!(closed.changed())).await {
+                Either::Left((x, _)) => x,
+                Either::Right(_) => {
+                    log::debug!("[udp]listener of {} is closed, remove the association", laddr);
+                    Ok(Err(ErrorKind::ConnectionRefused.into()))
+                }
+            }
+        };
+
+        match recv.await {
             Err(_) => {
                 log::debug!("[udp]rear recvfrom timeout");
                 break;
             }
+            // already logged
+            Ok(Err(e)) if e.kind() == ErrorKind::ConnectionRefused => break,
             Ok(Err(e)) => {
                 log::error!("[udp]rear recvfrom failed: {}", e);
                 break;
@@ -162,10 +212,13 @@ async fn send_back(
         };
 
         let pkts = registry.iter().map(|pkt| pkt.ref_with_addr(&laddr_s));
-        if let Err(e) = batched::send_all(&lsock, pkts).await {
+        if let Err(e) = batched::send_all(lsock, pkts).await {
             log::error!("[udp]failed to sendto client{}: {}", &laddr, e);
             break;
         }
+        conn_opts
+            .stat
+            .add_traffic(0, registry.iter().map(|x| x.len() as u64).sum());
     }
 
     sockmap.remove(&laddr);
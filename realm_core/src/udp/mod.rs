@@ -5,35 +5,59 @@ mod sockmap;
 mod middle;
 mod batched;
 
+#[cfg(target_os = "linux")]
+mod errqueue;
+
 use std::io::Result;
+use std::sync::Arc;
+
+use tokio::sync::watch;
 
-use crate::trick::Ref;
-use crate::endpoint::Endpoint;
+use crate::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
 
 use sockmap::SockMap;
 use middle::associate_and_relay;
 
 /// Launch a udp relay.
 pub async fn run_udp(endpoint: Endpoint) -> Result<()> {
-    let Endpoint {
-        laddr,
-        raddr,
-        bind_opts,
-        conn_opts,
-        ..
-    } = endpoint;
-
-    let sockmap = SockMap::new();
+    let lis = bind(&endpoint).unwrap_or_else(|e| panic!("[udp]failed to bind {}: {}", endpoint.laddr, e));
+    run_udp_with(lis, endpoint).await
+}
 
-    let lis = socket::bind(&laddr, bind_opts).unwrap_or_else(|e| panic!("[udp]failed to bind {}: {}", laddr, e));
+/// Bind the socket of a udp relay, see [`crate::tcp::bind`].
+pub fn bind(endpoint: &Endpoint) -> Result<std::net::UdpSocket> {
+    socket::bind(&endpoint.laddr, endpoint.bind_opts.clone())
+}
 
-    let lis = Ref::new(&lis);
-    let raddr = Ref::new(&raddr);
-    let conn_opts = Ref::new(&conn_opts);
-    let sockmap = Ref::new(&sockmap);
+/// Launch a udp relay on a bound socket.
+///
+/// Associations keep the relay alive, and are removed
+/// once this future is dropped, as no client is served.
+pub async fn run_udp_with(lis: std::net::UdpSocket, endpoint: Endpoint) -> Result<()> {
+    let Endpoint { raddr, conn_opts, .. } = endpoint;
+
+    // dropped along with this future
+    let (_open, closed) = watch::channel(());
+    let relay = Arc::new(Relay {
+        lis: tokio::net::UdpSocket::from_std(lis)?,
+        raddr,
+        conn_opts,
+        sockmap: SockMap::new(),
+        closed,
+    });
     loop {
-        if let Err(e) = associate_and_relay(lis, raddr, conn_opts, sockmap).await {
+        if let Err(e) = associate_and_relay(&relay).await {
             log::error!("[udp]error: {}", e);
         }
     }
 }
+
+/// Shared by the listening loop and the associations.
+struct Relay {
+    lis: tokio::net::UdpSocket,
+    raddr: RemoteAddr,
+    conn_opts: ConnectOpts,
+    sockmap: SockMap,
+    // closed once the listening loop is dropped
+    closed: watch::Receiver<()>,
+}
@@ -6,8 +6,8 @@ use realm_syscall::new_udp_socket;
 
 use crate::endpoint::{BindOpts, ConnectOpts};
 
-pub fn bind(laddr: &SocketAddr, bind_opts: BindOpts) -> Result<UdpSocket> {
-    let BindOpts { ipv6_only } = bind_opts;
+pub fn bind(laddr: &SocketAddr, bind_opts: BindOpts) -> Result<std::net::UdpSocket> {
+    let BindOpts { ipv6_only, .. } = bind_opts;
     let socket = new_udp_socket(laddr)?;
 
     // ipv6_only
@@ -20,7 +20,7 @@ pub fn bind(laddr: &SocketAddr, bind_opts: BindOpts) -> Result<UdpSocket> {
 
     socket.bind(&(*laddr).into())?;
 
-    UdpSocket::from_std(socket.into())
+    Ok(socket.into())
 }
 
 pub fn associate(raddr: &SocketAddr, conn_opts: &ConnectOpts) -> Result<UdpSocket> {
@@ -46,5 +46,9 @@ pub fn associate(raddr: &SocketAddr, conn_opts: &ConnectOpts) -> Result<UdpSocke
         realm_syscall::bind_to_device(&socket, iface)?;
     }
 
+    // detect unreachable remote peers
+    #[cfg(target_os = "linux")]
+    super::errqueue::set_recv_err(&socket, raddr)?;
+
     UdpSocket::from_std(socket.into())
 }
@@ -0,0 +1,42 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
+
+#[tokio::test]
+async fn accept_delay() {
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:10900".parse().unwrap(),
+        raddr: "127.0.0.1:20900"
+            .parse::<SocketAddr>()
+            .map(RemoteAddr::SocketAddr)
+            .unwrap(),
+        conn_opts: ConnectOpts {
+            accept_delay: 300,
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    };
+
+    let lis = TcpListener::bind("127.0.0.1:20900").await.unwrap();
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let start = Instant::now();
+    let mut stream = TcpStream::connect("127.0.0.1:10900").await.unwrap();
+    stream.write_all(b"ping").await.unwrap();
+
+    // the remote peer is not dialed until the delay is over
+    let (mut remote, _) = lis.accept().await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(300));
+
+    let mut buf = [0u8; 4];
+    remote.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+}
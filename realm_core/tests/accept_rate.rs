@@ -0,0 +1,58 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::{sleep, Instant};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::ConnectOpts;
+
+mod common;
+
+type Seen = Arc<Mutex<Vec<Instant>>>;
+
+// record when each dial arrives, one per accepted client
+async fn backend(addr: &str, seen: Seen) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    let mut streams = Vec::new();
+    loop {
+        let (stream, _) = lis.accept().await.unwrap();
+        seen.lock().unwrap().push(Instant::now());
+        streams.push(stream);
+    }
+}
+
+#[tokio::test]
+async fn accept_rate() {
+    let conn_opts = ConnectOpts {
+        accept_rate: 10,
+        ..Default::default()
+    };
+    let endpoint = common::endpoint("127.0.0.1:12310", "127.0.0.1:22310", conn_opts);
+
+    let seen = Seen::default();
+    tokio::spawn(backend("127.0.0.1:22310", seen.clone()));
+    tokio::spawn(run_tcp(endpoint));
+
+    sleep(Duration::from_millis(500)).await;
+
+    // the handshakes finish in the backlog at once
+    let mut streams = Vec::new();
+    for _ in 0..10 {
+        streams.push(TcpStream::connect("127.0.0.1:12310").await.unwrap());
+    }
+
+    sleep(Duration::from_millis(1500)).await;
+
+    let seen = seen.lock().unwrap().clone();
+    assert_eq!(seen.len(), 10);
+
+    for (a, b) in seen.iter().zip(seen.iter().skip(1)) {
+        let gap = *b - *a;
+        assert!(gap >= Duration::from_millis(80), "{:?}", gap);
+    }
+
+    let span = *seen.last().unwrap() - seen[0];
+    assert!(span >= Duration::from_millis(850), "{:?}", span);
+    assert!(span <= Duration::from_millis(1200), "{:?}", span);
+}
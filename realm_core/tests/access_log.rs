@@ -0,0 +1,192 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, ConnectOpts};
+use realm_core::access::{Exchange, Format};
+
+mod common;
+use common::{LOGS, capture_logs};
+
+// tests run in parallel, find the one of a request
+async fn next_line(request: &str) -> String {
+    let wait = async {
+        loop {
+            {
+                let mut lines = LOGS.lock().unwrap();
+                if let Some(pos) = lines.iter().position(|x| x.contains(request)) {
+                    return lines.remove(pos);
+                }
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+    };
+    timeout(Duration::from_secs(3), wait).await.unwrap()
+}
+
+// drops the time, which is checked by `time` below
+fn strip_time(line: &str) -> String {
+    let (head, rest) = line.split_once(" [").unwrap();
+    let (time, tail) = rest.split_once("] ").unwrap();
+    assert!(time.ends_with(" +0000"), "{}", line);
+    format!("{} {}", head, tail)
+}
+
+fn endpoint(laddr: &str, raddr: &str, format: Format) -> Endpoint {
+    let conn_opts = ConnectOpts {
+        access_log: Some(format),
+        ..Default::default()
+    };
+    common::endpoint(laddr, raddr, conn_opts)
+}
+
+const RESPONSE: &[u8] = b"HTTP/1.1 404 Not Found\r\nContent-Length: 2\r\n\r\nno";
+
+// answers one request on each connection
+async fn upstream(addr: &str) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0; 0x1000];
+            let mut n = 0;
+            while !buf[..n].ends_with(b"\r\n\r\n") {
+                n += stream.read(&mut buf[n..]).await.unwrap();
+            }
+            stream.write_all(RESPONSE).await.unwrap();
+        });
+    }
+}
+
+#[test]
+fn time() {
+    let exchange = Exchange::default();
+    exchange.on_request(b"GET / HTTP/1.0\r\n\r\n");
+    exchange.on_response(b"HTTP/1.0 200 OK\r\n\r\n");
+    let time = UNIX_EPOCH + Duration::from_secs(971186136);
+    let line = exchange.line(Format::Common, [10, 0, 0, 1].into(), time);
+    assert_eq!(
+        line,
+        "10.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET / HTTP/1.0\" 200 19"
+    );
+}
+
+#[tokio::test]
+async fn plain_http() {
+    capture_logs(log::LevelFilter::Info);
+    tokio::spawn(upstream("127.0.0.1:22280"));
+    tokio::spawn(run_tcp(endpoint(
+        "127.0.0.1:12280",
+        "127.0.0.1:22280",
+        Format::Combined,
+    )));
+    sleep(Duration::from_millis(500)).await;
+
+    let mut stream = TcpStream::connect("127.0.0.1:12280").await.unwrap();
+    let client = stream.local_addr().unwrap().ip();
+    stream
+        .write_all(b"GET /a?b=\"c\" HTTP/1.1\r\nHost: x\r\nReferer: http://x/\r\nUser-Agent: curl/8.0\r\n\r\n")
+        .await
+        .unwrap();
+    let mut buf = vec![0; RESPONSE.len()];
+    stream.read_exact(&mut buf).await.unwrap();
+    drop(stream);
+
+    let line = next_line("GET /a").await;
+    assert_eq!(
+        strip_time(&line),
+        format!(
+            "{} - - \"GET /a?b=\\\"c\\\" HTTP/1.1\" 404 {} \"http://x/\" \"curl/8.0\"",
+            client,
+            RESPONSE.len()
+        )
+    );
+}
+
+#[cfg(feature = "transport")]
+#[tokio::test]
+async fn ws_upgrade() {
+    use realm_core::kaminari::ws::WsConf;
+    use realm_core::kaminari::mix::{MixAccept, MixConnect, MixClientConf, MixServerConf};
+
+    capture_logs(log::LevelFilter::Info);
+    let upstream = TcpListener::bind("127.0.0.1:22281").await.unwrap();
+    // closed at once, which ends the relay
+    tokio::spawn(async move {
+        loop {
+            drop(upstream.accept().await.unwrap());
+        }
+    });
+    let mut endpoint = endpoint("127.0.0.1:12281", "127.0.0.1:22281", Format::Common);
+    let ws = WsConf {
+        host: String::from("example.com"),
+        path: String::from("/ws"),
+    };
+    endpoint.conn_opts.transport = Some((
+        MixAccept::new_shared(MixServerConf {
+            ws: Some(ws),
+            tls: None,
+        }),
+        MixConnect::new_shared(MixClientConf { ws: None, tls: None }),
+    ));
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let mut stream = TcpStream::connect("127.0.0.1:12281").await.unwrap();
+    let client = stream.local_addr().unwrap().ip();
+    stream
+        .write_all(
+            b"GET /ws HTTP/1.1\r\nHost: example.com\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+              Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut buf = vec![0; 0x1000];
+    let n = stream.read(&mut buf).await.unwrap();
+    assert!(
+        buf[..n].starts_with(b"HTTP/1.1 101"),
+        "{:?}",
+        String::from_utf8_lossy(&buf[..n])
+    );
+    drop(stream);
+
+    // nothing relayed after the handshake
+    let line = next_line("GET /ws").await;
+    assert_eq!(strip_time(&line), format!("{} - - \"GET /ws HTTP/1.1\" 101 -", client));
+}
+
+#[cfg(feature = "transport")]
+#[tokio::test]
+async fn ws_partial_upgrade() {
+    use realm_core::kaminari::ws::WsConf;
+    use realm_core::kaminari::mix::{MixAccept, MixConnect, MixClientConf, MixServerConf};
+
+    let mut endpoint = endpoint("127.0.0.1:12282", "127.0.0.1:22282", Format::Common);
+    let ws = WsConf {
+        host: String::from("example.com"),
+        path: String::from("/ws"),
+    };
+    endpoint.conn_opts.transport = Some((
+        MixAccept::new_shared(MixServerConf {
+            ws: Some(ws),
+            tls: None,
+        }),
+        MixConnect::new_shared(MixClientConf { ws: None, tls: None }),
+    ));
+    endpoint.conn_opts.handshake_timeout = 1;
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    // the head never ends, the peek and then the handshake give up
+    let mut stream = TcpStream::connect("127.0.0.1:12282").await.unwrap();
+    stream
+        .write_all(b"GET /ws HTTP/1.1\r\nHost: example.com\r\n")
+        .await
+        .unwrap();
+    let mut buf = vec![0; 0x1000];
+    let res = timeout(Duration::from_secs(4), stream.read(&mut buf)).await;
+    assert!(matches!(res, Ok(Ok(0) | Err(_))), "{:?}", res);
+}
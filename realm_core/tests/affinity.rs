@@ -0,0 +1,83 @@
+#![cfg(feature = "balance")]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, ConnectOpts};
+use realm_core::health::{Affinity, Health};
+use realm_core::balance::{Balancer, Strategy};
+
+mod common;
+use common::remote;
+
+async fn backend(addr: &str, idx: u8) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        stream.write_all(&[idx]).await.unwrap();
+    }
+}
+
+async fn who(laddr: &str) -> u8 {
+    let mut stream = TcpStream::connect(laddr).await.unwrap();
+    let mut buf = [0u8; 1];
+    stream.read_exact(&mut buf).await.unwrap();
+    buf[0]
+}
+
+#[tokio::test]
+async fn affinity() {
+    let health = Arc::new(Health::new(2, Duration::from_secs(60)));
+    let endpoint = |laddr: &str, affinity: Affinity| Endpoint {
+        laddr: laddr.parse().unwrap(),
+        raddr: remote("127.0.0.1:22130"),
+        conn_opts: ConnectOpts {
+            balancer: Balancer::new(Strategy::IpHash, &[1, 1]),
+            health: health.clone(),
+            affinity: Arc::new(affinity),
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: vec![remote("127.0.0.1:22131")],
+    };
+
+    tokio::spawn(backend("127.0.0.1:22130", 0));
+    tokio::spawn(backend("127.0.0.1:22131", 1));
+    tokio::spawn(run_tcp(endpoint(
+        "127.0.0.1:12130",
+        Affinity::new(Duration::from_secs(2)),
+    )));
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:12131", Affinity::default())));
+    sleep(Duration::from_millis(500)).await;
+
+    // the peer this client is hashed onto, and sticks to
+    let mapped = who("127.0.0.1:12130").await;
+    let other = 1 - mapped;
+    for _ in 0..4 {
+        assert_eq!(who("127.0.0.1:12130").await, mapped);
+    }
+
+    // failover
+    health.mark_down(mapped as usize);
+    for _ in 0..4 {
+        assert_eq!(who("127.0.0.1:12130").await, other);
+    }
+
+    // recovered, still on the new peer
+    health.mark_up(mapped as usize);
+    assert_eq!(who("127.0.0.1:12130").await, other);
+    sleep(Duration::from_secs(1)).await;
+    assert_eq!(who("127.0.0.1:12130").await, other);
+
+    // without affinity, back at once
+    assert_eq!(who("127.0.0.1:12131").await, mapped);
+
+    // back once the window is over
+    sleep(Duration::from_millis(1200)).await;
+    assert_eq!(who("127.0.0.1:12130").await, mapped);
+}
@@ -0,0 +1,133 @@
+#![cfg(feature = "transport")]
+
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, ConnectOpts};
+
+use realm_core::kaminari::AsyncConnect;
+use realm_core::kaminari::tls::{TlsClientConf, TlsServerConf};
+use realm_core::kaminari::mix::{MixAccept, MixConnect, MixClientConf, MixServerConf};
+
+mod common;
+use common::remote;
+
+async fn connect_with_alpn(laddr: &str, alpn: &[&str]) -> String {
+    let cc = MixConnect::new_shared(MixClientConf {
+        ws: None,
+        tls: Some(TlsClientConf {
+            sni: String::from("localhost"),
+            alpn: alpn.iter().map(|x| Vec::from(*x)).collect(),
+            insecure: true,
+            early_data: false,
+        }),
+    });
+
+    let stream = TcpStream::connect(laddr).await.unwrap();
+    let mut buf = vec![0; 0x2000];
+    let mut stream = cc.connect(stream, &mut buf).await.unwrap();
+
+    stream.write_all(b"who").await.unwrap();
+    stream.flush().await.unwrap();
+    let n = stream.read(&mut buf).await.unwrap();
+    String::from_utf8_lossy(&buf[..n]).into_owned()
+}
+
+async fn backend(addr: &str, name: &'static str) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0; 32];
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_eq!(b"who", &buf[..n]);
+            stream.write_all(name.as_bytes()).await.unwrap();
+        });
+    }
+}
+
+#[tokio::test]
+async fn alpn_routes() {
+    env_logger::init();
+
+    let ac = MixAccept::new_shared(MixServerConf {
+        ws: None,
+        tls: Some(TlsServerConf {
+            crt: String::new(),
+            key: String::new(),
+            ocsp: String::new(),
+            server_name: String::from("localhost"),
+        }),
+    });
+    let cc = MixConnect::new_shared(MixClientConf { ws: None, tls: None });
+
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:10200".parse().unwrap(),
+        raddr: remote("127.0.0.1:20200"),
+        conn_opts: ConnectOpts {
+            transport: Some((ac, cc)),
+            alpn_routes: vec![
+                (String::from("h2"), remote("127.0.0.1:20201")),
+                (String::from("http/1.1"), remote("127.0.0.1:20202")),
+            ],
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    };
+
+    tokio::spawn(run_tcp(endpoint));
+    tokio::spawn(backend("127.0.0.1:20200", "default"));
+    tokio::spawn(backend("127.0.0.1:20201", "a"));
+    tokio::spawn(backend("127.0.0.1:20202", "b"));
+
+    sleep(Duration::from_millis(500)).await;
+
+    assert_eq!(connect_with_alpn("127.0.0.1:10200", &["h2"]).await, "a");
+    assert_eq!(connect_with_alpn("127.0.0.1:10200", &["http/1.1"]).await, "b");
+    assert_eq!(connect_with_alpn("127.0.0.1:10200", &["spdy/3"]).await, "default");
+
+    // the order of the routes wins over the client's
+    assert_eq!(connect_with_alpn("127.0.0.1:10200", &["http/1.1", "h2"]).await, "a");
+    assert_eq!(connect_with_alpn("127.0.0.1:10200", &["h2", "http/1.1"]).await, "a");
+    assert_eq!(connect_with_alpn("127.0.0.1:10200", &["spdy/3", "http/1.1"]).await, "b");
+}
+
+#[tokio::test]
+async fn partial_hello() {
+    let ac = MixAccept::new_shared(MixServerConf {
+        ws: None,
+        tls: Some(TlsServerConf {
+            crt: String::new(),
+            key: String::new(),
+            ocsp: String::new(),
+            server_name: String::from("localhost"),
+        }),
+    });
+    let cc = MixConnect::new_shared(MixClientConf { ws: None, tls: None });
+
+    let conn_opts = ConnectOpts {
+        transport: Some((ac, cc)),
+        alpn_routes: vec![(String::from("h2"), remote("127.0.0.1:20204"))],
+        handshake_timeout: 1,
+        ..Default::default()
+    };
+    tokio::spawn(run_tcp(common::endpoint(
+        "127.0.0.1:10203",
+        "127.0.0.1:20203",
+        conn_opts,
+    )));
+    sleep(Duration::from_millis(500)).await;
+
+    // the header of a handshake record, then nothing
+    let mut stream = TcpStream::connect("127.0.0.1:10203").await.unwrap();
+    stream.write_all(&[0x16, 0x03, 0x01]).await.unwrap();
+
+    let mut buf = [0u8; 32];
+    let res = timeout(Duration::from_secs(3), stream.read(&mut buf)).await;
+    assert!(matches!(res, Ok(Ok(0) | Err(_))), "{:?}", res);
+}
@@ -0,0 +1,69 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
+
+#[tokio::test]
+async fn coalesce() {
+    env_logger::init();
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:10100".parse().unwrap(),
+        raddr: "127.0.0.1:20100"
+            .parse::<SocketAddr>()
+            .map(RemoteAddr::SocketAddr)
+            .unwrap(),
+        conn_opts: ConnectOpts {
+            coalesce_size: 4096,
+            coalesce_delay: 200,
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    };
+
+    tokio::spawn(run_tcp(endpoint));
+
+    let task1 = async {
+        sleep(Duration::from_millis(500)).await;
+        let mut stream = TcpStream::connect("127.0.0.1:10100").await.unwrap();
+        stream.set_nodelay(true).unwrap();
+
+        // 50 tiny writes, spread over ~50ms
+        for _ in 0..50 {
+            stream.write_all(b"0123456789").await.unwrap();
+            sleep(Duration::from_millis(1)).await;
+        }
+
+        // keep the connection open until the server is done
+        let mut buf = [0u8; 1];
+        let _ = stream.read(&mut buf).await;
+    };
+
+    let task2 = async {
+        let lis = TcpListener::bind("127.0.0.1:20100").await.unwrap();
+        let (mut stream, _) = lis.accept().await.unwrap();
+
+        let mut buf = vec![0; 4096];
+        let mut total = 0;
+        let mut reads = 0;
+
+        while total < 500 {
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_ne!(n, 0);
+            log::debug!("b got {} bytes", n);
+            total += n;
+            reads += 1;
+        }
+
+        // without coalescing, each write shows up on its own
+        assert_eq!(total, 500);
+        assert!(reads <= 3, "{} reads", reads);
+    };
+
+    tokio::join!(task1, task2);
+}
@@ -0,0 +1,58 @@
+//! Fixtures shared by the integration tests, each test uses a part of them.
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+use tokio::net::TcpListener;
+
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
+
+/// Messages logged since [`capture_logs`].
+pub static LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct Capture;
+
+impl log::Log for Capture {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        LOGS.lock().unwrap().push(record.args().to_string());
+    }
+
+    fn flush(&self) {}
+}
+
+/// Record messages into [`LOGS`], only the first call takes effect.
+pub fn capture_logs(level: log::LevelFilter) {
+    if log::set_logger(&Capture).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+pub fn remote(s: &str) -> RemoteAddr {
+    s.parse::<SocketAddr>().map(RemoteAddr::SocketAddr).unwrap()
+}
+
+pub fn endpoint(laddr: &str, raddr: &str, conn_opts: ConnectOpts) -> Endpoint {
+    Endpoint {
+        laddr: laddr.parse().unwrap(),
+        raddr: remote(raddr),
+        conn_opts,
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    }
+}
+
+pub async fn echo(addr: &str) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        tokio::spawn(async move {
+            let (mut rd, mut wr) = stream.split();
+            let _ = tokio::io::copy(&mut rd, &mut wr).await;
+        });
+    }
+}
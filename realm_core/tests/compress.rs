@@ -0,0 +1,94 @@
+#![cfg(feature = "compress")]
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, ConnectOpts, Compress};
+
+mod common;
+use common::echo;
+
+fn endpoint(laddr: &str, raddr: &str, compress: Compress) -> Endpoint {
+    let conn_opts = ConnectOpts {
+        compress: Some(compress),
+        ..Default::default()
+    };
+    common::endpoint(laddr, raddr, conn_opts)
+}
+
+// the wire between the two relays, counts the bytes towards the second
+async fn wire(laddr: &str, raddr: &'static str, count: Arc<AtomicUsize>) {
+    let lis = TcpListener::bind(laddr).await.unwrap();
+    loop {
+        let (mut local, _) = lis.accept().await.unwrap();
+        let count = count.clone();
+        tokio::spawn(async move {
+            let mut remote = TcpStream::connect(raddr).await.unwrap();
+            let (mut lrd, mut lwr) = local.split();
+            let (mut rrd, mut rwr) = remote.split();
+            let up = async {
+                let mut buf = vec![0; 0x1000];
+                loop {
+                    let n = lrd.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    count.fetch_add(n, Ordering::Relaxed);
+                    rwr.write_all(&buf[..n]).await.unwrap();
+                }
+                rwr.shutdown().await.unwrap();
+            };
+            let down = tokio::io::copy(&mut rrd, &mut lwr);
+            let _ = tokio::join!(up, down);
+        });
+    }
+}
+
+#[tokio::test]
+async fn round_trip() {
+    let count = Arc::new(AtomicUsize::new(0));
+    tokio::spawn(echo("127.0.0.1:22290"));
+    tokio::spawn(run_tcp(endpoint(
+        "127.0.0.1:12292",
+        "127.0.0.1:22290",
+        Compress::Listen(6),
+    )));
+    tokio::spawn(wire("127.0.0.1:12291", "127.0.0.1:12292", count.clone()));
+    tokio::spawn(run_tcp(endpoint(
+        "127.0.0.1:12290",
+        "127.0.0.1:12291",
+        Compress::Remote(6),
+    )));
+    sleep(Duration::from_millis(500)).await;
+
+    let data: Vec<u8> = (0..0x40000).map(|i| b"realm compress "[i % 15]).collect();
+    let mut stream = TcpStream::connect("127.0.0.1:12290").await.unwrap();
+    // no half close, which tears down both ways with brutal-shutdown
+    let (mut rd, mut wr) = stream.split();
+    let send = async { wr.write_all(&data).await.unwrap() };
+    let recv = async {
+        let mut buf = vec![0; data.len()];
+        let mut n = 0;
+        while n < buf.len() {
+            match rd.read(&mut buf[n..]).await.unwrap() {
+                0 => break,
+                x => n += x,
+            }
+        }
+        buf.truncate(n);
+        buf
+    };
+    let (_, echoed) = timeout(Duration::from_secs(5), async { tokio::join!(send, recv) })
+        .await
+        .unwrap();
+
+    assert!(echoed == data, "{} bytes echoed of {}", echoed.len(), data.len());
+    let wired = count.load(Ordering::Relaxed);
+    assert!(wired * 10 < data.len(), "{} bytes on the wire of {}", wired, data.len());
+}
@@ -0,0 +1,94 @@
+#![cfg(target_os = "linux")]
+
+use std::mem::ManuallyDrop;
+use std::net::SocketAddr;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+
+use realm_core::tcp::{bind, run_tcp_with};
+use realm_core::endpoint::{Endpoint, RemoteAddr, BindOpts, ConnectOpts};
+
+fn congestion<T: AsRawFd>(socket: &T) -> String {
+    let mut buf = [0u8; 16];
+    let mut len = buf.len() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_CONGESTION,
+            buf.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    assert_eq!(ret, 0);
+    let name = buf[..len as usize].split(|x| *x == 0).next().unwrap();
+    String::from_utf8(name.to_vec()).unwrap()
+}
+
+// the relay's sockets are in this process as well
+fn find_socket(local: SocketAddr, peer: SocketAddr) -> String {
+    for entry in std::fs::read_dir("/proc/self/fd").unwrap() {
+        let fd = match entry.unwrap().file_name().to_str().unwrap().parse() {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        let stream = ManuallyDrop::new(unsafe { std::net::TcpStream::from_raw_fd(fd) });
+        if stream.local_addr().ok() == Some(local) && stream.peer_addr().ok() == Some(peer) {
+            return congestion(&*stream);
+        }
+    }
+    panic!("no socket {} -> {}", local, peer);
+}
+
+#[tokio::test]
+async fn congestion_control() {
+    // anything other than the system default
+    let default = std::fs::read_to_string("/proc/sys/net/ipv4/tcp_congestion_control").unwrap();
+    let algo = if default.trim() == "reno" { "cubic" } else { "reno" };
+
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:12230".parse().unwrap(),
+        raddr: "127.0.0.1:22230"
+            .parse::<SocketAddr>()
+            .map(RemoteAddr::SocketAddr)
+            .unwrap(),
+        bind_opts: BindOpts {
+            congestion: Some(String::from(algo)),
+            ..Default::default()
+        },
+        conn_opts: ConnectOpts {
+            congestion: Some(String::from(algo)),
+            ..Default::default()
+        },
+        extra_raddrs: Vec::new(),
+    };
+
+    let lis = bind(&endpoint).unwrap();
+    assert_eq!(congestion(&lis), algo);
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let upstream = TcpListener::bind("127.0.0.1:22230").await.unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = upstream.accept().await.unwrap();
+            tx.send((stream, peer)).unwrap();
+        }
+    });
+    tokio::spawn(run_tcp_with(lis, endpoint));
+    sleep(Duration::from_millis(200)).await;
+
+    let client = TcpStream::connect("127.0.0.1:12230").await.unwrap();
+    let (upstream, relay) = rx.recv().await.unwrap();
+    assert_eq!(congestion(&client), default.trim());
+    assert_eq!(congestion(&upstream), default.trim());
+
+    // both sockets of the relay
+    let laddr = "127.0.0.1:12230".parse().unwrap();
+    let raddr = "127.0.0.1:22230".parse().unwrap();
+    assert_eq!(find_socket(laddr, client.local_addr().unwrap()), algo);
+    assert_eq!(find_socket(relay, raddr), algo);
+}
@@ -0,0 +1,66 @@
+use std::sync::Arc;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::limit::ConnLimit;
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
+
+mod common;
+use common::echo;
+
+// None if nothing is echoed within the wait
+async fn try_echo(stream: &mut TcpStream, wait: Duration) -> Option<usize> {
+    let mut buf = vec![0; 32];
+    timeout(wait, stream.read(&mut buf)).await.ok().map(|x| x.unwrap_or(0))
+}
+
+#[tokio::test]
+async fn conn_queue() {
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:11900".parse().unwrap(),
+        raddr: "127.0.0.1:21900"
+            .parse::<SocketAddr>()
+            .map(RemoteAddr::SocketAddr)
+            .unwrap(),
+        conn_opts: ConnectOpts {
+            conn_limit: Arc::new(ConnLimit::new(1, 1, Duration::from_secs(2))),
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    };
+
+    tokio::spawn(echo("127.0.0.1:21900"));
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let wait = Duration::from_millis(300);
+
+    let mut served = TcpStream::connect("127.0.0.1:11900").await.unwrap();
+    served.write_all(b"hello").await.unwrap();
+    assert_eq!(try_echo(&mut served, wait).await, Some(5));
+
+    // waits for the slot
+    let mut queued = TcpStream::connect("127.0.0.1:11900").await.unwrap();
+    queued.write_all(b"hello").await.unwrap();
+    assert_eq!(try_echo(&mut queued, wait).await, None);
+
+    // the queue is full, closed at once
+    let mut rejected = TcpStream::connect("127.0.0.1:11900").await.unwrap();
+    assert_eq!(try_echo(&mut rejected, wait).await, Some(0));
+
+    // served once the slot is free
+    drop(served);
+    assert_eq!(try_echo(&mut queued, Duration::from_secs(1)).await, Some(5));
+
+    // closed once the wait times out
+    let start = Instant::now();
+    let mut expired = TcpStream::connect("127.0.0.1:11900").await.unwrap();
+    assert_eq!(try_echo(&mut expired, Duration::from_secs(3)).await, Some(0));
+    assert!(start.elapsed() >= Duration::from_millis(1900));
+}
@@ -0,0 +1,125 @@
+#![cfg(feature = "transport")]
+
+use std::sync::{Arc, Mutex};
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts, Correlation};
+
+use realm_core::kaminari::ws::WsConf;
+use realm_core::kaminari::mix::{MixAccept, MixConnect, MixClientConf, MixServerConf};
+
+mod common;
+use common::{LOGS, capture_logs};
+
+// upgrades anything, records the header
+async fn upstream(addr: &str, ids: Arc<Mutex<Vec<String>>>) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        let ids = ids.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0; 0x1000];
+            let mut n = 0;
+            while !buf[..n].ends_with(b"\r\n\r\n") {
+                n += stream.read(&mut buf[n..]).await.unwrap();
+            }
+            let head = String::from_utf8_lossy(&buf[..n]).into_owned();
+            let header = |name: &str| {
+                head.lines()
+                    .filter_map(|x| x.split_once(':'))
+                    .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                    .map(|(_, v)| v.trim().to_string())
+            };
+            ids.lock().unwrap().extend(header("x-correlation-id"));
+
+            let key = header("sec-websocket-key").unwrap();
+            let accept = lightws::handshake::derive_accept_key(key.as_bytes());
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                String::from_utf8_lossy(&accept)
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            let _ = stream.read(&mut buf).await;
+        });
+    }
+}
+
+#[tokio::test]
+async fn correlation() {
+    capture_logs(log::LevelFilter::Info);
+
+    let ws = WsConf {
+        host: String::from("a.test"),
+        path: String::from("/ws"),
+    };
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:12200".parse().unwrap(),
+        raddr: "127.0.0.1:22200"
+            .parse::<SocketAddr>()
+            .map(RemoteAddr::SocketAddr)
+            .unwrap(),
+        conn_opts: ConnectOpts {
+            transport: Some((
+                MixAccept::new_shared(MixServerConf { ws: None, tls: None }),
+                MixConnect::new_shared(MixClientConf {
+                    ws: Some(ws.clone()),
+                    tls: None,
+                }),
+            )),
+            correlation: Some(Correlation {
+                header: String::from("X-Correlation-Id"),
+                ws,
+                tls: None,
+            }),
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    };
+
+    let ids = Arc::new(Mutex::new(Vec::new()));
+    tokio::spawn(upstream("127.0.0.1:22200", ids.clone()));
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let mut clients = Vec::new();
+    for _ in 0..4 {
+        clients.push(TcpStream::connect("127.0.0.1:12200").await.unwrap());
+        sleep(Duration::from_millis(100)).await;
+    }
+    sleep(Duration::from_millis(200)).await;
+
+    let upstream: Vec<String> = ids.lock().unwrap().clone();
+    assert_eq!(upstream.len(), 4);
+    assert_eq!(upstream.iter().collect::<HashSet<_>>().len(), 4, "{:?}", upstream);
+    for id in &upstream {
+        assert_eq!(id.len(), 36, "{}", id);
+        assert_eq!(&id[14..15], "4", "{}", id);
+    }
+
+    // one access log per client, with the same id
+    let logged: Vec<(SocketAddr, String)> = {
+        let logs = LOGS.lock().unwrap();
+        logs.iter()
+            .filter(|x| x.starts_with("[tcp]") && x.contains(" as "))
+            .map(|x| {
+                let (client, _) = x[5..].split_once(" => ").unwrap();
+                let (_, id) = x.rsplit_once(", id=").unwrap();
+                (client.parse().unwrap(), id.to_string())
+            })
+            .collect()
+    };
+    assert_eq!(logged.len(), 4, "{:?}", logged);
+    for (client, stream) in logged.iter().zip(&clients) {
+        assert_eq!(client.0, stream.local_addr().unwrap());
+    }
+    let logged: HashSet<_> = logged.into_iter().map(|x| x.1).collect();
+    assert_eq!(logged, upstream.into_iter().collect());
+}
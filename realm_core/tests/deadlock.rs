@@ -0,0 +1,97 @@
+use std::time::{Duration, Instant};
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, ConnectOpts};
+
+mod common;
+use common::{LOGS, capture_logs};
+
+fn endpoint(laddr: &str, raddr: &str, deadlock_nudge: &[u8]) -> Endpoint {
+    let conn_opts = ConnectOpts {
+        deadlock_timeout: 1,
+        deadlock_nudge: deadlock_nudge.to_vec(),
+        ..Default::default()
+    };
+    common::endpoint(laddr, raddr, conn_opts)
+}
+
+// waits for the client, then greets once a line arrives
+async fn server(addr: &str) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0; 32];
+            while let Ok(n @ 1..) = stream.read(&mut buf).await {
+                if buf[..n].ends_with(b"\r\n") {
+                    stream.write_all(b"hello").await.unwrap();
+                }
+            }
+        });
+    }
+}
+
+#[tokio::test]
+async fn deadlock() {
+    capture_logs(log::LevelFilter::Warn);
+
+    tokio::spawn(server("127.0.0.1:22190"));
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:12190", "127.0.0.1:22190", b"")));
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:12191", "127.0.0.1:22190", b"\r\n")));
+    sleep(Duration::from_millis(500)).await;
+
+    // both sides wait, closed instead of hanging
+    let start = Instant::now();
+    let mut stream = TcpStream::connect("127.0.0.1:12190").await.unwrap();
+    let mut buf = [0u8; 5];
+    let n = timeout(Duration::from_secs(3), stream.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap_or(0);
+    let elapsed = start.elapsed();
+    assert_eq!(n, 0);
+    assert!(
+        elapsed >= Duration::from_millis(900) && elapsed < Duration::from_millis(1500),
+        "{:?}",
+        elapsed
+    );
+
+    let client = stream.local_addr().unwrap();
+    sleep(Duration::from_millis(100)).await;
+    {
+        let logs = LOGS.lock().unwrap();
+        let log = logs
+            .iter()
+            .find(|x| x.contains("reason=deadlock"))
+            .unwrap_or_else(|| panic!("no deadlock log: {:?}", logs));
+        assert_eq!(
+            *log,
+            format!(
+                "[tcp]{} dropped, reason=deadlock: no data from either side in 1s, remote=127.0.0.1:22190",
+                client
+            )
+        );
+    }
+
+    // the nudge makes the server speak
+    let start = Instant::now();
+    let mut stream = TcpStream::connect("127.0.0.1:12191").await.unwrap();
+    timeout(Duration::from_secs(3), stream.read_exact(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buf, b"hello");
+    assert!(start.elapsed() >= Duration::from_millis(900), "{:?}", start.elapsed());
+
+    // a client speaking first is relayed at once
+    let start = Instant::now();
+    let mut stream = TcpStream::connect("127.0.0.1:12190").await.unwrap();
+    stream.write_all(b"hi\r\n").await.unwrap();
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+    assert!(start.elapsed() < Duration::from_millis(300), "{:?}", start.elapsed());
+}
@@ -0,0 +1,58 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use tokio::net::UdpSocket;
+
+use realm_core::dns;
+use realm_core::dns::config::{ResolverConfig, ResolverOpts, NameServerConfigGroup};
+use realm_core::endpoint::RemoteAddr;
+
+// answer A queries with 127.0.0.n, n = 1, 2, .. per query
+async fn nameserver(addr: &str) {
+    let sock = UdpSocket::bind(addr).await.unwrap();
+    let mut buf = vec![0u8; 512];
+    let mut n = 0u8;
+    loop {
+        let (len, peer) = sock.recv_from(&mut buf).await.unwrap();
+        let query = &buf[..len];
+
+        // question follows the 12 bytes header
+        let mut end = 12;
+        while query[end] != 0 {
+            end += query[end] as usize + 1;
+        }
+        let qtype = u16::from_be_bytes([query[end + 1], query[end + 2]]);
+        let question = &query[12..end + 5];
+
+        let mut resp = Vec::from(&query[..2]);
+        resp.extend_from_slice(&[0x81, 0x80, 0, 1, 0, (qtype == 1) as u8, 0, 0, 0, 0]);
+        resp.extend_from_slice(question);
+        if qtype == 1 {
+            n += 1;
+            // name pointer, type A, class IN, ttl 3600, rdata
+            resp.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4, 127, 0, 0, n]);
+        }
+        sock.send_to(&resp, peer).await.unwrap();
+    }
+}
+
+async fn lookup(raddr: &RemoteAddr) -> IpAddr {
+    dns::resolve_addr(raddr).await.unwrap().iter().next().unwrap().ip()
+}
+
+#[tokio::test]
+async fn clear_cache() {
+    tokio::spawn(nameserver("127.0.0.1:20700"));
+
+    let servers = NameServerConfigGroup::from_ips_clear(&[IpAddr::V4(Ipv4Addr::LOCALHOST)], 20700, true);
+    let conf = ResolverConfig::from_parts(None, Vec::new(), servers);
+    dns::build(Some(conf), Some(ResolverOpts::default()));
+
+    let raddr = RemoteAddr::DomainName(String::from("realm.test."), 443);
+
+    assert_eq!(lookup(&raddr).await, IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)));
+    // cached
+    assert_eq!(lookup(&raddr).await, IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)));
+
+    dns::clear_cache();
+    assert_eq!(lookup(&raddr).await, IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)));
+}
@@ -0,0 +1,81 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::limit::ConnLimit;
+use realm_core::endpoint::ConnectOpts;
+
+mod common;
+use common::{LOGS, capture_logs, echo, endpoint};
+
+// wait until closed by the relay
+async fn closed(stream: &mut TcpStream) {
+    let mut buf = vec![0; 32];
+    let n = timeout(Duration::from_secs(3), stream.read(&mut buf)).await.unwrap();
+    assert_eq!(n.unwrap_or(0), 0);
+}
+
+#[tokio::test]
+async fn drop_reason() {
+    capture_logs(log::LevelFilter::Warn);
+
+    tokio::spawn(echo("127.0.0.1:22110"));
+    tokio::spawn(run_tcp(endpoint(
+        "127.0.0.1:12110",
+        "127.0.0.1:22110",
+        ConnectOpts {
+            first_byte_timeout: 1,
+            ..Default::default()
+        },
+    )));
+    tokio::spawn(run_tcp(endpoint(
+        "127.0.0.1:12111",
+        "127.0.0.1:22110",
+        ConnectOpts {
+            conn_limit: Arc::new(ConnLimit::new(1, 0, Duration::ZERO)),
+            ..Default::default()
+        },
+    )));
+    sleep(Duration::from_millis(500)).await;
+
+    let mut silent = TcpStream::connect("127.0.0.1:12110").await.unwrap();
+    let addr1 = silent.local_addr().unwrap();
+    closed(&mut silent).await;
+
+    let mut served = TcpStream::connect("127.0.0.1:12111").await.unwrap();
+    served.write_all(b"hello").await.unwrap();
+    served.read_exact(&mut [0u8; 5]).await.unwrap();
+    let mut rejected = TcpStream::connect("127.0.0.1:12111").await.unwrap();
+    let addr2 = rejected.local_addr().unwrap();
+    closed(&mut rejected).await;
+    sleep(Duration::from_millis(100)).await;
+
+    let logs = LOGS.lock().unwrap();
+    let find = |pat: &str| {
+        logs.iter()
+            .find(|x| x.contains(pat))
+            .unwrap_or_else(|| panic!("no {} log", pat))
+            .clone()
+    };
+
+    let log = find("reason=client_silent");
+    assert!(
+        log.starts_with(&format!("[tcp]{} dropped, reason=client_silent: ", addr1)),
+        "{}",
+        log
+    );
+
+    let log = find("reason=max_conns");
+    assert!(
+        log.starts_with(&format!("[tcp]{} dropped, reason=max_conns: ", addr2)),
+        "{}",
+        log
+    );
+
+    // each drop is logged once
+    assert_eq!(logs.iter().filter(|x| x.contains(" dropped, ")).count(), 2);
+}
@@ -0,0 +1,47 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
+
+#[tokio::test]
+async fn first_byte_timeout() {
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:11600".parse().unwrap(),
+        raddr: "127.0.0.1:21600"
+            .parse::<SocketAddr>()
+            .map(RemoteAddr::SocketAddr)
+            .unwrap(),
+        conn_opts: ConnectOpts {
+            first_byte_timeout: 1,
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    };
+
+    let lis = TcpListener::bind("127.0.0.1:21600").await.unwrap();
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    // a silent client is closed, the remote peer is never dialed
+    let mut silent = TcpStream::connect("127.0.0.1:11600").await.unwrap();
+    let start = Instant::now();
+    let mut buf = [0u8; 16];
+    let res = timeout(Duration::from_secs(3), silent.read(&mut buf)).await.unwrap();
+    assert!(matches!(res, Ok(0) | Err(_)));
+    assert!(start.elapsed() >= Duration::from_millis(900));
+    assert!(timeout(Duration::from_millis(100), lis.accept()).await.is_err());
+
+    // a client sending in time is relayed
+    let mut client = TcpStream::connect("127.0.0.1:11600").await.unwrap();
+    sleep(Duration::from_millis(500)).await;
+    client.write_all(b"hello").await.unwrap();
+    let (mut remote, _) = lis.accept().await.unwrap();
+    remote.read_exact(&mut buf[..5]).await.unwrap();
+    assert_eq!(&buf[..5], b"hello");
+}
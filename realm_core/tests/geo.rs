@@ -0,0 +1,107 @@
+#![cfg(feature = "geo")]
+
+use std::sync::Arc;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::{TcpSocket, TcpListener};
+use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, ConnectOpts};
+use realm_core::geo::{GeoDb, GeoInfo, GeoKey, GeoRoutes};
+
+mod common;
+use common::remote;
+
+const DB: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/geo.csv");
+
+async fn backend(addr: &str, name: &'static str) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0; 32];
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_eq!(b"who", &buf[..n]);
+            stream.write_all(name.as_bytes()).await.unwrap();
+        });
+    }
+}
+
+async fn who(src: &str) -> String {
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind(SocketAddr::new(src.parse().unwrap(), 0)).unwrap();
+    let mut stream = socket.connect("127.0.0.1:11500".parse().unwrap()).await.unwrap();
+    stream.write_all(b"who").await.unwrap();
+    let mut buf = vec![0; 32];
+    let n = stream.read(&mut buf).await.unwrap();
+    String::from_utf8_lossy(&buf[..n]).into_owned()
+}
+
+#[test]
+fn geo_db() {
+    let db = GeoDb::load(DB).unwrap();
+    assert_eq!(db.len(), 4);
+
+    let info = |country: &str, asn| GeoInfo {
+        country: Some(String::from(country)),
+        asn,
+    };
+    let lookup = |ip: &str| db.lookup(&ip.parse().unwrap()).cloned();
+    assert_eq!(lookup("127.0.0.1"), Some(info("JP", None)));
+    assert_eq!(lookup("127.0.0.3"), Some(info("US", Some(13335))));
+    assert_eq!(lookup("::ffff:127.0.0.4"), Some(info("US", None)));
+    assert_eq!(lookup("2001:db8::1"), Some(info("DE", Some(3320))));
+    assert_eq!(lookup("127.0.0.5"), None);
+    assert_eq!(lookup("::1"), None);
+
+    assert!(GeoDb::parse("127.0.0.1,JP,").is_err());
+    assert!(GeoDb::parse("127.0.0.0/33,JP,").is_err());
+    assert!(GeoDb::parse("127.0.0.1/32,JP,x").is_err());
+    assert!(GeoDb::parse("127.0.0.0/24,JP,\n127.0.0.1/32,US,").is_err());
+
+    assert_eq!("cn".parse(), Ok(GeoKey::Country(String::from("CN"))));
+    assert_eq!("AS13335".parse(), Ok(GeoKey::Asn(13335)));
+    assert!("CHN".parse::<GeoKey>().is_err());
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn geo_routes() {
+    let routes = GeoRoutes::new(
+        GeoDb::load(DB).unwrap(),
+        vec![
+            (GeoKey::Country(String::from("JP")), remote("127.0.0.1:21501")),
+            (GeoKey::Country(String::from("US")), remote("127.0.0.1:21502")),
+            (GeoKey::Asn(13335), remote("127.0.0.1:21503")),
+        ],
+    );
+
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:11500".parse().unwrap(),
+        raddr: remote("127.0.0.1:21500"),
+        conn_opts: ConnectOpts {
+            geo_routes: Some(Arc::new(routes)),
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    };
+
+    tokio::spawn(backend("127.0.0.1:21500", "default"));
+    tokio::spawn(backend("127.0.0.1:21501", "jp"));
+    tokio::spawn(backend("127.0.0.1:21502", "us"));
+    tokio::spawn(backend("127.0.0.1:21503", "as13335"));
+    tokio::spawn(run_tcp(endpoint));
+
+    sleep(Duration::from_millis(500)).await;
+
+    assert_eq!(who("127.0.0.1").await, "jp");
+    // asn takes precedence
+    assert_eq!(who("127.0.0.2").await, "as13335");
+    assert_eq!(who("127.0.0.4").await, "us");
+    // not in the database
+    assert_eq!(who("127.0.0.5").await, "default");
+}
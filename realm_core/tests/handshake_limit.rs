@@ -0,0 +1,123 @@
+#![cfg(feature = "transport")]
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::limit::HandshakeLimit;
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
+
+use realm_core::kaminari::{AsyncConnect, IOStream};
+use realm_core::kaminari::tls::{TlsClientConf, TlsServerConf};
+use realm_core::kaminari::mix::{MixAccept, MixConnect, MixClientConf, MixServerConf};
+
+mod common;
+use common::echo;
+
+async fn connect(laddr: &str) -> impl IOStream {
+    let cc = MixConnect::new_shared(MixClientConf {
+        ws: None,
+        tls: Some(TlsClientConf {
+            sni: String::from("localhost"),
+            alpn: Vec::new(),
+            insecure: true,
+            early_data: false,
+        }),
+    });
+
+    let stream = TcpStream::connect(laddr).await.unwrap();
+    let mut buf = vec![0; 0x2000];
+    cc.connect(stream, &mut buf).await.unwrap()
+}
+
+#[tokio::test]
+async fn handshake_limit() {
+    let ac = MixAccept::new_shared(MixServerConf {
+        ws: None,
+        tls: Some(TlsServerConf {
+            crt: String::new(),
+            key: String::new(),
+            ocsp: String::new(),
+            server_name: String::from("localhost"),
+        }),
+    });
+    let cc = MixConnect::new_shared(MixClientConf { ws: None, tls: None });
+    let limit = Arc::new(HandshakeLimit::new(2));
+
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:12150".parse().unwrap(),
+        raddr: "127.0.0.1:22150"
+            .parse::<SocketAddr>()
+            .map(RemoteAddr::SocketAddr)
+            .unwrap(),
+        conn_opts: ConnectOpts {
+            transport: Some((ac, cc)),
+            handshake_limit: limit.clone(),
+            handshake_timeout: 10,
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    };
+
+    tokio::spawn(echo("127.0.0.1:22150"));
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let mut established = connect("127.0.0.1:12150").await;
+
+    // sample the handshakes in progress
+    let done = Arc::new(AtomicBool::new(false));
+    let peak = Arc::new(AtomicUsize::new(0));
+    let sampler = {
+        let (limit, done, peak) = (limit.clone(), done.clone(), peak.clone());
+        tokio::spawn(async move {
+            while !done.load(Ordering::Relaxed) {
+                peak.fetch_max(limit.in_progress(), Ordering::Relaxed);
+                sleep(Duration::from_millis(1)).await;
+            }
+        })
+    };
+
+    let storm: Vec<_> = (0..20)
+        .map(|_| {
+            tokio::spawn(async {
+                let mut stream = connect("127.0.0.1:12150").await;
+                stream.write_all(b"hello").await.unwrap();
+                stream.flush().await.unwrap();
+                let mut buf = [0u8; 5];
+                stream.read_exact(&mut buf).await.unwrap();
+                assert_eq!(&buf, b"hello");
+            })
+        })
+        .collect();
+
+    // the established connection keeps going during the storm
+    let mut slowest = Duration::ZERO;
+    while storm.iter().any(|x| !x.is_finished()) {
+        let start = Instant::now();
+        established.write_all(b"ping").await.unwrap();
+        established.flush().await.unwrap();
+        let mut buf = [0u8; 4];
+        established.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        slowest = slowest.max(start.elapsed());
+        sleep(Duration::from_millis(5)).await;
+    }
+
+    for x in storm {
+        x.await.unwrap();
+    }
+    done.store(true, Ordering::Relaxed);
+    sampler.await.unwrap();
+
+    assert_eq!(peak.load(Ordering::Relaxed), 2);
+    assert_eq!(limit.in_progress(), 0);
+    assert!(slowest < Duration::from_millis(500), "{:?}", slowest);
+}
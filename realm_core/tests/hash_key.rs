@@ -0,0 +1,97 @@
+#![cfg(feature = "balance")]
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, ConnectOpts, HashKey};
+use realm_core::balance::{Balancer, Strategy};
+
+mod common;
+use common::remote;
+
+fn endpoint(laddr: &str, hash_key: HashKey) -> Endpoint {
+    let conn_opts = ConnectOpts {
+        balancer: Balancer::new(Strategy::IpHash, &[1, 1]),
+        hash_key,
+        ..Default::default()
+    };
+    Endpoint {
+        extra_raddrs: vec![remote("127.0.0.1:22161")],
+        ..common::endpoint(laddr, "127.0.0.1:22160", conn_opts)
+    }
+}
+
+async fn backend(addr: &str, idx: u8) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        stream.write_all(&[idx]).await.unwrap();
+    }
+}
+
+// which backend serves the request
+async fn who(laddr: &str, head: &str) -> u8 {
+    let mut stream = TcpStream::connect(laddr).await.unwrap();
+    stream.write_all(head.as_bytes()).await.unwrap();
+    let mut buf = [0u8; 1];
+    stream.read_exact(&mut buf).await.unwrap();
+    buf[0]
+}
+
+#[tokio::test]
+async fn hash_key() {
+    tokio::spawn(backend("127.0.0.1:22160", 0));
+    tokio::spawn(backend("127.0.0.1:22161", 1));
+    tokio::spawn(run_tcp(endpoint(
+        "127.0.0.1:12160",
+        HashKey::Header(String::from("x-user")),
+    )));
+    tokio::spawn(run_tcp(endpoint(
+        "127.0.0.1:12161",
+        HashKey::Cookie(String::from("sid")),
+    )));
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:12162", HashKey::Source)));
+    let mut stalled = endpoint("127.0.0.1:12163", HashKey::Header(String::from("x-user")));
+    stalled.conn_opts.handshake_timeout = 1;
+    tokio::spawn(run_tcp(stalled));
+    sleep(Duration::from_millis(500)).await;
+
+    let header = |user: &str| format!("GET / HTTP/1.1\r\nHost: a.test\r\nX-User: {}\r\n\r\n", user);
+    let cookie = |sid: &str| format!("GET / HTTP/1.1\r\nCookie: a=1; sid={}\r\n\r\n", sid);
+
+    // same source ip, spread by the key, and sticky
+    for (laddr, head) in [
+        ("127.0.0.1:12160", &header as &dyn Fn(&str) -> String),
+        ("127.0.0.1:12161", &cookie),
+    ] {
+        let mut peers = HashMap::new();
+        for i in 0..16 {
+            let user = format!("user{}", i);
+            peers.insert(user.clone(), who(laddr, &head(&user)).await);
+        }
+        assert!(peers.values().any(|x| *x == 0), "{:?}", peers);
+        assert!(peers.values().any(|x| *x == 1), "{:?}", peers);
+        for (user, peer) in peers {
+            assert_eq!(who(laddr, &head(&user)).await, peer);
+        }
+    }
+
+    // keyed by the source ip
+    let peer = who("127.0.0.1:12162", &header("user0")).await;
+    for i in 1..16 {
+        assert_eq!(who("127.0.0.1:12162", &header(&format!("user{}", i))).await, peer);
+    }
+
+    // without the key, fall back to the source ip
+    assert_eq!(who("127.0.0.1:12160", "GET / HTTP/1.1\r\n\r\n").await, peer);
+    assert_eq!(who("127.0.0.1:12161", &header("user0")).await, peer);
+
+    // a head that never ends, given up after the deadline
+    let partial = who("127.0.0.1:12163", "GET / HTTP/1.1\r\nX-User: user0\r\n");
+    assert_eq!(timeout(Duration::from_secs(3), partial).await.unwrap(), peer);
+}
@@ -0,0 +1,95 @@
+#![cfg(feature = "balance")]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, ConnectOpts};
+use realm_core::balance::Balancer;
+use realm_core::health::{Health, UnhealthyPolicy};
+
+mod common;
+use common::remote;
+
+// both peers are marked down, while they are actually reachable
+async fn run(laddr: &str, raddrs: [&str; 2], policy: UnhealthyPolicy) -> [TcpListener; 2] {
+    let health = Health::new(2, Duration::from_secs(60));
+    health.mark_down(0);
+    health.mark_down(1);
+
+    let endpoint = Endpoint {
+        laddr: laddr.parse().unwrap(),
+        raddr: remote(raddrs[0]),
+        conn_opts: ConnectOpts {
+            balancer: Balancer::parse_from_str("roundrobin: 1, 1"),
+            health: Arc::new(health),
+            unhealthy_policy: policy,
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: vec![remote(raddrs[1])],
+    };
+
+    let lis = [
+        TcpListener::bind(raddrs[0]).await.unwrap(),
+        TcpListener::bind(raddrs[1]).await.unwrap(),
+    ];
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+    lis
+}
+
+async fn no_accept(lis: &[TcpListener; 2]) {
+    let accept = async { tokio::select!(_ = lis[0].accept() => (), _ = lis[1].accept() => ()) };
+    assert!(timeout(Duration::from_millis(200), accept).await.is_err());
+}
+
+#[tokio::test]
+async fn unhealthy_drop() {
+    let lis = run(
+        "127.0.0.1:10500",
+        ["127.0.0.1:20500", "127.0.0.1:20501"],
+        UnhealthyPolicy::DropImmediately,
+    )
+    .await;
+
+    let mut stream = TcpStream::connect("127.0.0.1:10500").await.unwrap();
+    let mut buf = [0u8; 32];
+    let n = stream.read(&mut buf).await.unwrap_or(0);
+    assert_eq!(n, 0);
+
+    no_accept(&lis).await;
+}
+
+#[tokio::test]
+async fn unhealthy_try_all() {
+    let lis = run(
+        "127.0.0.1:10501",
+        ["127.0.0.1:20502", "127.0.0.1:20503"],
+        UnhealthyPolicy::TryAllAnyway,
+    )
+    .await;
+
+    let _stream = TcpStream::connect("127.0.0.1:10501").await.unwrap();
+    let accept = async { tokio::select!(_ = lis[0].accept() => (), _ = lis[1].accept() => ()) };
+    timeout(Duration::from_secs(1), accept).await.unwrap();
+}
+
+#[tokio::test]
+async fn unhealthy_maintenance() {
+    let resp = b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n";
+    let policy = UnhealthyPolicy::Maintenance(resp.to_vec());
+    let lis = run("127.0.0.1:10502", ["127.0.0.1:20504", "127.0.0.1:20505"], policy).await;
+
+    let mut stream = TcpStream::connect("127.0.0.1:10502").await.unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+    let mut buf = Vec::new();
+    stream.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(buf, resp);
+
+    no_accept(&lis).await;
+}
@@ -0,0 +1,85 @@
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use tokio::net::TcpStream;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::time::{sleep, timeout};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
+
+mod common;
+use common::echo;
+
+#[tokio::test]
+async fn idle_reaped() {
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:12250".parse().unwrap(),
+        raddr: "127.0.0.1:22250"
+            .parse::<SocketAddr>()
+            .map(RemoteAddr::SocketAddr)
+            .unwrap(),
+        conn_opts: ConnectOpts {
+            idle_timeout: 1,
+            prune_interval: 300,
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    };
+    let conns = endpoint.conn_opts.conns.clone();
+    tokio::spawn(echo("127.0.0.1:22250"));
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(200)).await;
+
+    let mut stream = TcpStream::connect("127.0.0.1:12250").await.unwrap();
+    let mut buf = [0u8; 2];
+
+    // data keeps it alive
+    for _ in 0..4 {
+        stream.write_all(b"hi").await.unwrap();
+        stream.read_exact(&mut buf).await.unwrap();
+        sleep(Duration::from_millis(500)).await;
+    }
+    assert_eq!(conns.list().len(), 1);
+
+    // closed within one interval after crossing the timeout
+    stream.write_all(b"hi").await.unwrap();
+    stream.read_exact(&mut buf).await.unwrap();
+    let last = Instant::now();
+    let n = timeout(Duration::from_secs(3), stream.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    let elapsed = last.elapsed();
+    assert_eq!(n, 0);
+    assert!(elapsed >= Duration::from_secs(1), "{:?}", elapsed);
+    assert!(elapsed <= Duration::from_millis(1300 + 100), "{:?}", elapsed);
+    assert!(conns.list().is_empty());
+}
+
+#[tokio::test]
+async fn idle_reaped_after_stop() {
+    let conn_opts = ConnectOpts {
+        idle_timeout: 1,
+        prune_interval: 300,
+        ..Default::default()
+    };
+    let endpoint = common::endpoint("127.0.0.1:12251", "127.0.0.1:22251", conn_opts);
+    tokio::spawn(echo("127.0.0.1:22251"));
+    let listener = tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(200)).await;
+
+    let mut stream = TcpStream::connect("127.0.0.1:12251").await.unwrap();
+    let mut buf = [0u8; 2];
+    stream.write_all(b"hi").await.unwrap();
+    stream.read_exact(&mut buf).await.unwrap();
+
+    // stop accepting, the connection is still swept
+    listener.abort();
+    let n = timeout(Duration::from_secs(3), stream.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(n, 0);
+}
@@ -0,0 +1,72 @@
+#![cfg(target_os = "linux")]
+
+use std::net::SocketAddr;
+use std::os::fd::BorrowedFd;
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::sleep;
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
+use realm_core::realm_syscall::socket2::SockRef;
+
+// (time, interval, retries) of the socket in this process
+fn keepalive_of(local: SocketAddr, peer: SocketAddr) -> (Duration, Duration, u32) {
+    for entry in std::fs::read_dir("/proc/self/fd").unwrap() {
+        let fd = match entry.unwrap().file_name().to_str().unwrap().parse() {
+            Ok(fd) => fd,
+            Err(_) => continue,
+        };
+        let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+        let sock = SockRef::from(&fd);
+        let l = sock.local_addr().ok().and_then(|x| x.as_socket());
+        let p = sock.peer_addr().ok().and_then(|x| x.as_socket());
+        if (l, p) != (Some(local), Some(peer)) {
+            continue;
+        }
+        assert!(sock.keepalive().unwrap());
+        return (
+            sock.keepalive_time().unwrap(),
+            sock.keepalive_interval().unwrap(),
+            sock.keepalive_retries().unwrap(),
+        );
+    }
+    panic!("no socket of {} => {}", local, peer);
+}
+
+#[tokio::test]
+async fn keepalive() {
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:11000".parse().unwrap(),
+        raddr: "127.0.0.1:21000"
+            .parse::<SocketAddr>()
+            .map(RemoteAddr::SocketAddr)
+            .unwrap(),
+        conn_opts: ConnectOpts {
+            tcp_keepalive: 20,
+            tcp_keepalive_interval: 7,
+            tcp_keepalive_probe: 4,
+            connect_timeout: 5,
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    };
+
+    let lis = TcpListener::bind("127.0.0.1:21000").await.unwrap();
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let client = TcpStream::connect("127.0.0.1:11000").await.unwrap();
+    let (_remote, relay) = lis.accept().await.unwrap();
+
+    let expect = (Duration::from_secs(20), Duration::from_secs(7), 4);
+    // accepted from the client
+    assert_eq!(
+        keepalive_of(client.peer_addr().unwrap(), client.local_addr().unwrap()),
+        expect
+    );
+    // connected to the remote peer
+    assert_eq!(keepalive_of(relay, "127.0.0.1:21000".parse().unwrap()), expect);
+}
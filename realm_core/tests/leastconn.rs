@@ -0,0 +1,94 @@
+#![cfg(feature = "balance")]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout};
+use tokio::io::AsyncReadExt;
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, ConnectOpts};
+use realm_core::health::Load;
+use realm_core::balance::{Balancer, Strategy};
+
+mod common;
+use common::remote;
+
+// reports its index once a connection is accepted, holds it until eof
+async fn upstream(addr: &str, idx: usize, tx: mpsc::UnboundedSender<usize>) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        tx.send(idx).unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 32];
+            while stream.read(&mut buf).await.is_ok_and(|n| n != 0) {}
+        });
+    }
+}
+
+fn endpoint(laddr: &str, raddrs: [&str; 2], weights: &[u8]) -> Endpoint {
+    let conn_opts = ConnectOpts {
+        balancer: Balancer::new(Strategy::LeastConn, weights),
+        load: Arc::new(Load::new(2)),
+        ..Default::default()
+    };
+    Endpoint {
+        extra_raddrs: vec![remote(raddrs[1])],
+        ..common::endpoint(laddr, raddrs[0], conn_opts)
+    }
+}
+
+// the peer of each new connection
+async fn open(laddr: &str, n: usize, rx: &mut mpsc::UnboundedReceiver<usize>) -> (Vec<TcpStream>, Vec<usize>) {
+    let mut streams = Vec::new();
+    let mut peers = Vec::new();
+    for _ in 0..n {
+        streams.push(TcpStream::connect(laddr).await.unwrap());
+        peers.push(timeout(Duration::from_secs(3), rx.recv()).await.unwrap().unwrap());
+    }
+    (streams, peers)
+}
+
+#[tokio::test]
+async fn least_conn() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    tokio::spawn(upstream("127.0.0.1:22270", 0, tx.clone()));
+    tokio::spawn(upstream("127.0.0.1:22271", 1, tx));
+    let raddrs = ["127.0.0.1:22270", "127.0.0.1:22271"];
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:12270", raddrs, &[1, 1])));
+    sleep(Duration::from_millis(500)).await;
+
+    // ties go to the first peer
+    let (first, peers) = open("127.0.0.1:12270", 4, &mut rx).await;
+    assert_eq!(peers, [0, 1, 0, 1]);
+
+    // close the ones on the first peer
+    let mut held = Vec::new();
+    for (stream, peer) in first.into_iter().zip(peers) {
+        if peer == 1 {
+            held.push(stream);
+        }
+    }
+    sleep(Duration::from_millis(200)).await;
+
+    // new ones go there until both have 2, then the tie again
+    let (_second, peers) = open("127.0.0.1:12270", 4, &mut rx).await;
+    assert_eq!(peers, [0, 0, 0, 1]);
+}
+
+#[tokio::test]
+async fn weighted_least_conn() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    tokio::spawn(upstream("127.0.0.1:22272", 0, tx.clone()));
+    tokio::spawn(upstream("127.0.0.1:22273", 1, tx));
+    let raddrs = ["127.0.0.1:22272", "127.0.0.1:22273"];
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:12272", raddrs, &[2, 1])));
+    sleep(Duration::from_millis(500)).await;
+
+    // 0/2 = 0/1, 1/2 > 0/1, 1/2 < 1/1, 2/2 = 1/1, 3/2 > 1/1
+    let (_streams, peers) = open("127.0.0.1:12272", 6, &mut rx).await;
+    assert_eq!(peers, [0, 1, 0, 0, 1, 0]);
+}
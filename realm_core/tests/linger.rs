@@ -0,0 +1,62 @@
+use std::io::Result;
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout};
+use tokio::io::AsyncReadExt;
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, ConnectOpts};
+
+mod common;
+
+fn endpoint(laddr: &str, raddr: &str, linger: Option<usize>) -> Endpoint {
+    let conn_opts = ConnectOpts {
+        linger,
+        // both sides are silent, the relay closes them
+        deadlock_timeout: 1,
+        ..Default::default()
+    };
+    common::endpoint(laddr, raddr, conn_opts)
+}
+
+// reports how the relay closed the connection
+async fn upstream(addr: &str, tx: mpsc::UnboundedSender<Result<usize>>) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 32];
+            let _ = tx.send(stream.read(&mut buf).await);
+        });
+    }
+}
+
+async fn closed(laddr: &str) -> Result<usize> {
+    let mut stream = TcpStream::connect(laddr).await.unwrap();
+    let mut buf = [0u8; 32];
+    timeout(Duration::from_secs(3), stream.read(&mut buf)).await.unwrap()
+}
+
+#[tokio::test]
+async fn linger() {
+    let (tx1, mut rx1) = mpsc::unbounded_channel();
+    let (tx2, mut rx2) = mpsc::unbounded_channel();
+    tokio::spawn(upstream("127.0.0.1:22210", tx1));
+    tokio::spawn(upstream("127.0.0.1:22211", tx2));
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:12210", "127.0.0.1:22210", Some(0))));
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:12211", "127.0.0.1:22211", None)));
+    sleep(Duration::from_millis(500)).await;
+
+    // linger 0 resets both sides
+    let e = closed("127.0.0.1:12210").await.unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::ConnectionReset);
+    let e = rx1.recv().await.unwrap().unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::ConnectionReset);
+
+    // a graceful close by default
+    assert_eq!(closed("127.0.0.1:12211").await.unwrap(), 0);
+    assert_eq!(rx2.recv().await.unwrap().unwrap(), 0);
+}
@@ -0,0 +1,75 @@
+#![cfg(feature = "proxy")]
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::sleep;
+use tokio::io::AsyncReadExt;
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{ConnectOpts, ProxyOpts};
+
+mod common;
+use common::{LOGS, capture_logs};
+
+// keep the proxy v1 line
+async fn backend(addr: &str, seen: Arc<Mutex<String>>) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    let (mut stream, _) = lis.accept().await.unwrap();
+    let mut buf = [0; 128];
+    let mut n = 0;
+    while !buf[..n].contains(&b'\n') {
+        n += stream.read(&mut buf[n..]).await.unwrap();
+    }
+    *seen.lock().unwrap() = String::from_utf8_lossy(&buf[..n]).into_owned();
+    sleep(Duration::from_secs(1)).await;
+}
+
+#[tokio::test]
+async fn mapped_addr() {
+    capture_logs(log::LevelFilter::Info);
+
+    let conn_opts = ConnectOpts {
+        proxy_opts: ProxyOpts {
+            send_proxy: true,
+            send_proxy_version: 1,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let endpoint = common::endpoint("[::]:12320", "127.0.0.1:22320", conn_opts);
+    let conns = endpoint.conn_opts.conns.clone();
+
+    let seen = Arc::new(Mutex::new(String::new()));
+    tokio::spawn(backend("127.0.0.1:22320", seen.clone()));
+    tokio::spawn(run_tcp(endpoint));
+
+    sleep(Duration::from_millis(500)).await;
+
+    // over ipv4, seen as ::ffff:127.0.0.1 by the listener
+    let stream = TcpStream::connect("127.0.0.1:12320").await.unwrap();
+    let client = stream.local_addr().unwrap();
+
+    sleep(Duration::from_millis(500)).await;
+
+    // registry
+    let list = conns.list();
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0].src, client);
+    assert_eq!(list[0].dst, "127.0.0.1:12320".parse().unwrap());
+
+    // proxy protocol
+    let header = seen.lock().unwrap().clone();
+    assert!(
+        header.starts_with(&format!("PROXY TCP4 {} ", client.ip())),
+        "{}",
+        header
+    );
+
+    // logs
+    let logs = LOGS.lock().unwrap();
+    let prefix = format!("[tcp]{} => ", client);
+    assert!(logs.iter().any(|x| x.starts_with(&prefix)), "{:?}", logs);
+    assert!(!logs.iter().any(|x| x.contains("::ffff:")), "{:?}", logs);
+}
@@ -0,0 +1,96 @@
+#![cfg(all(feature = "balance", feature = "transport"))]
+
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, ConnectOpts, PeerOpts};
+use realm_core::balance::Balancer;
+
+use realm_core::kaminari::tls::{TlsClientConf, TlsServerConf};
+use realm_core::kaminari::mix::{MixAccept, MixConnect, MixClientConf, MixServerConf};
+
+mod common;
+use common::{echo, remote};
+
+fn tls_connect(insecure: bool) -> MixConnect {
+    MixConnect::new_shared(MixClientConf {
+        ws: None,
+        tls: Some(TlsClientConf {
+            sni: String::from("localhost"),
+            alpn: Vec::new(),
+            insecure,
+            early_data: false,
+        }),
+    })
+}
+
+// a self-signed certificate, which only passes without verification
+fn tls_server(laddr: &str, raddr: &str) -> Endpoint {
+    let ac = MixAccept::new_shared(MixServerConf {
+        ws: None,
+        tls: Some(TlsServerConf {
+            crt: String::new(),
+            key: String::new(),
+            ocsp: String::new(),
+            server_name: String::from("localhost"),
+        }),
+    });
+    let cc = MixConnect::new_shared(MixClientConf { ws: None, tls: None });
+    Endpoint {
+        laddr: laddr.parse().unwrap(),
+        raddr: remote(raddr),
+        conn_opts: ConnectOpts {
+            transport: Some((ac, cc)),
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    }
+}
+
+// whether hello is echoed through the relay
+async fn echoed(laddr: &str) -> bool {
+    let mut stream = TcpStream::connect(laddr).await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    let res = timeout(Duration::from_secs(3), stream.read_exact(&mut buf)).await;
+    matches!(res, Ok(Ok(_))) && &buf == b"hello"
+}
+
+#[tokio::test]
+async fn peer_insecure() {
+    tokio::spawn(echo("127.0.0.1:22300"));
+    tokio::spawn(run_tcp(tls_server("127.0.0.1:12301", "127.0.0.1:22300")));
+    tokio::spawn(run_tcp(tls_server("127.0.0.1:12302", "127.0.0.1:22300")));
+
+    // verified by default, except the first peer
+    let plain = MixAccept::new_shared(MixServerConf { ws: None, tls: None });
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:12300".parse().unwrap(),
+        raddr: remote("127.0.0.1:12301"),
+        conn_opts: ConnectOpts {
+            transport: Some((plain, tls_connect(false))),
+            peer_opts: vec![
+                PeerOpts {
+                    transport: Some(tls_connect(true)),
+                    ..Default::default()
+                },
+                PeerOpts::default(),
+            ],
+            balancer: Balancer::parse_from_str("roundrobin: 1, 1"),
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: vec![remote("127.0.0.1:12302")],
+    };
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    assert!(echoed("127.0.0.1:12300").await);
+    assert!(!echoed("127.0.0.1:12300").await);
+    assert!(echoed("127.0.0.1:12300").await);
+}
@@ -0,0 +1,119 @@
+#![cfg(feature = "balance")]
+
+use std::sync::Arc;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::health::Health;
+use realm_core::endpoint::{Endpoint, ConnectOpts};
+use realm_core::realm_syscall::socket2::{Domain, Socket, Type};
+
+mod common;
+use common::remote;
+
+fn endpoint(laddr: &str, per_attempt_timeout: usize) -> Endpoint {
+    let conn_opts = ConnectOpts {
+        connect_timeout: 3,
+        per_attempt_timeout,
+        health: Arc::new(Health::new(2, Duration::from_secs(60))),
+        ..Default::default()
+    };
+    Endpoint {
+        extra_raddrs: vec![remote("127.0.0.1:22181")],
+        ..common::endpoint(laddr, "127.0.0.1:22180", conn_opts)
+    }
+}
+
+// a full accept queue drops new syns, connecting hangs
+async fn slow(addr: &str) -> Socket {
+    let addr: SocketAddr = addr.parse().unwrap();
+    let lis = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+    lis.bind(&addr.into()).unwrap();
+    lis.listen(0).unwrap();
+    for _ in 0..4 {
+        let _ = timeout(Duration::from_millis(100), TcpStream::connect(addr))
+            .await
+            .map(|x| x.map(std::mem::forget));
+    }
+    lis
+}
+
+async fn fast(addr: &str) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        stream.write_all(b"fast").await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn per_attempt() {
+    let _slow = slow("127.0.0.1:22180").await;
+    tokio::spawn(fast("127.0.0.1:22181"));
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:12180", 1)));
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:12181", 0)));
+    sleep(Duration::from_millis(500)).await;
+
+    // the first peer is given up after 1s
+    let start = Instant::now();
+    let mut stream = TcpStream::connect("127.0.0.1:12180").await.unwrap();
+    let mut buf = [0u8; 4];
+    stream.read_exact(&mut buf).await.unwrap();
+    let elapsed = start.elapsed();
+    assert_eq!(&buf, b"fast");
+    assert!(
+        elapsed >= Duration::from_millis(900) && elapsed < Duration::from_millis(1500),
+        "{:?}",
+        elapsed
+    );
+
+    // then it is down, the next one goes to the second at once
+    let start = Instant::now();
+    let mut stream = TcpStream::connect("127.0.0.1:12180").await.unwrap();
+    stream.read_exact(&mut buf).await.unwrap();
+    assert!(start.elapsed() < Duration::from_millis(300), "{:?}", start.elapsed());
+
+    // without it, the whole connect timeout is used, and the client is closed
+    let start = Instant::now();
+    let mut stream = TcpStream::connect("127.0.0.1:12181").await.unwrap();
+    let n = stream.read(&mut buf).await.unwrap_or(0);
+    let elapsed = start.elapsed();
+    assert_eq!(n, 0);
+    assert!(elapsed >= Duration::from_millis(2900), "{:?}", elapsed);
+}
+
+#[tokio::test]
+async fn per_attempt_budget() {
+    let _slow1 = slow("127.0.0.1:22182").await;
+    let _slow2 = slow("127.0.0.1:22183").await;
+    let conn_opts = ConnectOpts {
+        connect_timeout: 2,
+        per_attempt_timeout: 2,
+        health: Arc::new(Health::new(2, Duration::from_secs(60))),
+        ..Default::default()
+    };
+    let endpoint = Endpoint {
+        extra_raddrs: vec![remote("127.0.0.1:22183")],
+        ..common::endpoint("127.0.0.1:12182", "127.0.0.1:22182", conn_opts)
+    };
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    // both peers hang, closed once the connect timeout is used up
+    let start = Instant::now();
+    let mut stream = TcpStream::connect("127.0.0.1:12182").await.unwrap();
+    let mut buf = [0u8; 4];
+    let n = stream.read(&mut buf).await.unwrap_or(0);
+    let elapsed = start.elapsed();
+    assert_eq!(n, 0);
+    assert!(
+        elapsed >= Duration::from_millis(1900) && elapsed < Duration::from_millis(3000),
+        "{:?}",
+        elapsed
+    );
+}
@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, ConnectOpts};
+
+mod common;
+use common::remote;
+
+async fn backend(addr: &str, name: &'static str) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0; 32];
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_eq!(b"who", &buf[..n]);
+            stream.write_all(name.as_bytes()).await.unwrap();
+        });
+    }
+}
+
+async fn who(laddr: &str) -> String {
+    let mut stream = TcpStream::connect(laddr).await.unwrap();
+    stream.write_all(b"who").await.unwrap();
+    let mut buf = vec![0; 32];
+    let n = stream.read(&mut buf).await.unwrap();
+    String::from_utf8_lossy(&buf[..n]).into_owned()
+}
+
+fn endpoint(laddr: &str) -> Endpoint {
+    let conn_opts = ConnectOpts {
+        port_routes: vec![(80, remote("127.0.0.1:21101")), (11100, remote("127.0.0.1:21102"))],
+        ..Default::default()
+    };
+    common::endpoint(laddr, "127.0.0.1:21100", conn_opts)
+}
+
+// Only the fallback of original_dst is covered: without a redirect,
+// the original destination is the listen address. A connection
+// redirected by iptables, whose destination is read back with
+// SO_ORIGINAL_DST, needs root and a netfilter table, so it is not
+// tested here.
+#[tokio::test]
+async fn port_routes_local_fallback() {
+    tokio::spawn(backend("127.0.0.1:21100", "default"));
+    tokio::spawn(backend("127.0.0.1:21101", "a"));
+    tokio::spawn(backend("127.0.0.1:21102", "b"));
+
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:11100")));
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:11101")));
+
+    sleep(Duration::from_millis(500)).await;
+
+    assert_eq!(who("127.0.0.1:11100").await, "b");
+    assert_eq!(who("127.0.0.1:11101").await, "default");
+}
@@ -0,0 +1,58 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use std::io::Read;
+
+use tokio::net::TcpListener;
+use tokio::time::sleep;
+
+use realm_core::tcp::{run_tcp, DropReason};
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pre_close() {
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:12260".parse().unwrap(),
+        raddr: "127.0.0.1:22260"
+            .parse::<SocketAddr>()
+            .map(RemoteAddr::SocketAddr)
+            .unwrap(),
+        conn_opts: ConnectOpts {
+            first_byte_timeout: 1,
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    };
+
+    // takes a while, the client must not be closed meanwhile
+    let notified = Arc::new(Mutex::new(Vec::new()));
+    let notified2 = notified.clone();
+    endpoint.conn_opts.pre_close.set(Some(Box::new(move |client, reason| {
+        notified2.lock().unwrap().push((client, reason, Instant::now()));
+        std::thread::sleep(Duration::from_millis(300));
+    })));
+
+    let _upstream = TcpListener::bind("127.0.0.1:22260").await.unwrap();
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(200)).await;
+
+    // blocking, in case the callback holds the io driver
+    let (client, closed) = tokio::task::spawn_blocking(|| {
+        let mut stream = std::net::TcpStream::connect("127.0.0.1:12260").unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+        let mut buf = [0u8; 1];
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+        (stream.local_addr().unwrap(), Instant::now())
+    })
+    .await
+    .unwrap();
+
+    let notified = notified.lock().unwrap();
+    assert_eq!(notified.len(), 1);
+    let (addr, reason, at) = notified[0];
+    assert_eq!(addr, client);
+    assert_eq!(reason, DropReason::ClientSilent);
+    assert!(closed >= at + Duration::from_millis(300));
+}
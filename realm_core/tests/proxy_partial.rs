@@ -0,0 +1,119 @@
+#![cfg(feature = "proxy")]
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts, ProxyOpts};
+
+mod common;
+use common::{LOGS, capture_logs, echo};
+
+// how long until closed by the relay, and the reason logged
+async fn dropped(stream: &mut TcpStream, start: Instant) -> (Duration, String) {
+    let mut buf = vec![0; 32];
+    let n = timeout(Duration::from_secs(5), stream.read(&mut buf)).await.unwrap();
+    assert_eq!(n.unwrap_or(0), 0);
+    let elapsed = start.elapsed();
+    sleep(Duration::from_millis(100)).await;
+
+    let client = stream.local_addr().unwrap();
+    let logs = LOGS.lock().unwrap();
+    let log = logs
+        .iter()
+        .find(|x| x.starts_with(&format!("[tcp]{} dropped", client)))
+        .unwrap_or_else(|| panic!("{} is not dropped", client));
+    (elapsed, log.clone())
+}
+
+#[tokio::test]
+async fn proxy_partial() {
+    capture_logs(log::LevelFilter::Warn);
+
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:12140".parse().unwrap(),
+        raddr: "127.0.0.1:22140"
+            .parse::<SocketAddr>()
+            .map(RemoteAddr::SocketAddr)
+            .unwrap(),
+        conn_opts: ConnectOpts {
+            proxy_opts: ProxyOpts {
+                accept_proxy: true,
+                accept_proxy_timeout: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    };
+
+    tokio::spawn(echo("127.0.0.1:22140"));
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    // v2, tcp over ipv4, 12 bytes of addresses
+    let v2 = [
+        b"\r\n\r\n\0\r\nQUIT\n".as_slice(),
+        &[0x21, 0x11, 0, 12],
+        &[127, 0, 0, 2, 127, 0, 0, 3, 0x03, 0xe8, 0x07, 0xd0],
+    ]
+    .concat();
+    let v1 = b"PROXY TCP4 127.0.0.2 127.0.0.3 1000 2000\r\n";
+
+    // truncated, then closed
+    let start = Instant::now();
+    let mut stream = TcpStream::connect("127.0.0.1:12140").await.unwrap();
+    stream.write_all(&v2[..20]).await.unwrap();
+    stream.shutdown().await.unwrap();
+    let (elapsed, log) = dropped(&mut stream, start).await;
+    assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+    assert!(log.contains("reason=proxy_malformed: "), "{}", log);
+    assert!(log.contains("truncated"), "{}", log);
+
+    // not a proxy header
+    let start = Instant::now();
+    let mut stream = TcpStream::connect("127.0.0.1:12140").await.unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+    let (elapsed, log) = dropped(&mut stream, start).await;
+    assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+    assert!(log.contains("reason=proxy_malformed: "), "{}", log);
+
+    // dribbled slower than the timeout
+    let start = Instant::now();
+    let mut stream = TcpStream::connect("127.0.0.1:12140").await.unwrap();
+    let (mut rd, mut wr) = stream.split();
+    let dribble = async {
+        for x in v1.iter() {
+            if wr.write_all(&[*x]).await.is_err() {
+                break;
+            }
+            sleep(Duration::from_millis(200)).await;
+        }
+    };
+    let mut buf = vec![0; 32];
+    tokio::select! {
+        _ = dribble => panic!("not dropped"),
+        n = rd.read(&mut buf) => assert_eq!(n.unwrap_or(0), 0),
+    }
+    let (elapsed, log) = dropped(&mut stream, start).await;
+    assert!(elapsed >= Duration::from_millis(1900), "{:?}", elapsed);
+    assert!(log.contains("reason=proxy_timeout: "), "{}", log);
+
+    // dribbled within the timeout
+    for header in [v1.as_slice(), &v2] {
+        let mut stream = TcpStream::connect("127.0.0.1:12140").await.unwrap();
+        for chunk in header.chunks(5) {
+            stream.write_all(chunk).await.unwrap();
+            sleep(Duration::from_millis(50)).await;
+        }
+        stream.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+}
@@ -0,0 +1,85 @@
+#![cfg(all(feature = "proxy", feature = "balance"))]
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, ConnectOpts, ProxyOpts, PeerOpts};
+use realm_core::balance::Balancer;
+
+mod common;
+use common::remote;
+
+const V2_SIG: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+type Seen = Arc<Mutex<Vec<(usize, bool)>>>;
+
+// record (peer, whether the stream starts with a proxy v2 header)
+async fn backend(addr: &str, peer: usize, seen: Seen) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        let seen = seen.clone();
+        tokio::spawn(async move {
+            let mut buf = [0; 128];
+            let mut n = 0;
+            while n < V2_SIG.len() {
+                match stream.read(&mut buf[n..]).await.unwrap() {
+                    0 => break,
+                    x => n += x,
+                }
+            }
+            seen.lock().unwrap().push((peer, buf[..n].starts_with(V2_SIG)));
+        });
+    }
+}
+
+#[tokio::test]
+async fn proxy_per_peer() {
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:10800".parse().unwrap(),
+        raddr: remote("127.0.0.1:20800"),
+        conn_opts: ConnectOpts {
+            proxy_opts: ProxyOpts {
+                send_proxy: false,
+                send_proxy_version: 2,
+                ..Default::default()
+            },
+            peer_opts: vec![
+                PeerOpts::default(),
+                PeerOpts {
+                    send_proxy: Some(true),
+                    ..Default::default()
+                },
+            ],
+            balancer: Balancer::parse_from_str("roundrobin: 1, 1"),
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: vec![remote("127.0.0.1:20801")],
+    };
+
+    let seen = Seen::default();
+    tokio::spawn(backend("127.0.0.1:20800", 0, seen.clone()));
+    tokio::spawn(backend("127.0.0.1:20801", 1, seen.clone()));
+    tokio::spawn(run_tcp(endpoint));
+
+    sleep(Duration::from_millis(500)).await;
+
+    let mut streams = Vec::new();
+    for _ in 0..2 {
+        let mut stream = TcpStream::connect("127.0.0.1:10800").await.unwrap();
+        stream.write_all(b"hello, this is a long enough payload").await.unwrap();
+        streams.push(stream);
+    }
+
+    sleep(Duration::from_millis(500)).await;
+
+    let mut seen = seen.lock().unwrap().clone();
+    seen.sort();
+    assert_eq!(seen, vec![(0, false), (1, true)]);
+}
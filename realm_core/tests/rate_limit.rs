@@ -0,0 +1,66 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
+
+#[tokio::test]
+async fn rate_limit() {
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:11700".parse().unwrap(),
+        raddr: "127.0.0.1:21700"
+            .parse::<SocketAddr>()
+            .map(RemoteAddr::SocketAddr)
+            .unwrap(),
+        conn_opts: ConnectOpts::default(),
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    };
+    let stat = endpoint.conn_opts.stat.clone();
+    let limit = endpoint.conn_opts.rate_limit.clone();
+
+    let lis = TcpListener::bind("127.0.0.1:21700").await.unwrap();
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let mut client = TcpStream::connect("127.0.0.1:11700").await.unwrap();
+    let (mut remote, _) = lis.accept().await.unwrap();
+
+    // the remote keeps sending, the client keeps receiving
+    tokio::spawn(async move {
+        let buf = vec![0u8; 64 * 1024];
+        while remote.write_all(&buf).await.is_ok() {}
+    });
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 64 * 1024];
+        while matches!(client.read(&mut buf).await, Ok(n) if n != 0) {}
+    });
+
+    let downloaded = |secs| {
+        let stat = stat.clone();
+        async move {
+            let start = stat.snapshot().bytes_down;
+            sleep(Duration::from_secs_f64(secs)).await;
+            stat.snapshot().bytes_down - start
+        }
+    };
+
+    sleep(Duration::from_millis(200)).await;
+    let unlimited = downloaded(0.5).await;
+    assert!(unlimited > 4 * 1024 * 1024, "unlimited: {}", unlimited);
+
+    // lowered during the transfer, takes effect on the active connection
+    limit.set(256 * 1024);
+    sleep(Duration::from_millis(500)).await;
+    let limited = downloaded(1.0).await;
+    assert!((128 * 1024..512 * 1024).contains(&limited), "limited: {}", limited);
+
+    limit.set(0);
+    sleep(Duration::from_millis(200)).await;
+    let restored = downloaded(0.5).await;
+    assert!(restored > 4 * 1024 * 1024, "restored: {}", restored);
+}
@@ -0,0 +1,124 @@
+use std::time::{Duration, Instant};
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, ConnectOpts};
+
+mod common;
+use common::{echo, remote};
+
+// reads whatever is sent, never replies
+async fn silent(addr: &str) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0; 32];
+            while let Ok(1..) = stream.read(&mut buf).await {}
+        });
+    }
+}
+
+// None if the connection is still open after the wait
+async fn try_read(stream: &mut TcpStream, wait: Duration) -> Option<usize> {
+    let mut buf = vec![0; 32];
+    timeout(wait, stream.read(&mut buf)).await.ok().map(|x| x.unwrap_or(0))
+}
+
+#[tokio::test]
+async fn remote_silent() {
+    let endpoint = |laddr: &str, raddr: &str| {
+        let conn_opts = ConnectOpts {
+            remote_first_byte_timeout: 1,
+            ..Default::default()
+        };
+        common::endpoint(laddr, raddr, conn_opts)
+    };
+
+    tokio::spawn(silent("127.0.0.1:22100"));
+    tokio::spawn(echo("127.0.0.1:22101"));
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:12100", "127.0.0.1:22100")));
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:12101", "127.0.0.1:22101")));
+    sleep(Duration::from_millis(500)).await;
+
+    // closed once the window is over
+    let start = Instant::now();
+    let mut stream = TcpStream::connect("127.0.0.1:12100").await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    assert_eq!(try_read(&mut stream, Duration::from_secs(3)).await, Some(0));
+    assert!(start.elapsed() >= Duration::from_millis(900));
+
+    // idle after the first byte is fine
+    let mut stream = TcpStream::connect("127.0.0.1:12101").await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    assert_eq!(try_read(&mut stream, Duration::from_secs(1)).await, Some(5));
+    assert_eq!(try_read(&mut stream, Duration::from_millis(1500)).await, None);
+    stream.write_all(b"hello").await.unwrap();
+    assert_eq!(try_read(&mut stream, Duration::from_secs(1)).await, Some(5));
+}
+
+// the ws handshake succeeds, then the upstream hangs
+#[cfg(all(feature = "transport", feature = "balance"))]
+#[tokio::test]
+async fn remote_silent_rebalance() {
+    use std::sync::Arc;
+    use realm_core::health::Health;
+    use realm_core::kaminari::ws::WsConf;
+    use realm_core::kaminari::mix::{MixAccept, MixConnect, MixClientConf, MixServerConf};
+
+    let ws = || {
+        Some(WsConf {
+            host: String::from("realm"),
+            path: String::from("/silent"),
+        })
+    };
+    let ws_endpoint = |laddr: &str, raddr: &str| {
+        let conn_opts = ConnectOpts {
+            transport: Some((
+                MixAccept::new_shared(MixServerConf { ws: ws(), tls: None }),
+                MixConnect::new_shared(MixClientConf { ws: None, tls: None }),
+            )),
+            ..Default::default()
+        };
+        common::endpoint(laddr, raddr, conn_opts)
+    };
+
+    // client => 12102[ws] => [ws]12103 => silent
+    //                     => [ws]12104 => echo
+    let health = Arc::new(Health::new(2, Duration::from_secs(60)));
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:12102".parse().unwrap(),
+        raddr: remote("127.0.0.1:12103"),
+        conn_opts: ConnectOpts {
+            remote_first_byte_timeout: 1,
+            transport: Some((
+                MixAccept::new_shared(MixServerConf { ws: None, tls: None }),
+                MixConnect::new_shared(MixClientConf { ws: ws(), tls: None }),
+            )),
+            health: health.clone(),
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: vec![remote("127.0.0.1:12104")],
+    };
+
+    tokio::spawn(silent("127.0.0.1:22102"));
+    tokio::spawn(echo("127.0.0.1:22103"));
+    tokio::spawn(run_tcp(ws_endpoint("127.0.0.1:12103", "127.0.0.1:22102")));
+    tokio::spawn(run_tcp(ws_endpoint("127.0.0.1:12104", "127.0.0.1:22103")));
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let mut stream = TcpStream::connect("127.0.0.1:12102").await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    assert_eq!(try_read(&mut stream, Duration::from_secs(3)).await, Some(0));
+    assert!(!health.is_up(0));
+
+    // balanced to the other peer
+    let mut stream = TcpStream::connect("127.0.0.1:12102").await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    assert_eq!(try_read(&mut stream, Duration::from_secs(1)).await, Some(5));
+}
@@ -0,0 +1,87 @@
+use std::sync::Arc;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener, UdpSocket};
+use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::dns::new_resolver;
+use realm_core::dns::config::{NameServerConfigGroup, ResolverConfig};
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
+
+// answers every A query with this ip
+async fn nameserver(addr: &str, ip: Ipv4Addr) {
+    let sock = UdpSocket::bind(addr).await.unwrap();
+    let mut buf = vec![0; 512];
+    loop {
+        let (n, peer) = sock.recv_from(&mut buf).await.unwrap();
+        let query = &buf[..n];
+
+        // skip qname, then qtype and qclass
+        let mut end = 12;
+        while query[end] != 0 {
+            end += query[end] as usize + 1;
+        }
+        end += 5;
+        let is_a = query[end - 4..end - 2] == [0, 1];
+
+        let mut resp = Vec::from(&query[..2]);
+        resp.extend([0x81, 0x80, 0, 1, 0, is_a as u8, 0, 0, 0, 0]);
+        resp.extend(&query[12..end]);
+        if is_a {
+            resp.extend([0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4]);
+            resp.extend(ip.octets());
+        }
+        sock.send_to(&resp, peer).await.unwrap();
+    }
+}
+
+async fn backend(addr: &str, name: &'static str) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        stream.write_all(name.as_bytes()).await.unwrap();
+    }
+}
+
+async fn who(laddr: &str) -> String {
+    let mut stream = TcpStream::connect(laddr).await.unwrap();
+    let mut buf = vec![0; 32];
+    let n = stream.read(&mut buf).await.unwrap();
+    String::from_utf8_lossy(&buf[..n]).into_owned()
+}
+
+fn endpoint(laddr: &str, nameserver: &str) -> Endpoint {
+    let nameserver: SocketAddr = nameserver.parse().unwrap();
+    let servers = NameServerConfigGroup::from_ips_clear(&[nameserver.ip()], nameserver.port(), true);
+    let conf = ResolverConfig::from_parts(None, Vec::new(), servers);
+
+    Endpoint {
+        laddr: laddr.parse().unwrap(),
+        raddr: RemoteAddr::DomainName(String::from("backend.realm.test"), 22000),
+        conn_opts: ConnectOpts {
+            resolver: Some(Arc::new(new_resolver(Some(conf), None))),
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    }
+}
+
+#[tokio::test]
+async fn endpoint_resolver() {
+    tokio::spawn(nameserver("127.0.0.1:22053", Ipv4Addr::new(127, 0, 0, 1)));
+    tokio::spawn(nameserver("127.0.0.1:22054", Ipv4Addr::new(127, 0, 0, 2)));
+    tokio::spawn(backend("127.0.0.1:22000", "a"));
+    tokio::spawn(backend("127.0.0.2:22000", "b"));
+
+    // the same name, resolved differently
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:12000", "127.0.0.1:22053")));
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:12001", "127.0.0.1:22054")));
+    sleep(Duration::from_millis(500)).await;
+
+    assert_eq!(who("127.0.0.1:12000").await, "a");
+    assert_eq!(who("127.0.0.1:12001").await, "b");
+}
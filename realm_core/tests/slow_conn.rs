@@ -0,0 +1,86 @@
+#![cfg(feature = "transport")]
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
+
+use realm_core::kaminari::AsyncConnect;
+use realm_core::kaminari::ws::WsConf;
+use realm_core::kaminari::mix::{MixAccept, MixConnect, MixClientConf, MixServerConf};
+
+mod common;
+use common::{LOGS, capture_logs};
+
+#[tokio::test]
+async fn slow_conn() {
+    capture_logs(log::LevelFilter::Warn);
+
+    let ws = || {
+        Some(WsConf {
+            host: String::from("realm"),
+            path: String::from("/slow"),
+        })
+    };
+    let ac = MixAccept::new_shared(MixServerConf { ws: ws(), tls: None });
+    let cc = MixConnect::new_shared(MixClientConf { ws: None, tls: None });
+
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:10600".parse().unwrap(),
+        raddr: "127.0.0.1:20600"
+            .parse::<SocketAddr>()
+            .map(RemoteAddr::SocketAddr)
+            .unwrap(),
+        conn_opts: ConnectOpts {
+            transport: Some((ac, cc)),
+            slow_conn_threshold: 100,
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    };
+
+    tokio::spawn(run_tcp(endpoint));
+    tokio::spawn(async {
+        let lis = TcpListener::bind("127.0.0.1:20600").await.unwrap();
+        let (mut stream, _) = lis.accept().await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        stream.write_all(&buf).await.unwrap();
+    });
+    sleep(Duration::from_millis(500)).await;
+
+    // the websocket upgrade is sent late
+    let stream = TcpStream::connect("127.0.0.1:10600").await.unwrap();
+    sleep(Duration::from_millis(300)).await;
+
+    let cc = MixConnect::new_shared(MixClientConf { ws: ws(), tls: None });
+    let mut buf = vec![0; 0x2000];
+    let mut stream = cc.connect(stream, &mut buf).await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    // ws frames need a larger buffer
+    let n = stream.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+
+    let logs = LOGS.lock().unwrap();
+    let log = logs
+        .iter()
+        .find(|x| x.contains("slow connection"))
+        .expect("no slow log");
+    assert!(log.contains("127.0.0.1:20600"), "{}", log);
+    assert!(log.contains("dns=") && log.contains("connect="), "{}", log);
+
+    let handshake: u64 = log
+        .split("handshake=")
+        .nth(1)
+        .unwrap()
+        .trim_end_matches("ms")
+        .parse()
+        .unwrap();
+    assert!(handshake >= 250, "{}", log);
+}
@@ -0,0 +1,128 @@
+#![cfg(feature = "transport")]
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, ConnectOpts};
+
+use realm_core::kaminari::AsyncConnect;
+use realm_core::kaminari::tls::{TlsClientConf, TlsServerConf};
+use realm_core::kaminari::mix::{MixAccept, MixConnect, MixClientConf, MixServerConf};
+
+mod common;
+use common::{echo, remote};
+
+// None if the handshake fails
+async fn connect_with_sni(laddr: &str, sni: &str) -> Option<String> {
+    let cc = MixConnect::new_shared(MixClientConf {
+        ws: None,
+        tls: Some(TlsClientConf {
+            sni: String::from(sni),
+            alpn: Vec::new(),
+            insecure: true,
+            early_data: false,
+        }),
+    });
+
+    let stream = TcpStream::connect(laddr).await.unwrap();
+    let mut buf = vec![0; 0x2000];
+    let mut stream = cc.connect(stream, &mut buf).await.ok()?;
+
+    stream.write_all(b"hello").await.unwrap();
+    stream.flush().await.unwrap();
+    let n = stream.read(&mut buf).await.unwrap();
+    Some(String::from_utf8_lossy(&buf[..n]).into_owned())
+}
+
+fn endpoint(laddr: &str, allow_missing_sni: bool) -> Endpoint {
+    let ac = MixAccept::new_shared(MixServerConf {
+        ws: None,
+        tls: Some(TlsServerConf {
+            crt: String::new(),
+            key: String::new(),
+            ocsp: String::new(),
+            server_name: String::from("localhost"),
+        }),
+    });
+    let cc = MixConnect::new_shared(MixClientConf { ws: None, tls: None });
+
+    let conn_opts = ConnectOpts {
+        transport: Some((ac, cc)),
+        sni_allowlist: vec![String::from("allowed.test"), String::from("*.wild.test")],
+        allow_missing_sni,
+        ..Default::default()
+    };
+    common::endpoint(laddr, "127.0.0.1:22120", conn_opts)
+}
+
+#[tokio::test]
+async fn sni_allowlist() {
+    tokio::spawn(echo("127.0.0.1:22120"));
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:12120", false)));
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:12121", true)));
+    sleep(Duration::from_millis(500)).await;
+
+    let hello = Some(String::from("hello"));
+    let laddr = "127.0.0.1:12120";
+
+    assert_eq!(connect_with_sni(laddr, "allowed.test").await, hello);
+    assert_eq!(connect_with_sni(laddr, "ALLOWED.test").await, hello);
+    assert_eq!(connect_with_sni(laddr, "a.wild.test").await, hello);
+
+    assert_eq!(connect_with_sni(laddr, "unknown.test").await, None);
+    assert_eq!(connect_with_sni(laddr, "wild.test").await, None);
+    assert_eq!(connect_with_sni(laddr, "a.allowed.test").await, None);
+
+    // no sni is sent for an ip address
+    assert_eq!(connect_with_sni(laddr, "127.0.0.1").await, None);
+    assert_eq!(connect_with_sni("127.0.0.1:12121", "127.0.0.1").await, hello);
+    assert_eq!(connect_with_sni("127.0.0.1:12121", "unknown.test").await, None);
+}
+
+#[tokio::test]
+async fn partial_hello() {
+    // counts the dials
+    let dials = Arc::new(AtomicUsize::new(0));
+    let lis = TcpListener::bind("127.0.0.1:22122").await.unwrap();
+    let counter = dials.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = lis.accept().await.unwrap();
+            counter.fetch_add(1, Ordering::Relaxed);
+            tokio::spawn(async move {
+                sleep(Duration::from_secs(5)).await;
+                drop(stream);
+            });
+        }
+    });
+    for (laddr, allow_missing_sni) in [("127.0.0.1:12122", false), ("127.0.0.1:12123", true)] {
+        let mut endpoint = endpoint(laddr, allow_missing_sni);
+        endpoint.raddr = remote("127.0.0.1:22122");
+        endpoint.conn_opts.handshake_timeout = 1;
+        tokio::spawn(run_tcp(endpoint));
+    }
+    sleep(Duration::from_millis(500)).await;
+
+    // the header of a handshake record, then nothing
+    let closed = |laddr: &'static str| async move {
+        let mut stream = TcpStream::connect(laddr).await.unwrap();
+        stream.write_all(&[0x16, 0x03, 0x01]).await.unwrap();
+        let mut buf = [0u8; 32];
+        let res = timeout(Duration::from_secs(4), stream.read(&mut buf)).await;
+        matches!(res, Ok(Ok(0) | Err(_)))
+    };
+
+    // dropped before dialing
+    assert!(closed("127.0.0.1:12122").await);
+    assert_eq!(dials.load(Ordering::Relaxed), 0);
+
+    // taken as no server name, then closed by the handshake timeout
+    assert!(closed("127.0.0.1:12123").await);
+    assert_eq!(dials.load(Ordering::Relaxed), 1);
+}
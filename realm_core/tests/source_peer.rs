@@ -0,0 +1,89 @@
+#![cfg(all(feature = "balance", target_os = "linux"))]
+
+use std::sync::{Arc, Mutex};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::sleep;
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, ConnectOpts, PeerOpts};
+use realm_core::balance::Balancer;
+
+mod common;
+use common::remote;
+
+fn source(s: &str) -> Option<SocketAddr> {
+    Some(SocketAddr::new(s.parse().unwrap(), 0))
+}
+
+type Seen = Arc<Mutex<Vec<(usize, IpAddr)>>>;
+
+// record (peer, source ip of the dial)
+async fn backend(addr: &str, peer: usize, seen: Seen) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (_, src) = lis.accept().await.unwrap();
+        seen.lock().unwrap().push((peer, src.ip()));
+    }
+}
+
+#[tokio::test]
+async fn source_per_peer() {
+    // a route to the second peer, on the port of the first
+    let routed = "127.0.0.1:11301";
+    let endpoint = |laddr: &str, port_routes| Endpoint {
+        laddr: laddr.parse().unwrap(),
+        raddr: remote("127.0.0.1:21300"),
+        conn_opts: ConnectOpts {
+            peer_opts: vec![
+                PeerOpts {
+                    bind_address: source("127.0.0.2"),
+                    ..Default::default()
+                },
+                PeerOpts {
+                    bind_address: source("127.0.0.3"),
+                    ..Default::default()
+                },
+            ],
+            balancer: Balancer::parse_from_str("roundrobin: 1, 1"),
+            port_routes,
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: vec![remote("127.0.0.1:21301")],
+    };
+
+    let seen = Seen::default();
+    tokio::spawn(backend("127.0.0.1:21300", 0, seen.clone()));
+    tokio::spawn(backend("127.0.0.1:21301", 1, seen.clone()));
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:11300", Vec::new())));
+    tokio::spawn(run_tcp(endpoint(routed, vec![(11301, remote("127.0.0.1:21301"))])));
+
+    sleep(Duration::from_millis(500)).await;
+
+    let mut streams = Vec::new();
+    for _ in 0..2 {
+        streams.push(TcpStream::connect("127.0.0.1:11300").await.unwrap());
+    }
+
+    sleep(Duration::from_millis(500)).await;
+
+    let mut dials = seen.lock().unwrap().clone();
+    dials.sort();
+    assert_eq!(
+        dials,
+        vec![(0, "127.0.0.2".parse().unwrap()), (1, "127.0.0.3".parse().unwrap())]
+    );
+
+    // routed, with the options of the same remote
+    for _ in 0..2 {
+        streams.push(TcpStream::connect(routed).await.unwrap());
+    }
+
+    sleep(Duration::from_millis(500)).await;
+
+    let dials = seen.lock().unwrap().clone();
+    assert_eq!(dials[2..], [(1, "127.0.0.3".parse().unwrap()); 2]);
+}
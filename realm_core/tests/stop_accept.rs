@@ -0,0 +1,69 @@
+use std::time::Duration;
+
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::{self, run_tcp_with};
+use realm_core::udp::{self, run_udp_with};
+
+mod common;
+use common::{echo, endpoint};
+
+async fn round_trip(stream: &mut TcpStream) {
+    stream.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[tokio::test]
+async fn stop_accept() {
+    let endpoint = endpoint("127.0.0.1:12340", "127.0.0.1:22340", Default::default());
+    let lis = tcp::bind(&endpoint).unwrap();
+
+    tokio::spawn(echo("127.0.0.1:22340"));
+    let task = tokio::spawn(run_tcp_with(lis, endpoint));
+
+    sleep(Duration::from_millis(500)).await;
+
+    let mut stream = TcpStream::connect("127.0.0.1:12340").await.unwrap();
+    round_trip(&mut stream).await;
+
+    // the endpoint is freed along with the listener, unless held by the connection
+    task.abort();
+    sleep(Duration::from_millis(100)).await;
+
+    round_trip(&mut stream).await;
+    assert!(TcpStream::connect("127.0.0.1:12340").await.is_err());
+}
+
+#[tokio::test]
+async fn stop_receive() {
+    let endpoint = endpoint("127.0.0.1:12341", "127.0.0.1:22341", Default::default());
+    let lis = udp::bind(&endpoint).unwrap();
+
+    let remote = UdpSocket::bind("127.0.0.1:22341").await.unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 64];
+        loop {
+            let (n, addr) = remote.recv_from(&mut buf).await.unwrap();
+            remote.send_to(&buf[..n], addr).await.unwrap();
+        }
+    });
+    let task = tokio::spawn(run_udp_with(lis, endpoint));
+
+    sleep(Duration::from_millis(500)).await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.send_to(b"hello", "127.0.0.1:12341").await.unwrap();
+    let mut buf = [0u8; 64];
+    let n = client.recv(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"hello");
+
+    // the association is removed, then the port is released
+    task.abort();
+    sleep(Duration::from_millis(100)).await;
+
+    assert!(UdpSocket::bind("127.0.0.1:12341").await.is_ok());
+}
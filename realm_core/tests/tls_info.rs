@@ -0,0 +1,96 @@
+#![cfg(feature = "transport")]
+
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, ConnectOpts};
+
+use realm_core::kaminari::tls::{TlsClientConf, TlsServerConf};
+use realm_core::kaminari::mix::{MixAccept, MixConnect, MixClientConf, MixServerConf};
+
+mod common;
+use common::{LOGS, capture_logs};
+
+fn endpoint(laddr: &str, raddr: &str, ac: MixAccept, cc: MixConnect) -> Endpoint {
+    let conn_opts = ConnectOpts {
+        transport: Some((ac, cc)),
+        ..Default::default()
+    };
+    common::endpoint(laddr, raddr, conn_opts)
+}
+
+#[tokio::test]
+async fn tls_info() {
+    capture_logs(log::LevelFilter::Debug);
+
+    // client => [plain]a[tls] => [tls]b[plain] => backend
+    let plain_ac = MixAccept::new_shared(MixServerConf { ws: None, tls: None });
+    let plain_cc = MixConnect::new_shared(MixClientConf { ws: None, tls: None });
+    let tls_ac = MixAccept::new_shared(MixServerConf {
+        ws: None,
+        tls: Some(TlsServerConf {
+            crt: String::new(),
+            key: String::new(),
+            ocsp: String::new(),
+            server_name: String::from("localhost"),
+        }),
+    });
+    let tls_cc = MixConnect::new_shared(MixClientConf {
+        ws: None,
+        tls: Some(TlsClientConf {
+            sni: String::from("localhost"),
+            alpn: Vec::new(),
+            insecure: true,
+            early_data: false,
+        }),
+    });
+
+    tokio::spawn(run_tcp(endpoint(
+        "127.0.0.1:11800",
+        "127.0.0.1:11801",
+        plain_ac,
+        tls_cc,
+    )));
+    tokio::spawn(run_tcp(endpoint(
+        "127.0.0.1:11801",
+        "127.0.0.1:21800",
+        tls_ac,
+        plain_cc,
+    )));
+    tokio::spawn(async {
+        let lis = TcpListener::bind("127.0.0.1:21800").await.unwrap();
+        let (mut stream, _) = lis.accept().await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        stream.write_all(&buf).await.unwrap();
+    });
+    sleep(Duration::from_millis(500)).await;
+
+    let mut stream = TcpStream::connect("127.0.0.1:11800").await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+
+    let logs = LOGS.lock().unwrap();
+    let find = |pat: &str| {
+        logs.iter()
+            .find(|x| x.contains(pat))
+            .unwrap_or_else(|| panic!("no {} log", pat))
+            .clone()
+    };
+
+    // kaminari prefers tls 1.3
+    for log in [find("tls connected"), find("tls accepted")] {
+        assert!(log.contains("version=TLSv1_3"), "{}", log);
+        assert!(log.contains("cipher=TLS13_"), "{}", log);
+        assert!(log.contains("alpn=none"), "{}", log);
+    }
+
+    // plain sides are not logged
+    assert_eq!(logs.iter().filter(|x| x.contains("tls ")).count(), 2);
+}
@@ -0,0 +1,91 @@
+#![cfg(feature = "trace")]
+
+use std::sync::Arc;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::trace::Tracer;
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
+
+#[tokio::test]
+async fn trace() {
+    let path = std::env::temp_dir().join("realm_trace_test.log");
+    let path = path.to_str().unwrap();
+    let tracer = Tracer::create(path, 4096, false).unwrap();
+
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:11200".parse().unwrap(),
+        raddr: "127.0.0.1:21200"
+            .parse::<SocketAddr>()
+            .map(RemoteAddr::SocketAddr)
+            .unwrap(),
+        conn_opts: ConnectOpts {
+            tracer: Some(Arc::new(tracer)),
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    };
+
+    let lis = TcpListener::bind("127.0.0.1:21200").await.unwrap();
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let mut client = TcpStream::connect("127.0.0.1:11200").await.unwrap();
+    let (mut remote, _) = lis.accept().await.unwrap();
+    let mut buf = [0u8; 64];
+
+    client.write_all(b"secret request").await.unwrap();
+    remote.read_exact(&mut buf[..14]).await.unwrap();
+    remote.write_all(b"response").await.unwrap();
+    client.read_exact(&mut buf[..8]).await.unwrap();
+
+    let client_addr = client.local_addr().unwrap().to_string();
+    drop((client, remote));
+    sleep(Duration::from_millis(200)).await;
+
+    let trace = std::fs::read_to_string(path).unwrap();
+    let records: Vec<(&str, &str, &str)> = trace
+        .lines()
+        .map(|line| {
+            let fields: Vec<&str> = line.split(' ').collect();
+            assert_eq!(fields[1], client_addr);
+            (fields[2], fields[3], fields[4])
+        })
+        .collect();
+
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].0, "up");
+    assert_eq!(records[0].1, "14");
+    assert_eq!(records[1].0, "down");
+    assert_eq!(records[1].1, "8");
+
+    // hashed, payloads are not leaked
+    assert!(records[0].2.starts_with("fnv="));
+    assert!(!trace.contains("secret"));
+}
+
+#[test]
+fn trace_size_cap() {
+    use realm_core::trace::Dir;
+
+    let path = std::env::temp_dir().join("realm_trace_cap_test.log");
+    let path = path.to_str().unwrap();
+    let tracer = Tracer::create(path, 80, true).unwrap();
+    let client = "127.0.0.1:50000".parse().unwrap();
+
+    for _ in 0..3 {
+        tracer.record(&client, Dir::Up, b"abc");
+    }
+
+    let trace = std::fs::read_to_string(path).unwrap();
+    let lines: Vec<&str> = trace.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].ends_with("127.0.0.1:50000 up 3 hex=616263"));
+    assert_eq!(lines[1], "# size cap reached");
+}
@@ -0,0 +1,142 @@
+#![cfg(feature = "transport")]
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::ConnectOpts;
+
+use realm_core::kaminari::tls::TlsClientConf;
+use realm_core::kaminari::mix::{MixAccept, MixConnect, MixClientConf, MixServerConf};
+
+mod common;
+
+// a plain echo server, which breaks any tls handshake
+async fn backend(addr: &str, dials: Arc<AtomicUsize>) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        dials.fetch_add(1, Ordering::Relaxed);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            loop {
+                match stream.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if stream.write_all(&buf[..n]).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[tokio::test]
+async fn transport_fallback() {
+    let plain_ac = MixAccept::new_shared(MixServerConf { ws: None, tls: None });
+    let plain_cc = MixConnect::new_shared(MixClientConf { ws: None, tls: None });
+    let tls_cc = MixConnect::new_shared(MixClientConf {
+        ws: None,
+        tls: Some(TlsClientConf {
+            sni: String::from("localhost"),
+            alpn: Vec::new(),
+            insecure: true,
+            early_data: false,
+        }),
+    });
+
+    let conn_opts = ConnectOpts {
+        transport: Some((plain_ac, tls_cc)),
+        transport_fallback: vec![plain_cc],
+        ..Default::default()
+    };
+    let endpoint = common::endpoint("127.0.0.1:12330", "127.0.0.1:22330", conn_opts);
+
+    let dials = Arc::new(AtomicUsize::new(0));
+    tokio::spawn(backend("127.0.0.1:22330", dials.clone()));
+    tokio::spawn(run_tcp(endpoint));
+
+    sleep(Duration::from_millis(500)).await;
+
+    let mut stream = TcpStream::connect("127.0.0.1:12330").await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+
+    // the tls one, then the plain one
+    assert_eq!(dials.load(Ordering::Relaxed), 2);
+}
+
+// never answers a tls handshake, echoes anything else
+async fn blackhole(addr: &str, dials: Arc<AtomicUsize>) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        dials.fetch_add(1, Ordering::Relaxed);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            let mut tls = None;
+            loop {
+                match stream.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if *tls.get_or_insert(buf[0] == 0x16) {
+                            continue;
+                        }
+                        if stream.write_all(&buf[..n]).await.is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[tokio::test]
+async fn transport_fallback_stalled() {
+    let plain_ac = MixAccept::new_shared(MixServerConf { ws: None, tls: None });
+    let plain_cc = MixConnect::new_shared(MixClientConf { ws: None, tls: None });
+    let tls_cc = MixConnect::new_shared(MixClientConf {
+        ws: None,
+        tls: Some(TlsClientConf {
+            sni: String::from("localhost"),
+            alpn: Vec::new(),
+            insecure: true,
+            early_data: false,
+        }),
+    });
+
+    let conn_opts = ConnectOpts {
+        transport: Some((plain_ac, tls_cc)),
+        transport_fallback: vec![plain_cc],
+        handshake_timeout: 4,
+        ..Default::default()
+    };
+    let endpoint = common::endpoint("127.0.0.1:12331", "127.0.0.1:22331", conn_opts);
+
+    let dials = Arc::new(AtomicUsize::new(0));
+    tokio::spawn(blackhole("127.0.0.1:22331", dials.clone()));
+    tokio::spawn(run_tcp(endpoint));
+
+    sleep(Duration::from_millis(500)).await;
+
+    // the tls one is given up after 2s, before the whole 4s
+    let mut stream = TcpStream::connect("127.0.0.1:12331").await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    timeout(Duration::from_secs(3), stream.read_exact(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buf, b"hello");
+    assert_eq!(dials.load(Ordering::Relaxed), 2);
+}
@@ -0,0 +1,84 @@
+#![cfg(target_os = "linux")]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::UdpSocket;
+use tokio::time::{sleep, timeout};
+
+use realm_core::udp::run_udp;
+use realm_core::stat::Stat;
+use realm_core::endpoint::{Endpoint, ConnectOpts};
+
+mod common;
+use common::{LOGS, capture_logs};
+
+fn endpoint(laddr: &str, raddr: &str, stat: Arc<Stat>) -> Endpoint {
+    let conn_opts = ConnectOpts {
+        associate_timeout: 30,
+        stat,
+        ..Default::default()
+    };
+    common::endpoint(laddr, raddr, conn_opts)
+}
+
+async fn pong(addr: &str) {
+    let socket = UdpSocket::bind(addr).await.unwrap();
+    let mut buf = vec![0; 32];
+    loop {
+        let (_, peer) = socket.recv_from(&mut buf).await.unwrap();
+        socket.send_to(b"pong", peer).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn udp_unreachable() {
+    capture_logs(log::LevelFilter::Warn);
+
+    let (alive, dead) = (Arc::new(Stat::default()), Arc::new(Stat::default()));
+    tokio::spawn(pong("127.0.0.1:22170"));
+    tokio::spawn(run_udp(endpoint("127.0.0.1:12170", "127.0.0.1:22170", alive.clone())));
+    // nothing listens on the remote port
+    tokio::spawn(run_udp(endpoint("127.0.0.1:12171", "127.0.0.1:22171", dead.clone())));
+    sleep(Duration::from_millis(500)).await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut buf = vec![0; 32];
+    client.send_to(b"ping", "127.0.0.1:12170").await.unwrap();
+    let n = timeout(Duration::from_secs(1), client.recv(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buf[..n], b"pong");
+
+    client.send_to(b"ping", "127.0.0.1:12171").await.unwrap();
+    sleep(Duration::from_millis(300)).await;
+
+    // the association to the closed port is gone
+    let stat = dead.snapshot();
+    assert_eq!((stat.total_conns, stat.active_conns), (1, 0));
+    let stat = alive.snapshot();
+    assert_eq!((stat.total_conns, stat.active_conns), (1, 1));
+
+    let client_addr = client.local_addr().unwrap();
+    {
+        let logs = LOGS.lock().unwrap();
+        let log = logs
+            .iter()
+            .find(|x| x.contains("unreachable"))
+            .unwrap_or_else(|| panic!("no unreachable log: {:?}", logs));
+        assert_eq!(
+            *log,
+            format!(
+                "[udp]{} => 127.0.0.1:22171 is unreachable, remove the association",
+                client_addr
+            )
+        );
+    }
+
+    // a new association for the next packet
+    client.send_to(b"ping", "127.0.0.1:12171").await.unwrap();
+    sleep(Duration::from_millis(300)).await;
+    let stat = dead.snapshot();
+    assert_eq!((stat.total_conns, stat.active_conns), (2, 0));
+}
@@ -0,0 +1,109 @@
+#![cfg(feature = "transport")]
+
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, ConnectOpts, WsClose};
+
+use realm_core::kaminari::ws::WsConf;
+use realm_core::kaminari::mix::{MixAccept, MixConnect, MixClientConf, MixServerConf};
+
+mod common;
+
+// upgrades once, sends back all the frames after it
+async fn upstream(addr: &str, tx: mpsc::UnboundedSender<Vec<(u8, Vec<u8>)>>) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0; 0x1000];
+            let mut n = 0;
+            while !buf[..n].ends_with(b"\r\n\r\n") {
+                n += stream.read(&mut buf[n..]).await.unwrap();
+            }
+            let head = String::from_utf8_lossy(&buf[..n]).into_owned();
+            let key = head
+                .lines()
+                .filter_map(|x| x.split_once(':'))
+                .find(|(k, _)| k.eq_ignore_ascii_case("sec-websocket-key"))
+                .map(|(_, v)| v.trim().to_string())
+                .unwrap();
+            let accept = lightws::handshake::derive_accept_key(key.as_bytes());
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                String::from_utf8_lossy(&accept)
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+
+            let mut data = Vec::new();
+            stream.read_to_end(&mut data).await.unwrap();
+
+            // short masked frames only
+            let mut frames = Vec::new();
+            let mut data = &data[..];
+            while !data.is_empty() {
+                assert!(data[1] & 0x80 != 0 && data[1] & 0x7f < 126, "{:?}", data);
+                let len = (data[1] & 0x7f) as usize;
+                let mask = &data[2..6];
+                let payload = data[6..6 + len]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, x)| x ^ mask[i % 4])
+                    .collect();
+                frames.push((data[0], payload));
+                data = &data[6 + len..];
+            }
+            tx.send(frames).unwrap();
+        });
+    }
+}
+
+fn endpoint(laddr: &str, raddr: &str, code: Option<u16>) -> Endpoint {
+    let ws = WsConf {
+        host: String::from("example.com"),
+        path: String::from("/ws"),
+    };
+    let conn_opts = ConnectOpts {
+        transport: Some((
+            MixAccept::new_shared(MixServerConf { ws: None, tls: None }),
+            MixConnect::new_shared(MixClientConf {
+                ws: Some(ws.clone()),
+                tls: None,
+            }),
+        )),
+        ws_close: code.map(|code| WsClose { code, ws, tls: None }),
+        ..Default::default()
+    };
+    common::endpoint(laddr, raddr, conn_opts)
+}
+
+#[tokio::test]
+async fn ws_close() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    tokio::spawn(upstream("127.0.0.1:22240", tx));
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:12240", "127.0.0.1:22240", Some(4000))));
+    tokio::spawn(run_tcp(endpoint("127.0.0.1:12241", "127.0.0.1:22240", None)));
+    sleep(Duration::from_millis(500)).await;
+
+    // data, then a close frame with the code
+    let mut stream = TcpStream::connect("127.0.0.1:12240").await.unwrap();
+    stream.write_all(b"hi").await.unwrap();
+    sleep(Duration::from_millis(100)).await;
+    stream.shutdown().await.unwrap();
+    let frames = timeout(Duration::from_secs(3), rx.recv()).await.unwrap().unwrap();
+    assert_eq!(frames, [(0x82, b"hi".to_vec()), (0x88, 4000u16.to_be_bytes().to_vec())]);
+
+    // without it, the tcp connection is closed only
+    let mut stream = TcpStream::connect("127.0.0.1:12241").await.unwrap();
+    stream.write_all(b"hi").await.unwrap();
+    sleep(Duration::from_millis(100)).await;
+    stream.shutdown().await.unwrap();
+    let frames = timeout(Duration::from_secs(3), rx.recv()).await.unwrap().unwrap();
+    assert_eq!(frames, [(0x82, b"hi".to_vec())]);
+}
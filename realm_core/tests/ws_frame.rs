@@ -0,0 +1,124 @@
+#![cfg(feature = "transport")]
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts, FrameLimit};
+
+use realm_core::kaminari::AsyncConnect;
+use realm_core::kaminari::ws::WsConf;
+use realm_core::kaminari::mix::{MixAccept, MixConnect, MixClientConf, MixServerConf};
+
+mod common;
+use common::{LOGS, capture_logs};
+
+fn ws_conf() -> WsConf {
+    WsConf {
+        host: String::from("example.com"),
+        path: String::from("/ws"),
+    }
+}
+
+// echoes, counts what is received
+async fn backend(addr: &str, received: Arc<AtomicUsize>) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        let received = received.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0; 0x1000];
+            while let Ok(n @ 1..) = stream.read(&mut buf).await {
+                received.fetch_add(n, Ordering::Relaxed);
+                if stream.write_all(&buf[..n]).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+#[tokio::test]
+async fn ws_max_frame_size() {
+    capture_logs(log::LevelFilter::Warn);
+
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:12220".parse().unwrap(),
+        raddr: "127.0.0.1:22220"
+            .parse::<SocketAddr>()
+            .map(RemoteAddr::SocketAddr)
+            .unwrap(),
+        conn_opts: ConnectOpts {
+            transport: Some((
+                MixAccept::new_shared(MixServerConf {
+                    ws: Some(ws_conf()),
+                    tls: None,
+                }),
+                MixConnect::new_shared(MixClientConf { ws: None, tls: None }),
+            )),
+            frame_limit: Some(FrameLimit {
+                max: 256,
+                listen: Some((ws_conf(), None)),
+                remote: None,
+            }),
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    };
+
+    let received = Arc::new(AtomicUsize::new(0));
+    tokio::spawn(backend("127.0.0.1:22220", received.clone()));
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    let client = MixConnect::new_shared(MixClientConf {
+        ws: Some(ws_conf()),
+        tls: None,
+    });
+    let stream = TcpStream::connect("127.0.0.1:12220").await.unwrap();
+    let client_addr = stream.local_addr().unwrap();
+    let mut buf = vec![0; 0x1000];
+    let mut stream = client.connect(stream, &mut buf).await.unwrap();
+
+    // a frame at the cap is relayed
+    stream.write_all(&[b'a'; 256]).await.unwrap();
+    stream.flush().await.unwrap();
+    let mut echoed = Vec::new();
+    while echoed.len() < 256 {
+        // lightws reads into at least 14 bytes
+        let n = stream.read(&mut buf).await.unwrap();
+        echoed.extend_from_slice(&buf[..n]);
+    }
+    assert_eq!(echoed, [b'a'; 256]);
+    assert_eq!(received.load(Ordering::Relaxed), 256);
+
+    // one byte over it, closed without relaying any of it
+    stream.write_all(&[b'b'; 257]).await.unwrap();
+    stream.flush().await.unwrap();
+    let res = timeout(Duration::from_secs(1), stream.read(&mut buf)).await.unwrap();
+    assert!(matches!(res, Ok(0) | Err(_)), "{:?}", res);
+    assert_eq!(received.load(Ordering::Relaxed), 256);
+
+    sleep(Duration::from_millis(100)).await;
+    {
+        let logs = LOGS.lock().unwrap();
+        let log = logs
+            .iter()
+            .find(|x| x.contains("reason=frame_too_large"))
+            .unwrap_or_else(|| panic!("no frame_too_large log: {:?}", logs));
+        assert_eq!(
+            *log,
+            format!(
+                "[tcp]{} dropped, reason=frame_too_large: ws frame of 257b exceeds 256b, remote=127.0.0.1:22220",
+                client_addr
+            )
+        );
+    }
+}
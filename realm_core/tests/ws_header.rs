@@ -0,0 +1,87 @@
+#![cfg(feature = "transport")]
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::{TcpStream, TcpListener};
+use tokio::time::{sleep, timeout};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use realm_core::tcp::run_tcp;
+use realm_core::endpoint::{Endpoint, RemoteAddr, ConnectOpts};
+
+use realm_core::kaminari::AsyncConnect;
+use realm_core::kaminari::ws::WsConf;
+use realm_core::kaminari::mix::{MixAccept, MixConnect, MixClientConf, MixServerConf};
+
+fn ws_conf() -> WsConf {
+    WsConf {
+        host: String::from("example.com"),
+        path: String::from("/ws"),
+    }
+}
+
+async fn backend(addr: &str) {
+    let lis = TcpListener::bind(addr).await.unwrap();
+    loop {
+        let (mut stream, _) = lis.accept().await.unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0; 32];
+            while let Ok(n) = stream.read(&mut buf).await {
+                if n == 0 || stream.write_all(&buf[..n]).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+#[tokio::test]
+async fn ws_max_header_size() {
+    let ac = MixAccept::new_shared(MixServerConf {
+        ws: Some(ws_conf()),
+        tls: None,
+    });
+    let cc = MixConnect::new_shared(MixClientConf { ws: None, tls: None });
+
+    let endpoint = Endpoint {
+        laddr: "127.0.0.1:11400".parse().unwrap(),
+        raddr: "127.0.0.1:21400"
+            .parse::<SocketAddr>()
+            .map(RemoteAddr::SocketAddr)
+            .unwrap(),
+        conn_opts: ConnectOpts {
+            transport: Some((ac, cc)),
+            ws_max_header_size: 512,
+            ..Default::default()
+        },
+        bind_opts: Default::default(),
+        extra_raddrs: Vec::new(),
+    };
+
+    tokio::spawn(backend("127.0.0.1:21400"));
+    tokio::spawn(run_tcp(endpoint));
+    sleep(Duration::from_millis(500)).await;
+
+    // a normal upgrade request fits
+    let client = MixConnect::new_shared(MixClientConf {
+        ws: Some(ws_conf()),
+        tls: None,
+    });
+    let stream = TcpStream::connect("127.0.0.1:11400").await.unwrap();
+    let mut buf = vec![0; 0x2000];
+    let mut stream = client.connect(stream, &mut buf).await.unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    stream.flush().await.unwrap();
+    let n = stream.read(&mut buf).await.unwrap();
+    assert_eq!(b"hello", &buf[..n]);
+
+    // an oversized one is rejected, without waiting for its end
+    let mut stream = TcpStream::connect("127.0.0.1:11400").await.unwrap();
+    let mut req = Vec::from(&b"GET /ws HTTP/1.1\r\nHost: example.com\r\nX-Pad: "[..]);
+    req.resize(2048, b'a');
+    stream.write_all(&req).await.unwrap();
+
+    let res = timeout(Duration::from_secs(1), stream.read(&mut buf)).await.unwrap();
+    assert!(matches!(res, Ok(0) | Err(_)));
+}
@@ -5,6 +5,7 @@ use std::fmt::{Display, Formatter};
 use crate::{Token, Balance};
 use crate::ip_hash::IpHash;
 use crate::round_robin::RoundRobin;
+use crate::least_conn::LeastConn;
 
 /// Balance strategy.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -12,6 +13,7 @@ pub enum Strategy {
     Off,
     IpHash,
     RoundRobin,
+    LeastConn,
 }
 
 impl From<&str> for Strategy {
@@ -21,6 +23,7 @@ impl From<&str> for Strategy {
             "off" => Off,
             "iphash" => IpHash,
             "roundrobin" => RoundRobin,
+            "leastconn" => LeastConn,
             _ => panic!("unknown strategy: {}", s),
         }
     }
@@ -32,6 +35,7 @@ impl Display for Strategy {
             Strategy::Off => write!(f, "off"),
             Strategy::IpHash => write!(f, "iphash"),
             Strategy::RoundRobin => write!(f, "roundrobin"),
+            Strategy::LeastConn => write!(f, "leastconn"),
         }
     }
 }
@@ -40,6 +44,9 @@ impl Display for Strategy {
 #[derive(Debug)]
 pub struct BalanceCtx<'a> {
     pub src_ip: &'a IpAddr,
+    /// Active connections of peers, None if a peer is down.
+    /// Only used by leastconn.
+    pub loads: &'a [Option<u64>],
 }
 
 /// Combinated load balancer.
@@ -48,6 +55,7 @@ pub enum Balancer {
     Off,
     IpHash(Arc<IpHash>),
     RoundRobin(Arc<RoundRobin>),
+    LeastConn(Arc<LeastConn>),
 }
 
 impl Balancer {
@@ -57,6 +65,7 @@ impl Balancer {
             Strategy::Off => Self::Off,
             Strategy::IpHash => Self::IpHash(Arc::new(IpHash::new(weights))),
             Strategy::RoundRobin => Self::RoundRobin(Arc::new(RoundRobin::new(weights))),
+            Strategy::LeastConn => Self::LeastConn(Arc::new(LeastConn::new(weights))),
         }
     }
 
@@ -66,6 +75,7 @@ impl Balancer {
             Balancer::Off => Strategy::Off,
             Balancer::IpHash(_) => Strategy::IpHash,
             Balancer::RoundRobin(_) => Strategy::RoundRobin,
+            Balancer::LeastConn(_) => Strategy::LeastConn,
         }
     }
 
@@ -75,6 +85,7 @@ impl Balancer {
             Balancer::Off => 0,
             Balancer::IpHash(iphash) => iphash.total(),
             Balancer::RoundRobin(rr) => rr.total(),
+            Balancer::LeastConn(lc) => lc.total(),
         }
     }
 
@@ -84,6 +95,7 @@ impl Balancer {
             Balancer::Off => Some(Token(0)),
             Balancer::IpHash(iphash) => iphash.next(ctx.src_ip),
             Balancer::RoundRobin(rr) => rr.next(&()),
+            Balancer::LeastConn(lc) => lc.next(ctx.loads),
         }
     }
 
@@ -140,5 +152,7 @@ mod tests {
         run(Strategy::RoundRobin, &[1, 2, 3]);
         run(Strategy::RoundRobin, &[1, 2, 3]);
         run(Strategy::RoundRobin, &[1, 2, 3]);
+        run(Strategy::LeastConn, &[]);
+        run(Strategy::LeastConn, &[1, 2, 3]);
     }
 }
@@ -80,15 +80,16 @@ mod chash {
     const SEED: u32 = 0xbc9f1d34;
     const M: u32 = 0xc6a4a793;
 
+    // overflow is part of the hash
     macro_rules! c_add {
         ($a:expr, $b:expr) => {
-            unsafe { $a.unchecked_add($b) }
+            $a.wrapping_add($b)
         };
     }
 
     macro_rules! c_mul {
         ($a:expr, $b:expr) => {
-            unsafe { $a.unchecked_mul($b) }
+            $a.wrapping_mul($b)
         };
     }
 
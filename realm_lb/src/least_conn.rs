@@ -0,0 +1,68 @@
+use super::{Balance, Token};
+
+/// Least connection balancer.
+///
+/// Peers are compared by active connections per weight,
+/// a peer without a weight is weighted 1.
+#[derive(Debug)]
+pub struct LeastConn {
+    weights: Vec<u8>,
+}
+
+impl Balance for LeastConn {
+    /// Active connections of peers, None if a peer is down.
+    type State = [Option<u64>];
+
+    fn total(&self) -> u8 {
+        self.weights.len() as u8
+    }
+
+    fn new(weights: &[u8]) -> Self {
+        assert!(weights.len() <= u8::MAX as usize);
+
+        Self {
+            weights: weights.to_vec(),
+        }
+    }
+
+    /// The peer with the fewest active connections per weight.
+    /// Ties go to the lowest token. Return None if all peers are down.
+    fn next(&self, state: &Self::State) -> Option<Token> {
+        let weight = |idx: usize| self.weights.get(idx).map_or(1, |x| (*x).max(1)) as u64;
+
+        // a/w < b/v <=> a*v < b*w
+        state
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, active)| active.map(|x| (idx, x, weight(idx))))
+            .min_by(|(_, a, w), (_, b, v)| (a * v).cmp(&(b * w)))
+            .map(|(idx, ..)| Token(idx as u8))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lc_fewest() {
+        let lc = LeastConn::new(&[1, 1, 1]);
+        assert_eq!(lc.next(&[Some(2), Some(1), Some(3)]), Some(Token(1)));
+        // ties go to the first
+        assert_eq!(lc.next(&[Some(1), Some(0), Some(0)]), Some(Token(1)));
+        // down peers are skipped
+        assert_eq!(lc.next(&[Some(2), None, Some(3)]), Some(Token(0)));
+        assert_eq!(lc.next(&[None, None, None]), None);
+    }
+
+    #[test]
+    fn lc_weighted() {
+        let lc = LeastConn::new(&[2, 1]);
+        assert_eq!(lc.next(&[Some(1), Some(1)]), Some(Token(0)));
+        assert_eq!(lc.next(&[Some(2), Some(1)]), Some(Token(0)));
+        assert_eq!(lc.next(&[Some(3), Some(1)]), Some(Token(1)));
+        // weighted 1 without a weight
+        let lc = LeastConn::new(&[]);
+        assert_eq!(lc.next(&[Some(1), Some(0)]), Some(Token(1)));
+    }
+}
@@ -4,7 +4,7 @@ pub struct Token(pub u8);
 
 /// Load balance traits.
 pub trait Balance {
-    type State;
+    type State: ?Sized;
 
     /// Constructor.
     fn new(weights: &[u8]) -> Self;
@@ -22,5 +22,8 @@ pub mod ip_hash;
 /// Round-robin impl.
 pub mod round_robin;
 
+/// Least-connection impl.
+pub mod least_conn;
+
 mod balancer;
 pub use balancer::{Balancer, BalanceCtx, Strategy};
@@ -131,7 +131,7 @@ pub fn add_global_options(app: Command) -> Command {
             .display_order(2),
     ]);
 
-    // log
+    // log, the access log belongs to network
     let app = app.next_help_heading("LOG OPTIONS").args(&[
         Arg::new("log_level")
             .long("log-level")
@@ -143,6 +143,16 @@ pub fn add_global_options(app: Command) -> Command {
             .help("override log output")
             .value_name("path")
             .display_order(1),
+        Arg::new("log_rate_limit")
+            .long("log-rate-limit")
+            .help("override log rate limit, per second")
+            .value_name("number")
+            .display_order(2),
+        Arg::new("access_log")
+            .long("access-log")
+            .help("log closed connections in common or combined format(off)")
+            .value_name("format")
+            .display_order(3),
     ]);
 
     // dns
@@ -177,6 +187,11 @@ pub fn add_global_options(app: Command) -> Command {
             .help("override dns servers")
             .value_name("servers")
             .display_order(5),
+        Arg::new("dns_fallback")
+            .long("dns-fallback")
+            .help("query dns servers one by one, trust the first or try all")
+            .value_name("fallback")
+            .display_order(6),
     ]);
 
     // proxy-protocol belogs to network
@@ -218,11 +233,123 @@ pub fn add_global_options(app: Command) -> Command {
             .help("override default tcp keepalive interval(15s)")
             .value_name("second")
             .display_order(2),
+        Arg::new("tcp_keepalive_interval")
+            .long("tcp-keepalive-interval")
+            .help("override interval between tcp keepalive probes")
+            .value_name("second")
+            .display_order(3),
         Arg::new("tcp_keepalive_probe")
             .long("tcp-keepalive-probe")
             .help("override default tcp keepalive count(3)")
             .value_name("count")
+            .display_order(4),
+        Arg::new("slow_conn_threshold")
+            .long("slow-conn-threshold")
+            .help("log connections slower than this(off)")
+            .value_name("millisecond")
+            .display_order(5),
+        Arg::new("accept_delay")
+            .long("accept-delay")
+            .help("delay before handling a new connection(0)")
+            .value_name("millisecond")
+            .display_order(6),
+        Arg::new("first_byte_timeout")
+            .long("first-byte-timeout")
+            .help("close clients sending nothing for this long(off)")
+            .value_name("second")
+            .display_order(7),
+        Arg::new("remote_first_byte_timeout")
+            .long("remote-first-byte-timeout")
+            .help("close remotes sending nothing for this long(off)")
+            .value_name("second")
+            .display_order(8),
+        Arg::new("handshake_timeout")
+            .long("handshake-timeout")
+            .help("close transport handshakes slower than this(off)")
+            .value_name("second")
+            .display_order(9),
+        Arg::new("per_attempt_timeout")
+            .long("per-attempt-timeout")
+            .help("connect timeout of each remote peer when balancing(off)")
+            .value_name("second")
+            .display_order(10),
+        Arg::new("deadlock_timeout")
+            .long("deadlock-timeout")
+            .help("close connections where neither side speaks for this long(off)")
+            .value_name("second")
+            .display_order(11),
+        Arg::new("idle_timeout")
+            .long("idle-timeout")
+            .help("close connections moving no data for this long(off)")
+            .value_name("second")
+            .display_order(12),
+        Arg::new("prune_interval")
+            .long("prune-interval")
+            .help("sweep idle connections this often(1000)")
+            .value_name("millisecond")
+            .display_order(13),
+    ]);
+
+    // coalescing belongs to network
+    let app = app.next_help_heading("COALESCE OPTIONS").args([
+        Arg::new("coalesce_size")
+            .long("coalesce-size")
+            .help("merge small writes up to this size(off)")
+            .value_name("byte")
+            .display_order(0),
+        Arg::new("coalesce_delay")
+            .long("coalesce-delay")
+            .help("override coalesce flush delay(5ms)")
+            .value_name("millisecond")
+            .display_order(1),
+    ]);
+
+    // socket options belong to network
+    let app = app.next_help_heading("SOCKET OPTIONS").args([
+        Arg::new("bind_source")
+            .long("bind-source")
+            .help("override default send through ip")
+            .value_name("ip")
+            .display_order(0),
+        Arg::new("linger")
+            .long("linger")
+            .help("linger on close for this long, 0 resets(system default)")
+            .value_name("second")
+            .display_order(1),
+        Arg::new("congestion")
+            .long("congestion")
+            .help("tcp congestion control, linux only(system default)")
+            .value_name("algorithm")
+            .display_order(2),
+    ]);
+
+    // limits belong to network
+    let app = app.next_help_heading("LIMIT OPTIONS").args([
+        Arg::new("max_conns")
+            .long("max-conns")
+            .help("max tcp connections(unlimited)")
+            .value_name("number")
+            .display_order(0),
+        Arg::new("conn_queue_depth")
+            .long("conn-queue-depth")
+            .help("queue connections beyond max-conns(0)")
+            .value_name("number")
+            .display_order(1),
+        Arg::new("conn_queue_timeout")
+            .long("conn-queue-timeout")
+            .help("override connection queue timeout(5s)")
+            .value_name("second")
+            .display_order(2),
+        Arg::new("max_handshakes")
+            .long("max-handshakes")
+            .help("max concurrent transport handshakes(unlimited)")
+            .value_name("number")
             .display_order(3),
+        Arg::new("accept_rate")
+            .long("accept-rate")
+            .help("max accepted tcp connections per second(unlimited)")
+            .value_name("number")
+            .display_order(4),
     ]);
 
     app
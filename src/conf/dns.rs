@@ -4,7 +4,7 @@ use std::net::ToSocketAddrs;
 use serde::{Serialize, Deserialize};
 use realm_core::dns::config;
 use config::{LookupIpStrategy, NameServerConfig, Protocol};
-use config::{ResolverConfig, ResolverOpts};
+use config::{ResolverConfig, ResolverOpts, ServerOrderingStrategy};
 
 use super::Config;
 
@@ -104,6 +104,38 @@ impl From<DnsProtocol> for Vec<Protocol> {
     }
 }
 
+// dns fallback
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsFallback {
+    // the first server that answers is trusted
+    First,
+    // a negative answer goes on to the next server
+    All,
+}
+
+impl Display for DnsFallback {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        use DnsFallback::*;
+        let s = match self {
+            First => "first",
+            All => "all",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl From<String> for DnsFallback {
+    fn from(s: String) -> Self {
+        use DnsFallback::*;
+        match s.to_ascii_lowercase().as_str() {
+            "first" => First,
+            "all" => All,
+            _ => panic!("unknown dns fallback: {}", s),
+        }
+    }
+}
+
 // dns config
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct DnsConf {
@@ -134,6 +166,10 @@ pub struct DnsConf {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub nameservers: Option<Vec<String>>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<DnsFallback>,
 }
 
 impl Display for DnsConf {
@@ -159,6 +195,7 @@ impl Display for DnsConf {
             cache_size,
             protocol,
             nameservers,
+            fallback,
         } = self;
 
         let mode = default!(mode);
@@ -183,7 +220,11 @@ impl Display for DnsConf {
             min_ttl, max_ttl, cache_size
         )
         .unwrap();
-        write!(f, "servers={}", &nameservers)
+        write!(f, "servers={}", &nameservers)?;
+        if let Some(fallback) = fallback {
+            write!(f, ", fallback={}", fallback)?;
+        }
+        Ok(())
     }
 }
 
@@ -201,13 +242,14 @@ impl Config for DnsConf {
             min_ttl,
             max_ttl,
             cache_size,
+            fallback,
         } = self;
 
         // parse into ResolverOpts
         // default value:
         // https://docs.rs/trust-dns-resolver/latest/src/trust_dns_resolver/config.rs.html#681-737
 
-        let opts = if empty![mode, min_ttl, max_ttl, cache_size] {
+        let opts = if empty![mode, min_ttl, max_ttl, cache_size, fallback] {
             None
         } else {
             let ip_strategy: LookupIpStrategy = mode.map(|x| x.into()).unwrap_or_default();
@@ -233,14 +275,21 @@ impl Config for DnsConf {
 
             replace!(ip_strategy, positive_min_ttl, positive_max_ttl, cache_size,);
 
+            // one server at a time, in the given order
+            if fallback.is_some() {
+                opts.server_ordering_strategy = ServerOrderingStrategy::UserProvidedOrder;
+                opts.num_concurrent_reqs = 1;
+            }
+
             Some(opts)
         };
 
         // parse into ResolverConfig
         let protocol = protocol.unwrap_or_default();
-        if nameservers.is_none() && (protocol == DnsProtocol::default()) {
+        if nameservers.is_none() && (protocol == DnsProtocol::default()) && fallback.is_none() {
             return (None, opts);
         }
+        let trust_negative_responses = fallback != Some(DnsFallback::All);
 
         let mut conf = ResolverConfig::new();
         let protocols: Vec<Protocol> = protocol.into();
@@ -264,7 +313,7 @@ impl Config for DnsConf {
                     socket_addr,
                     protocol,
                     tls_dns_name: None,
-                    trust_negative_responses: true,
+                    trust_negative_responses,
                     bind_addr: None,
                 });
             }
@@ -282,6 +331,7 @@ impl Config for DnsConf {
         rst!(self, cache_size, other);
         rst!(self, protocol, other);
         rst!(self, nameservers, other);
+        rst!(self, fallback, other);
         self
     }
 
@@ -294,6 +344,7 @@ impl Config for DnsConf {
         take!(self, cache_size, other);
         take!(self, protocol, other);
         take!(self, nameservers, other);
+        take!(self, fallback, other);
         self
     }
 
@@ -319,6 +370,11 @@ impl Config for DnsConf {
             .get_one::<String>("dns_servers")
             .map(|x| x.split(',').map(String::from).collect());
 
+        let fallback = matches
+            .get_one::<String>("dns_fallback")
+            .cloned()
+            .map(DnsFallback::from);
+
         Self {
             mode,
             min_ttl,
@@ -326,10 +382,96 @@ impl Config for DnsConf {
             cache_size,
             protocol,
             nameservers,
+            fallback,
         }
     }
 
     fn is_empty(&self) -> bool {
-        crate::empty![self => mode, min_ttl, max_ttl, cache_size]
+        crate::empty![self => mode, min_ttl, max_ttl, cache_size, fallback]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+    use realm_core::dns::new_resolver;
+
+    // answers 127.0.0.7 to any query, or name error if nx
+    async fn nameserver(addr: &str, nx: bool) {
+        let socket = UdpSocket::bind(addr).await.unwrap();
+        let mut buf = vec![0; 512];
+        loop {
+            let (n, peer) = socket.recv_from(&mut buf).await.unwrap();
+            // header, then the question: labels, type and class
+            let mut end = 12;
+            while buf[end] != 0 {
+                end += buf[end] as usize + 1;
+            }
+            end += 5;
+            assert!(end <= n);
+
+            let mut resp = buf[..end].to_vec();
+            resp[2] = 0x81;
+            resp[3] = if nx { 0x83 } else { 0x80 };
+            // an answer, or the soa which makes the name error trusted
+            resp[6..12].copy_from_slice(&[0, !nx as u8, 0, nx as u8, 0, 0]);
+            if nx {
+                resp.extend_from_slice(&[0xc0, 0x0c, 0, 6, 0, 1, 0, 0, 0, 60, 0, 22, 0, 0]);
+                resp.extend_from_slice(&[0; 20]);
+            } else {
+                resp.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 127, 0, 0, 7]);
+            }
+            socket.send_to(&resp, peer).await.unwrap();
+        }
+    }
+
+    async fn resolve(nameservers: &[&str], fallback: &str) -> Option<std::net::IpAddr> {
+        let conf: DnsConf = toml::from_str(&format!(
+            r#"
+            mode = "ipv4_only"
+            protocol = "udp"
+            nameservers = {:?}
+            fallback = "{}"
+            "#,
+            nameservers, fallback
+        ))
+        .unwrap();
+        let (conf, opts) = conf.build();
+        let mut opts = opts.unwrap();
+        opts.timeout = Duration::from_millis(300);
+        opts.attempts = 0;
+        let resolver = new_resolver(conf, Some(opts));
+        let res = resolver.lookup_ip("realm.test.").await.ok()?;
+        res.iter().next()
+    }
+
+    #[tokio::test]
+    async fn dead_primary() {
+        // never answers
+        let _dead = UdpSocket::bind("127.0.0.1:25300").await.unwrap();
+        tokio::spawn(nameserver("127.0.0.1:25301", false));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let ip = resolve(&["127.0.0.1:25300", "127.0.0.1:25301"], "first").await;
+        assert_eq!(ip, Some([127, 0, 0, 7].into()));
+    }
+
+    #[tokio::test]
+    async fn not_found_on_primary() {
+        tokio::spawn(nameserver("127.0.0.1:25302", true));
+        tokio::spawn(nameserver("127.0.0.1:25303", false));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let nameservers = ["127.0.0.1:25302", "127.0.0.1:25303"];
+
+        assert_eq!(resolve(&nameservers, "first").await, None);
+        assert_eq!(resolve(&nameservers, "all").await, Some([127, 0, 0, 7].into()));
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown variant `some`")]
+    fn unknown_fallback() {
+        let _: DnsConf = toml::from_str(r#"fallback = "some""#).unwrap();
     }
 }
@@ -1,20 +1,37 @@
 use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
 use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
 
-use realm_core::endpoint::{Endpoint, RemoteAddr};
+use realm_core::endpoint::{ConnectOpts, Endpoint, PeerOpts, RemoteAddr};
+
+#[cfg(feature = "balance")]
+use realm_core::endpoint::HashKey;
 
 #[cfg(feature = "balance")]
 use realm_core::balance::Balancer;
 
+#[cfg(feature = "balance")]
+use realm_core::health::{Affinity, Health, Load, UnhealthyPolicy};
+
 #[cfg(feature = "transport")]
 use realm_core::kaminari::mix::{MixAccept, MixConnect};
 
-use super::{Config, NetConf, NetInfo};
+use super::{Config, DnsConf, NetConf, NetInfo};
+use super::transport::{self, Diagnostic};
+use super::net::build_bind_source;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EndpointConf {
     pub listen: String,
 
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen_interface: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cpu_affinity: Vec<usize>,
+
     pub remote: String,
 
     #[serde(default)]
@@ -25,6 +42,30 @@ pub struct EndpointConf {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub balance: Option<String>,
 
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unhealthy_policy: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub maintenance_response: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub affinity_timeout: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash_key: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speaks_first: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deadlock_nudge: Option<String>,
+
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub through: Option<String>,
@@ -41,11 +82,108 @@ pub struct EndpointConf {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub remote_transport: Option<String>,
 
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub remote_transport_fallback: Vec<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ws_max_header_size: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ws_max_frame_size: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ws_close_code: Option<u16>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_header: Option<String>,
+
+    // in the order written, which is the priority
+    #[serde(default, with = "ordered")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub alpn_routes: Vec<(String, String)>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sni_allowlist: Vec<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_missing_sni: Option<bool>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub port_routes: BTreeMap<String, String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geo_db: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub geo_routes: BTreeMap<String, String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub remote_options: BTreeMap<String, RemoteOptions>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_max_size: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_payload: Option<bool>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compress: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compress_level: Option<u8>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsConf>,
+
     #[serde(default)]
     #[serde(skip_serializing_if = "Config::is_empty")]
     pub network: NetConf,
 }
 
+/// Entry of `remote_options`, keyed by one of the remotes.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RemoteOptions {
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bind_source: Option<IpAddr>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub send_proxy: Option<bool>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub send_proxy_version: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub insecure: Option<bool>,
+}
+
 impl EndpointConf {
     fn build_local(&self) -> SocketAddr {
         self.listen
@@ -90,11 +228,275 @@ impl EndpointConf {
 
     #[cfg(feature = "balance")]
     fn build_balancer(&self) -> Balancer {
-        if let Some(s) = &self.balance {
-            Balancer::parse_from_str(s)
-        } else {
-            Balancer::default()
+        use realm_core::balance::Strategy;
+
+        match &self.balance {
+            Some(s) => match Self::least_conn_weights(s) {
+                // weights are checked here, realm_lb skips an invalid one
+                Some(weights) => Balancer::new(Strategy::LeastConn, &weights),
+                // extra remotes as backups only
+                None if s.trim() == "off" => Balancer::Off,
+                None => Balancer::parse_from_str(s),
+            },
+            // not guessed, spreading or backing up behaves quite differently
+            None if !self.extra_remotes.is_empty() => {
+                panic!("extra_remotes: require balance, e.g. roundrobin, or off to use them as backups only")
+            }
+            None => Balancer::default(),
+        }
+    }
+
+    // weights of "leastconn" or "leastconn: $weight1, .."
+    #[cfg(feature = "balance")]
+    fn least_conn_weights(s: &str) -> Option<Vec<u8>> {
+        let (strategy, weights) = s.split_once(':').unwrap_or((s, ""));
+        if strategy.trim() != "leastconn" {
+            return None;
+        }
+        let weights = weights
+            .split(',')
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .map(|x| match x.parse::<u8>() {
+                Ok(w) if w != 0 => w,
+                _ => panic!("balance: invalid leastconn weight {}", x),
+            })
+            .collect();
+        Some(weights)
+    }
+
+    #[cfg(feature = "balance")]
+    fn build_load(&self) -> Load {
+        Load::new(self.extra_remotes.len() + 1)
+    }
+
+    #[cfg(feature = "balance")]
+    fn build_health(&self) -> Health {
+        use std::time::Duration;
+        use crate::consts::UNHEALTHY_COOLDOWN;
+        let peers = self.extra_remotes.len() + 1;
+        Health::new(peers, Duration::from_secs(UNHEALTHY_COOLDOWN as u64))
+    }
+
+    #[cfg(feature = "balance")]
+    fn build_affinity(&self, balancer: &Balancer) -> Affinity {
+        use std::time::Duration;
+        use realm_core::balance::Strategy;
+
+        let timeout = match self.affinity_timeout {
+            Some(x) => x,
+            None => return Affinity::default(),
+        };
+        assert!(
+            balancer.strategy() == Strategy::IpHash,
+            "affinity_timeout: require iphash balance"
+        );
+        Affinity::new(Duration::from_secs(timeout as u64))
+    }
+
+    #[cfg(feature = "balance")]
+    fn build_hash_key(&self, balancer: &Balancer) -> HashKey {
+        use realm_core::balance::Strategy;
+
+        let key = match &self.hash_key {
+            Some(s) => HashKey::from(s.as_str()),
+            None => return HashKey::default(),
+        };
+        if key == HashKey::Source {
+            return key;
+        }
+        assert!(
+            balancer.strategy() == Strategy::IpHash,
+            "hash_key: require iphash balance"
+        );
+
+        // the request is encrypted
+        #[cfg(feature = "transport")]
+        {
+            use realm_core::kaminari::opt::get_tls_server_conf;
+            let listen_tls = self.listen_transport.as_ref().and_then(|s| get_tls_server_conf(s));
+            assert!(
+                listen_tls.is_none(),
+                "hash_key: not supported by a tls listen_transport"
+            );
+        }
+        key
+    }
+
+    // options waiting for the client, while it waits for the remote peer
+    fn check_speaks_first(&self, conn_opts: &ConnectOpts) {
+        let server = match self.speaks_first.as_deref() {
+            None | Some("client") => false,
+            Some("server") => true,
+            Some(x) => panic!("speaks_first: unknown value {}", x),
+        };
+        if !server {
+            return;
+        }
+        assert!(
+            conn_opts.first_byte_timeout == 0,
+            "first_byte_timeout: deadlock, the remote peer speaks first"
+        );
+        #[cfg(feature = "balance")]
+        assert!(
+            conn_opts.hash_key == HashKey::Source,
+            "hash_key: deadlock, the remote peer speaks first"
+        );
+    }
+
+    fn build_deadlock_nudge(&self, conn_opts: &ConnectOpts) -> Vec<u8> {
+        let nudge = match &self.deadlock_nudge {
+            Some(s) => s.as_bytes().to_vec(),
+            None => return Vec::new(),
+        };
+        assert!(
+            conn_opts.deadlock_timeout != 0,
+            "deadlock_nudge: require deadlock_timeout"
+        );
+        assert!(!nudge.is_empty(), "deadlock_nudge: must not be empty");
+        nudge
+    }
+
+    #[cfg(feature = "balance")]
+    fn build_unhealthy_policy(&self) -> UnhealthyPolicy {
+        let policy = match &self.unhealthy_policy {
+            Some(s) => UnhealthyPolicy::from(s.as_str()),
+            None => UnhealthyPolicy::default(),
+        };
+
+        match (policy, &self.maintenance_response) {
+            (UnhealthyPolicy::Maintenance(_), Some(resp)) => UnhealthyPolicy::Maintenance(resp.as_bytes().to_vec()),
+            (UnhealthyPolicy::Maintenance(_), None) => {
+                panic!("unhealthy_policy: maintenance requires a maintenance_response")
+            }
+            (policy, _) => policy,
+        }
+    }
+
+    fn build_port_routes(&self) -> Vec<(u16, RemoteAddr)> {
+        self.port_routes
+            .iter()
+            .map(|(port, remote)| {
+                let port = port
+                    .parse()
+                    .unwrap_or_else(|_| panic!("port_routes: invalid port {}", port));
+                (port, Self::build_remote_x(remote))
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "geo")]
+    fn build_geo_routes(&self) -> Option<std::sync::Arc<realm_core::geo::GeoRoutes>> {
+        use realm_core::geo::{GeoDb, GeoKey, GeoRoutes};
+
+        if self.geo_routes.is_empty() {
+            return None;
         }
+
+        let path = self.geo_db.as_ref().expect("geo_routes: require a geo_db");
+        let db = GeoDb::load(path).unwrap_or_else(|e| panic!("geo_db: failed to load {}: {}", path, e));
+        let routes = self
+            .geo_routes
+            .iter()
+            .map(|(key, remote)| {
+                let key: GeoKey = key
+                    .parse()
+                    .unwrap_or_else(|_| panic!("geo_routes: invalid country or asn {}", key));
+                (key, Self::build_remote_x(remote))
+            })
+            .collect();
+        Some(std::sync::Arc::new(GeoRoutes::new(db, routes)))
+    }
+
+    fn build_peer_opts(&self) -> Vec<PeerOpts> {
+        if self.remote_options.is_empty() {
+            return Vec::new();
+        }
+
+        let remotes: Vec<&String> = std::iter::once(&self.remote).chain(self.extra_remotes.iter()).collect();
+        let mut peer_opts = vec![PeerOpts::default(); remotes.len()];
+
+        for (remote, opts) in &self.remote_options {
+            let idx = remotes
+                .iter()
+                .position(|r| *r == remote)
+                .unwrap_or_else(|| panic!("remote_options: {} is not a remote", remote));
+            let peer = &mut peer_opts[idx];
+            peer.bind_address = opts.bind_source.map(build_bind_source);
+            peer.bind_interface.clone_from(&opts.interface);
+
+            #[cfg(feature = "proxy")]
+            {
+                peer.send_proxy = opts.send_proxy;
+                peer.send_proxy_version = opts.send_proxy_version;
+            }
+
+            #[cfg(feature = "transport")]
+            {
+                peer.transport = opts.insecure.map(|x| self.build_peer_transport(x));
+            }
+        }
+
+        peer_opts
+    }
+
+    // split dns, resolve remote peers apart from other endpoints
+    fn build_resolver(&self) -> Option<std::sync::Arc<realm_core::dns::Resolver>> {
+        let (conf, opts) = self.dns.clone()?.build();
+        Some(std::sync::Arc::new(realm_core::dns::new_resolver(conf, opts)))
+    }
+
+    #[cfg(feature = "trace")]
+    fn build_tracer(&self) -> Option<std::sync::Arc<realm_core::trace::Tracer>> {
+        use realm_core::trace::Tracer;
+        use crate::consts::TRACE_MAX_SIZE;
+
+        let path = self.trace.as_ref()?;
+        let max_size = self.trace_max_size.unwrap_or(TRACE_MAX_SIZE);
+        let payload = self.trace_payload.unwrap_or(false);
+        let tracer = Tracer::create(path, max_size as u64, payload)
+            .unwrap_or_else(|e| panic!("trace: failed to create {}: {}", path, e));
+        Some(std::sync::Arc::new(tracer))
+    }
+
+    #[cfg(feature = "compress")]
+    fn build_compress(&self) -> Option<realm_core::endpoint::Compress> {
+        use realm_core::endpoint::Compress;
+        use crate::consts::COMPRESS_LEVEL;
+
+        let side = self.compress.as_ref()?;
+        // the transport streams are not plain relays
+        if self.listen_transport.is_some() || self.remote_transport.is_some() {
+            panic!("compress: require no listen_transport or remote_transport");
+        }
+        let level = self.compress_level.unwrap_or(COMPRESS_LEVEL);
+        if level > 10 {
+            panic!("compress: invalid level {}", level);
+        }
+        match side.as_str() {
+            "listen" => Some(Compress::Listen(level)),
+            "remote" => Some(Compress::Remote(level)),
+            _ => panic!("compress: unknown side {}", side),
+        }
+    }
+
+    /// Malformed options of listen_transport, remote_transport
+    /// and remote_transport_fallback.
+    pub fn diagnose_transport(&self) -> Vec<Diagnostic> {
+        let fallback = self
+            .remote_transport_fallback
+            .iter()
+            .filter(|s| s.as_str() != "plain")
+            .map(|s| ("remote_transport_fallback", Some(s)));
+        [
+            ("listen_transport", self.listen_transport.as_ref()),
+            ("remote_transport", self.remote_transport.as_ref()),
+        ]
+        .into_iter()
+        .chain(fallback)
+        .filter_map(|(field, s)| Some(transport::diagnose(field, s?)))
+        .flatten()
+        .collect()
     }
 
     #[cfg(feature = "transport")]
@@ -133,6 +535,190 @@ impl EndpointConf {
             Some((ac, cc))
         }
     }
+
+    // the remote transport, skip verifying tls or not
+    #[cfg(feature = "transport")]
+    fn build_peer_transport(&self, insecure: bool) -> MixConnect {
+        use realm_core::kaminari::mix::MixClientConf;
+        use realm_core::kaminari::opt::get_ws_conf;
+        use realm_core::kaminari::opt::get_tls_client_conf;
+
+        let remote_transport = self.remote_transport.as_deref().unwrap_or_default();
+        let Some(mut tls) = get_tls_client_conf(remote_transport) else {
+            panic!("remote_options: insecure requires a tls remote_transport");
+        };
+        // these connect with the endpoint's tls verification
+        if self.ws_max_frame_size.is_some() || self.ws_close_code.is_some() || self.correlation_header.is_some() {
+            panic!("remote_options: insecure conflicts with ws_max_frame_size, ws_close_code or correlation_header");
+        }
+        tls.insecure = insecure;
+        MixConnect::new_shared(MixClientConf {
+            ws: get_ws_conf(remote_transport),
+            tls: Some(tls),
+        })
+    }
+
+    // tried in order once the remote transport fails
+    #[cfg(feature = "transport")]
+    fn build_transport_fallback(&self) -> Vec<MixConnect> {
+        use realm_core::kaminari::mix::MixClientConf;
+        use realm_core::kaminari::opt::get_ws_conf;
+        use realm_core::kaminari::opt::get_tls_client_conf;
+
+        if self.remote_transport_fallback.is_empty() {
+            return Vec::new();
+        }
+        assert!(
+            self.remote_transport.is_some(),
+            "remote_transport_fallback: require remote_transport"
+        );
+        // these replace the remote transport
+        if self.ws_max_frame_size.is_some() || self.ws_close_code.is_some() || self.correlation_header.is_some() {
+            panic!("remote_transport_fallback: conflicts with ws_max_frame_size, ws_close_code or correlation_header");
+        }
+        self.remote_transport_fallback
+            .iter()
+            .map(|s| {
+                MixConnect::new_shared(MixClientConf {
+                    ws: get_ws_conf(s),
+                    tls: get_tls_client_conf(s),
+                })
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "transport")]
+    fn build_ws_max_header_size(&self) -> usize {
+        use realm_core::kaminari::opt::get_ws_conf;
+
+        let size = match self.ws_max_header_size {
+            Some(x) => x,
+            None => return 0,
+        };
+
+        let listen_ws = self.listen_transport.as_ref().and_then(|s| get_ws_conf(s));
+        assert!(listen_ws.is_some(), "ws_max_header_size: require a ws listen_transport");
+        assert!(size != 0, "ws_max_header_size: must be positive");
+        size
+    }
+
+    #[cfg(feature = "transport")]
+    fn build_frame_limit(&self) -> Option<realm_core::endpoint::FrameLimit> {
+        use realm_core::endpoint::FrameLimit;
+        use realm_core::kaminari::nop::{NopAccept, NopConnect};
+        use realm_core::kaminari::tls::{TlsAccept, TlsConnect};
+        use realm_core::kaminari::opt::{get_ws_conf, get_tls_server_conf, get_tls_client_conf};
+
+        let max = self.ws_max_frame_size?;
+        assert!(max != 0, "ws_max_frame_size: must be positive");
+
+        let listen_transport = self.listen_transport.as_deref().unwrap_or_default();
+        let remote_transport = self.remote_transport.as_deref().unwrap_or_default();
+        let listen = get_ws_conf(listen_transport).map(|ws| {
+            let tls = get_tls_server_conf(listen_transport).map(|x| TlsAccept::new_shared(NopAccept {}, x));
+            (ws, tls)
+        });
+        let remote = get_ws_conf(remote_transport).map(|ws| {
+            let tls = get_tls_client_conf(remote_transport).map(|x| TlsConnect::new_shared(NopConnect {}, x));
+            (ws, tls)
+        });
+        assert!(
+            listen.is_some() || remote.is_some(),
+            "ws_max_frame_size: require a ws listen_transport or remote_transport"
+        );
+        Some(FrameLimit { max, listen, remote })
+    }
+
+    #[cfg(feature = "transport")]
+    fn build_ws_close(&self) -> Option<realm_core::endpoint::WsClose> {
+        use realm_core::endpoint::WsClose;
+        use realm_core::kaminari::nop::NopConnect;
+        use realm_core::kaminari::tls::TlsConnect;
+        use realm_core::kaminari::opt::{get_ws_conf, get_tls_client_conf};
+        use crate::consts::WS_CLOSE_CODE;
+
+        let remote_transport = self.remote_transport.as_deref().unwrap_or_default();
+        let ws = get_ws_conf(remote_transport);
+        let code = match (self.ws_close_code, ws.is_some()) {
+            (Some(0), _) => return None,
+            (Some(_), false) => panic!("ws_close_code: require a ws remote_transport"),
+            (None, false) => return None,
+            (code, true) => code.unwrap_or(WS_CLOSE_CODE),
+        };
+        // the codes an endpoint may send
+        assert!(
+            matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999),
+            "ws_close_code: {} is not a valid close code",
+            code
+        );
+
+        let tls = get_tls_client_conf(remote_transport).map(|x| TlsConnect::new_shared(NopConnect {}, x));
+        Some(WsClose {
+            code,
+            ws: ws.unwrap(),
+            tls,
+        })
+    }
+
+    #[cfg(feature = "transport")]
+    fn build_correlation(&self) -> Option<realm_core::endpoint::Correlation> {
+        use realm_core::endpoint::Correlation;
+        use realm_core::kaminari::nop::NopConnect;
+        use realm_core::kaminari::tls::TlsConnect;
+        use realm_core::kaminari::opt::{get_ws_conf, get_tls_client_conf};
+
+        let header = self.correlation_header.as_ref()?;
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        assert!(
+            !header.is_empty() && header.chars().all(valid),
+            "correlation_header: invalid header name {}",
+            header
+        );
+
+        // sent with the upgrade request
+        let remote_transport = self.remote_transport.as_deref().unwrap_or_default();
+        let ws = get_ws_conf(remote_transport).expect("correlation_header: require a ws remote_transport");
+        let tls = get_tls_client_conf(remote_transport).map(|x| TlsConnect::new_shared(NopConnect {}, x));
+        Some(Correlation {
+            header: header.clone(),
+            ws,
+            tls,
+        })
+    }
+
+    #[cfg(feature = "transport")]
+    fn build_alpn_routes(&self) -> Vec<(String, RemoteAddr)> {
+        use realm_core::kaminari::opt::get_tls_server_conf;
+
+        if self.alpn_routes.is_empty() {
+            return Vec::new();
+        }
+
+        let listen_tls = self.listen_transport.as_ref().and_then(|s| get_tls_server_conf(s));
+        assert!(listen_tls.is_some(), "alpn_routes: require a tls listen_transport");
+
+        self.alpn_routes
+            .iter()
+            .map(|(alpn, remote)| (alpn.clone(), Self::build_remote_x(remote)))
+            .collect()
+    }
+
+    #[cfg(feature = "transport")]
+    fn build_sni_allowlist(&self) -> (Vec<String>, bool) {
+        use realm_core::kaminari::opt::get_tls_server_conf;
+
+        let allow_missing = self.allow_missing_sni.unwrap_or_default();
+        if self.sni_allowlist.is_empty() {
+            assert!(!allow_missing, "allow_missing_sni: require sni_allowlist");
+            return (Vec::new(), false);
+        }
+
+        let listen_tls = self.listen_transport.as_ref().and_then(|s| get_tls_server_conf(s));
+        assert!(listen_tls.is_some(), "sni_allowlist: require a tls listen_transport");
+
+        let list = self.sni_allowlist.iter().map(|x| x.to_ascii_lowercase()).collect();
+        (list, allow_missing)
+    }
 }
 
 #[derive(Debug)]
@@ -140,6 +726,95 @@ pub struct EndpointInfo {
     pub no_tcp: bool,
     pub use_udp: bool,
     pub endpoint: Endpoint,
+    /// Listen on every address of this interface, at the port of laddr.
+    pub listen_interface: Option<String>,
+    /// Cores the tasks of this endpoint run on, empty means any.
+    pub cpu_affinity: Vec<usize>,
+}
+
+impl EndpointInfo {
+    /// Addresses to listen on, those of listen_interface are enumerated again.
+    pub fn listen_addrs(&self) -> std::io::Result<Vec<SocketAddr>> {
+        let iface = match &self.listen_interface {
+            Some(x) => x,
+            None => return Ok(vec![self.endpoint.laddr]),
+        };
+        let port = self.endpoint.laddr.port();
+        let addrs: Vec<_> = interface_addrs(iface)?
+            .into_iter()
+            .map(|mut x| {
+                x.set_port(port);
+                x
+            })
+            .collect();
+        if addrs.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AddrNotAvailable,
+                format!("no address on {}", iface),
+            ));
+        }
+        Ok(addrs)
+    }
+}
+
+// only the cores this process may run on
+fn build_cpu_affinity(mut cores: Vec<usize>) -> Vec<usize> {
+    if cores.is_empty() {
+        return cores;
+    }
+    let available = crate::affinity::available().unwrap_or_else(|e| panic!("cpu_affinity: {}", e));
+    if let Some(core) = cores.iter().find(|x| !available.contains(x)) {
+        panic!("cpu_affinity: core {} is not available", core);
+    }
+    cores.sort_unstable();
+    cores.dedup();
+    cores
+}
+
+/// Addresses currently assigned to an interface, with port 0.
+#[cfg(unix)]
+pub fn interface_addrs(name: &str) -> std::io::Result<Vec<SocketAddr>> {
+    use std::ffi::CStr;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+
+    let mut ifap: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifap) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let mut addrs = Vec::new();
+    let mut cur = ifap;
+    while !cur.is_null() {
+        let ifa = unsafe { &*cur };
+        cur = ifa.ifa_next;
+        if ifa.ifa_addr.is_null() || unsafe { CStr::from_ptr(ifa.ifa_name) }.to_bytes() != name.as_bytes() {
+            continue;
+        }
+        match unsafe { (*ifa.ifa_addr).sa_family } as libc::c_int {
+            libc::AF_INET => {
+                let sin = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+                let ip = Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+                addrs.push(SocketAddr::V4(SocketAddrV4::new(ip, 0)));
+            }
+            libc::AF_INET6 => {
+                // link-local ones need the scope
+                let sin6 = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in6) };
+                let ip = Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+                addrs.push(SocketAddr::V6(SocketAddrV6::new(ip, 0, 0, sin6.sin6_scope_id)));
+            }
+            _ => {}
+        }
+    }
+    unsafe { libc::freeifaddrs(ifap) };
+    Ok(addrs)
+}
+
+#[cfg(not(unix))]
+pub fn interface_addrs(_: &str) -> std::io::Result<Vec<SocketAddr>> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "listen_interface: require unix",
+    ))
 }
 
 impl Config for EndpointConf {
@@ -150,6 +825,12 @@ impl Config for EndpointConf {
     }
 
     fn build(self) -> Self::Output {
+        // before anything else parses them
+        #[cfg(feature = "transport")]
+        if let Some(diag) = self.diagnose_transport().first() {
+            panic!("{}", diag);
+        }
+
         let laddr = self.build_local();
         let raddr = self.build_remote();
 
@@ -161,25 +842,69 @@ impl Config for EndpointConf {
             mut conn_opts,
             no_tcp,
             use_udp,
-        } = self.network.build();
+        } = self.network.clone().build();
 
         // build left fields of conn_opts
 
-        conn_opts.bind_address = self.build_send_through();
+        // override network.bind_source
+        if let Some(addr) = self.build_send_through() {
+            conn_opts.bind_address = Some(addr);
+        }
 
         #[cfg(feature = "balance")]
         {
             conn_opts.balancer = self.build_balancer();
+            conn_opts.health = std::sync::Arc::new(self.build_health());
+            conn_opts.unhealthy_policy = self.build_unhealthy_policy();
+            conn_opts.affinity = std::sync::Arc::new(self.build_affinity(&conn_opts.balancer));
+            conn_opts.load = std::sync::Arc::new(self.build_load());
+            conn_opts.hash_key = self.build_hash_key(&conn_opts.balancer);
         }
 
         #[cfg(feature = "transport")]
         {
             conn_opts.transport = self.build_transport();
+            conn_opts.alpn_routes = self.build_alpn_routes();
+            (conn_opts.sni_allowlist, conn_opts.allow_missing_sni) = self.build_sni_allowlist();
+            conn_opts.ws_max_header_size = self.build_ws_max_header_size();
+            conn_opts.correlation = self.build_correlation();
+            conn_opts.frame_limit = self.build_frame_limit();
+            conn_opts.ws_close = self.build_ws_close();
+            conn_opts.transport_fallback = self.build_transport_fallback();
         }
 
+        conn_opts.port_routes = self.build_port_routes();
+        #[cfg(feature = "geo")]
+        {
+            conn_opts.geo_routes = self.build_geo_routes();
+        }
+        conn_opts.peer_opts = self.build_peer_opts();
+        // the redialed connections get no header
+        #[cfg(all(feature = "transport", feature = "proxy"))]
+        {
+            let send_proxy =
+                conn_opts.proxy_opts.send_proxy || conn_opts.peer_opts.iter().any(|x| x.send_proxy == Some(true));
+            assert!(
+                conn_opts.transport_fallback.is_empty() || !send_proxy,
+                "remote_transport_fallback: conflicts with send_proxy"
+            );
+        }
+        conn_opts.resolver = self.build_resolver();
+        #[cfg(feature = "trace")]
+        {
+            conn_opts.tracer = self.build_tracer();
+        }
+        #[cfg(feature = "compress")]
+        {
+            conn_opts.compress = self.build_compress();
+        }
+
+        conn_opts.deadlock_nudge = self.build_deadlock_nudge(&conn_opts);
+        self.check_speaks_first(&conn_opts);
+
         conn_opts.bind_interface = self.interface;
 
-        EndpointInfo {
+        let info = EndpointInfo {
             no_tcp,
             use_udp,
             endpoint: Endpoint {
@@ -189,7 +914,20 @@ impl Config for EndpointConf {
                 conn_opts,
                 extra_raddrs,
             },
+            listen_interface: self.listen_interface,
+            cpu_affinity: build_cpu_affinity(self.cpu_affinity),
+        };
+        if let Some(iface) = &info.listen_interface {
+            assert!(
+                laddr.ip().is_unspecified(),
+                "listen_interface: require an unspecified listen address, e.g. 0.0.0.0:{}",
+                laddr.port()
+            );
+            if let Err(e) = info.listen_addrs() {
+                panic!("listen_interface: {}: {}", iface, e);
+            }
         }
+        info
     }
 
     fn rst_field(&mut self, _: &Self) -> &mut Self {
@@ -210,14 +948,137 @@ impl Config for EndpointConf {
 
         EndpointConf {
             listen,
+            listen_interface: None,
+            cpu_affinity: Vec::new(),
             remote,
             through,
             interface,
             listen_transport,
             remote_transport,
+            remote_transport_fallback: Vec::new(),
+            alpn_routes: Default::default(),
+            sni_allowlist: Vec::new(),
+            allow_missing_sni: None,
+            port_routes: Default::default(),
+            geo_db: None,
+            geo_routes: Default::default(),
+            remote_options: Default::default(),
+            ws_max_header_size: None,
+            ws_max_frame_size: None,
+            ws_close_code: None,
+            correlation_header: None,
+            trace: None,
+            trace_max_size: None,
+            trace_payload: None,
+            compress: None,
+            compress_level: None,
+            dns: None,
             network: Default::default(),
             extra_remotes: Vec::new(),
             balance: None,
+            unhealthy_policy: None,
+            affinity_timeout: None,
+            speaks_first: None,
+            deadlock_nudge: None,
+            hash_key: None,
+            maintenance_response: None,
+        }
+    }
+}
+
+/// A table kept in the order written, for those whose order matters.
+mod ordered {
+    use std::fmt::{self, Formatter};
+    use serde::de::{MapAccess, Visitor};
+    use serde::ser::SerializeMap;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(table: &[(String, String)], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(table.len()))?;
+        for (k, v) in table {
+            map.serialize_entry(k, v)?;
+        }
+        map.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<(String, String)>, D::Error> {
+        struct Entries;
+
+        impl<'de> Visitor<'de> for Entries {
+            type Value = Vec<(String, String)>;
+
+            fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+                f.write_str("a table of strings")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut table = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    table.push(entry);
+                }
+                Ok(table)
+            }
         }
+
+        deserializer.deserialize_map(Entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, EndpointConf};
+
+    #[test]
+    #[cfg(feature = "transport")]
+    fn alpn_routes_in_order() {
+        let conf: EndpointConf = toml::from_str(
+            r#"
+            listen = "127.0.0.1:10500"
+            remote = "127.0.0.1:20500"
+            listen_transport = "tls;servername=localhost"
+            alpn_routes = { "http/1.1" = "127.0.0.1:20501", "h2" = "127.0.0.1:20502" }
+            "#,
+        )
+        .unwrap();
+        let json = serde_json::to_string(&conf).unwrap();
+        assert!(json.contains(r#""alpn_routes":{"http/1.1":"127.0.0.1:20501","h2":"127.0.0.1:20502"}"#));
+
+        let routes = conf.build().endpoint.conn_opts.alpn_routes;
+        let alpns: Vec<_> = routes.iter().map(|(x, _)| x.as_str()).collect();
+        assert_eq!(alpns, ["http/1.1", "h2"]);
+    }
+
+    #[test]
+    #[cfg(feature = "balance")]
+    fn least_conn_strategy() {
+        use realm_core::balance::Strategy;
+
+        let conf: EndpointConf = toml::from_str(
+            r#"
+            listen = "127.0.0.1:10511"
+            remote = "127.0.0.1:20511"
+            extra_remotes = ["127.0.0.1:20512"]
+            balance = "leastconn: 2"
+            "#,
+        )
+        .unwrap();
+        let balancer = conf.build().endpoint.conn_opts.balancer;
+        assert_eq!(balancer.strategy(), Strategy::LeastConn);
+        assert_eq!(balancer.total(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "transport")]
+    #[should_panic(expected = "remote_transport: quic is not supported")]
+    fn quic_transport() {
+        let conf: EndpointConf = toml::from_str(
+            r#"
+            listen = "127.0.0.1:10510"
+            remote = "127.0.0.1:20510"
+            remote_transport = "quic;sni=example.com;alpn=h3"
+            "#,
+        )
+        .unwrap();
+        conf.build();
     }
 }
@@ -69,14 +69,40 @@ impl From<LegacyConf> for FullConf {
             .zip(remote)
             .map(|(listen, remote)| EndpointConf {
                 listen,
+                listen_interface: None,
+                cpu_affinity: Vec::new(),
                 remote,
                 through: None,
                 interface: None,
                 listen_transport: None,
                 remote_transport: None,
+                remote_transport_fallback: Vec::new(),
+                alpn_routes: Default::default(),
+                sni_allowlist: Vec::new(),
+                allow_missing_sni: None,
+                port_routes: Default::default(),
+                geo_db: None,
+                geo_routes: Default::default(),
+                remote_options: Default::default(),
+                ws_max_header_size: None,
+                ws_max_frame_size: None,
+                ws_close_code: None,
+                correlation_header: None,
+                trace: None,
+                trace_max_size: None,
+                trace_payload: None,
+                compress: None,
+                compress_level: None,
+                dns: None,
                 network: Default::default(),
                 extra_remotes: Vec::new(),
                 balance: None,
+                unhealthy_policy: None,
+                affinity_timeout: None,
+                speaks_first: None,
+                deadlock_nudge: None,
+                hash_key: None,
+                maintenance_response: None,
             })
             .collect();
 
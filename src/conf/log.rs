@@ -69,20 +69,29 @@ pub struct LogConf {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub output: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<usize>,
 }
 
 impl Config for LogConf {
-    type Output = (LevelFilter, fern::Output);
+    type Output = (LevelFilter, fern::Output, usize);
 
     fn is_empty(&self) -> bool {
-        crate::empty![self => level, output]
+        crate::empty![self => level, output, rate_limit]
     }
 
     fn build(self) -> Self::Output {
         use std::io;
         use std::fs::OpenOptions;
-        let LogConf { level, output } = self;
+        let LogConf {
+            level,
+            output,
+            rate_limit,
+        } = self;
         let level = level.unwrap_or_default();
+        let rate_limit = rate_limit.unwrap_or_default();
         let output = output.unwrap_or_else(|| String::from(DEFAULT_LOG_FILE));
 
         let output: fern::Output = match output.as_str() {
@@ -96,7 +105,7 @@ impl Config for LogConf {
                 .into(),
         };
 
-        (level.into(), output)
+        (level.into(), output, rate_limit)
     }
 
     fn rst_field(&mut self, other: &Self) -> &mut Self {
@@ -105,6 +114,7 @@ impl Config for LogConf {
 
         rst!(self, level, other);
         rst!(self, output, other);
+        rst!(self, rate_limit, other);
         self
     }
 
@@ -114,6 +124,7 @@ impl Config for LogConf {
 
         take!(self, level, other);
         take!(self, output, other);
+        take!(self, rate_limit, other);
         self
     }
 
@@ -122,16 +133,29 @@ impl Config for LogConf {
 
         let output = matches.get_one("log_output").cloned();
 
-        Self { level, output }
+        let rate_limit = matches
+            .get_one::<String>("log_rate_limit")
+            .and_then(|x| x.parse::<usize>().ok());
+
+        Self {
+            level,
+            output,
+            rate_limit,
+        }
     }
 }
 
 impl Display for LogConf {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let LogConf { level, output } = self.clone();
+        let LogConf {
+            level,
+            output,
+            rate_limit,
+        } = self.clone();
         let level = level.unwrap_or_default();
         let output = output.unwrap_or_else(|| String::from("stdout"));
+        let rate_limit = rate_limit.unwrap_or_default();
 
-        write!(f, "level={}, output={}", level, output)
+        write!(f, "level={}, output={}, rate_limit={}", level, output, rate_limit)
     }
 }
@@ -9,13 +9,16 @@ mod log;
 pub use self::log::{LogLevel, LogConf};
 
 mod dns;
-pub use dns::{DnsMode, DnsProtocol, DnsConf};
+pub use dns::{DnsMode, DnsProtocol, DnsFallback, DnsConf};
 
 mod net;
 pub use net::{NetConf, NetInfo};
 
 mod endpoint;
-pub use endpoint::{EndpointConf, EndpointInfo};
+pub use endpoint::{EndpointConf, EndpointInfo, interface_addrs};
+
+mod transport;
+pub use transport::Diagnostic;
 
 mod legacy;
 pub use legacy::LegacyConf;
@@ -173,6 +176,9 @@ impl FullConf {
     pub fn apply_global_opts(&mut self) -> &mut Self {
         self.endpoints.iter_mut().for_each(|x| {
             x.network.take_field(&self.network);
+            if let Some(dns) = &mut x.dns {
+                dns.take_field(&self.dns);
+            }
         });
 
         self
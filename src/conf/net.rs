@@ -1,13 +1,22 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
 use serde::{Serialize, Deserialize};
+use realm_core::access;
 use realm_core::endpoint::{BindOpts, ConnectOpts};
+use realm_core::limit::{ConnLimit, HandshakeLimit};
 
 use super::Config;
 use crate::consts::{TCP_TIMEOUT, UDP_TIMEOUT};
 use crate::consts::{TCP_KEEPALIVE, TCP_KEEPALIVE_PROBE};
+use crate::consts::COALESCE_DELAY;
 use crate::consts::PROXY_PROTOCOL_VERSION;
 use crate::consts::PROXY_PROTOCOL_TIMEOUT;
+use crate::consts::CONN_QUEUE_TIMEOUT;
+use crate::consts::PRUNE_INTERVAL;
 
-#[derive(Serialize, Debug, Deserialize, Clone, Copy, Default)]
+#[derive(Serialize, Debug, Deserialize, Clone, Default)]
 pub struct NetConf {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -42,6 +51,11 @@ pub struct NetConf {
 
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_keepalive_interval: Option<usize>,
+
+    #[serde(default)]
+    #[serde(alias = "tcp_keepalive_retries")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub tcp_keepalive_probe: Option<usize>,
 
     #[serde(default)]
@@ -51,6 +65,86 @@ pub struct NetConf {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub udp_timeout: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coalesce_size: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coalesce_delay: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bind_source: Option<IpAddr>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub linger: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub congestion: Option<String>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow_conn_threshold: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accept_delay: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accept_rate: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_byte_timeout: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_first_byte_timeout: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deadlock_timeout: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_timeout: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prune_interval: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_conns: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conn_queue_depth: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conn_queue_timeout: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_handshakes: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handshake_timeout: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_attempt_timeout: Option<usize>,
+
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_log: Option<String>,
 }
 
 #[derive(Debug)]
@@ -68,7 +162,11 @@ impl Config for NetConf {
         crate::empty![self =>
             no_tcp, use_udp, ipv6_only,
             send_proxy, accept_proxy, send_proxy_version, accept_proxy_timeout,
-            tcp_keepalive, tcp_keepalive_probe, tcp_timeout, udp_timeout
+            tcp_keepalive, tcp_keepalive_interval, tcp_keepalive_probe, tcp_timeout, udp_timeout,
+            coalesce_size, coalesce_delay, bind_source, linger, congestion, slow_conn_threshold,
+            accept_delay, accept_rate, first_byte_timeout, remote_first_byte_timeout,
+            deadlock_timeout, idle_timeout, prune_interval, max_conns, conn_queue_depth,
+            conn_queue_timeout, max_handshakes, handshake_timeout, per_attempt_timeout, access_log
         ]
     }
 
@@ -86,27 +184,124 @@ impl Config for NetConf {
         let use_udp = unbox!(use_udp);
         let ipv6_only = unbox!(ipv6_only);
         let tcp_kpa = unbox!(tcp_keepalive, TCP_KEEPALIVE);
+        let tcp_kpa_intv = unbox!(tcp_keepalive_interval);
         let tcp_kpa_probe = unbox!(tcp_keepalive_probe, TCP_KEEPALIVE_PROBE);
         let tcp_timeout = unbox!(tcp_timeout, TCP_TIMEOUT);
         let udp_timeout = unbox!(udp_timeout, UDP_TIMEOUT);
-
-        let bind_opts = BindOpts { ipv6_only };
+        let coalesce_size = unbox!(coalesce_size);
+        let coalesce_delay = unbox!(coalesce_delay, COALESCE_DELAY);
+        let bind_address = self.bind_source.map(build_bind_source);
+        #[cfg(target_os = "linux")]
+        let congestion = self.congestion.as_deref().map(build_congestion);
+        #[cfg(not(target_os = "linux"))]
+        assert!(self.congestion.is_none(), "congestion: require linux");
+        let slow_conn_threshold = unbox!(slow_conn_threshold);
+        let accept_delay = unbox!(accept_delay);
+        let accept_rate = unbox!(accept_rate);
+        let first_byte_timeout = unbox!(first_byte_timeout);
+        let remote_first_byte_timeout = unbox!(remote_first_byte_timeout);
+        let deadlock_timeout = unbox!(deadlock_timeout);
+        let idle_timeout = unbox!(idle_timeout);
+        let prune_interval = build_prune_interval(idle_timeout, self.prune_interval);
+        let access_log = self.access_log.as_deref().map(access::Format::from);
+        let conn_limit = build_conn_limit(
+            unbox!(max_conns),
+            unbox!(conn_queue_depth),
+            unbox!(conn_queue_timeout, CONN_QUEUE_TIMEOUT),
+        );
+        let handshake_limit = Arc::new(HandshakeLimit::new(unbox!(max_handshakes)));
+        let handshake_timeout = unbox!(handshake_timeout);
+
+        let bind_opts = BindOpts {
+            ipv6_only,
+            #[cfg(target_os = "linux")]
+            congestion: congestion.clone(),
+        };
         let conn_opts = ConnectOpts {
             tcp_keepalive: tcp_kpa,
+            tcp_keepalive_interval: tcp_kpa_intv,
             tcp_keepalive_probe: tcp_kpa_probe,
             connect_timeout: tcp_timeout,
             associate_timeout: udp_timeout,
+            coalesce_size,
+            coalesce_delay,
+            slow_conn_threshold,
+            accept_delay,
+            accept_rate,
+            first_byte_timeout,
+            remote_first_byte_timeout,
+            deadlock_timeout,
+            idle_timeout,
+            prune_interval,
+            access_log,
+            linger: self.linger,
+
+            #[cfg(target_os = "linux")]
+            congestion,
+
+            // from endpoint
+            deadlock_nudge: Vec::new(),
+
+            bind_address,
 
             // from endpoint
-            bind_address: None,
             bind_interface: None,
 
+            port_routes: Vec::new(),
+
+            peer_opts: Vec::new(),
+
             #[cfg(feature = "balance")]
             balancer: Default::default(),
 
+            #[cfg(feature = "balance")]
+            health: Default::default(),
+
+            #[cfg(feature = "balance")]
+            unhealthy_policy: Default::default(),
+
+            #[cfg(feature = "balance")]
+            affinity: Default::default(),
+
+            #[cfg(feature = "balance")]
+            load: Default::default(),
+
+            #[cfg(feature = "balance")]
+            hash_key: Default::default(),
+
+            #[cfg(feature = "balance")]
+            per_attempt_timeout: unbox!(per_attempt_timeout),
+
             #[cfg(feature = "transport")]
             transport: None,
 
+            #[cfg(feature = "transport")]
+            alpn_routes: Vec::new(),
+
+            #[cfg(feature = "transport")]
+            sni_allowlist: Vec::new(),
+
+            #[cfg(feature = "transport")]
+            allow_missing_sni: false,
+
+            #[cfg(feature = "transport")]
+            ws_max_header_size: 0,
+
+            #[cfg(feature = "transport")]
+            correlation: None,
+
+            #[cfg(feature = "transport")]
+            frame_limit: None,
+
+            #[cfg(feature = "transport")]
+            ws_close: None,
+
+            #[cfg(feature = "transport")]
+            transport_fallback: Vec::new(),
+
+            #[cfg(feature = "geo")]
+            geo_routes: None,
+
             #[cfg(feature = "proxy")]
             proxy_opts: {
                 use realm_core::endpoint::ProxyOpts;
@@ -121,6 +316,28 @@ impl Config for NetConf {
                     accept_proxy_timeout,
                 }
             },
+
+            stat: Default::default(),
+
+            conns: Default::default(),
+
+            pre_close: Default::default(),
+
+            rate_limit: Default::default(),
+
+            conn_limit,
+
+            handshake_limit,
+            handshake_timeout,
+
+            // from endpoint
+            resolver: None,
+
+            #[cfg(feature = "trace")]
+            tracer: None,
+
+            #[cfg(feature = "compress")]
+            compress: None,
         };
 
         NetInfo {
@@ -133,12 +350,13 @@ impl Config for NetConf {
 
     fn rst_field(&mut self, other: &Self) -> &mut Self {
         use crate::rst;
-        let other = *other;
+        let other = other.clone();
 
         rst!(self, no_tcp, other);
         rst!(self, use_udp, other);
         rst!(self, ipv6_only, other);
         rst!(self, tcp_keepalive, other);
+        rst!(self, tcp_keepalive_interval, other);
         rst!(self, tcp_keepalive_probe, other);
         rst!(self, tcp_timeout, other);
         rst!(self, udp_timeout, other);
@@ -146,17 +364,38 @@ impl Config for NetConf {
         rst!(self, accept_proxy, other);
         rst!(self, send_proxy_version, other);
         rst!(self, accept_proxy_timeout, other);
+        rst!(self, coalesce_size, other);
+        rst!(self, coalesce_delay, other);
+        rst!(self, bind_source, other);
+        rst!(self, linger, other);
+        rst!(self, congestion, other);
+        rst!(self, slow_conn_threshold, other);
+        rst!(self, accept_delay, other);
+        rst!(self, accept_rate, other);
+        rst!(self, first_byte_timeout, other);
+        rst!(self, remote_first_byte_timeout, other);
+        rst!(self, deadlock_timeout, other);
+        rst!(self, idle_timeout, other);
+        rst!(self, prune_interval, other);
+        rst!(self, max_conns, other);
+        rst!(self, conn_queue_depth, other);
+        rst!(self, conn_queue_timeout, other);
+        rst!(self, max_handshakes, other);
+        rst!(self, handshake_timeout, other);
+        rst!(self, per_attempt_timeout, other);
+        rst!(self, access_log, other);
         self
     }
 
     fn take_field(&mut self, other: &Self) -> &mut Self {
         use crate::take;
-        let other = *other;
+        let other = other.clone();
 
         take!(self, no_tcp, other);
         take!(self, use_udp, other);
         take!(self, ipv6_only, other);
         take!(self, tcp_keepalive, other);
+        take!(self, tcp_keepalive_interval, other);
         take!(self, tcp_keepalive_probe, other);
         take!(self, tcp_timeout, other);
         take!(self, udp_timeout, other);
@@ -164,6 +403,26 @@ impl Config for NetConf {
         take!(self, accept_proxy, other);
         take!(self, send_proxy_version, other);
         take!(self, accept_proxy_timeout, other);
+        take!(self, coalesce_size, other);
+        take!(self, coalesce_delay, other);
+        take!(self, bind_source, other);
+        take!(self, linger, other);
+        take!(self, congestion, other);
+        take!(self, slow_conn_threshold, other);
+        take!(self, accept_delay, other);
+        take!(self, accept_rate, other);
+        take!(self, first_byte_timeout, other);
+        take!(self, remote_first_byte_timeout, other);
+        take!(self, deadlock_timeout, other);
+        take!(self, idle_timeout, other);
+        take!(self, prune_interval, other);
+        take!(self, max_conns, other);
+        take!(self, conn_queue_depth, other);
+        take!(self, conn_queue_timeout, other);
+        take!(self, max_handshakes, other);
+        take!(self, handshake_timeout, other);
+        take!(self, per_attempt_timeout, other);
+        take!(self, access_log, other);
         self
     }
 
@@ -186,7 +445,8 @@ impl Config for NetConf {
         let ipv6_only = unpack!("ipv6_only");
 
         let tcp_keepalive = unpack!("tcp_keepalive", usize);
-        let tcp_keepalive_probe = unpack!("tcp_keepalive", usize);
+        let tcp_keepalive_interval = unpack!("tcp_keepalive_interval", usize);
+        let tcp_keepalive_probe = unpack!("tcp_keepalive_probe", usize);
         let tcp_timeout = unpack!("tcp_timeout", usize);
         let udp_timeout = unpack!("udp_timeout", usize);
 
@@ -196,11 +456,37 @@ impl Config for NetConf {
         let accept_proxy = unpack!("accept_proxy", bool);
         let accept_proxy_timeout = unpack!("accept_proxy_timeout", usize);
 
+        let coalesce_size = unpack!("coalesce_size", usize);
+        let coalesce_delay = unpack!("coalesce_delay", usize);
+
+        let bind_source = unpack!("bind_source", IpAddr);
+        let linger = unpack!("linger", usize);
+        let congestion = unpack!("congestion", String);
+
+        let slow_conn_threshold = unpack!("slow_conn_threshold", usize);
+        let accept_delay = unpack!("accept_delay", usize);
+        let accept_rate = unpack!("accept_rate", usize);
+        let first_byte_timeout = unpack!("first_byte_timeout", usize);
+        let remote_first_byte_timeout = unpack!("remote_first_byte_timeout", usize);
+        let deadlock_timeout = unpack!("deadlock_timeout", usize);
+        let idle_timeout = unpack!("idle_timeout", usize);
+        let prune_interval = unpack!("prune_interval", usize);
+
+        let max_conns = unpack!("max_conns", usize);
+        let conn_queue_depth = unpack!("conn_queue_depth", usize);
+        let conn_queue_timeout = unpack!("conn_queue_timeout", usize);
+        let max_handshakes = unpack!("max_handshakes", usize);
+        let handshake_timeout = unpack!("handshake_timeout", usize);
+        let per_attempt_timeout = unpack!("per_attempt_timeout", usize);
+
+        let access_log = unpack!("access_log", String);
+
         Self {
             no_tcp,
             use_udp,
             ipv6_only,
             tcp_keepalive,
+            tcp_keepalive_interval,
             tcp_keepalive_probe,
             tcp_timeout,
             udp_timeout,
@@ -208,6 +494,354 @@ impl Config for NetConf {
             accept_proxy,
             send_proxy_version,
             accept_proxy_timeout,
+            coalesce_size,
+            coalesce_delay,
+            bind_source,
+            linger,
+            congestion,
+            slow_conn_threshold,
+            accept_delay,
+            accept_rate,
+            first_byte_timeout,
+            remote_first_byte_timeout,
+            deadlock_timeout,
+            idle_timeout,
+            prune_interval,
+            max_conns,
+            conn_queue_depth,
+            conn_queue_timeout,
+            max_handshakes,
+            handshake_timeout,
+            per_attempt_timeout,
+            access_log,
         }
     }
 }
+
+// only loaded algorithms can be set
+#[cfg(target_os = "linux")]
+fn build_congestion(algo: &str) -> String {
+    let path = "/proc/sys/net/ipv4/tcp_available_congestion_control";
+    if let Ok(available) = std::fs::read_to_string(path) {
+        assert!(
+            available.split_whitespace().any(|x| x == algo),
+            "congestion: {} is not available, load it first, e.g. modprobe tcp_{}",
+            algo,
+            algo
+        );
+    }
+    String::from(algo)
+}
+
+// a queue is useless without a limit
+fn build_conn_limit(max: usize, depth: usize, timeout: usize) -> Arc<ConnLimit> {
+    if max == 0 && depth != 0 {
+        panic!("conn_queue_depth: require max_conns");
+    }
+    Arc::new(ConnLimit::new(max, depth, Duration::from_secs(timeout as u64)))
+}
+
+// sweeping is useless without a timeout
+fn build_prune_interval(idle_timeout: usize, interval: Option<usize>) -> usize {
+    if idle_timeout == 0 && interval.is_some() {
+        panic!("prune_interval: require idle_timeout");
+    }
+    match interval.unwrap_or(PRUNE_INTERVAL) {
+        0 => panic!("prune_interval: must be positive"),
+        n => n,
+    }
+}
+
+// a non-local ip fails to bind
+pub(super) fn build_bind_source(ip: IpAddr) -> SocketAddr {
+    let addr = SocketAddr::new(ip, 0);
+    if let Err(e) = std::net::UdpSocket::bind(addr) {
+        panic!("bind_source: {} is not a local address: {}", ip, e);
+    }
+    addr
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::conf::{Config, EndpointConf};
+    use realm_core::tcp::run_tcp;
+    use tokio::net::{TcpListener, TcpStream};
+
+    fn endpoint(bind_source: &str) -> EndpointConf {
+        toml::from_str(&format!(
+            r#"
+            listen = "127.0.0.1:10400"
+            remote = "127.0.0.1:20400"
+            network = {{ bind_source = "{}" }}
+            "#,
+            bind_source
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn bind_source() {
+        let lis = TcpListener::bind("127.0.0.1:20400").await.unwrap();
+        tokio::spawn(run_tcp(endpoint("127.0.0.2").build().endpoint));
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+        let _local = TcpStream::connect("127.0.0.1:10400").await.unwrap();
+        let (_, addr) = lis.accept().await.unwrap();
+        assert_eq!(addr.ip().to_string(), "127.0.0.2");
+    }
+
+    #[test]
+    #[should_panic(expected = "not a local address")]
+    fn bind_source_not_local() {
+        // TEST-NET-1
+        endpoint("192.0.2.1").build();
+    }
+
+    #[test]
+    fn tcp_keepalive() {
+        let conf: super::NetConf = toml::from_str(
+            r#"
+            tcp_keepalive = 20
+            tcp_keepalive_interval = 7
+            tcp_keepalive_retries = 4
+            "#,
+        )
+        .unwrap();
+        let conn_opts = conf.build().conn_opts;
+        assert_eq!(conn_opts.tcp_keepalive, 20);
+        assert_eq!(conn_opts.tcp_keepalive_interval, 7);
+        assert_eq!(conn_opts.tcp_keepalive_probe, 4);
+    }
+
+    #[test]
+    fn conn_queue() {
+        let conf: super::NetConf = toml::from_str("max_conns = 2\nconn_queue_depth = 3").unwrap();
+        let conn_limit = conf.build().conn_opts.conn_limit;
+        assert_eq!(conn_limit.max(), 2);
+        assert_eq!(conn_limit.depth(), 3);
+        assert_eq!(conn_limit.timeout().as_secs(), 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "require max_conns")]
+    fn conn_queue_without_limit() {
+        let conf: super::NetConf = toml::from_str("conn_queue_depth = 3").unwrap();
+        conf.build();
+    }
+
+    #[test]
+    #[should_panic(expected = "prune_interval: require idle_timeout")]
+    fn prune_without_idle_timeout() {
+        let conf: super::NetConf = toml::from_str("prune_interval = 100").unwrap();
+        conf.build();
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown access log format: apache")]
+    fn access_log_unknown_format() {
+        let conf: super::NetConf = toml::from_str(r#"access_log = "apache""#).unwrap();
+        conf.build();
+    }
+
+    #[test]
+    fn linger() {
+        let conf: super::NetConf = toml::from_str("linger = 5").unwrap();
+        assert_eq!(conf.build().conn_opts.linger, Some(5));
+        let conf: super::NetConf = toml::from_str("").unwrap();
+        assert_eq!(conf.build().conn_opts.linger, None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn congestion() {
+        let conf: super::NetConf = toml::from_str(r#"congestion = "reno""#).unwrap();
+        let super::NetInfo {
+            conn_opts, bind_opts, ..
+        } = conf.build();
+        assert_eq!(conn_opts.congestion.as_deref(), Some("reno"));
+        assert_eq!(bind_opts.congestion.as_deref(), Some("reno"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[should_panic(expected = "congestion: nope is not available")]
+    fn congestion_not_available() {
+        let conf: super::NetConf = toml::from_str(r#"congestion = "nope""#).unwrap();
+        conf.build();
+    }
+
+    #[test]
+    #[should_panic(expected = "first_byte_timeout: deadlock, the remote peer speaks first")]
+    fn server_speaks_first() {
+        let conf: EndpointConf = toml::from_str(
+            r#"
+            listen = "127.0.0.1:10410"
+            remote = "127.0.0.1:20410"
+            speaks_first = "server"
+            network.first_byte_timeout = 5
+            "#,
+        )
+        .unwrap();
+        conf.build();
+    }
+
+    #[cfg(feature = "balance")]
+    #[test]
+    #[should_panic(expected = "extra_remotes: require balance")]
+    fn extra_remotes_without_balance() {
+        let conf: EndpointConf = toml::from_str(
+            r#"
+            listen = "127.0.0.1:10410"
+            remote = "127.0.0.1:20410"
+            extra_remotes = ["127.0.0.1:20411"]
+            "#,
+        )
+        .unwrap();
+        conf.build();
+    }
+
+    #[cfg(feature = "balance")]
+    #[test]
+    fn extra_remotes_as_backups() {
+        use realm_core::balance::Strategy;
+
+        let conf: EndpointConf = toml::from_str(
+            r#"
+            listen = "127.0.0.1:10410"
+            remote = "127.0.0.1:20410"
+            extra_remotes = ["127.0.0.1:20411"]
+            balance = "off"
+            "#,
+        )
+        .unwrap();
+        let info = conf.build();
+        assert_eq!(info.endpoint.conn_opts.balancer.strategy(), Strategy::Off);
+        assert_eq!(info.endpoint.extra_raddrs.len(), 1);
+    }
+
+    #[cfg(feature = "balance")]
+    #[test]
+    #[should_panic(expected = "balance: invalid leastconn weight 0")]
+    fn least_conn_zero_weight() {
+        let conf: EndpointConf = toml::from_str(
+            r#"
+            listen = "127.0.0.1:10410"
+            remote = "127.0.0.1:20410"
+            extra_remotes = ["127.0.0.1:20411"]
+            balance = "leastconn: 0, 1"
+            "#,
+        )
+        .unwrap();
+        conf.build();
+    }
+
+    #[cfg(feature = "transport")]
+    #[test]
+    #[should_panic(expected = "remote_options: insecure requires a tls remote_transport")]
+    fn peer_insecure_without_tls() {
+        let conf: EndpointConf = toml::from_str(
+            r#"
+            listen = "127.0.0.1:10410"
+            remote = "127.0.0.1:20410"
+            remote_transport = "ws;host=example.com;path=/ws"
+
+            [remote_options."127.0.0.1:20410"]
+            insecure = true
+            "#,
+        )
+        .unwrap();
+        conf.build();
+    }
+
+    #[cfg(feature = "transport")]
+    #[test]
+    #[should_panic(expected = "remote_transport_fallback: require remote_transport")]
+    fn transport_fallback_without_transport() {
+        let conf: EndpointConf = toml::from_str(
+            r#"
+            listen = "127.0.0.1:10410"
+            remote = "127.0.0.1:20410"
+            remote_transport_fallback = ["plain"]
+            "#,
+        )
+        .unwrap();
+        conf.build();
+    }
+
+    #[cfg(feature = "transport")]
+    #[test]
+    #[should_panic(expected = "remote_transport_fallback: sni requires tls")]
+    fn transport_fallback_malformed() {
+        let conf: EndpointConf = toml::from_str(
+            r#"
+            listen = "127.0.0.1:10410"
+            remote = "127.0.0.1:20410"
+            remote_transport = "ws;host=example.com;path=/ws;tls;sni=example.com"
+            remote_transport_fallback = ["ws;host=example.com;path=/ws;sni=example.com", "plain"]
+            "#,
+        )
+        .unwrap();
+        conf.build();
+    }
+
+    #[cfg(feature = "transport")]
+    #[test]
+    #[should_panic(expected = "remote_transport: hostname is unknown")]
+    fn transport_unknown_option() {
+        let conf: EndpointConf = toml::from_str(
+            r#"
+            listen = "127.0.0.1:10410"
+            remote = "127.0.0.1:20410"
+            remote_transport = "ws;hostname=example.com;path=/ws"
+            "#,
+        )
+        .unwrap();
+        conf.build();
+    }
+
+    #[cfg(feature = "transport")]
+    #[test]
+    #[should_panic(expected = "ws_max_frame_size: require a ws listen_transport or remote_transport")]
+    fn ws_max_frame_size_without_ws() {
+        let conf: EndpointConf = toml::from_str(
+            r#"
+            listen = "127.0.0.1:10410"
+            remote = "127.0.0.1:20410"
+            remote_transport = "tls;sni=example.com"
+            ws_max_frame_size = 4096
+            "#,
+        )
+        .unwrap();
+        conf.build();
+    }
+
+    #[cfg(feature = "transport")]
+    #[test]
+    #[should_panic(expected = "ws_close_code: 1005 is not a valid close code")]
+    fn ws_close_code_invalid() {
+        let conf: EndpointConf = toml::from_str(
+            r#"
+            listen = "127.0.0.1:10410"
+            remote = "127.0.0.1:20410"
+            remote_transport = "ws;host=example.com;path=/ws"
+            ws_close_code = 1005
+            "#,
+        )
+        .unwrap();
+        conf.build();
+    }
+
+    #[test]
+    #[should_panic(expected = "listen_interface: nope0")]
+    fn listen_interface_missing() {
+        let conf: EndpointConf = toml::from_str(
+            r#"
+            listen = "0.0.0.0:10410"
+            listen_interface = "nope0"
+            remote = "127.0.0.1:20410"
+            "#,
+        )
+        .unwrap();
+        conf.build();
+    }
+}
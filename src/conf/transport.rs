@@ -0,0 +1,168 @@
+//! Transport string diagnostics.
+//!
+//! kaminari parses a transport string leniently: an unknown option is
+//! ignored, `hostname=x` is taken as `host`, and a missing value panics
+//! with the kind only. The options are checked one by one here first,
+//! so that a malformed one is reported by its name.
+
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+
+use serde::Serialize;
+
+/// A malformed option of a transport string.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    /// `listen_transport`, `remote_transport` or `remote_transport_fallback`.
+    pub field: &'static str,
+    /// The offending option, e.g. `host`.
+    pub option: String,
+    pub reason: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} {}", self.field, self.option, self.reason)
+    }
+}
+
+// options and which kind they belong to
+const LISTEN_OPTS: &[(&str, &str)] = &[
+    ("host", "ws"),
+    ("path", "ws"),
+    ("cert", "tls"),
+    ("key", "tls"),
+    ("ocsp", "tls"),
+    ("servername", "tls"),
+];
+const REMOTE_OPTS: &[(&str, &str)] = &[("host", "ws"), ("path", "ws"), ("sni", "tls"), ("alpn", "tls")];
+const REMOTE_FLAGS: &[(&str, &str)] = &[("insecure", "tls"), ("0rtt", "tls")];
+
+/// Check every option of a transport string, empty if it is well formed.
+pub fn diagnose(field: &'static str, s: &str) -> Vec<Diagnostic> {
+    let (opts, flags) = match field {
+        "listen_transport" => (LISTEN_OPTS, &[][..]),
+        _ => (REMOTE_OPTS, REMOTE_FLAGS),
+    };
+
+    let mut diags = Vec::new();
+    let mut report = |option: &str, reason: String| {
+        diags.push(Diagnostic {
+            field,
+            option: String::from(option),
+            reason,
+        })
+    };
+
+    let tokens: Vec<&str> = s.split(';').map(str::trim).filter(|x| !x.is_empty()).collect();
+    let has = |kind: &str| tokens.contains(&kind);
+    let mut seen: Vec<&str> = Vec::new();
+    let mut values: Vec<(&str, &str)> = Vec::new();
+
+    for token in &tokens {
+        let (name, value) = match token.split_once('=') {
+            Some((k, v)) => (k.trim(), Some(v.trim())),
+            None => (*token, None),
+        };
+        if seen.contains(&name) {
+            report(name, String::from("is set more than once"));
+            continue;
+        }
+        seen.push(name);
+
+        if name == "quic" {
+            report(name, String::from("is not supported"));
+            continue;
+        }
+        if matches!(name, "ws" | "tls") {
+            if value.is_some() {
+                report(name, String::from("takes no value"));
+            }
+            continue;
+        }
+        if let Some((_, kind)) = flags.iter().find(|(x, _)| *x == name) {
+            if value.is_some() {
+                report(name, String::from("takes no value"));
+            } else if !has(kind) {
+                report(name, format!("requires {}", kind));
+            }
+            continue;
+        }
+        let kind = match opts.iter().find(|(x, _)| *x == name) {
+            Some((_, kind)) => kind,
+            None => {
+                report(name, String::from("is unknown"));
+                continue;
+            }
+        };
+        match value {
+            Some(v) if !v.is_empty() => values.push((name, v)),
+            _ => {
+                report(name, String::from("requires a value"));
+                continue;
+            }
+        }
+        if !has(kind) {
+            report(name, format!("requires {}", kind));
+        }
+    }
+
+    let get = |name: &str| values.iter().find(|(k, _)| *k == name).map(|(_, v)| *v);
+    let given = |name: &str| seen.contains(&name);
+
+    if let Some(host) = get("host") {
+        if host.contains('/') || host.chars().any(char::is_whitespace) {
+            report("host", format!("{} is not a host name", host));
+        }
+    }
+    if let Some(sni) = get("sni") {
+        let label = |x: &str| !x.is_empty() && x.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if sni.parse::<IpAddr>().is_err() && !sni.trim_end_matches('.').split('.').all(label) {
+            report("sni", format!("{} is not a dns name or an ip address", sni));
+        }
+    }
+    if let Some(path) = get("path") {
+        if !path.starts_with('/') {
+            report("path", format!("{} does not start with /", path));
+        }
+    }
+    if let Some(alpn) = get("alpn") {
+        if alpn.split(',').any(|x| x.trim().is_empty()) {
+            report("alpn", format!("{} has an empty protocol", alpn));
+        }
+    }
+    for name in ["cert", "key", "ocsp"] {
+        if let Some(Err(e)) = get(name).map(std::fs::metadata) {
+            report(name, format!("{}: {}", get(name).unwrap(), e));
+        }
+    }
+
+    // the ones kaminari requires
+    if has("ws") {
+        for name in ["host", "path"] {
+            if !given(name) {
+                report(name, String::from("is missing, required by ws"));
+            }
+        }
+    }
+    if has("tls") {
+        match field {
+            "listen_transport" => {
+                if !given("servername") {
+                    for name in ["cert", "key"] {
+                        if !given(name) {
+                            report(name, String::from("is missing, required by tls without servername"));
+                        }
+                    }
+                }
+            }
+            _ => {
+                if !given("sni") {
+                    report("sni", String::from("is missing, required by tls"));
+                }
+            }
+        }
+    }
+
+    diags
+}
@@ -9,6 +9,33 @@ pub const TCP_KEEPALIVE: usize = 15;
 pub const TCP_KEEPALIVE_PROBE: usize = 3;
 pub const UDP_TIMEOUT: usize = 30;
 
+// default seconds a connection may wait for a free slot
+pub const CONN_QUEUE_TIMEOUT: usize = 5;
+
+// default milliseconds between two sweeps of idle connections
+pub const PRUNE_INTERVAL: usize = 1000;
+
+// default write coalescing window, in milliseconds
+pub const COALESCE_DELAY: usize = 5;
+
+// seconds a remote peer stays down after a failed connect
+pub const UNHEALTHY_COOLDOWN: usize = 10;
+
+// milliseconds a watermark must stay crossed before the scaling callback fires
+pub const SCALING_DEBOUNCE: usize = 500;
+
+// nice value of the background thread, above the relay threads
+pub const BACKGROUND_NICE: i32 = 10;
+
+// default size cap of a trace file, in bytes
+pub const TRACE_MAX_SIZE: usize = 16 * 1024 * 1024;
+
+// default deflate level of a compressed relay
+pub const COMPRESS_LEVEL: u8 = 6;
+
+// default status code of the close frame to a ws remote peer
+pub const WS_CLOSE_CODE: u16 = 1000;
+
 // default haproxy proxy-protocol version
 pub const PROXY_PROTOCOL_VERSION: usize = 2;
 
@@ -30,6 +57,9 @@ def_feat!(FEATURE_JEMALLOC, "jemalloc");
 def_feat!(FEATURE_MULTI_THREAD, "multi-thread");
 def_feat!(FEATURE_TRANSPORT, "transport");
 def_feat!(FEATURE_BRUTAL_SHUTDOWN, "brutal-shutdown");
+def_feat!(FEATURE_TRACE, "trace");
+def_feat!(FEATURE_GEO, "geo");
+def_feat!(FEATURE_COMPRESS, "compress");
 
 pub struct Features {
     pub mimalloc: bool,
@@ -40,6 +70,9 @@ pub struct Features {
     pub balance: bool,
     pub transport: bool,
     pub brutal_shutdown: bool,
+    pub trace: bool,
+    pub geo: bool,
+    pub compress: bool,
 }
 
 pub const FEATURES: Features = Features {
@@ -51,6 +84,9 @@ pub const FEATURES: Features = Features {
     balance: FEATURE_BALANCE,
     transport: FEATURE_TRANSPORT,
     brutal_shutdown: FEATURE_BRUTAL_SHUTDOWN,
+    trace: FEATURE_TRACE,
+    geo: FEATURE_GEO,
+    compress: FEATURE_COMPRESS,
 };
 
 impl Display for Features {
@@ -68,6 +104,9 @@ impl Display for Features {
         disp_feat!(balance, "balance");
         disp_feat!(brutal_shutdown, "brutal");
         disp_feat!(transport, "transport");
+        disp_feat!(trace, "trace");
+        disp_feat!(geo, "geo");
+        disp_feat!(compress, "compress");
         disp_feat!(multi_thread, "multi-thread");
         disp_feat!(mimalloc, "mimalloc");
         disp_feat!(jemalloc, "jemalloc");
@@ -1,24 +1,165 @@
 pub mod cmd;
 pub mod conf;
 pub mod consts;
+pub mod logger;
+pub mod affinity;
 use conf::{EndpointConf, NetConf};
 pub use realm_core as core;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const ENV_CONFIG: &str = "REALM_CONF";
 
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex, Once};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use crate::conf::{Config, LogConf, DnsConf, EndpointInfo};
+use crate::core::stat::{Stat, StatSnapshot};
+use crate::core::registry::Registry;
+use crate::core::endpoint::RemoteAddr;
 
 use once_cell::sync::Lazy;
-use std::net::TcpListener;
+use std::net::{SocketAddr, TcpListener};
 
 // 全局运行时映射，用于管理多个Realm实例
-static RUNTIME_MAP: Lazy<Arc<Mutex<HashMap<String, (tokio::runtime::Runtime, usize, String)>>>> =
-    Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+static RUNTIME_MAP: Lazy<Arc<Mutex<HashMap<String, Instance>>>> = Lazy::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+// 监听地址到配置键的索引，须在持有RUNTIME_MAP时更新
+static LISTEN_INDEX: Lazy<Mutex<HashMap<String, String>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 运行中的Realm实例
+struct Instance {
+    // 主运行时，故障转移后为None
+    runtime: Option<tokio::runtime::Runtime>,
+    // 引用计数
+    count: usize,
+    listen_addr: String,
+    // 远端地址
+    remote: RemoteAddr,
+    // 流量统计
+    stat: Arc<Stat>,
+    // 活跃连接
+    conns: Arc<Registry>,
+    // 扩缩容回调任务
+    scaling: Option<tokio::task::JoinHandle<()>>,
+    // 端点及其监听套接字，故障转移时复用
+    endpoints: Vec<Bound>,
+    // 主运行时最近一次心跳，毫秒
+    heartbeat: Arc<AtomicU64>,
+    // 在主运行时上的端点任务
+    tasks: Vec<tokio::task::JoinHandle<()>>,
+    // 在备用运行时上的端点任务
+    standby: Vec<tokio::task::JoinHandle<()>>,
+    // 已禁用，监听套接字已关闭
+    disabled: bool,
+    // 启动时的端点配置，不含监听地址
+    resolved: String,
+    // 工作线程绑定的核心，重新启用时沿用
+    cores: Vec<usize>,
+}
+
+/// 已绑定监听套接字的端点
+struct Bound {
+    endpoint: core::endpoint::Endpoint,
+    tcp: Option<std::net::TcpListener>,
+    udp: Option<std::net::UdpSocket>,
+    no_tcp: bool,
+    use_udp: bool,
+    // 监听该网卡的每个地址
+    listen_interface: Option<String>,
+}
+
+impl Instance {
+    /// 停止接受新连接，已有连接继续运行
+    fn stop_accept(&mut self) {
+        for task in self.tasks.drain(..).chain(self.standby.drain(..)) {
+            task.abort();
+        }
+        for bound in self.endpoints.iter_mut() {
+            bound.tcp = None;
+            bound.udp = None;
+        }
+    }
+
+    /// 关闭实例
+    fn shutdown(self) {
+        if let Some(runtime) = self.runtime {
+            runtime.shutdown_background();
+        }
+        // 备用运行时是共享的，端点任务结束时udp关联随之结束，tcp连接在下面断开
+        for task in self.standby {
+            task.abort();
+        }
+        if let Some(task) = self.scaling {
+            task.abort();
+        }
+        for conn in self.conns.list() {
+            self.conns.kill(conn.id);
+        }
+    }
+}
+
+impl Bound {
+    /// 绑定端点的每个监听地址，设置了listen_interface时重新枚举该网卡当前的地址，
+    /// 失败时返回出错的地址
+    fn bind(info: EndpointInfo) -> Result<Vec<Self>, (SocketAddr, std::io::Error)> {
+        let laddrs = info.listen_addrs().map_err(|e| (info.endpoint.laddr, e))?;
+        let EndpointInfo {
+            endpoint,
+            no_tcp,
+            use_udp,
+            listen_interface,
+            // 属于实例的运行时
+            cpu_affinity: _,
+        } = info;
+
+        let mut bounds = Vec::with_capacity(laddrs.len());
+        for laddr in laddrs {
+            let mut endpoint = endpoint.clone();
+            endpoint.laddr = laddr;
+            let bind = || {
+                let udp = use_udp.then(|| core::udp::bind(&endpoint)).transpose()?;
+                let tcp = (!no_tcp).then(|| core::tcp::bind(&endpoint)).transpose()?;
+                std::io::Result::Ok((tcp, udp))
+            };
+            let (tcp, udp) = bind().map_err(|e| (laddr, e))?;
+            bounds.push(Bound {
+                endpoint,
+                tcp,
+                udp,
+                no_tcp,
+                use_udp,
+                listen_interface: listen_interface.clone(),
+            });
+        }
+        Ok(bounds)
+    }
+}
+
+/// 扩缩容回调，up为true表示活跃连接数达到高水位，false表示回落到低水位
+pub type ScalingCallback = extern "C" fn(config_key: *const c_char, up: bool, active: u64);
+
+/// 连接关闭前的回调，client为客户端地址，reason为日志中的关闭原因，如"idle"
+pub type CloseCallback = extern "C" fn(config_key: *const c_char, client: *const c_char, reason: *const c_char);
+
+/// 实例的生命周期事件
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    /// 开始创建实例
+    Starting = 0,
+    /// 已绑定监听地址并开始接受连接
+    Ready = 1,
+    /// 开始关闭实例
+    Stopping = 2,
+    /// 已关闭，创建失败时紧随Starting
+    Stopped = 3,
+}
+
+/// 生命周期回调，timestamp为事件发生时的unix时间，毫秒
+pub type LifecycleCallback = extern "C" fn(config_key: *const c_char, event: LifecycleEvent, timestamp: u64);
 
 // 日志初始化标志
 static LOG_INIT: Once = Once::new();
@@ -26,6 +167,104 @@ static LOG_INIT: Once = Once::new();
 // DNS初始化标志
 static DNS_INIT: Once = Once::new();
 
+// 备用运行时，主运行时卡死时接管端点
+static STANDBY: Lazy<std::io::Result<tokio::runtime::Runtime>> = Lazy::new(create_runtime);
+
+// 后台运行时，运行健康检查、扩缩容回调等后台任务
+static BACKGROUND: Lazy<std::io::Result<tokio::runtime::Handle>> = Lazy::new(create_background);
+
+// 当前线程最近一次的错误信息
+thread_local! {
+    static LAST_ERROR: std::cell::RefCell<Option<CString>> = const { std::cell::RefCell::new(None) };
+}
+
+// 看门狗超时，毫秒，0表示关闭
+static WATCHDOG_TIMEOUT: AtomicU64 = AtomicU64::new(0);
+
+// 看门狗初始化标志
+static WATCHDOG_INIT: Once = Once::new();
+
+// TCP keepalive参数: (空闲时间, 探测间隔, 探测次数)，None表示使用默认值
+static TCP_KEEPALIVE: Mutex<Option<(usize, usize, usize)>> = Mutex::new(None);
+
+// 严格模式，相同配置键的实例以不同的配置启动时报错，而不是合并
+static STRICT_START: AtomicBool = AtomicBool::new(false);
+
+// 生命周期回调
+static LIFECYCLE: Mutex<Option<LifecycleCallback>> = Mutex::new(None);
+
+// 配置键到工作线程CPU核心的映射，启动实例时读取
+static CPU_AFFINITY: Lazy<Mutex<HashMap<String, Vec<usize>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+// StatsD导出配置，None表示关闭
+#[cfg(feature = "statsd")]
+static STATSD: Mutex<Option<Statsd>> = Mutex::new(None);
+
+// StatsD导出线程初始化标志
+#[cfg(feature = "statsd")]
+static STATSD_INIT: Once = Once::new();
+
+/// StatsD导出配置
+#[cfg(feature = "statsd")]
+struct Statsd {
+    // 已连接到StatsD地址
+    socket: std::net::UdpSocket,
+    prefix: String,
+    interval: Duration,
+}
+
+// 健康检查的监听任务，None表示关闭
+#[cfg(feature = "admin")]
+static HEALTH: Mutex<Option<tokio::task::JoinHandle<()>>> = Mutex::new(None);
+
+// 就绪信号状态
+#[cfg(unix)]
+static READINESS: Mutex<Readiness> = Mutex::new(Readiness { pending: 0, fd: None });
+
+/// 就绪信号，没有正在启动的实例时写入fd，仅发送一次
+#[cfg(unix)]
+struct Readiness {
+    // 正在启动的实例数
+    pending: usize,
+    fd: Option<std::os::raw::c_int>,
+}
+
+#[cfg(unix)]
+impl Readiness {
+    fn notify(&mut self) {
+        if self.pending != 0 {
+            return;
+        }
+        if let Some(fd) = self.fd.take() {
+            match write_ready(fd) {
+                Ok(()) => log::info!("Readiness has been signaled to fd {}", fd),
+                Err(e) => log::warn!("Failed to signal readiness to fd {}: {}", fd, e),
+            }
+        }
+    }
+}
+
+/// 实例启动中，离开时可能发出就绪信号
+#[cfg(unix)]
+struct Starting;
+
+#[cfg(unix)]
+impl Starting {
+    fn enter() -> Self {
+        READINESS.lock().unwrap().pending += 1;
+        Starting
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Starting {
+    fn drop(&mut self) {
+        let mut readiness = READINESS.lock().unwrap();
+        readiness.pending -= 1;
+        readiness.notify();
+    }
+}
+
 /// 在C语言中使用Realm库的方法:
 ///
 /// 1. 包含头文件:
@@ -40,6 +279,8 @@ static DNS_INIT: Once = Once::new();
 /// 注意:
 /// - 确保已经正确编译并链接了Realm库
 /// - start_realm函数不再阻塞，而是在后台运行
+/// - 配置无效、绑定失败或运行时创建失败时返回NULL，可调用realm_last_error获取错误信息
+/// - 相同参数的实例已存在时共享该实例并增加引用计数，严格模式见realm_set_strict_start
 #[no_mangle]
 pub extern "C" fn start_realm(
     remote: *const c_char,
@@ -54,35 +295,64 @@ pub extern "C" fn start_realm(
     // 将C字符串转换为Rust字符串
     let (remote, host, path) = convert_cstr_to_str(remote, host, path);
 
-    // 创建唯一的配置键
-    let config_key = format!("{}-{}-{}-{}-{}", remote, host, path, tls, insecure);
-    let mut runtime_map = RUNTIME_MAP.lock().expect("Failed to lock RUNTIME_MAP");
-
-    // 检查是否已存在相同配置的实例
-    if let Some((_, count, listen_addr)) = runtime_map.get_mut(&config_key) {
-        *count += 1;
-        return std::ffi::CString::new(listen_addr.clone()).unwrap().into_raw();
+    match start(remote, host, path, tls, insecure) {
+        Ok((_, listen_addr)) => CString::new(listen_addr).unwrap().into_raw(),
+        Err(e) => {
+            log::error!("Failed to start realm: {}", e);
+            set_last_error(e);
+            std::ptr::null()
+        }
     }
+}
 
-    // 创建网络配置
-    let net = create_net_conf();
-
-    // 绑定到本地随机端口
-    let listen_addr = bind_to_random_port();
-
-    // 创建端点配置
-    let endpoint = create_endpoint_conf(remote, listen_addr.clone(), net, path, tls, insecure);
+/// 返回当前线程最近一次失败的错误信息，用于start_realm返回NULL之后
+///
+/// 注意:
+/// - 没有错误时返回NULL
+/// - 返回的字符串需要调用realm_free_string释放
+#[no_mangle]
+pub extern "C" fn realm_last_error() -> *const c_char {
+    LAST_ERROR.with(|x| match &*x.borrow() {
+        Some(e) => e.clone().into_raw(),
+        None => std::ptr::null(),
+    })
+}
 
-    // 构建端点信息
-    let endpoints = build_endpoints(endpoint);
+/// 检查start_realm的参数，不启动实例。配置有效时返回NULL，否则返回JSON数组，
+/// 逐项指出出错的传输选项及原因:
+///
+///    [{"field":"remote_transport","option":"path","reason":"ws does not start with /"}]
+///
+/// 注意:
+/// - 传输选项之外的错误只有一项，其field和option为null
+/// - 返回的字符串需要调用realm_free_string释放
+#[no_mangle]
+pub extern "C" fn realm_validate_config(
+    remote: *const c_char,
+    host: *const c_char,
+    path: *const c_char,
+    tls: bool,
+    insecure: bool,
+) -> *const c_char {
+    let (remote, host, path) = convert_cstr_to_str(remote, host, path);
 
-    // 创建运行时并启动服务
-    let runtime = create_runtime();
-    runtime.spawn(run(endpoints));
+    match validate(remote, host, path, tls, insecure) {
+        Ok(()) => std::ptr::null(),
+        Err(diags) => CString::new(diags.to_string()).unwrap().into_raw(),
+    }
+}
 
-    // 将新的运行时实例添加到映射中
-    runtime_map.insert(config_key, (runtime, 1, listen_addr.clone()));
-    std::ffi::CString::new(listen_addr).unwrap().into_raw()
+/// 记录当前线程的错误信息
+fn set_last_error(e: String) {
+    // 错误信息中不应有NUL，有则截断
+    let e = match CString::new(e) {
+        Ok(x) => x,
+        Err(e) => {
+            let n = e.nul_position();
+            CString::new(&e.into_vec()[..n]).unwrap()
+        }
+    };
+    LAST_ERROR.with(|x| *x.borrow_mut() = Some(e));
 }
 
 #[no_mangle]
@@ -96,22 +366,1219 @@ pub extern "C" fn stop_realm(
     // 将C字符串转换为Rust字符串
     let (remote, host, path) = convert_cstr_to_str(remote, host, path);
 
+    // 创建唯一的配置键
+    let config_key = format!("{}-{}-{}-{}-{}", remote, host, path, tls, insecure);
+    stop(&config_key);
+}
+
+/// 设置就绪信号，所有正在启动的实例绑定完监听端口后，向fd写入一个字节，
+/// 可用于eventfd或pipe，配合systemd Type=notify式的进程管理
+///
+/// 注意:
+/// - 仅发送一次，再次设置会替换之前未发送的fd
+/// - 调用时没有正在启动的实例则立即发送，启动失败的实例不会阻塞信号
+/// - realm_start_batch的所有配置处理完后才发送
+/// - eventfd写入的是8字节计数1
+/// - fd由调用方负责关闭，发送前不应关闭
+/// - 仅支持Unix
+#[cfg(unix)]
+#[no_mangle]
+pub extern "C" fn realm_notify_ready_fd(fd: std::os::raw::c_int) {
+    let mut readiness = READINESS.lock().unwrap();
+    readiness.fd = Some(fd);
+    readiness.notify();
+}
+
+/// 按start_realm返回的监听地址关闭实例，与stop_realm相同，减少其引用计数
+///
+/// 注意:
+/// - 未找到监听该地址的实例时返回false
+#[no_mangle]
+pub extern "C" fn realm_stop_by_listen(listen_addr: *const c_char) -> bool {
+    let listen_addr = convert_key(listen_addr);
+    let config_key = LISTEN_INDEX.lock().unwrap().get(listen_addr).cloned();
+
+    match config_key {
+        Some(config_key) => stop(&config_key),
+        None => {
+            log::warn!("No Realm instance found listening on {}", listen_addr);
+            false
+        }
+    }
+}
+
+/// 关闭所有实例，不论引用计数，先停止接受新连接并等待已有连接结束，
+/// 超过deadline_ms毫秒仍未结束的连接被强制断开，返回强制断开的连接数
+///
+/// 注意:
+/// - 阻塞直到所有实例关闭，最长约deadline_ms毫秒
+/// - deadline_ms为0时立即强制断开
+/// - 强制断开时记录警告日志
+/// - 调用期间启动的实例不受影响
+#[no_mangle]
+pub extern "C" fn realm_stop_all(deadline_ms: u64) -> u64 {
+    stop_all_within(Duration::from_millis(deadline_ms)) as u64
+}
+
+/// 批量启动Realm实例，configs为JSON数组:
+///
+///    [{"remote":"example.com:443","host":"example.com","path":"/ws","tls":true,"insecure":false}]
+///
+/// 返回与configs一一对应的JSON数组，成功项包含监听地址，失败项包含错误信息:
+///
+///    [{"config_key":"example.com:443-example.com-/ws-true-false","listen_addr":"127.0.0.1:40000"},
+///     {"config_key":null,"error":"missing field `path`"}]
+///
+/// 注意:
+/// - tls和insecure可省略，默认为false
+/// - 某项失败不影响其他项，已启动的实例不会回滚
+/// - atomic为true时，任一项失败则回滚本次启动的所有实例，成功项的错误信息为"rolled back"
+/// - configs不是JSON数组时返回NULL
+/// - 返回的字符串需要调用realm_free_string释放
+#[no_mangle]
+pub extern "C" fn realm_start_batch(configs: *const c_char, atomic: bool) -> *const c_char {
+    #[derive(serde::Deserialize)]
+    struct StartConf {
+        remote: String,
+        host: String,
+        path: String,
+        #[serde(default)]
+        tls: bool,
+        #[serde(default)]
+        insecure: bool,
+    }
+
+    // 成功为(config_key, listen_addr)，失败为(config_key, error)
+    type StartResult = Result<(String, String), (Option<String>, String)>;
+
+    initialize_once();
+
+    // 所有配置处理完后才算就绪
+    #[cfg(unix)]
+    let _starting = Starting::enter();
+
+    let configs = convert_key(configs);
+    let configs: Vec<serde_json::Value> = match serde_json::from_str(configs) {
+        Ok(x) => x,
+        Err(e) => {
+            log::warn!("Invalid batch configs: {}", e);
+            return std::ptr::null();
+        }
+    };
+
+    let results: Vec<StartResult> = configs
+        .into_iter()
+        .map(|conf| {
+            let conf: StartConf = serde_json::from_value(conf).map_err(|e| (None, e.to_string()))?;
+            let StartConf {
+                remote,
+                host,
+                path,
+                tls,
+                insecure,
+            } = conf;
+            start(&remote, &host, &path, tls, insecure).map_err(|e| {
+                let config_key = format!("{}-{}-{}-{}-{}", remote, host, path, tls, insecure);
+                log::warn!("Failed to start realm with config {}: {}", config_key, e);
+                (Some(config_key), e)
+            })
+        })
+        .collect();
+
+    let rollback = atomic && results.iter().any(Result::is_err);
+    let results: Vec<serde_json::Value> = results
+        .into_iter()
+        .map(|res| match res {
+            Ok((config_key, _)) if rollback => {
+                stop(&config_key);
+                serde_json::json!({ "config_key": config_key, "error": "rolled back" })
+            }
+            Ok((config_key, listen_addr)) => {
+                serde_json::json!({ "config_key": config_key, "listen_addr": listen_addr })
+            }
+            Err((config_key, error)) => serde_json::json!({ "config_key": config_key, "error": error }),
+        })
+        .collect();
+
+    let results = serde_json::Value::Array(results).to_string();
+    CString::new(results).unwrap().into_raw()
+}
+
+/// 清除隧道远端域名的DNS缓存，之后的连接将重新解析
+///
+/// config_key由start_realm的参数组成: "remote-host-path-tls-insecure"，
+/// 例如 "example.com:443-host-/path-true-false"
+///
+/// 注意:
+/// - 解析器不支持按域名清除，这会清空整个DNS缓存
+/// - 未找到对应实例时返回false
+#[no_mangle]
+pub extern "C" fn realm_flush_remote_dns(config_key: *const c_char) -> bool {
+    let config_key = convert_key(config_key);
+    let runtime_map = RUNTIME_MAP.lock().expect("Failed to lock RUNTIME_MAP");
+
+    match runtime_map.get(config_key) {
+        Some(instance) => {
+            if let RemoteAddr::DomainName(..) = instance.remote {
+                core::dns::clear_cache();
+                log::info!("DNS cache of {} has been flushed", instance.remote);
+            }
+            true
+        }
+        None => {
+            log::warn!("No Realm instance found with config {}", config_key);
+            false
+        }
+    }
+}
+
+/// 禁用隧道，关闭其运行时和监听套接字，释放端口，但保留实例及其引用计数
+///
+/// 注意:
+/// - 与stop_realm不同，实例仍在列表中，可通过realm_enable重新启用
+/// - 活跃连接会被断开，扩缩容回调需重新设置
+/// - 已禁用时不做任何操作
+/// - 未找到对应实例时返回false
+#[no_mangle]
+pub extern "C" fn realm_disable(config_key: *const c_char) -> bool {
+    let config_key = convert_key(config_key);
+    let mut runtime_map = RUNTIME_MAP.lock().expect("Failed to lock RUNTIME_MAP");
+
+    let instance = match runtime_map.get_mut(config_key) {
+        Some(x) => x,
+        None => {
+            log::warn!("No Realm instance found with config {}", config_key);
+            return false;
+        }
+    };
+    if instance.disabled {
+        return true;
+    }
+
+    if let Some(runtime) = instance.runtime.take() {
+        runtime.shutdown_background();
+    }
+    instance.tasks.clear();
+    // 同shutdown
+    for task in instance.standby.drain(..) {
+        task.abort();
+    }
+    if let Some(task) = instance.scaling.take() {
+        task.abort();
+    }
+    for conn in instance.conns.list() {
+        instance.conns.kill(conn.id);
+    }
+    for bound in instance.endpoints.iter_mut() {
+        bound.tcp = None;
+        bound.udp = None;
+    }
+    instance.disabled = true;
+    log::info!("Realm instance with config {} has been disabled", config_key);
+    true
+}
+
+/// 重新启用realm_disable禁用的隧道，在原监听地址上重新绑定并启动
+///
+/// 注意:
+/// - 未禁用时不做任何操作
+/// - 监听网卡所有地址的端点按网卡当前的地址重新绑定
+/// - 沿用启动时的CPU亲和性
+/// - 未找到对应实例或原监听地址无法绑定时返回false
+#[no_mangle]
+pub extern "C" fn realm_enable(config_key: *const c_char) -> bool {
+    let config_key = convert_key(config_key);
+    let mut runtime_map = RUNTIME_MAP.lock().expect("Failed to lock RUNTIME_MAP");
+
+    let instance = match runtime_map.get_mut(config_key) {
+        Some(x) => x,
+        None => {
+            log::warn!("No Realm instance found with config {}", config_key);
+            return false;
+        }
+    };
+    if !instance.disabled {
+        return true;
+    }
+
+    let mut endpoints = Vec::with_capacity(instance.endpoints.len());
+    let mut interfaces = std::collections::HashSet::new();
+    for bound in instance.endpoints.iter() {
+        // 同一网卡的监听只绑定一次，按当前的地址
+        if let Some(iface) = &bound.listen_interface {
+            if !interfaces.insert((iface.clone(), bound.endpoint.laddr.port())) {
+                continue;
+            }
+        }
+        let info = EndpointInfo {
+            no_tcp: bound.no_tcp,
+            use_udp: bound.use_udp,
+            endpoint: bound.endpoint.clone(),
+            listen_interface: bound.listen_interface.clone(),
+            // 绑定时不使用，运行时按instance.cores创建
+            cpu_affinity: Vec::new(),
+        };
+        match Bound::bind(info) {
+            Ok(x) => endpoints.extend(x),
+            Err((laddr, e)) => {
+                log::warn!("Failed to enable {}, bind {}: {}", config_key, laddr, e);
+                return false;
+            }
+        }
+    }
+
+    let runtime = match create_runtime_on(&instance.cores) {
+        Ok(x) => x,
+        Err(e) => {
+            log::warn!("Failed to enable {}, build runtime: {}", config_key, e);
+            return false;
+        }
+    };
+    instance.tasks = match spawn_endpoints(runtime.handle(), &endpoints) {
+        Ok(x) => x,
+        Err(e) => {
+            log::warn!("Failed to enable {}, spawn endpoints: {}", config_key, e);
+            return false;
+        }
+    };
+    instance.heartbeat.store(now_millis(), Ordering::Relaxed);
+    runtime.spawn(beat(instance.heartbeat.clone()));
+    instance.runtime = Some(runtime);
+    instance.endpoints = endpoints;
+    instance.disabled = false;
+    log::info!("Realm instance with config {} has been enabled", config_key);
+    true
+}
+
+/// 设置扩缩容回调，活跃连接数达到high时以up=true调用，回落到low时以up=false调用
+///
+/// 注意:
+/// - 需满足low < high，水位需持续越过SCALING_DEBOUNCE毫秒才会触发，避免抖动
+/// - 回调在Realm的后台线程中执行，不应阻塞
+/// - 再次设置会替换之前的回调，callback为NULL时取消
+/// - 未找到对应实例或参数无效时返回false
+#[no_mangle]
+pub extern "C" fn realm_set_scaling_callback(
+    config_key: *const c_char,
+    high: u64,
+    low: u64,
+    callback: Option<ScalingCallback>,
+) -> bool {
+    let config_key = convert_key(config_key);
+    let mut runtime_map = RUNTIME_MAP.lock().expect("Failed to lock RUNTIME_MAP");
+
+    let instance = match runtime_map.get_mut(config_key) {
+        Some(x) => x,
+        None => {
+            log::warn!("No Realm instance found with config {}", config_key);
+            return false;
+        }
+    };
+
+    if let Some(task) = instance.scaling.take() {
+        task.abort();
+    }
+
+    let callback = match callback {
+        Some(x) => x,
+        None => return true,
+    };
+
+    if low >= high {
+        log::warn!("Invalid scaling watermarks: low={}, high={}", low, high);
+        return false;
+    }
+
+    let key = CString::new(config_key).unwrap();
+    let stat = instance.stat.clone();
+    let handle = match background() {
+        Ok(x) => x,
+        Err(e) => {
+            log::warn!("{}, failed to run the scaling callback of {}", e, config_key);
+            return false;
+        }
+    };
+    let task = handle.spawn(watch_scaling(key, stat, high, low, callback));
+    instance.scaling = Some(task);
+    true
+}
+
+/// 设置连接关闭前的回调，连接因超时、连接数限制等原因被主动关闭时，在客户端的连接关闭之前调用，
+/// 宿主程序可据此做出反应，例如预先建立替代的连接
+///
+/// 注意:
+/// - reason与日志中的reason相同，见readme的log一节
+/// - 回调在Realm的工作线程中执行，返回后才关闭连接，不应阻塞
+/// - 仅对之后接受的TCP连接生效，每个连接会多占用一个fd
+/// - 再次设置会替换之前的回调，callback为NULL时取消
+/// - 未找到对应实例时返回false
+#[no_mangle]
+pub extern "C" fn realm_set_close_callback(config_key: *const c_char, callback: Option<CloseCallback>) -> bool {
+    let config_key = convert_key(config_key);
+    let runtime_map = RUNTIME_MAP.lock().expect("Failed to lock RUNTIME_MAP");
+
+    let instance = match runtime_map.get(config_key) {
+        Some(x) => x,
+        None => {
+            log::warn!("No Realm instance found with config {}", config_key);
+            return false;
+        }
+    };
+
+    // 所有端点共享同一个回调
+    let pre_close = &instance.endpoints[0].endpoint.conn_opts.pre_close;
+    let callback = callback.map(|callback| {
+        let key = CString::new(config_key).unwrap();
+        Box::new(move |client: SocketAddr, reason: core::tcp::DropReason| {
+            let client = CString::new(client.to_string()).unwrap();
+            let reason = CString::new(reason.code()).unwrap();
+            callback(key.as_ptr(), client.as_ptr(), reason.as_ptr());
+        }) as Box<_>
+    });
+    pre_close.set(callback);
+    true
+}
+
+/// 启用备用运行时，实例的主运行时超过watchdog_timeout毫秒没有心跳时，
+/// 关闭主运行时，并在备用运行时上重新启动其端点
+///
+/// 注意:
+/// - watchdog_timeout为0时关闭
+/// - 端点复用原有的监听套接字，监听地址不变
+/// - 主运行时上的连接会被断开，扩缩容回调继续运行
+#[no_mangle]
+pub extern "C" fn realm_enable_standby(watchdog_timeout: u64) {
+    WATCHDOG_TIMEOUT.store(watchdog_timeout, Ordering::Relaxed);
+    if watchdog_timeout == 0 {
+        return;
+    }
+
+    // 预热
+    if let Err(e) = standby() {
+        log::error!("{}, standby is disabled", e);
+        WATCHDOG_TIMEOUT.store(0, Ordering::Relaxed);
+        return;
+    }
+    WATCHDOG_INIT.call_once(|| {
+        std::thread::Builder::new()
+            .name(String::from("realm-watchdog"))
+            .spawn(watchdog)
+            .expect("Failed to spawn watchdog");
+    });
+}
+
+/// 看门狗，检查各实例的心跳
+fn watchdog() {
+    loop {
+        let timeout = WATCHDOG_TIMEOUT.load(Ordering::Relaxed);
+        std::thread::sleep(Duration::from_millis((timeout / 4).clamp(10, 1000)));
+        if timeout == 0 {
+            continue;
+        }
+
+        // 启用时已预热，不应失败
+        let handle = match standby() {
+            Ok(x) => x,
+            Err(e) => {
+                log::error!("{}, standby is disabled", e);
+                WATCHDOG_TIMEOUT.store(0, Ordering::Relaxed);
+                continue;
+            }
+        };
+
+        let now = now_millis();
+        let mut runtime_map = RUNTIME_MAP.lock().expect("Failed to lock RUNTIME_MAP");
+        for (config_key, instance) in runtime_map.iter_mut() {
+            let last = instance.heartbeat.load(Ordering::Relaxed);
+            if instance.runtime.is_none() || now.saturating_sub(last) <= timeout {
+                continue;
+            }
+
+            log::warn!(
+                "Runtime of {} is not responding for {}ms, failover to standby",
+                config_key,
+                now - last
+            );
+            // 先在备用运行时上启动，失败时保留主运行时，再次超时后重试
+            instance.standby = match spawn_endpoints(handle, &instance.endpoints) {
+                Ok(x) => x,
+                Err(e) => {
+                    log::error!("Failed to failover {}: {}", config_key, e);
+                    instance.heartbeat.store(now, Ordering::Relaxed);
+                    continue;
+                }
+            };
+            if let Some(runtime) = instance.runtime.take() {
+                runtime.shutdown_background();
+            }
+            instance.tasks.clear();
+        }
+    }
+}
+
+/// 主运行时心跳
+async fn beat(heartbeat: Arc<AtomicU64>) {
+    let mut interval = tokio::time::interval(Duration::from_millis(100));
+    loop {
+        interval.tick().await;
+        heartbeat.store(now_millis(), Ordering::Relaxed);
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_millis() as u64)
+}
+
+/// 监控活跃连接数，越过水位时调用回调
+async fn watch_scaling(key: CString, stat: Arc<Stat>, high: u64, low: u64, callback: ScalingCallback) {
+    use crate::consts::SCALING_DEBOUNCE;
+    let debounce = Duration::from_millis(SCALING_DEBOUNCE as u64);
+    let mut interval = tokio::time::interval(Duration::from_millis(100));
+
+    let mut up = false;
+    let mut since: Option<Instant> = None;
+
+    loop {
+        interval.tick().await;
+        let active = stat.snapshot().active_conns;
+
+        let crossed = if up { active <= low } else { active >= high };
+        if !crossed {
+            since = None;
+            continue;
+        }
+
+        if since.get_or_insert_with(Instant::now).elapsed() < debounce {
+            continue;
+        }
+
+        up = !up;
+        since = None;
+        log::info!(
+            "Scaling callback of {}: up={}, active={}",
+            key.to_string_lossy(),
+            up,
+            active
+        );
+        callback(key.as_ptr(), up, active);
+    }
+}
+
+/// 列出隧道的活跃TCP连接，返回JSON字符串:
+///
+///    [{"id":1,"protocol":"tcp","src":"127.0.0.1:50000","dst":"127.0.0.1:40000"}]
+///
+/// 注意:
+/// - src为客户端地址，dst为本地监听地址
+/// - 未找到对应实例时返回NULL
+/// - 返回的字符串需要调用realm_free_string释放
+#[no_mangle]
+pub extern "C" fn realm_list_connections(config_key: *const c_char) -> *const c_char {
+    let config_key = convert_key(config_key);
+    let runtime_map = RUNTIME_MAP.lock().expect("Failed to lock RUNTIME_MAP");
+
+    let instance = match runtime_map.get(config_key) {
+        Some(x) => x,
+        None => {
+            log::warn!("No Realm instance found with config {}", config_key);
+            return std::ptr::null();
+        }
+    };
+
+    let conns: Vec<_> = instance
+        .conns
+        .list()
+        .into_iter()
+        .map(|x| {
+            serde_json::json!({
+                "id": x.id,
+                "protocol": "tcp",
+                "src": x.src.to_string(),
+                "dst": x.dst.to_string(),
+            })
+        })
+        .collect();
+    let json = serde_json::Value::Array(conns).to_string();
+    CString::new(json).unwrap().into_raw()
+}
+
+/// 断开隧道的指定连接，connection_id来自realm_list_connections
+///
+/// 注意:
+/// - 未找到对应实例或连接时返回false
+#[no_mangle]
+pub extern "C" fn realm_kill_connection(config_key: *const c_char, connection_id: u64) -> bool {
+    let config_key = convert_key(config_key);
+    let runtime_map = RUNTIME_MAP.lock().expect("Failed to lock RUNTIME_MAP");
+
+    match runtime_map.get(config_key) {
+        Some(instance) => {
+            let killed = instance.conns.kill(connection_id);
+            if killed {
+                log::info!("Connection {} of {} has been killed", connection_id, config_key);
+            }
+            killed
+        }
+        None => {
+            log::warn!("No Realm instance found with config {}", config_key);
+            false
+        }
+    }
+}
+
+/// 设置隧道的TCP限速，单位为字节/秒，上下行分别计算，0表示不限速
+///
+/// 注意:
+/// - 立即对活跃连接和之后的连接生效，无需重启实例
+/// - 隧道内所有TCP连接共享该限速，不限制UDP
+/// - 未找到对应实例时返回false
+#[no_mangle]
+pub extern "C" fn realm_set_rate_limit(config_key: *const c_char, bytes_per_sec: u64) -> bool {
+    let config_key = convert_key(config_key);
+    let runtime_map = RUNTIME_MAP.lock().expect("Failed to lock RUNTIME_MAP");
+
+    match runtime_map.get(config_key) {
+        Some(instance) => {
+            // 所有端点共享同一个限速
+            instance.endpoints[0].endpoint.conn_opts.rate_limit.set(bytes_per_sec);
+            log::info!("Rate limit of {} has been set to {}B/s", config_key, bytes_per_sec);
+            true
+        }
+        None => {
+            log::warn!("No Realm instance found with config {}", config_key);
+            false
+        }
+    }
+}
+
+/// 设置实例工作线程的CPU亲和性，之后以config_key启动的实例，其运行时每个核心一个工作线程，
+/// 并绑定到这些核心，用于NUMA或大小核系统
+///
+/// config_key由start_realm的参数组成: "remote-host-path-tls-insecure"
+///
+/// 注意:
+/// - 仅支持Linux，其他平台上启动该实例会失败
+/// - cores为NULL或len为0时清除设置
+/// - 已运行的实例不受影响，需停止后重新启动
+/// - 核心不在本进程可用的范围内时，启动失败
+/// - 故障转移后端点运行在备用运行时上，不再绑定
+#[no_mangle]
+pub extern "C" fn realm_set_cpu_affinity(config_key: *const c_char, cores: *const u32, len: usize) {
+    let config_key = convert_key(config_key);
+    let cores = convert_cores(cores, len);
+
+    let mut affinity = CPU_AFFINITY.lock().unwrap();
+    if cores.is_empty() {
+        affinity.remove(config_key);
+        log::info!("CPU affinity of {} has been cleared", config_key);
+    } else {
+        log::info!("CPU affinity of {} has been set to {:?}", config_key, cores);
+        affinity.insert(config_key.to_string(), cores);
+    }
+}
+
+/// 设置严格模式。参数相同的实例已存在时，start_realm默认共享该实例；
+/// 严格模式下，若按当前设置（如CPU亲和性、TCP keepalive）解析出的配置与该实例不同，
+/// 则返回NULL而不是共享
+///
+/// 注意:
+/// - 默认关闭，对之后的start_realm和realm_start_batch生效
+/// - 配置相同时仍共享实例
+#[no_mangle]
+pub extern "C" fn realm_set_strict_start(strict: bool) {
+    STRICT_START.store(strict, Ordering::Relaxed);
+}
+
+/// 设置生命周期回调，实例启动和关闭时依次以Starting、Ready、Stopping、Stopped调用
+///
+/// 注意:
+/// - 对所有实例生效，再次设置会替换之前的回调，callback为NULL时取消
+/// - 共享已有实例的start_realm，以及未关闭实例的stop_realm不产生事件
+/// - 回调在调用start_realm、stop_realm等函数的线程中执行，且持有内部锁，不应阻塞，也不应调用Realm的其他函数
+#[no_mangle]
+pub extern "C" fn realm_set_lifecycle_callback(callback: Option<LifecycleCallback>) {
+    *LIFECYCLE.lock().unwrap() = callback;
+}
+
+/// 设置TCP keepalive参数，单位为秒，仅对之后启动的实例生效
+///
+/// 注意:
+/// - time为0时关闭keepalive，interval为0时与time相同
+/// - OpenBSD不支持interval和retries，Windows不支持retries，此时使用系统默认值
+#[no_mangle]
+pub extern "C" fn realm_set_tcp_keepalive(time: u32, interval: u32, retries: u32) {
+    let kpa = (time as usize, interval as usize, retries as usize);
+    *TCP_KEEPALIVE.lock().unwrap() = Some(kpa);
+}
+
+/// 列出所有隧道及其统计，返回JSON字符串:
+///
+///    [{"config_key":"...","listen_addr":"127.0.0.1:40000","remote":"example.com:443","family":"v4","enabled":true,
+///      "active_connections":1,"total_connections":5,"bytes_up":1024,"bytes_down":4096}]
+///
+/// 注意:
+/// - family取自监听套接字: v4、v6，或同时接受v4与v6的dual
+/// - 返回的字符串需要调用realm_free_string释放
+#[no_mangle]
+pub extern "C" fn realm_list_endpoints() -> *const c_char {
+    let runtime_map = RUNTIME_MAP.lock().expect("Failed to lock RUNTIME_MAP");
+
+    let endpoints: Vec<_> = runtime_map
+        .iter()
+        .map(|(config_key, instance)| {
+            let stat = instance.stat.snapshot();
+            serde_json::json!({
+                "config_key": config_key,
+                "listen_addr": instance.listen_addr,
+                "remote": instance.remote.to_string(),
+                "family": family(&instance.endpoints[0]),
+                "enabled": !instance.disabled,
+                "active_connections": stat.active_conns,
+                "total_connections": stat.total_conns,
+                "bytes_up": stat.bytes_up,
+                "bytes_down": stat.bytes_down,
+            })
+        })
+        .collect();
+    let json = serde_json::Value::Array(endpoints).to_string();
+    CString::new(json).unwrap().into_raw()
+}
+
+/// 定期将各隧道的统计以UDP推送到StatsD，interval_ms为推送间隔，毫秒:
+///
+///    realm.<tunnel>.active_connections:1|g
+///    realm.<tunnel>.connections:5|c
+///    realm.<tunnel>.bytes_up:1024|c
+///    realm.<tunnel>.bytes_down:4096|c
+///
+/// 注意:
+/// - realm为prefix，为空时省略；<tunnel>为配置键，字母、数字、-和_以外的字符替换为_
+/// - 计数器为两次推送之间的增量，每个隧道一个UDP包
+/// - 再次调用时替换原有配置，interval_ms为0时停止推送
+/// - addr无法解析时返回false
+/// - 需要启用statsd特性
+#[cfg(feature = "statsd")]
+#[no_mangle]
+pub extern "C" fn realm_start_statsd(addr: *const c_char, prefix: *const c_char, interval_ms: u64) -> bool {
+    use std::net::{ToSocketAddrs, UdpSocket};
+
+    if interval_ms == 0 {
+        *STATSD.lock().unwrap() = None;
+        log::info!("StatsD exporter has been stopped");
+        return true;
+    }
+
+    let (addr, prefix) = convert_statsd(addr, prefix);
+    let socket = addr
+        .to_socket_addrs()
+        .and_then(|mut x| x.next().ok_or_else(|| std::io::ErrorKind::NotFound.into()))
+        .and_then(|x| {
+            let local: SocketAddr = match x {
+                SocketAddr::V4(_) => "0.0.0.0:0".parse().unwrap(),
+                SocketAddr::V6(_) => "[::]:0".parse().unwrap(),
+            };
+            let socket = UdpSocket::bind(local)?;
+            socket.connect(x)?;
+            Ok(socket)
+        });
+    let socket = match socket {
+        Ok(x) => x,
+        Err(e) => {
+            log::warn!("Failed to start StatsD exporter to {}: {}", addr, e);
+            return false;
+        }
+    };
+
+    *STATSD.lock().unwrap() = Some(Statsd {
+        socket,
+        prefix: String::from(prefix),
+        interval: Duration::from_millis(interval_ms),
+    });
+    STATSD_INIT.call_once(|| {
+        std::thread::Builder::new()
+            .name(String::from("realm-statsd"))
+            .spawn(statsd)
+            .expect("Failed to spawn StatsD exporter");
+    });
+    log::info!("StatsD exporter to {} every {}ms", addr, interval_ms);
+    true
+}
+
+/// StatsD导出线程
+#[cfg(feature = "statsd")]
+fn statsd() {
+    // 上次推送的统计，用于计算增量
+    let mut last: HashMap<String, StatSnapshot> = HashMap::new();
+    let mut pushed = Instant::now();
+
+    loop {
+        let interval = STATSD.lock().unwrap().as_ref().map(|x| x.interval);
+        let tick = interval.map_or(Duration::from_millis(100), |x| x / 4);
+        std::thread::sleep(tick.clamp(Duration::from_millis(10), Duration::from_millis(1000)));
+        match interval {
+            Some(x) if pushed.elapsed() >= x => pushed = Instant::now(),
+            _ => continue,
+        }
+
+        let stats: Vec<_> = {
+            let runtime_map = RUNTIME_MAP.lock().expect("Failed to lock RUNTIME_MAP");
+            runtime_map
+                .iter()
+                .map(|(config_key, instance)| (config_key.clone(), instance.stat.snapshot()))
+                .collect()
+        };
+        last.retain(|k, _| stats.iter().any(|(x, _)| x == k));
+
+        let statsd = STATSD.lock().unwrap();
+        let statsd = match statsd.as_ref() {
+            Some(x) => x,
+            None => continue,
+        };
+        for (config_key, stat) in stats {
+            let prev = last.insert(config_key.clone(), stat).unwrap_or_default();
+            let packet = statsd_packet(&statsd.prefix, &config_key, &stat, &prev);
+            if let Err(e) = statsd.socket.send(packet.as_bytes()) {
+                log::debug!("Failed to push {} to StatsD: {}", config_key, e);
+            }
+        }
+    }
+}
+
+/// 一个隧道的StatsD指标，计数器为相对prev的增量
+#[cfg(feature = "statsd")]
+fn statsd_packet(prefix: &str, config_key: &str, stat: &StatSnapshot, prev: &StatSnapshot) -> String {
+    let tunnel: String = config_key
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect();
+    let name = match prefix {
+        "" => tunnel,
+        _ => format!("{}.{}", prefix, tunnel),
+    };
+
+    [
+        format!("{}.active_connections:{}|g", name, stat.active_conns),
+        format!(
+            "{}.connections:{}|c",
+            name,
+            stat.total_conns.saturating_sub(prev.total_conns)
+        ),
+        format!("{}.bytes_up:{}|c", name, stat.bytes_up.saturating_sub(prev.bytes_up)),
+        format!(
+            "{}.bytes_down:{}|c",
+            name,
+            stat.bytes_down.saturating_sub(prev.bytes_down)
+        ),
+    ]
+    .join("\n")
+}
+
+/// 在addr上启动HTTP健康检查:
+///
+///    GET /healthz  进程存活，总是返回200
+///    GET /readyz   所有实例已就绪时返回200，否则返回503
+///
+/// 注意:
+/// - 就绪指至少有一个实例，没有正在启动或已禁用的实例，且每个实例至少有一个健康的远端
+/// - 运行在后台运行时上，再次调用时替换原有的监听，addr为NULL时停止
+/// - addr无法绑定时返回false
+/// - 需要启用admin特性
+#[cfg(feature = "admin")]
+#[no_mangle]
+pub extern "C" fn realm_start_health(addr: *const c_char) -> bool {
+    let mut health = HEALTH.lock().unwrap();
+    if let Some(task) = health.take() {
+        task.abort();
+        // 等待旧的监听关闭，以便复用地址
+        let _ = futures::executor::block_on(task);
+    }
+    if addr.is_null() {
+        log::info!("Health endpoint has been stopped");
+        return true;
+    }
+
+    let addr = convert_health(addr);
+    let lis = match TcpListener::bind(addr).and_then(|x| x.set_nonblocking(true).map(|_| x)) {
+        Ok(x) => x,
+        Err(e) => {
+            log::warn!("Failed to start health endpoint on {}: {}", addr, e);
+            return false;
+        }
+    };
+
+    let handle = match background() {
+        Ok(x) => x,
+        Err(e) => {
+            log::warn!("Failed to start health endpoint on {}: {}", addr, e);
+            return false;
+        }
+    };
+    *health = Some(handle.spawn(serve_health(lis)));
+    log::info!("Health endpoint on {}", addr);
+    true
+}
+
+/// 健康检查的HTTP服务
+#[cfg(feature = "admin")]
+async fn serve_health(lis: TcpListener) {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let lis = tokio::net::TcpListener::from_std(lis).expect("Failed to register health listener");
+    loop {
+        let mut stream = match lis.accept().await {
+            Ok((x, _)) => x,
+            Err(e) => {
+                log::debug!("Failed to accept health check: {}", e);
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            // 读完请求头，只使用请求行
+            let mut buf = vec![0; 1024];
+            let mut n = 0;
+            while !buf[..n].windows(4).any(|x| x == b"\r\n\r\n") && n < buf.len() {
+                match tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf[n..])).await {
+                    Ok(Ok(x @ 1..)) => n += x,
+                    _ => return,
+                }
+            }
+
+            let line = String::from_utf8_lossy(&buf[..n]);
+            let (status, body) = match line.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+                ["GET", "/healthz"] => ("200 OK", "ok"),
+                ["GET", "/readyz"] if ready() => ("200 OK", "ready"),
+                ["GET", "/readyz"] => ("503 Service Unavailable", "not ready"),
+                _ => ("404 Not Found", "not found"),
+            };
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+                status,
+                body.len() + 1,
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+            let _ = stream.shutdown().await;
+        });
+    }
+}
+
+/// 所有实例是否已就绪
+#[cfg(feature = "admin")]
+fn ready() -> bool {
+    #[cfg(unix)]
+    if READINESS.lock().unwrap().pending != 0 {
+        return false;
+    }
+
+    let runtime_map = RUNTIME_MAP.lock().expect("Failed to lock RUNTIME_MAP");
+    !runtime_map.is_empty()
+        && runtime_map.values().all(|instance| {
+            #[cfg(feature = "balance")]
+            {
+                let endpoint = &instance.endpoints[0].endpoint;
+                let total = 1 + endpoint.extra_raddrs.len();
+                !instance.disabled && endpoint.conn_opts.health.pick(0, total).is_some()
+            }
+            #[cfg(not(feature = "balance"))]
+            {
+                !instance.disabled
+            }
+        })
+}
+
+/// 获取所有Realm实例的汇总统计，返回JSON字符串:
+///
+///    {"tunnels":2,"active_connections":3,"total_connections":10,"bytes_up":1024,"bytes_down":4096}
+///
+/// 注意:
+/// - UDP关联也计为连接
+/// - 返回的字符串需要调用realm_free_string释放
+#[no_mangle]
+pub extern "C" fn realm_global_stats() -> *const c_char {
+    let json = global_stats().to_string();
+    CString::new(json).unwrap().into_raw()
+}
+
+/// 释放由本库返回的字符串
+///
+/// # Safety
+///
+/// s必须是本库返回的字符串，且只能释放一次
+#[no_mangle]
+pub unsafe extern "C" fn realm_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// 汇总各实例的统计
+fn global_stats() -> serde_json::Value {
+    let runtime_map = RUNTIME_MAP.lock().expect("Failed to lock RUNTIME_MAP");
+    let sum = runtime_map
+        .values()
+        .map(|x| x.stat.snapshot())
+        .fold(StatSnapshot::default(), |a, b| a + b);
+
+    serde_json::json!({
+        "tunnels": runtime_map.len(),
+        "active_connections": sum.active_conns,
+        "total_connections": sum.total_conns,
+        "bytes_up": sum.bytes_up,
+        "bytes_down": sum.bytes_down,
+    })
+}
+
+/// 监听套接字的地址族
+fn family(bound: &Bound) -> &'static str {
+    let laddr = match (&bound.tcp, &bound.udp) {
+        (Some(tcp), _) => tcp.local_addr(),
+        (_, Some(udp)) => udp.local_addr(),
+        _ => Ok(bound.endpoint.laddr),
+    };
+
+    match laddr.unwrap_or(bound.endpoint.laddr) {
+        SocketAddr::V4(_) => "v4",
+        // 未设置ipv6_only的通配地址同时接受v4
+        SocketAddr::V6(x) if x.ip().is_unspecified() && !bound.endpoint.bind_opts.ipv6_only => "dual",
+        SocketAddr::V6(_) => "v6",
+    }
+}
+
+/// 启动实例，已存在相同配置的实例时增加其引用计数，返回配置键和监听地址
+fn start(remote: &str, host: &str, path: &str, tls: bool, insecure: bool) -> Result<(String, String), String> {
+    // 在RUNTIME_MAP解锁后离开
+    #[cfg(unix)]
+    let _starting = Starting::enter();
+
     // 创建唯一的配置键
     let config_key = format!("{}-{}-{}-{}-{}", remote, host, path, tls, insecure);
     let mut runtime_map = RUNTIME_MAP.lock().expect("Failed to lock RUNTIME_MAP");
 
+    // 检查是否已存在相同配置的实例
+    if let Some(instance) = runtime_map.get_mut(&config_key) {
+        if STRICT_START.load(Ordering::Relaxed) {
+            let mut endpoint = create_endpoint_conf(remote, String::new(), create_net_conf(), path, tls, insecure);
+            endpoint.cpu_affinity = cpu_affinity(&config_key);
+            if resolve(&endpoint) != instance.resolved {
+                return Err(format!(
+                    "Config {} is already started with a different config",
+                    config_key
+                ));
+            }
+        }
+        instance.count += 1;
+        return Ok((config_key, instance.listen_addr.clone()));
+    }
+
+    // 配置无效或绑定失败时panic，在此捕获，避免毒化RUNTIME_MAP
+    lifecycle(&config_key, LifecycleEvent::Starting);
+    let instance = std::panic::catch_unwind(|| {
+        // 绑定到本地随机端口
+        create_instance(
+            remote,
+            bind_to_random_port(),
+            path,
+            tls,
+            insecure,
+            cpu_affinity(&config_key),
+        )
+    })
+    .map_err(panic_message)
+    .and_then(|x| x)
+    .inspect_err(|_| lifecycle(&config_key, LifecycleEvent::Stopped))?;
+
+    // 将新的运行时实例添加到映射中
+    let listen_addr = instance.listen_addr.clone();
+    runtime_map.insert(config_key.clone(), instance);
+    LISTEN_INDEX
+        .lock()
+        .unwrap()
+        .insert(listen_addr.clone(), config_key.clone());
+    lifecycle(&config_key, LifecycleEvent::Ready);
+    Ok((config_key, listen_addr))
+}
+
+/// 调用生命周期回调
+fn lifecycle(config_key: &str, event: LifecycleEvent) {
+    if let Some(callback) = *LIFECYCLE.lock().unwrap() {
+        let key = CString::new(config_key).unwrap_or_default();
+        callback(key.as_ptr(), event, now_millis());
+    }
+}
+
+/// 端点配置除监听地址外的部分，用于比较
+fn resolve(endpoint: &EndpointConf) -> String {
+    let mut value = serde_json::to_value(endpoint).unwrap();
+    value.as_object_mut().unwrap().remove("listen");
+    value.to_string()
+}
+
+/// 构建start_realm的配置而不绑定，返回传输选项的诊断或构建时的错误
+fn validate(remote: &str, host: &str, path: &str, tls: bool, insecure: bool) -> Result<(), serde_json::Value> {
+    // 与create_instance相同，监听地址不会被绑定
+    let config_key = format!("{}-{}-{}-{}-{}", remote, host, path, tls, insecure);
+    let mut endpoint = create_endpoint_conf(
+        remote,
+        String::from("127.0.0.1:0"),
+        create_net_conf(),
+        path,
+        tls,
+        insecure,
+    );
+    endpoint.cpu_affinity = cpu_affinity(&config_key);
+
+    let diags = endpoint.diagnose_transport();
+    if !diags.is_empty() {
+        return Err(serde_json::json!(diags));
+    }
+
+    // 其余的错误在构建时panic
+    std::panic::catch_unwind(|| endpoint.build())
+        .map(drop)
+        .map_err(|e| serde_json::json!([{ "field": null, "option": null, "reason": panic_message(e) }]))
+}
+
+/// 取出panic的信息
+fn panic_message(e: Box<dyn std::any::Any + Send>) -> String {
+    match (e.downcast_ref::<String>(), e.downcast_ref::<&str>()) {
+        (Some(x), _) => x.clone(),
+        (_, Some(x)) => x.to_string(),
+        _ => String::from("unknown error"),
+    }
+}
+
+/// 创建实例并在新的运行时上启动，运行时创建失败时返回错误
+fn create_instance(
+    remote: &str,
+    listen_addr: String,
+    path: &str,
+    tls: bool,
+    insecure: bool,
+    cpu_affinity: Vec<usize>,
+) -> Result<Instance, String> {
+    // 创建网络配置
+    let net = create_net_conf();
+
+    // 创建端点配置
+    let mut endpoint = create_endpoint_conf(remote, listen_addr, net, path, tls, insecure);
+    endpoint.cpu_affinity = cpu_affinity;
+    create_instance_with(endpoint)
+}
+
+/// 按端点配置创建实例并在新的运行时上启动
+fn create_instance_with(endpoint: EndpointConf) -> Result<Instance, String> {
+    let listen_addr = endpoint.listen.clone();
+    let resolved = resolve(&endpoint);
+
+    // 构建端点信息
+    let endpoints = build_endpoints(endpoint);
+    let remote = endpoints[0].endpoint.raddr.clone();
+    let stat = endpoints[0].endpoint.conn_opts.stat.clone();
+    let conns = endpoints[0].endpoint.conn_opts.conns.clone();
+    let cores = endpoints[0].cpu_affinity.clone();
+    let endpoints = bind_endpoints(endpoints);
+
+    // 创建运行时并启动服务，失败时监听套接字随endpoints关闭
+    let runtime = create_runtime_on(&cores).map_err(|e| format!("Failed to build runtime: {}", e))?;
+    let tasks =
+        spawn_endpoints(runtime.handle(), &endpoints).map_err(|e| format!("Failed to spawn endpoints: {}", e))?;
+    let heartbeat = Arc::new(AtomicU64::new(now_millis()));
+    runtime.spawn(beat(heartbeat.clone()));
+
+    Ok(Instance {
+        runtime: Some(runtime),
+        count: 1,
+        listen_addr,
+        remote,
+        stat,
+        conns,
+        scaling: None,
+        endpoints,
+        heartbeat,
+        tasks,
+        standby: Vec::new(),
+        disabled: false,
+        resolved,
+        cores,
+    })
+}
+
+/// 减少实例的引用计数，计数为0时关闭实例，未找到实例时返回false
+fn stop(config_key: &str) -> bool {
+    let mut runtime_map = RUNTIME_MAP.lock().expect("Failed to lock RUNTIME_MAP");
+
     // 检查并更新实例计数
-    if let Some((_, count, _)) = runtime_map.get_mut(&config_key) {
-        *count -= 1;
-        if *count == 0 {
+    if let Some(instance) = runtime_map.get_mut(config_key) {
+        instance.count -= 1;
+        if instance.count == 0 {
             // 如果计数为0，移除并关闭运行时
-            if let Some((runtime, _, _)) = runtime_map.remove(&config_key) {
-                runtime.shutdown_background();
+            if let Some(instance) = runtime_map.remove(config_key) {
+                LISTEN_INDEX.lock().unwrap().remove(&instance.listen_addr);
+                lifecycle(config_key, LifecycleEvent::Stopping);
+                instance.shutdown();
+                lifecycle(config_key, LifecycleEvent::Stopped);
                 log::info!("Realm instance with config {} has been stopped", config_key);
             }
         }
+        true
     } else {
         log::warn!("No Realm instance found with config {}", config_key);
+        false
+    }
+}
+
+/// 关闭所有实例，等待已有连接结束至多deadline，返回强制断开的连接数
+fn stop_all_within(deadline: Duration) -> usize {
+    let mut instances: Vec<(String, Instance)> = {
+        let mut runtime_map = RUNTIME_MAP.lock().expect("Failed to lock RUNTIME_MAP");
+        LISTEN_INDEX.lock().unwrap().clear();
+        runtime_map.drain().collect()
+    };
+    for (config_key, instance) in instances.iter_mut() {
+        lifecycle(config_key, LifecycleEvent::Stopping);
+        instance.stop_accept();
+    }
+
+    // 不持有RUNTIME_MAP，以免阻塞其他调用
+    let start = Instant::now();
+    while start.elapsed() < deadline && instances.iter().any(|(_, x)| !x.conns.list().is_empty()) {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    let mut forced = 0;
+    for (config_key, instance) in instances {
+        forced += instance.conns.list().len();
+        instance.shutdown();
+        lifecycle(&config_key, LifecycleEvent::Stopped);
+        log::info!("Realm instance with config {} has been stopped", config_key);
+    }
+    if forced != 0 {
+        log::warn!(
+            "{} connections are not finished in {}ms, forcibly closed",
+            forced,
+            deadline.as_millis()
+        );
+    }
+    forced
+}
+
+/// 向fd写入就绪信号
+#[cfg(unix)]
+fn write_ready(fd: std::os::raw::c_int) -> std::io::Result<()> {
+    let write = |buf: &[u8]| match unsafe { libc::write(fd, buf.as_ptr() as *const libc::c_void, buf.len()) } {
+        -1 => Err(std::io::Error::last_os_error()),
+        _ => Ok(()),
+    };
+
+    // eventfd只接受8字节的计数
+    match write(&[1]) {
+        Err(e) if e.raw_os_error() == Some(libc::EINVAL) => write(&1u64.to_ne_bytes()),
+        res => res,
     }
 }
 
@@ -136,11 +1603,58 @@ fn convert_cstr_to_str(
     }
 }
 
+/// 将C字符串转换为配置键
+fn convert_key(key: *const c_char) -> &'static str {
+    unsafe { CStr::from_ptr(key).to_str().expect("Invalid config key string") }
+}
+
+/// 将C字符串转换为StatsD地址和前缀
+#[cfg(feature = "statsd")]
+fn convert_statsd(addr: *const c_char, prefix: *const c_char) -> (&'static str, &'static str) {
+    unsafe {
+        (
+            CStr::from_ptr(addr).to_str().expect("Invalid addr string"),
+            CStr::from_ptr(prefix).to_str().expect("Invalid prefix string"),
+        )
+    }
+}
+
+/// 将C字符串转换为健康检查的监听地址
+#[cfg(feature = "admin")]
+fn convert_health(addr: *const c_char) -> &'static str {
+    unsafe { CStr::from_ptr(addr).to_str().expect("Invalid addr string") }
+}
+
+fn convert_cores(cores: *const u32, len: usize) -> Vec<usize> {
+    if cores.is_null() || len == 0 {
+        return Vec::new();
+    }
+    unsafe { std::slice::from_raw_parts(cores, len) }
+        .iter()
+        .map(|x| *x as usize)
+        .collect()
+}
+
+/// 配置键对应的CPU核心，未设置时为空
+fn cpu_affinity(config_key: &str) -> Vec<usize> {
+    CPU_AFFINITY
+        .lock()
+        .unwrap()
+        .get(config_key)
+        .cloned()
+        .unwrap_or_default()
+}
+
 /// 创建网络配置
 fn create_net_conf() -> NetConf {
     let mut net = NetConf::default();
     net.use_udp = Some(true);
     net.no_tcp = Some(false);
+    if let Some((time, interval, retries)) = *TCP_KEEPALIVE.lock().unwrap() {
+        net.tcp_keepalive = Some(time);
+        net.tcp_keepalive_interval = Some(interval);
+        net.tcp_keepalive_probe = Some(retries);
+    }
     net
 }
 
@@ -174,13 +1688,39 @@ fn create_endpoint_conf(
 
     EndpointConf {
         listen: listen_addr,
+        listen_interface: None,
+        cpu_affinity: Vec::new(),
         remote: remote.to_string(),
         extra_remotes: vec![],
         balance: None,
+        unhealthy_policy: None,
+        affinity_timeout: None,
+        speaks_first: None,
+        deadlock_nudge: None,
+        hash_key: None,
+        maintenance_response: None,
         through: None,
         interface: None,
         listen_transport: None,
         remote_transport: Some(remote_transport),
+        remote_transport_fallback: Vec::new(),
+        alpn_routes: Default::default(),
+        sni_allowlist: Vec::new(),
+        allow_missing_sni: None,
+        port_routes: Default::default(),
+        geo_db: None,
+        geo_routes: Default::default(),
+        remote_options: Default::default(),
+        ws_max_header_size: None,
+        ws_max_frame_size: None,
+        ws_close_code: None,
+        correlation_header: None,
+        trace: None,
+        trace_max_size: None,
+        trace_payload: None,
+        compress: None,
+        compress_level: None,
+        dns: None,
         network: net,
     }
 }
@@ -198,9 +1738,13 @@ fn build_endpoints(endpoint: EndpointConf) -> Vec<EndpointInfo> {
 fn setup_log(log: LogConf) {
     log::info!("Setting up log: {}", &log);
 
-    let (level, output) = log.build();
-    fern::Dispatch::new()
+    let (level, output, rate_limit) = log.build();
+    let (level, logger) = fern::Dispatch::new()
         .format(|out, message, record| {
+            // 访问日志按原样输出，便于现有工具解析
+            if record.target() == core::access::TARGET {
+                return out.finish(*message);
+            }
             out.finish(format_args!(
                 "{}[{}][{}]{}",
                 chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
@@ -211,8 +1755,15 @@ fn setup_log(log: LogConf) {
         })
         .level(level)
         .chain(output)
-        .apply()
-        .expect("Failed to setup logger")
+        .into_log();
+
+    // 限制同一处日志的输出频率
+    let logger = match rate_limit {
+        0 => logger,
+        rate => Box::new(crate::logger::RateLimit::new(logger, rate, Duration::from_secs(1))),
+    };
+    log::set_boxed_logger(logger).expect("Failed to setup logger");
+    log::set_max_level(level);
 }
 
 /// 设置DNS
@@ -223,50 +1774,1162 @@ fn setup_dns(dns: DnsConf) {
     core::dns::build_lazy(conf, opts);
 }
 
-/// 创建Tokio运行时
-fn create_runtime() -> tokio::runtime::Runtime {
+/// 创建Tokio运行时，fd耗尽等情况下会失败
+fn create_runtime() -> std::io::Result<tokio::runtime::Runtime> {
+    create_runtime_on(&[])
+}
+
+/// 创建Tokio运行时，cores非空时每个核心一个工作线程，并绑定到这些核心
+fn create_runtime_on(cores: &[usize]) -> std::io::Result<tokio::runtime::Runtime> {
     #[cfg(feature = "multi-thread")]
-    {
-        tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()
-            .expect("Failed to build multi-thread runtime")
-    }
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
 
     #[cfg(not(feature = "multi-thread"))]
+    let mut builder = tokio::runtime::Builder::new_current_thread();
+
+    builder.enable_all();
+    if !cores.is_empty() {
+        #[cfg(feature = "multi-thread")]
+        builder.worker_threads(cores.len());
+
+        let cores = cores.to_vec();
+        builder.on_thread_start(move || {
+            if let Err(e) = affinity::pin(&cores) {
+                log::warn!("Failed to pin thread to cores {:?}: {}", cores, e);
+            }
+        });
+    }
+    builder.build()
+}
+
+/// 备用运行时，首次使用时创建
+fn standby() -> Result<&'static tokio::runtime::Handle, String> {
+    match &*STANDBY {
+        Ok(x) => Ok(x.handle()),
+        Err(e) => Err(format!("Failed to build standby runtime: {}", e)),
+    }
+}
+
+/// 后台运行时，首次使用时创建
+fn background() -> Result<&'static tokio::runtime::Handle, String> {
+    match &*BACKGROUND {
+        Ok(x) => Ok(x),
+        Err(e) => Err(format!("Failed to build background runtime: {}", e)),
+    }
+}
+
+/// 创建后台运行时，在单独的低优先级线程上运行，不占用转发的工作线程
+fn create_background() -> std::io::Result<tokio::runtime::Handle> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let handle = runtime.handle().clone();
+    std::thread::Builder::new()
+        .name(String::from("realm-background"))
+        .spawn(move || {
+            lower_priority();
+            runtime.block_on(std::future::pending::<()>());
+        })?;
+    Ok(handle)
+}
+
+/// 降低当前线程的调度优先级，仅Linux上nice值按线程生效
+fn lower_priority() {
+    #[cfg(target_os = "linux")]
     {
-        tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .expect("Failed to build current-thread runtime")
+        use crate::consts::BACKGROUND_NICE;
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, BACKGROUND_NICE) } != 0 {
+            log::debug!(
+                "Failed to lower background priority: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
+
+/// 绑定端点的监听套接字
+fn bind_endpoints(endpoints: Vec<EndpointInfo>) -> Vec<Bound> {
+    endpoints
+        .into_iter()
+        .flat_map(|info| Bound::bind(info).unwrap_or_else(|(laddr, e)| panic!("failed to bind {}: {}", laddr, e)))
+        .collect()
+}
+
+/// 在运行时上启动端点，监听套接字复制失败时返回错误，不启动任何端点
+fn spawn_endpoints(
+    handle: &tokio::runtime::Handle,
+    endpoints: &[Bound],
+) -> std::io::Result<Vec<tokio::task::JoinHandle<()>>> {
+    use crate::core::tcp::run_tcp_with;
+    use crate::core::udp::run_udp_with;
+
+    let mut listeners = Vec::with_capacity(endpoints.len());
+    for Bound { endpoint, tcp, udp, .. } in endpoints {
+        let tcp = tcp.as_ref().map(|x| x.try_clone()).transpose()?;
+        let udp = udp.as_ref().map(|x| x.try_clone()).transpose()?;
+        listeners.push((endpoint, tcp, udp));
+    }
+
+    let mut tasks = Vec::with_capacity(endpoints.len() * 2);
+    for (endpoint, tcp, udp) in listeners {
+        if let Some(lis) = udp {
+            let endpoint = endpoint.clone();
+            tasks.push(handle.spawn(async move {
+                let _ = run_udp_with(lis, endpoint).await;
+            }));
+        }
+        if let Some(lis) = tcp {
+            let endpoint = endpoint.clone();
+            tasks.push(handle.spawn(async move {
+                let _ = run_tcp_with(lis, endpoint).await;
+            }));
+        }
     }
+    Ok(tasks)
 }
 
-/// 运行Realm服务
-async fn run(endpoints: Vec<EndpointInfo>) {
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
     use crate::core::tcp::run_tcp;
-    use crate::core::udp::run_udp;
-    use futures::future::join_all;
+    use crate::core::endpoint::{Endpoint, ConnectOpts};
+    use crate::core::kaminari::ws::WsConf;
+    use crate::core::kaminari::mix::{MixAccept, MixConnect, MixServerConf, MixClientConf};
 
-    let workers = endpoints
-        .into_iter()
-        .flat_map(
-            |EndpointInfo {
-                 endpoint,
-                 no_tcp,
-                 use_udp,
-             }| {
-                let mut tasks = Vec::with_capacity(2);
-                if use_udp {
-                    tasks.push(tokio::spawn(run_udp(endpoint.clone())));
-                }
-                if !no_tcp {
-                    tasks.push(tokio::spawn(run_tcp(endpoint)));
-                }
-                tasks
+    fn ws_server(laddr: &str, raddr: &str, path: &str) -> Endpoint {
+        let ac = MixAccept::new_shared(MixServerConf {
+            ws: Some(WsConf {
+                host: laddr.to_string(),
+                path: path.to_string(),
+            }),
+            tls: None,
+        });
+        let cc = MixConnect::new_shared(MixClientConf { ws: None, tls: None });
+
+        Endpoint {
+            laddr: laddr.parse().unwrap(),
+            raddr: RemoteAddr::SocketAddr(raddr.parse().unwrap()),
+            conn_opts: ConnectOpts {
+                transport: Some((ac, cc)),
+                ..Default::default()
             },
+            bind_opts: Default::default(),
+            extra_raddrs: Vec::new(),
+        }
+    }
+
+    async fn echo(addr: &str) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let lis = tokio::net::TcpListener::bind(addr).await.unwrap();
+        loop {
+            let (mut stream, _) = lis.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = vec![0; 64];
+                while let Ok(n @ 1..) = stream.read(&mut buf).await {
+                    stream.write_all(&buf[..n]).await.unwrap();
+                }
+            });
+        }
+    }
+
+    fn start(remote: &str) -> String {
+        let remote = CString::new(remote).unwrap();
+        let path = CString::new("/stats").unwrap();
+        let listen = start_realm(remote.as_ptr(), remote.as_ptr(), path.as_ptr(), false, false);
+        let listen_addr = unsafe { CStr::from_ptr(listen) }.to_str().unwrap().to_string();
+        unsafe { realm_free_string(listen as *mut c_char) };
+        listen_addr
+    }
+
+    // instances are shared by all tests
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    fn key(remote: &str) -> CString {
+        CString::new(format!("{0}-{0}-/stats-false-false", remote)).unwrap()
+    }
+
+    fn connect_echo(laddr: &str) -> std::net::TcpStream {
+        let mut stream = std::net::TcpStream::connect(laddr).unwrap();
+        let mut buf = [0u8; 5];
+        stream.write_all(b"hello").unwrap();
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+        stream
+    }
+
+    fn stop_all() {
+        // runtimes may not be dropped in place
+        for (_, instance) in RUNTIME_MAP.lock().unwrap().drain() {
+            instance.shutdown();
+        }
+        LISTEN_INDEX.lock().unwrap().clear();
+    }
+
+    fn ffi_stats() -> serde_json::Value {
+        let s = realm_global_stats();
+        let json = serde_json::from_str(unsafe { CStr::from_ptr(s) }.to_str().unwrap()).unwrap();
+        unsafe { realm_free_string(s as *mut c_char) };
+        json
+    }
+
+    fn per_tunnel_sum() -> StatSnapshot {
+        let runtime_map = RUNTIME_MAP.lock().unwrap();
+        runtime_map
+            .values()
+            .map(|x| x.stat.snapshot())
+            .fold(StatSnapshot::default(), |a, b| a + b)
+    }
+
+    #[test]
+    fn global_stats() {
+        let _serial = SERIAL.lock().unwrap();
+        let rt = create_runtime().unwrap();
+        rt.spawn(echo("127.0.0.1:20300"));
+        rt.spawn(run_tcp(ws_server("127.0.0.1:10300", "127.0.0.1:20300", "/stats")));
+        rt.spawn(run_tcp(ws_server("127.0.0.1:10301", "127.0.0.1:20300", "/stats")));
+        std::thread::sleep(Duration::from_millis(500));
+
+        let tunnels = [start("127.0.0.1:10300"), start("127.0.0.1:10301")];
+        std::thread::sleep(Duration::from_millis(500));
+
+        // one live connection per tunnel
+        let mut conns: Vec<_> = tunnels.iter().map(|laddr| connect_echo(laddr)).collect();
+
+        let stats = ffi_stats();
+        assert_eq!(stats["tunnels"], 2);
+        assert_eq!(stats["active_connections"], 2);
+        assert_eq!(stats["total_connections"], 2);
+
+        conns.clear();
+        std::thread::sleep(Duration::from_millis(500));
+
+        let sum = per_tunnel_sum();
+        let stats = ffi_stats();
+        assert_eq!(sum.active_conns, 0);
+        assert_eq!(sum.bytes_up, 10);
+        assert_eq!(sum.bytes_down, 10);
+        assert_eq!(stats["active_connections"], sum.active_conns);
+        assert_eq!(stats["total_connections"], sum.total_conns);
+        assert_eq!(stats["bytes_up"], sum.bytes_up);
+        assert_eq!(stats["bytes_down"], sum.bytes_down);
+
+        stop_all();
+        rt.shutdown_background();
+    }
+
+    #[test]
+    fn list_endpoints() {
+        let _serial = SERIAL.lock().unwrap();
+        let rt = create_runtime().unwrap();
+        rt.spawn(echo("127.0.0.1:20380"));
+        rt.spawn(run_tcp(ws_server("127.0.0.1:10380", "127.0.0.1:20380", "/stats")));
+        std::thread::sleep(Duration::from_millis(500));
+
+        let v4 = start("127.0.0.1:10380");
+        {
+            let mut runtime_map = RUNTIME_MAP.lock().unwrap();
+            for (key, listen) in [("v6", "[::1]:10381"), ("dual", "[::]:10382")] {
+                let instance = create_instance(
+                    "127.0.0.1:10380",
+                    listen.to_string(),
+                    "/stats",
+                    false,
+                    false,
+                    Vec::new(),
+                )
+                .unwrap();
+                runtime_map.insert(key.to_string(), instance);
+            }
+        }
+        std::thread::sleep(Duration::from_millis(500));
+        drop(connect_echo(&v4));
+        drop(connect_echo("[::1]:10381"));
+        drop(connect_echo("127.0.0.1:10382"));
+
+        let s = realm_list_endpoints();
+        let json: serde_json::Value = serde_json::from_str(unsafe { CStr::from_ptr(s) }.to_str().unwrap()).unwrap();
+        unsafe { realm_free_string(s as *mut c_char) };
+
+        let find = |key: &str| {
+            json.as_array()
+                .unwrap()
+                .iter()
+                .find(|x| x["config_key"] == key)
+                .unwrap()
+                .clone()
+        };
+        let key = key("127.0.0.1:10380");
+        let v4_endpoint = find(key.to_str().unwrap());
+        assert_eq!(v4_endpoint["listen_addr"], v4);
+        assert_eq!(v4_endpoint["remote"], "127.0.0.1:10380");
+        assert_eq!(v4_endpoint["family"], "v4");
+        assert_eq!(v4_endpoint["total_connections"], 1);
+        assert_eq!(find("v6")["family"], "v6");
+        assert_eq!(find("dual")["family"], "dual");
+        assert_eq!(json.as_array().unwrap().len(), 3);
+
+        stop_all();
+        rt.shutdown_background();
+    }
+
+    fn ffi_connections(key: &CStr) -> serde_json::Value {
+        let s = realm_list_connections(key.as_ptr());
+        let json = serde_json::from_str(unsafe { CStr::from_ptr(s) }.to_str().unwrap()).unwrap();
+        unsafe { realm_free_string(s as *mut c_char) };
+        json
+    }
+
+    #[test]
+    fn kill_connection() {
+        let _serial = SERIAL.lock().unwrap();
+        let rt = create_runtime().unwrap();
+        rt.spawn(echo("127.0.0.1:20320"));
+        rt.spawn(run_tcp(ws_server("127.0.0.1:10320", "127.0.0.1:20320", "/stats")));
+        std::thread::sleep(Duration::from_millis(500));
+
+        let laddr = start("127.0.0.1:10320");
+        std::thread::sleep(Duration::from_millis(500));
+        let key = key("127.0.0.1:10320");
+
+        let mut stream = connect_echo(&laddr);
+        let conns = ffi_connections(&key);
+        assert_eq!(conns.as_array().unwrap().len(), 1);
+        assert_eq!(conns[0]["protocol"], "tcp");
+        assert_eq!(conns[0]["src"], stream.local_addr().unwrap().to_string());
+        assert_eq!(conns[0]["dst"], laddr);
+
+        let id = conns[0]["id"].as_u64().unwrap();
+        assert!(realm_kill_connection(key.as_ptr(), id));
+        assert!(!realm_kill_connection(key.as_ptr(), id));
+
+        // closed by the relay
+        let mut buf = [0u8; 8];
+        stream.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        match stream.read(&mut buf) {
+            Ok(n) => assert_eq!(n, 0),
+            Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::ConnectionReset),
+        }
+        assert_eq!(ffi_connections(&key), serde_json::json!([]));
+
+        stop_all();
+        rt.shutdown_background();
+    }
+
+    #[test]
+    fn stop_by_listen() {
+        let _serial = SERIAL.lock().unwrap();
+        let rt = create_runtime().unwrap();
+        rt.spawn(echo("127.0.0.1:20360"));
+        rt.spawn(run_tcp(ws_server("127.0.0.1:10360", "127.0.0.1:20360", "/stats")));
+        std::thread::sleep(Duration::from_millis(500));
+
+        // started twice, stopped when the count drops to 0
+        let laddr = start("127.0.0.1:10360");
+        assert_eq!(start("127.0.0.1:10360"), laddr);
+        std::thread::sleep(Duration::from_millis(500));
+        let key = key("127.0.0.1:10360");
+        let listen = CString::new(laddr.as_str()).unwrap();
+
+        assert!(realm_stop_by_listen(listen.as_ptr()));
+        assert!(RUNTIME_MAP.lock().unwrap().contains_key(key.to_str().unwrap()));
+        drop(connect_echo(&laddr));
+
+        assert!(realm_stop_by_listen(listen.as_ptr()));
+        assert!(!RUNTIME_MAP.lock().unwrap().contains_key(key.to_str().unwrap()));
+        assert!(!realm_stop_by_listen(listen.as_ptr()));
+
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(std::net::TcpStream::connect(&laddr).is_err());
+
+        stop_all();
+        rt.shutdown_background();
+    }
+
+    #[test]
+    fn disable_enable() {
+        let _serial = SERIAL.lock().unwrap();
+        let rt = create_runtime().unwrap();
+        rt.spawn(echo("127.0.0.1:20420"));
+        rt.spawn(run_tcp(ws_server("127.0.0.1:10420", "127.0.0.1:20420", "/stats")));
+        std::thread::sleep(Duration::from_millis(500));
+
+        let laddr = start("127.0.0.1:10420");
+        std::thread::sleep(Duration::from_millis(500));
+        let key = key("127.0.0.1:10420");
+        let conn = connect_echo(&laddr);
+        let listed = || {
+            let s = realm_list_endpoints();
+            let json: serde_json::Value = serde_json::from_str(unsafe { CStr::from_ptr(s) }.to_str().unwrap()).unwrap();
+            unsafe { realm_free_string(s as *mut c_char) };
+            json.as_array()
+                .unwrap()
+                .iter()
+                .find(|x| x["config_key"] == key.to_str().unwrap())
+                .map(|x| x["enabled"].as_bool().unwrap())
+        };
+
+        // the port is freed, the entry is kept
+        assert!(realm_disable(key.as_ptr()));
+        assert!(realm_disable(key.as_ptr()));
+        assert_eq!(listed(), Some(false));
+        assert_eq!(LISTEN_INDEX.lock().unwrap()[&laddr], key.to_str().unwrap());
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(std::net::TcpStream::connect(&laddr).is_err());
+        let mut conn = conn;
+        assert_eq!(conn.read(&mut [0u8; 5]).unwrap_or(0), 0);
+
+        assert!(realm_enable(key.as_ptr()));
+        assert!(realm_enable(key.as_ptr()));
+        assert_eq!(listed(), Some(true));
+        std::thread::sleep(Duration::from_millis(200));
+        drop(connect_echo(&laddr));
+
+        let missing = CString::new("missing").unwrap();
+        assert!(!realm_disable(missing.as_ptr()));
+        assert!(!realm_enable(missing.as_ptr()));
+
+        stop_all();
+        rt.shutdown_background();
+    }
+
+    #[cfg(unix)]
+    fn readable(fd: std::os::raw::c_int) -> bool {
+        let mut pfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut pfd, 1, 100) == 1 }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn notify_ready_fd() {
+        let _serial = SERIAL.lock().unwrap();
+        let rt = create_runtime().unwrap();
+        rt.spawn(echo("127.0.0.1:20370"));
+        rt.spawn(run_tcp(ws_server("127.0.0.1:10370", "127.0.0.1:20370", "/stats")));
+        std::thread::sleep(Duration::from_millis(500));
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let [rd, wr] = fds;
+
+        // another start is still pending
+        let starting = Starting::enter();
+        realm_notify_ready_fd(wr);
+        let laddr = start("127.0.0.1:10370");
+        assert!(!readable(rd));
+
+        drop(starting);
+        assert!(readable(rd));
+        let mut buf = [0u8; 8];
+        assert_eq!(unsafe { libc::read(rd, buf.as_mut_ptr() as *mut libc::c_void, 8) }, 1);
+        drop(connect_echo(&laddr));
+
+        // sent only once
+        start("127.0.0.1:10370");
+        assert!(!readable(rd));
+
+        // nothing pending, sent at once
+        #[cfg(target_os = "linux")]
+        {
+            let efd = unsafe { libc::eventfd(0, 0) };
+            realm_notify_ready_fd(efd);
+            assert!(readable(efd));
+            assert_eq!(unsafe { libc::read(efd, buf.as_mut_ptr() as *mut libc::c_void, 8) }, 8);
+            assert_eq!(u64::from_ne_bytes(buf), 1);
+            unsafe { libc::close(efd) };
+        }
+
+        unsafe {
+            libc::close(rd);
+            libc::close(wr);
+        }
+        stop_all();
+        rt.shutdown_background();
+    }
+
+    #[test]
+    fn set_rate_limit() {
+        let _serial = SERIAL.lock().unwrap();
+        let rt = create_runtime().unwrap();
+        rt.spawn(echo("127.0.0.1:20350"));
+        rt.spawn(run_tcp(ws_server("127.0.0.1:10350", "127.0.0.1:20350", "/stats")));
+        std::thread::sleep(Duration::from_millis(500));
+
+        start("127.0.0.1:10350");
+        std::thread::sleep(Duration::from_millis(500));
+        let key = key("127.0.0.1:10350");
+
+        let rate = || {
+            let runtime_map = RUNTIME_MAP.lock().unwrap();
+            let instance = runtime_map.get(key.to_str().unwrap()).unwrap();
+            instance.endpoints[0].endpoint.conn_opts.rate_limit.rate()
+        };
+        assert_eq!(rate(), 0);
+        assert!(realm_set_rate_limit(key.as_ptr(), 1024));
+        assert_eq!(rate(), 1024);
+        assert!(realm_set_rate_limit(key.as_ptr(), 0));
+        assert_eq!(rate(), 0);
+
+        let missing = CString::new("missing").unwrap();
+        assert!(!realm_set_rate_limit(missing.as_ptr(), 1024));
+
+        stop_all();
+        rt.shutdown_background();
+    }
+
+    #[test]
+    fn standby_failover() {
+        let _serial = SERIAL.lock().unwrap();
+        let rt = create_runtime().unwrap();
+        rt.spawn(echo("127.0.0.1:20330"));
+        rt.spawn(run_tcp(ws_server("127.0.0.1:10330", "127.0.0.1:20330", "/stats")));
+        std::thread::sleep(Duration::from_millis(500));
+
+        realm_enable_standby(300);
+        let laddr = start("127.0.0.1:10330");
+        std::thread::sleep(Duration::from_millis(500));
+        drop(connect_echo(&laddr));
+
+        // saturate all workers of the primary runtime
+        let key = key("127.0.0.1:10330");
+        let key = key.to_str().unwrap();
+        {
+            let runtime_map = RUNTIME_MAP.lock().unwrap();
+            let runtime = runtime_map[key].runtime.as_ref().unwrap();
+            let workers = std::thread::available_parallelism().map_or(1, |x| x.get());
+            for _ in 0..workers * 2 {
+                runtime.spawn(async { std::thread::sleep(Duration::from_secs(3)) });
+            }
+        }
+        std::thread::sleep(Duration::from_millis(1000));
+
+        // served by the standby on the same address
+        assert!(RUNTIME_MAP.lock().unwrap()[key].runtime.is_none());
+        drop(connect_echo(&laddr));
+
+        realm_enable_standby(0);
+        stop_all();
+        rt.shutdown_background();
+    }
+
+    fn ffi_start_batch(configs: serde_json::Value, atomic: bool) -> serde_json::Value {
+        let configs = CString::new(configs.to_string()).unwrap();
+        let s = realm_start_batch(configs.as_ptr(), atomic);
+        let json = serde_json::from_str(unsafe { CStr::from_ptr(s) }.to_str().unwrap()).unwrap();
+        unsafe { realm_free_string(s as *mut c_char) };
+        json
+    }
+
+    #[test]
+    fn start_batch() {
+        let _serial = SERIAL.lock().unwrap();
+        let conf = |remote: &str| serde_json::json!({ "remote": remote, "host": remote, "path": "/stats" });
+        let key = |remote: &str| key(remote).into_string().unwrap();
+
+        let res = ffi_start_batch(
+            serde_json::json!([conf("127.0.0.1:10340"), conf("invalid"), { "remote": "127.0.0.1:10341" }, conf("127.0.0.1:10342")]),
+            false,
+        );
+        let res = res.as_array().unwrap();
+        assert_eq!(res.len(), 4);
+
+        // valid ones are started
+        for (i, remote) in [(0, "127.0.0.1:10340"), (3, "127.0.0.1:10342")] {
+            assert_eq!(res[i]["config_key"], key(remote));
+            let laddr = res[i]["listen_addr"].as_str().unwrap();
+            assert_eq!(RUNTIME_MAP.lock().unwrap()[&key(remote)].listen_addr, laddr);
+            std::net::TcpStream::connect(laddr).unwrap();
+        }
+
+        // invalid ones report errors
+        assert_eq!(res[1]["config_key"], key("invalid"));
+        assert!(res[1]["error"].is_string());
+        assert_eq!(res[2]["config_key"], serde_json::Value::Null);
+        assert!(res[2]["error"].as_str().unwrap().contains("host"));
+        assert_eq!(RUNTIME_MAP.lock().unwrap().len(), 2);
+
+        // atomic, started ones are rolled back
+        let res = ffi_start_batch(
+            serde_json::json!([conf("127.0.0.1:10340"), conf("127.0.0.1:10343"), conf("invalid")]),
+            true,
+        );
+        assert_eq!(res[0]["error"], "rolled back");
+        assert_eq!(res[1]["error"], "rolled back");
+        assert!(res[2]["error"].is_string());
+        {
+            let runtime_map = RUNTIME_MAP.lock().unwrap();
+            assert_eq!(runtime_map.len(), 2);
+            // an existing instance keeps running
+            assert_eq!(runtime_map[&key("127.0.0.1:10340")].count, 1);
+        }
+
+        let configs = CString::new("{}").unwrap();
+        assert!(realm_start_batch(configs.as_ptr(), false).is_null());
+
+        stop_all();
+    }
+
+    static SCALING: Mutex<Vec<(bool, u64)>> = Mutex::new(Vec::new());
+
+    extern "C" fn on_scaling(config_key: *const c_char, up: bool, active: u64) {
+        let config_key = unsafe { CStr::from_ptr(config_key) };
+        assert_eq!(
+            config_key.to_str().unwrap(),
+            "127.0.0.1:10310-127.0.0.1:10310-/stats-false-false"
+        );
+        SCALING.lock().unwrap().push((up, active));
+    }
+
+    #[test]
+    fn scaling_callback() {
+        let _serial = SERIAL.lock().unwrap();
+        let rt = create_runtime().unwrap();
+        rt.spawn(echo("127.0.0.1:20310"));
+        rt.spawn(run_tcp(ws_server("127.0.0.1:10310", "127.0.0.1:20310", "/stats")));
+        std::thread::sleep(Duration::from_millis(500));
+
+        let laddr = start("127.0.0.1:10310");
+        std::thread::sleep(Duration::from_millis(500));
+        let key = key("127.0.0.1:10310");
+        assert!(!realm_set_scaling_callback(key.as_ptr(), 1, 2, Some(on_scaling)));
+        assert!(realm_set_scaling_callback(key.as_ptr(), 2, 0, Some(on_scaling)));
+
+        // a short burst is ignored
+        let conns: Vec<_> = (0..2).map(|_| connect_echo(&laddr)).collect();
+        drop(conns);
+        std::thread::sleep(Duration::from_millis(1000));
+        assert!(SCALING.lock().unwrap().is_empty());
+
+        let mut conns: Vec<_> = (0..3).map(|_| connect_echo(&laddr)).collect();
+        std::thread::sleep(Duration::from_millis(1000));
+        assert_eq!(*SCALING.lock().unwrap(), [(true, 3)]);
+
+        conns.clear();
+        std::thread::sleep(Duration::from_millis(1000));
+        assert_eq!(*SCALING.lock().unwrap(), [(true, 3), (false, 0)]);
+
+        stop_all();
+        rt.shutdown_background();
+    }
+
+    static LIFECYCLE_EVENTS: Mutex<Vec<(LifecycleEvent, u64)>> = Mutex::new(Vec::new());
+
+    extern "C" fn on_lifecycle(config_key: *const c_char, event: LifecycleEvent, timestamp: u64) {
+        let config_key = unsafe { CStr::from_ptr(config_key) };
+        if config_key.to_str().unwrap() == "127.0.0.1:10480-127.0.0.1:10480-/stats-false-false" {
+            LIFECYCLE_EVENTS.lock().unwrap().push((event, timestamp));
+        }
+    }
+
+    #[test]
+    fn lifecycle_events() {
+        use LifecycleEvent::*;
+        let _serial = SERIAL.lock().unwrap();
+        realm_set_lifecycle_callback(Some(on_lifecycle));
+        let key = key("127.0.0.1:10480");
+
+        // the shared start and the first stop are silent
+        let begin = now_millis();
+        start("127.0.0.1:10480");
+        start("127.0.0.1:10480");
+        assert!(stop(key.to_str().unwrap()));
+        assert!(stop(key.to_str().unwrap()));
+        realm_set_lifecycle_callback(None);
+
+        let events = LIFECYCLE_EVENTS.lock().unwrap();
+        let kinds: Vec<_> = events.iter().map(|x| x.0).collect();
+        assert_eq!(kinds, [Starting, Ready, Stopping, Stopped]);
+        assert!(events[0].1 >= begin);
+        assert!(events.windows(2).all(|x| x[0].1 <= x[1].1));
+    }
+
+    #[cfg(feature = "admin")]
+    fn http_status(addr: &str, path: &str) -> u16 {
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, addr);
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response.split_whitespace().nth(1).unwrap().parse().unwrap()
+    }
+
+    #[cfg(feature = "admin")]
+    #[test]
+    fn health_endpoint() {
+        let _serial = SERIAL.lock().unwrap();
+        let rt = create_runtime().unwrap();
+        rt.spawn(echo("127.0.0.1:20430"));
+        rt.spawn(run_tcp(ws_server("127.0.0.1:10430", "127.0.0.1:20430", "/stats")));
+        std::thread::sleep(Duration::from_millis(500));
+
+        let addr = CString::new("127.0.0.1:10431").unwrap();
+        assert!(realm_start_health(addr.as_ptr()));
+        let invalid = CString::new("invalid").unwrap();
+        assert!(!realm_start_health(invalid.as_ptr()));
+        assert!(realm_start_health(addr.as_ptr()));
+        std::thread::sleep(Duration::from_millis(200));
+        let health = "127.0.0.1:10431";
+
+        // nothing to serve yet
+        assert_eq!(http_status(health, "/healthz"), 200);
+        assert_eq!(http_status(health, "/readyz"), 503);
+        assert_eq!(http_status(health, "/"), 404);
+
+        start("127.0.0.1:10430");
+        assert_eq!(http_status(health, "/readyz"), 200);
+
+        // not ready while another instance is starting
+        #[cfg(unix)]
+        {
+            let starting = Starting::enter();
+            assert_eq!(http_status(health, "/readyz"), 503);
+            drop(starting);
+            assert_eq!(http_status(health, "/readyz"), 200);
+        }
+
+        // nor with an unbound one
+        let key = key("127.0.0.1:10430");
+        assert!(realm_disable(key.as_ptr()));
+        assert_eq!(http_status(health, "/readyz"), 503);
+        assert!(realm_enable(key.as_ptr()));
+        assert_eq!(http_status(health, "/readyz"), 200);
+
+        assert!(realm_start_health(std::ptr::null()));
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(std::net::TcpStream::connect(health).is_err());
+
+        stop_all();
+        rt.shutdown_background();
+    }
+
+    #[cfg(feature = "admin")]
+    #[test]
+    fn background_runtime() {
+        let _serial = SERIAL.lock().unwrap();
+        let rt = create_runtime().unwrap();
+        rt.spawn(echo("127.0.0.1:20490"));
+        rt.spawn(run_tcp(ws_server("127.0.0.1:10490", "127.0.0.1:20490", "/stats")));
+        std::thread::sleep(Duration::from_millis(500));
+
+        let laddr = start("127.0.0.1:10490");
+        let addr = CString::new("127.0.0.1:10491").unwrap();
+        assert!(realm_start_health(addr.as_ptr()));
+        std::thread::sleep(Duration::from_millis(200));
+
+        // a thread of its own, with a lower priority
+        #[cfg(target_os = "linux")]
+        {
+            use crate::consts::BACKGROUND_NICE;
+            let nice: Vec<i32> = std::fs::read_dir("/proc/self/task")
+                .unwrap()
+                .filter_map(|x| {
+                    let path = x.ok()?.path();
+                    let comm = std::fs::read_to_string(path.join("comm")).ok()?;
+                    let stat = std::fs::read_to_string(path.join("stat")).ok()?;
+                    // the fields after the name, nice is the 19th one
+                    let fields: Vec<_> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+                    (comm.trim() == "realm-backgroun").then(|| fields[16].parse().unwrap())
+                })
+                .collect();
+            assert_eq!(nice, [BACKGROUND_NICE]);
+        }
+
+        // every worker of the relay runtime is kept busy
+        let mut stream = connect_echo(&laddr);
+        let busy = Arc::new(AtomicBool::new(true));
+        {
+            let runtime_map = RUNTIME_MAP.lock().unwrap();
+            let key = key("127.0.0.1:10490");
+            let instance = runtime_map.get(key.to_str().unwrap()).unwrap();
+            let runtime = instance.runtime.as_ref().unwrap();
+            let workers = std::thread::available_parallelism().map_or(1, |x| x.get());
+            for _ in 0..workers {
+                let busy = busy.clone();
+                runtime.spawn(async move {
+                    while busy.load(Ordering::Relaxed) {
+                        std::hint::spin_loop();
+                    }
+                });
+            }
+        }
+        std::thread::sleep(Duration::from_millis(100));
+
+        // the relay stalls, health checks are still answered
+        let mut buf = [0u8; 5];
+        stream.write_all(b"hello").unwrap();
+        stream.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        assert!(stream.read_exact(&mut buf).is_err());
+        let mut slowest = Duration::ZERO;
+        for _ in 0..10 {
+            let begin = Instant::now();
+            assert_eq!(http_status("127.0.0.1:10491", "/healthz"), 200);
+            slowest = slowest.max(begin.elapsed());
+        }
+        busy.store(false, Ordering::Relaxed);
+        assert!(slowest < Duration::from_millis(200), "{:?}", slowest);
+
+        // and it relays again once idle
+        connect_echo(&laddr);
+
+        assert!(realm_start_health(std::ptr::null()));
+        stop_all();
+        rt.shutdown_background();
+    }
+
+    #[cfg(feature = "statsd")]
+    #[test]
+    fn statsd_exporter() {
+        let _serial = SERIAL.lock().unwrap();
+        let rt = create_runtime().unwrap();
+        rt.spawn(echo("127.0.0.1:20390"));
+        rt.spawn(run_tcp(ws_server("127.0.0.1:10390", "127.0.0.1:20390", "/stats")));
+        std::thread::sleep(Duration::from_millis(500));
+
+        let statsd = std::net::UdpSocket::bind("127.0.0.1:10391").unwrap();
+        statsd.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        let recv = || {
+            let mut buf = vec![0; 1500];
+            let n = statsd.recv(&mut buf).unwrap();
+            (Instant::now(), String::from_utf8(buf[..n].to_vec()).unwrap())
+        };
+
+        let laddr = start("127.0.0.1:10390");
+        std::thread::sleep(Duration::from_millis(500));
+        let conn = connect_echo(&laddr);
+
+        let addr = CString::new("127.0.0.1:10391").unwrap();
+        let prefix = CString::new("realm").unwrap();
+        assert!(realm_start_statsd(addr.as_ptr(), prefix.as_ptr(), 200));
+
+        let name = "realm.127_0_0_1_10390-127_0_0_1_10390-_stats-false-false";
+        let (t1, packet) = recv();
+        assert_eq!(
+            packet.lines().collect::<Vec<_>>(),
+            [
+                format!("{}.active_connections:1|g", name),
+                format!("{}.connections:1|c", name),
+                format!("{}.bytes_up:5|c", name),
+                format!("{}.bytes_down:5|c", name),
+            ]
+        );
+
+        // counters are deltas since the last push
+        drop(conn);
+        let (t2, packet) = recv();
+        let (t3, _) = recv();
+        assert!(packet.contains(".connections:0|c"), "{}", packet);
+        assert!(packet.contains(".bytes_up:0|c"), "{}", packet);
+        for x in [t2 - t1, t3 - t2] {
+            assert!(
+                x >= Duration::from_millis(150) && x < Duration::from_millis(400),
+                "{:?}",
+                x
+            );
+        }
+
+        // stopped
+        assert!(realm_start_statsd(addr.as_ptr(), prefix.as_ptr(), 0));
+        std::thread::sleep(Duration::from_millis(300));
+        statsd.set_nonblocking(true).unwrap();
+        while statsd.recv(&mut [0u8; 1500]).is_ok() {}
+        std::thread::sleep(Duration::from_millis(500));
+        assert!(statsd.recv(&mut [0u8; 1500]).is_err());
+
+        let addr = CString::new("invalid").unwrap();
+        assert!(!realm_start_statsd(addr.as_ptr(), prefix.as_ptr(), 200));
+
+        stop_all();
+        rt.shutdown_background();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runtime_build_failure() {
+        // the limit is per process, lowered in a child of its own,
+        // which runs this test again
+        const CHILD: &str = "REALM_TEST_NOFILE_CHILD";
+        if std::env::var_os(CHILD).is_none() {
+            let output = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "tests::runtime_build_failure", "--nocapture"])
+                .env(CHILD, "1")
+                .output()
+                .unwrap();
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert!(output.status.success(), "{}", stdout);
+            assert!(stdout.contains("runtime not built"), "{}", stdout);
+            return;
+        }
+
+        // the next fd would be beyond the limit
+        let next = unsafe { libc::dup(2) };
+        assert!(next >= 0);
+        unsafe { libc::close(next) };
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        assert_eq!(unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) }, 0);
+        let lowered = libc::rlimit {
+            rlim_cur: next as libc::rlim_t,
+            ..limit
+        };
+        assert_eq!(unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &lowered) }, 0);
+        assert!(create_runtime().is_err());
+        println!("runtime not built");
+    }
+
+    #[test]
+    fn start_failure() {
+        let _serial = SERIAL.lock().unwrap();
+        assert!(realm_last_error().is_null());
+
+        // null and the last error instead of aborting
+        let remote = CString::new("invalid").unwrap();
+        let path = CString::new("/stats").unwrap();
+        let listen = start_realm(remote.as_ptr(), remote.as_ptr(), path.as_ptr(), false, false);
+        assert!(listen.is_null());
+        let e = realm_last_error();
+        assert!(!e.is_null());
+        assert!(!unsafe { CStr::from_ptr(e) }.to_str().unwrap().is_empty());
+        unsafe { realm_free_string(e as *mut c_char) };
+        assert!(!RUNTIME_MAP
+            .lock()
+            .unwrap()
+            .contains_key(&key("invalid").into_string().unwrap()));
+    }
+
+    #[test]
+    fn validate_config() {
+        let validate = |remote: &str, path: &str, tls: bool| {
+            let remote = CString::new(remote).unwrap();
+            let path = CString::new(path).unwrap();
+            let s = realm_validate_config(remote.as_ptr(), remote.as_ptr(), path.as_ptr(), tls, false);
+            if s.is_null() {
+                return None;
+            }
+            let diags: serde_json::Value =
+                serde_json::from_str(unsafe { CStr::from_ptr(s) }.to_str().unwrap()).unwrap();
+            unsafe { realm_free_string(s as *mut c_char) };
+            Some(diags)
+        };
+        let first = |diags: Option<serde_json::Value>| {
+            let diag = diags.unwrap()[0].clone();
+            (diag["option"].clone(), diag["reason"].as_str().unwrap().to_string())
+        };
+
+        assert_eq!(validate("127.0.0.1:443", "/ws", false), None);
+
+        // each pinpoints the option
+        let (option, reason) = first(validate("example.com:443", "ws", false));
+        assert_eq!(option, "path");
+        assert_eq!(reason, "ws does not start with /");
+
+        let (option, reason) = first(validate("example.com:443", "", false));
+        assert_eq!(option, "path");
+        assert_eq!(reason, "requires a value");
+
+        let (option, reason) = first(validate("example.com:443;mux_con=8", "/ws", false));
+        assert_eq!(option, "mux_con");
+        assert_eq!(reason, "is unknown");
+
+        let (option, reason) = first(validate("example.com/x:443", "/ws", false));
+        assert_eq!(option, "host");
+        assert_eq!(reason, "example.com/x:443 is not a host name");
+
+        let (option, reason) = first(validate("example.com:443", "/ws", true));
+        assert_eq!(option, "sni");
+        assert_eq!(reason, "example.com:443 is not a dns name or an ip address");
+
+        let (option, reason) = first(validate("example.com:443", "/ws;path=/x", false));
+        assert_eq!(option, "path");
+        assert_eq!(reason, "is set more than once");
+
+        // not a transport option
+        let (option, reason) = first(validate("invalid", "/ws", false));
+        assert!(option.is_null());
+        assert!(!reason.is_empty());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn cpu_affinity() {
+        let _serial = SERIAL.lock().unwrap();
+        let rt = create_runtime().unwrap();
+        rt.spawn(echo("127.0.0.1:20450"));
+        rt.spawn(run_tcp(ws_server("127.0.0.1:10450", "127.0.0.1:20450", "/stats")));
+        std::thread::sleep(Duration::from_millis(500));
+
+        // pinned to the last core only
+        let core = *affinity::available().unwrap().last().unwrap();
+        let config_key = key("127.0.0.1:10450");
+        realm_set_cpu_affinity(config_key.as_ptr(), &(core as u32), 1);
+        let listen = start("127.0.0.1:10450");
+        drop(connect_echo(&listen));
+
+        let pinned = || {
+            let handle = RUNTIME_MAP.lock().unwrap()[config_key.to_str().unwrap()]
+                .runtime
+                .as_ref()
+                .unwrap()
+                .handle()
+                .clone();
+            rt.block_on(handle.spawn(async { affinity::available().unwrap() }))
+                .unwrap()
+        };
+        assert_eq!(pinned(), [core]);
+
+        // kept once enabled again
+        assert!(realm_disable(config_key.as_ptr()));
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(realm_enable(config_key.as_ptr()));
+        drop(connect_echo(&listen));
+        assert_eq!(pinned(), [core]);
+        assert!(stop(config_key.to_str().unwrap()));
+
+        // not started with a core out of range
+        realm_set_cpu_affinity(config_key.as_ptr(), &(libc::CPU_SETSIZE as u32), 1);
+        let remote = CString::new("127.0.0.1:10450").unwrap();
+        let path = CString::new("/stats").unwrap();
+        let diags = realm_validate_config(remote.as_ptr(), remote.as_ptr(), path.as_ptr(), false, false);
+        let s = unsafe { CStr::from_ptr(diags) }.to_str().unwrap().to_string();
+        unsafe { realm_free_string(diags as *mut c_char) };
+        let diags: serde_json::Value = serde_json::from_str(&s).unwrap();
+        assert_eq!(
+            diags[0]["reason"],
+            format!("cpu_affinity: core {} is not available", libc::CPU_SETSIZE)
+        );
+        realm_set_cpu_affinity(config_key.as_ptr(), std::ptr::null(), 0);
+    }
+
+    #[test]
+    fn strict_start() {
+        let _serial = SERIAL.lock().unwrap();
+        let config_key = key("127.0.0.1:10460");
+        let listen = start("127.0.0.1:10460");
+
+        // started again once keepalive is changed
+        realm_set_tcp_keepalive(30, 10, 3);
+        assert_eq!(start("127.0.0.1:10460"), listen);
+
+        // rejected in strict mode, the instance is kept
+        realm_set_strict_start(true);
+        let remote = CString::new("127.0.0.1:10460").unwrap();
+        let path = CString::new("/stats").unwrap();
+        let res = start_realm(remote.as_ptr(), remote.as_ptr(), path.as_ptr(), false, false);
+        assert!(res.is_null());
+        let e = realm_last_error();
+        let msg = unsafe { CStr::from_ptr(e) }.to_str().unwrap().to_string();
+        unsafe { realm_free_string(e as *mut c_char) };
+        assert!(msg.contains("different config"), "{}", msg);
+        assert_eq!(RUNTIME_MAP.lock().unwrap()[config_key.to_str().unwrap()].count, 2);
+
+        // the same config is still shared
+        *TCP_KEEPALIVE.lock().unwrap() = None;
+        assert_eq!(start("127.0.0.1:10460"), listen);
+        assert_eq!(RUNTIME_MAP.lock().unwrap()[config_key.to_str().unwrap()].count, 3);
+
+        realm_set_strict_start(false);
+        for _ in 0..3 {
+            assert!(stop(config_key.to_str().unwrap()));
+        }
+    }
+
+    #[test]
+    fn stop_all_deadline() {
+        let _serial = SERIAL.lock().unwrap();
+        let rt = create_runtime().unwrap();
+        rt.spawn(echo("127.0.0.1:20470"));
+        rt.spawn(run_tcp(ws_server("127.0.0.1:10470", "127.0.0.1:20470", "/stats")));
+        std::thread::sleep(Duration::from_millis(500));
+
+        // drained, the connection ends before the deadline
+        let listen = start("127.0.0.1:10470");
+        let stream = connect_echo(&listen);
+        let closer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            drop(stream);
+        });
+        let started = Instant::now();
+        assert_eq!(realm_stop_all(3000), 0);
+        assert!(started.elapsed() < Duration::from_secs(3));
+        closer.join().unwrap();
+        assert!(RUNTIME_MAP.lock().unwrap().is_empty());
+        assert!(std::net::TcpStream::connect(&listen).is_err());
+
+        // escalated, the transfer goes on past the deadline
+        let listen = start("127.0.0.1:10470");
+        let mut stream = connect_echo(&listen);
+        let transfer = std::thread::spawn(move || {
+            let mut buf = [0u8; 5];
+            loop {
+                stream.write_all(b"hello")?;
+                stream.read_exact(&mut buf)?;
+            }
+        });
+        let started = Instant::now();
+        assert_eq!(realm_stop_all(300), 1);
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        let res: std::io::Result<()> = transfer.join().unwrap();
+        assert!(res.is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn listen_interface() {
+        let _serial = SERIAL.lock().unwrap();
+        let rt = create_runtime().unwrap();
+        rt.spawn(echo("127.0.0.1:20440"));
+        std::thread::sleep(Duration::from_millis(200));
+
+        let conf: EndpointConf = toml::from_str(
+            r#"
+            listen = "0.0.0.0:10440"
+            listen_interface = "lo"
+            remote = "127.0.0.1:20440"
+            "#,
         )
-        .collect::<Vec<_>>();
+        .unwrap();
+        let addrs: Vec<SocketAddr> = crate::conf::interface_addrs("lo")
+            .unwrap()
+            .into_iter()
+            .map(|mut x| {
+                x.set_port(10440);
+                x
+            })
+            .collect();
+        assert!(addrs.contains(&"127.0.0.1:10440".parse().unwrap()), "{:?}", addrs);
+        let listened = |instance: &Instance| -> Vec<SocketAddr> {
+            instance
+                .endpoints
+                .iter()
+                .map(|x| x.tcp.as_ref().unwrap().local_addr().unwrap())
+                .collect()
+        };
+
+        // one listener per address, under one instance
+        let instance = create_instance_with(conf).unwrap();
+        assert_eq!(listened(&instance), addrs);
+        RUNTIME_MAP.lock().unwrap().insert(String::from("lo"), instance);
+        std::thread::sleep(Duration::from_millis(300));
+        for addr in &addrs {
+            drop(connect_echo(&addr.to_string()));
+        }
+
+        // enumerated again once enabled
+        let key = CString::new("lo").unwrap();
+        assert!(realm_disable(key.as_ptr()));
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(realm_enable(key.as_ptr()));
+        assert_eq!(listened(&RUNTIME_MAP.lock().unwrap()["lo"]), addrs);
+        std::thread::sleep(Duration::from_millis(300));
+        for addr in &addrs {
+            drop(connect_echo(&addr.to_string()));
+        }
 
-    join_all(workers).await;
+        stop_all();
+        rt.shutdown_background();
+    }
 }
@@ -0,0 +1,220 @@
+//! Log rate limit.
+//!
+//! Messages are grouped by where they are logged. Within a window, a kind
+//! of messages is printed up to `rate` times, the rest is counted and
+//! collapsed into a `... repeated N times` summary, which is printed with
+//! the next log once the window is over.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::{Level, Log, Metadata, Record};
+
+pub struct RateLimit {
+    inner: Box<dyn Log>,
+    rate: usize,
+    window: Duration,
+    kinds: Mutex<HashMap<(String, u32), Window>>,
+}
+
+struct Window {
+    start: Instant,
+    count: usize,
+    suppressed: usize,
+    level: Level,
+    target: String,
+    // the last suppressed message
+    last: String,
+}
+
+impl RateLimit {
+    pub fn new(inner: Box<dyn Log>, rate: usize, window: Duration) -> Self {
+        Self {
+            inner,
+            rate,
+            window,
+            kinds: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn summarize(&self, summaries: Vec<Summary>) {
+        for Summary {
+            level,
+            target,
+            last,
+            suppressed,
+        } in summaries
+        {
+            self.inner.log(
+                &Record::builder()
+                    .level(level)
+                    .target(&target)
+                    .args(format_args!("{} ... repeated {} times", last, suppressed))
+                    .build(),
+            );
+        }
+    }
+}
+
+struct Summary {
+    level: Level,
+    target: String,
+    last: String,
+    suppressed: usize,
+}
+
+impl Window {
+    fn summary(&mut self) -> Option<Summary> {
+        if self.suppressed == 0 {
+            return None;
+        }
+        Some(Summary {
+            level: self.level,
+            target: self.target.clone(),
+            last: std::mem::take(&mut self.last),
+            suppressed: std::mem::take(&mut self.suppressed),
+        })
+    }
+}
+
+impl Log for RateLimit {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut ended = Vec::new();
+        let pass = {
+            let mut kinds = self.kinds.lock().unwrap();
+            kinds.retain(|_, w| {
+                if now.duration_since(w.start) < self.window {
+                    return true;
+                }
+                ended.extend(w.summary());
+                false
+            });
+
+            let kind = (
+                String::from(record.file().unwrap_or_else(|| record.target())),
+                record.line().unwrap_or_default(),
+            );
+            let w = kinds.entry(kind).or_insert_with(|| Window {
+                start: now,
+                count: 0,
+                suppressed: 0,
+                level: record.level(),
+                target: String::from(record.target()),
+                last: String::new(),
+            });
+            if w.count < self.rate {
+                w.count += 1;
+                true
+            } else {
+                w.suppressed += 1;
+                w.last = record.args().to_string();
+                false
+            }
+        };
+
+        self.summarize(ended);
+        if pass {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        let pending = self
+            .kinds
+            .lock()
+            .unwrap()
+            .values_mut()
+            .filter_map(Window::summary)
+            .collect();
+        self.summarize(pending);
+        self.inner.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    impl Log for Capture {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn error(logger: &RateLimit, msg: &str) {
+        logger.log(
+            &Record::builder()
+                .level(Level::Error)
+                .target("realm")
+                .file(Some("a.rs"))
+                .line(Some(1))
+                .args(format_args!("{}", msg))
+                .build(),
+        );
+    }
+
+    #[test]
+    fn collapse_repeated() {
+        let capture = Capture::default();
+        let logger = RateLimit::new(Box::new(capture.clone()), 2, Duration::from_millis(200));
+
+        for _ in 0..100 {
+            error(&logger, "connect failed");
+        }
+        assert_eq!(*capture.0.lock().unwrap(), ["connect failed", "connect failed"]);
+
+        // summarized with the next log, after the window
+        std::thread::sleep(Duration::from_millis(250));
+        error(&logger, "connect failed again");
+        assert_eq!(
+            *capture.0.lock().unwrap(),
+            [
+                "connect failed",
+                "connect failed",
+                "connect failed ... repeated 98 times",
+                "connect failed again"
+            ]
+        );
+
+        // other kinds are not limited
+        for i in 0..3 {
+            logger.log(
+                &Record::builder()
+                    .level(Level::Error)
+                    .file(Some("b.rs"))
+                    .line(Some(i))
+                    .args(format_args!("{}", i))
+                    .build(),
+            );
+        }
+        assert_eq!(capture.0.lock().unwrap().len(), 7);
+
+        error(&logger, "connect failed again");
+        error(&logger, "connect failed again");
+        logger.flush();
+        assert_eq!(
+            capture.0.lock().unwrap().last().unwrap(),
+            "connect failed again ... repeated 1 times"
+        );
+    }
+}